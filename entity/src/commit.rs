@@ -15,6 +15,11 @@ pub struct Model {
     pub author: Option<String>,
     pub committer: Option<String>,
     pub content: Option<String>,
+    pub cl: Option<i64>,
+    #[sea_orm(column_type = "Timestamp")]
+    pub author_time: Option<chrono::NaiveDateTime>,
+    #[sea_orm(column_type = "Timestamp")]
+    pub committer_time: Option<chrono::NaiveDateTime>,
     #[sea_orm(column_type = "Timestamp")]
     pub created_at: chrono::NaiveDateTime,
     #[sea_orm(column_type = "Timestamp")]
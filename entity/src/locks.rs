@@ -14,11 +14,3 @@ pub struct Model {
 pub enum Relation {}
 
 impl ActiveModelBehavior for ActiveModel {}
-
-/*
-CREATE TABLE locks (
-    id VARCHAR(200),
-    data VARCHAR(10000),
-    PRIMARY KEY (id)
-);
- */
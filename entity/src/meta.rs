@@ -15,12 +15,3 @@ pub struct Model {
 pub enum Relation {}
 
 impl ActiveModelBehavior for ActiveModel {}
-
-/*
-CREATE TABLE meta (
-    oid VARCHAR(100),
-    size INT,
-    exist TINYINT,
-    PRIMARY KEY (oid)
-);
- */
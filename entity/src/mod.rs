@@ -2,8 +2,19 @@
 
 pub mod prelude;
 
+pub mod backfill;
+pub mod cl_sequence;
 pub mod commit;
+pub mod commit_bloom;
+pub mod commit_edge;
+pub mod commit_graph;
+pub mod commit_mapping;
 pub mod locks;
 pub mod meta;
 pub mod node;
+pub mod node_data;
+pub mod object_index;
+pub mod object_signature;
+pub mod reachability_bitmap;
 pub mod refs;
+pub mod signing_key;
@@ -1,7 +1,18 @@
 //! `SeaORM` Entity. Generated by sea-orm-codegen 0.10.6
 
+pub use super::backfill::Entity as Backfill;
+pub use super::cl_sequence::Entity as ClSequence;
 pub use super::commit::Entity as Commit;
+pub use super::commit_bloom::Entity as CommitBloom;
+pub use super::commit_edge::Entity as CommitEdge;
+pub use super::commit_graph::Entity as CommitGraph;
+pub use super::commit_mapping::Entity as CommitMapping;
 pub use super::locks::Entity as Locks;
 pub use super::meta::Entity as Meta;
 pub use super::node::Entity as Node;
+pub use super::node_data::Entity as NodeData;
+pub use super::object_index::Entity as ObjectIndex;
+pub use super::object_signature::Entity as ObjectSignature;
+pub use super::reachability_bitmap::Entity as ReachabilityBitmap;
 pub use super::refs::Entity as Refs;
+pub use super::signing_key::Entity as SigningKey;
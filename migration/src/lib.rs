@@ -0,0 +1,49 @@
+//! Versioned schema migrations for the Gust database.
+//!
+//! Every table used by the `entity` crate is created by an ordered migration here, and the
+//! versions already applied are recorded in the `seaql_migrations` table. New schema changes
+//! must be added as a new migration at the end of [`Migrator::migrations`], never by editing
+//! one that has already been released.
+
+pub use sea_orm_migration::prelude::*;
+
+mod m20230523_000001_create_commit_table;
+mod m20230523_000002_create_node_table;
+mod m20230523_000003_create_refs_table;
+mod m20230523_000004_create_locks_table;
+mod m20230523_000005_create_meta_table;
+mod m20230601_000001_create_node_data_table;
+mod m20230602_000001_add_node_path;
+mod m20230603_000001_create_commit_mapping_table;
+mod m20230605_000001_add_commit_changelist;
+mod m20230606_000001_add_commit_metadata;
+mod m20230607_000001_create_commit_graph;
+mod m20230608_000001_create_commit_bloom_table;
+mod m20230609_000001_create_bitmap_tables;
+mod m20230610_000001_create_signature_tables;
+mod m20230611_000001_create_backfill_table;
+
+pub struct Migrator;
+
+#[async_trait::async_trait]
+impl MigratorTrait for Migrator {
+    fn migrations() -> Vec<Box<dyn MigrationTrait>> {
+        vec![
+            Box::new(m20230523_000001_create_commit_table::Migration),
+            Box::new(m20230523_000002_create_node_table::Migration),
+            Box::new(m20230523_000003_create_refs_table::Migration),
+            Box::new(m20230523_000004_create_locks_table::Migration),
+            Box::new(m20230523_000005_create_meta_table::Migration),
+            Box::new(m20230601_000001_create_node_data_table::Migration),
+            Box::new(m20230602_000001_add_node_path::Migration),
+            Box::new(m20230603_000001_create_commit_mapping_table::Migration),
+            Box::new(m20230605_000001_add_commit_changelist::Migration),
+            Box::new(m20230606_000001_add_commit_metadata::Migration),
+            Box::new(m20230607_000001_create_commit_graph::Migration),
+            Box::new(m20230608_000001_create_commit_bloom_table::Migration),
+            Box::new(m20230609_000001_create_bitmap_tables::Migration),
+            Box::new(m20230610_000001_create_signature_tables::Migration),
+            Box::new(m20230611_000001_create_backfill_table::Migration),
+        ]
+    }
+}
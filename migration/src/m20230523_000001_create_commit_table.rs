@@ -0,0 +1,60 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // `if_not_exists` lets databases created from the old SQL dump adopt the migrations
+        manager
+            .create_table(
+                Table::create()
+                    .table(Commit::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Commit::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Commit::GitId).string_len(40).not_null())
+                    .col(ColumnDef::new(Commit::Tree).string_len(40).not_null())
+                    .col(ColumnDef::new(Commit::Pid).string_len(40))
+                    .col(ColumnDef::new(Commit::Meta).binary().not_null())
+                    .col(ColumnDef::new(Commit::RepoPath).string_len(128).not_null())
+                    .col(ColumnDef::new(Commit::Author).string_len(64))
+                    .col(ColumnDef::new(Commit::Committer).string_len(64))
+                    .col(ColumnDef::new(Commit::Content).string_len(128))
+                    .col(ColumnDef::new(Commit::CreatedAt).date_time().not_null())
+                    .col(ColumnDef::new(Commit::UpdatedAt).date_time().not_null())
+                    .index(Index::create().name("idx_git_id").col(Commit::GitId))
+                    .index(Index::create().name("idx_tree").col(Commit::Tree))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Commit::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum Commit {
+    Table,
+    Id,
+    GitId,
+    Tree,
+    Pid,
+    Meta,
+    RepoPath,
+    Author,
+    Committer,
+    Content,
+    CreatedAt,
+    UpdatedAt,
+}
@@ -0,0 +1,58 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Node::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Node::Id)
+                            .big_integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Node::NodeId).big_integer().not_null())
+                    .col(ColumnDef::new(Node::GitId).string_len(64).not_null())
+                    .col(ColumnDef::new(Node::NodeType).string_len(16).not_null())
+                    .col(ColumnDef::new(Node::Name).string_len(128))
+                    .col(ColumnDef::new(Node::Mode).binary().not_null())
+                    .col(ColumnDef::new(Node::ContentSha).string_len(40))
+                    .col(ColumnDef::new(Node::Data).blob(BlobSize::Medium).not_null())
+                    .col(ColumnDef::new(Node::CreatedAt).date_time().not_null())
+                    .col(ColumnDef::new(Node::UpdatedAt).date_time().not_null())
+                    .index(Index::create().name("idx_git_id").col(Node::GitId))
+                    .index(Index::create().name("idx_name").col(Node::Name))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Node::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+#[allow(clippy::enum_variant_names)]
+enum Node {
+    Table,
+    Id,
+    NodeId,
+    GitId,
+    NodeType,
+    Name,
+    Mode,
+    ContentSha,
+    Data,
+    CreatedAt,
+    UpdatedAt,
+}
@@ -0,0 +1,47 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Refs::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Refs::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Refs::RepoPath).string_len(64).not_null())
+                    .col(ColumnDef::new(Refs::RefName).string_len(32).not_null())
+                    .col(ColumnDef::new(Refs::RefGitId).string_len(40).not_null())
+                    .col(ColumnDef::new(Refs::CreatedAt).date_time().not_null())
+                    .col(ColumnDef::new(Refs::UpdatedAt).date_time().not_null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Refs::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum Refs {
+    Table,
+    Id,
+    RepoPath,
+    RefName,
+    RefGitId,
+    CreatedAt,
+    UpdatedAt,
+}
@@ -0,0 +1,38 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Locks::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Locks::Id)
+                            .string_len(200)
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Locks::Data).string_len(10000))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Locks::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum Locks {
+    Table,
+    Id,
+    Data,
+}
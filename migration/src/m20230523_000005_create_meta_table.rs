@@ -0,0 +1,40 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Meta::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Meta::Oid)
+                            .string_len(100)
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Meta::Size).big_integer())
+                    .col(ColumnDef::new(Meta::Exist).tiny_integer())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Meta::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum Meta {
+    Table,
+    Oid,
+    Size,
+    Exist,
+}
@@ -0,0 +1,87 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(NodeData::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(NodeData::GitId)
+                            .string_len(64)
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(NodeData::NodeType).string_len(16).not_null())
+                    .col(
+                        ColumnDef::new(NodeData::Data)
+                            .blob(BlobSize::Medium)
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(NodeData::CreatedAt).date_time().not_null())
+                    .to_owned(),
+            )
+            .await?;
+
+        // move the content out of the node rows, keeping one copy per hash
+        manager
+            .get_connection()
+            .execute_unprepared(
+                "INSERT IGNORE INTO node_data (git_id, node_type, data, created_at) \
+                 SELECT git_id, node_type, data, created_at FROM node",
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Node::Table)
+                    .drop_column(Node::Data)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Node::Table)
+                    .add_column(ColumnDef::new(Node::Data).blob(BlobSize::Medium))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .get_connection()
+            .execute_unprepared(
+                "UPDATE node JOIN node_data ON node.git_id = node_data.git_id \
+                 SET node.data = node_data.data",
+            )
+            .await?;
+
+        manager
+            .drop_table(Table::drop().table(NodeData::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum NodeData {
+    Table,
+    GitId,
+    NodeType,
+    Data,
+    CreatedAt,
+}
+
+#[derive(Iden)]
+enum Node {
+    Table,
+    Data,
+}
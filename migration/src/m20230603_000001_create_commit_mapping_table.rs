@@ -0,0 +1,64 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(CommitMapping::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(CommitMapping::Id)
+                            .big_integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(CommitMapping::RepoPath)
+                            .string_len(128)
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(CommitMapping::MonoId)
+                            .string_len(40)
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(CommitMapping::SplitId).string_len(40))
+                    .col(
+                        ColumnDef::new(CommitMapping::CreatedAt)
+                            .date_time()
+                            .not_null(),
+                    )
+                    .index(
+                        Index::create()
+                            .name("uk_repo_path_mono_id")
+                            .unique()
+                            .col(CommitMapping::RepoPath)
+                            .col(CommitMapping::MonoId),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(CommitMapping::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum CommitMapping {
+    Table,
+    Id,
+    RepoPath,
+    MonoId,
+    SplitId,
+    CreatedAt,
+}
@@ -0,0 +1,87 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Commit::Table)
+                    .add_column(ColumnDef::new(Commit::Cl).big_integer())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_cl")
+                    .table(Commit::Table)
+                    .col(Commit::Cl)
+                    .to_owned(),
+            )
+            .await?;
+
+        // a single row holding the last changelist number handed out
+        manager
+            .create_table(
+                Table::create()
+                    .table(ClSequence::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(ClSequence::Id)
+                            .integer()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(ClSequence::Value).big_integer().not_null())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .exec_stmt(
+                Query::insert()
+                    .into_table(ClSequence::Table)
+                    .columns([ClSequence::Id, ClSequence::Value])
+                    .values_panic([1.into(), 0.into()])
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ClSequence::Table).to_owned())
+            .await?;
+
+        manager
+            .drop_index(Index::drop().name("idx_cl").table(Commit::Table).to_owned())
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Commit::Table)
+                    .drop_column(Commit::Cl)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum Commit {
+    Table,
+    Cl,
+}
+
+#[derive(Iden)]
+enum ClSequence {
+    Table,
+    Id,
+    Value,
+}
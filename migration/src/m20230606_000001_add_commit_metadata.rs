@@ -0,0 +1,75 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // the columns are filled from the commit object when it's saved, commits saved
+        // before this migration are filled by a backfill on start up
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Commit::Table)
+                    .modify_column(ColumnDef::new(Commit::Pid).text())
+                    .modify_column(ColumnDef::new(Commit::Author).string_len(255))
+                    .modify_column(ColumnDef::new(Commit::Committer).string_len(255))
+                    .modify_column(ColumnDef::new(Commit::Content).text())
+                    .add_column(ColumnDef::new(Commit::AuthorTime).date_time())
+                    .add_column(ColumnDef::new(Commit::CommitterTime).date_time())
+                    .to_owned(),
+            )
+            .await?;
+
+        for (name, col) in [
+            ("idx_author", Commit::Author),
+            ("idx_committer", Commit::Committer),
+            ("idx_committer_time", Commit::CommitterTime),
+        ] {
+            manager
+                .create_index(
+                    Index::create()
+                        .name(name)
+                        .table(Commit::Table)
+                        .col(col)
+                        .to_owned(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for name in ["idx_author", "idx_committer", "idx_committer_time"] {
+            manager
+                .drop_index(Index::drop().name(name).table(Commit::Table).to_owned())
+                .await?;
+        }
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Commit::Table)
+                    .drop_column(Commit::AuthorTime)
+                    .drop_column(Commit::CommitterTime)
+                    .modify_column(ColumnDef::new(Commit::Pid).string_len(40))
+                    .modify_column(ColumnDef::new(Commit::Author).string_len(64))
+                    .modify_column(ColumnDef::new(Commit::Committer).string_len(64))
+                    .modify_column(ColumnDef::new(Commit::Content).string_len(128))
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum Commit {
+    Table,
+    Pid,
+    Author,
+    Committer,
+    Content,
+    AuthorTime,
+    CommitterTime,
+}
@@ -0,0 +1,106 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(CommitGraph::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(CommitGraph::GitId)
+                            .string_len(40)
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(CommitGraph::Generation)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(CommitGraph::CommitTime)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // one row per parent, seq keeps the parent order of the commit
+        manager
+            .create_table(
+                Table::create()
+                    .table(CommitEdge::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(CommitEdge::Id)
+                            .big_integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(CommitEdge::CommitId)
+                            .string_len(40)
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(CommitEdge::ParentId)
+                            .string_len(40)
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(CommitEdge::Seq).integer().not_null())
+                    .index(
+                        Index::create()
+                            .name("uk_commit_id_seq")
+                            .unique()
+                            .col(CommitEdge::CommitId)
+                            .col(CommitEdge::Seq),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_parent_id")
+                    .table(CommitEdge::Table)
+                    .col(CommitEdge::ParentId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(CommitEdge::Table).to_owned())
+            .await?;
+
+        manager
+            .drop_table(Table::drop().table(CommitGraph::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum CommitGraph {
+    Table,
+    GitId,
+    Generation,
+    CommitTime,
+}
+
+#[derive(Iden)]
+enum CommitEdge {
+    Table,
+    Id,
+    CommitId,
+    ParentId,
+    Seq,
+}
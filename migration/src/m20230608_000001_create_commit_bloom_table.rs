@@ -0,0 +1,39 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // bloom_filter is null for commits changing too many paths to be worth a filter
+        manager
+            .create_table(
+                Table::create()
+                    .table(CommitBloom::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(CommitBloom::GitId)
+                            .string_len(40)
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(CommitBloom::BloomFilter).blob(BlobSize::Blob(None)))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(CommitBloom::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum CommitBloom {
+    Table,
+    GitId,
+    BloomFilter,
+}
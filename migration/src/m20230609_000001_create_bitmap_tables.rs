@@ -0,0 +1,109 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // the id of an object is its bit position in the reachability bitmaps
+        manager
+            .create_table(
+                Table::create()
+                    .table(ObjectIndex::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(ObjectIndex::Id)
+                            .big_integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(ObjectIndex::GitId).string_len(40).not_null())
+                    .col(
+                        ColumnDef::new(ObjectIndex::ObjectType)
+                            .string_len(16)
+                            .not_null(),
+                    )
+                    .index(
+                        Index::create()
+                            .name("uk_git_id")
+                            .unique()
+                            .col(ObjectIndex::GitId),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(ReachabilityBitmap::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(ReachabilityBitmap::Id)
+                            .big_integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(ReachabilityBitmap::RepoPath)
+                            .string_len(128)
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(ReachabilityBitmap::CommitId)
+                            .string_len(40)
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(ReachabilityBitmap::Bitmap)
+                            .blob(BlobSize::Long)
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(ReachabilityBitmap::CreatedAt)
+                            .date_time()
+                            .not_null(),
+                    )
+                    .index(
+                        Index::create()
+                            .name("uk_repo_path_commit_id")
+                            .unique()
+                            .col(ReachabilityBitmap::RepoPath)
+                            .col(ReachabilityBitmap::CommitId),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ReachabilityBitmap::Table).to_owned())
+            .await?;
+
+        manager
+            .drop_table(Table::drop().table(ObjectIndex::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum ObjectIndex {
+    Table,
+    Id,
+    GitId,
+    ObjectType,
+}
+
+#[derive(Iden)]
+enum ReachabilityBitmap {
+    Table,
+    Id,
+    RepoPath,
+    CommitId,
+    Bitmap,
+    CreatedAt,
+}
@@ -0,0 +1,99 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // the public keys of a user, signatures are checked against the keys of the email of
+        // the committer or tagger
+        manager
+            .create_table(
+                Table::create()
+                    .table(SigningKey::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(SigningKey::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(SigningKey::Email).string_len(255).not_null())
+                    .col(
+                        ColumnDef::new(SigningKey::KeyType)
+                            .string_len(16)
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(SigningKey::KeyId).string_len(64).not_null())
+                    .col(ColumnDef::new(SigningKey::PublicKey).text().not_null())
+                    .col(ColumnDef::new(SigningKey::CreatedAt).date_time().not_null())
+                    .index(
+                        Index::create()
+                            .name("uk_email_key_id")
+                            .unique()
+                            .col(SigningKey::Email)
+                            .col(SigningKey::KeyId),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(ObjectSignature::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(ObjectSignature::GitId)
+                            .string_len(40)
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(ObjectSignature::Status)
+                            .string_len(16)
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(ObjectSignature::KeyId).string_len(64))
+                    .col(
+                        ColumnDef::new(ObjectSignature::VerifiedAt)
+                            .date_time()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ObjectSignature::Table).to_owned())
+            .await?;
+
+        manager
+            .drop_table(Table::drop().table(SigningKey::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum SigningKey {
+    Table,
+    Id,
+    Email,
+    KeyType,
+    KeyId,
+    PublicKey,
+    CreatedAt,
+}
+
+#[derive(Iden)]
+enum ObjectSignature {
+    Table,
+    GitId,
+    Status,
+    KeyId,
+    VerifiedAt,
+}
@@ -0,0 +1,40 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // one row per data backfill run by the server on start up once it's finished, e.g. the
+        // commit graph of commits stored before it existed
+        manager
+            .create_table(
+                Table::create()
+                    .table(Backfill::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Backfill::Name)
+                            .string_len(64)
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Backfill::FinishedAt).date_time().not_null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Backfill::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum Backfill {
+    Table,
+    Name,
+    FinishedAt,
+}
@@ -0,0 +1,72 @@
+//!
+//! Changelist API, resolve the changelist numbers of trunk commits to commit ids and back.
+//!
+//! - `GET <repo path>/changelists/<cl>`: the commit the repo was at as of changelist `cl`.
+//!   Fetch it with `git fetch <repo url> <commit>` to sync the repo or a subdirectory to `cl`.
+//! - `GET <repo path>/commits/<commit id>/changelist`: the changelist a commit landed in.
+//!
+use std::path::PathBuf;
+
+use axum::body::Body;
+use axum::extract::State;
+use axum::http::{Response, StatusCode};
+use serde::Serialize;
+
+use crate::git::errors::GitError;
+use crate::gust::driver::ObjectStorage;
+use crate::lib::AppState;
+
+#[derive(Serialize, Debug)]
+pub struct ChangelistResponse {
+    pub changelist: i64,
+    pub commit: String,
+}
+
+pub async fn get_commit_by_changelist<T>(
+    state: State<AppState<T>>,
+    repo_path: PathBuf,
+    cl: i64,
+) -> Result<Response<Body>, (StatusCode, String)>
+where
+    T: ObjectStorage,
+{
+    let (changelist, commit) = state
+        .storage
+        .get_commit_by_changelist(&repo_path, cl)
+        .await
+        .map_err(error_response)?;
+    json_response(ChangelistResponse { changelist, commit })
+}
+
+pub async fn get_changelist_by_commit<T>(
+    state: State<AppState<T>>,
+    repo_path: PathBuf,
+    commit_id: &str,
+) -> Result<Response<Body>, (StatusCode, String)>
+where
+    T: ObjectStorage,
+{
+    let changelist = state
+        .storage
+        .get_changelist_by_commit(&repo_path, commit_id)
+        .await
+        .map_err(error_response)?;
+    json_response(ChangelistResponse {
+        changelist,
+        commit: commit_id.to_owned(),
+    })
+}
+
+fn json_response(body: ChangelistResponse) -> Result<Response<Body>, (StatusCode, String)> {
+    Ok(Response::builder()
+        .header("Content-Type", "application/json")
+        .body(Body::from(serde_json::to_string(&body).unwrap()))
+        .unwrap())
+}
+
+fn error_response(err: GitError) -> (StatusCode, String) {
+    match err {
+        GitError::ChangelistNotFound(_) => (StatusCode::NOT_FOUND, err.to_string()),
+        _ => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+    }
+}
@@ -0,0 +1,106 @@
+//!
+//! Commit search API.
+//!
+//! `GET <repo path>/commits?author=alice&since=2023-06-01&message=fix&limit=20` returns the
+//! matching commits of the repo or subdirectory, latest first. `since` and `until` are
+//! `YYYY-MM-DD` dates or RFC 3339 times. `author` and `committer` match the start of
+//! `name <email>`, `message` matches a substring of the message. `path=src/main.rs` limits the
+//! results to commits changing that file or directory.
+//!
+use std::path::PathBuf;
+
+use axum::body::Body;
+use axum::extract::State;
+use axum::http::{Response, StatusCode};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime};
+use serde::Deserialize;
+
+use crate::gust::driver::commit_search::CommitQuery;
+use crate::gust::driver::ObjectStorage;
+use crate::lib::AppState;
+
+#[derive(Deserialize, Debug, Default)]
+pub struct CommitSearchParams {
+    pub author: Option<String>,
+    pub committer: Option<String>,
+    pub since: Option<String>,
+    pub until: Option<String>,
+    pub message: Option<String>,
+    pub path: Option<String>,
+    pub limit: Option<u64>,
+}
+
+impl CommitSearchParams {
+    fn to_query(&self) -> Result<CommitQuery, String> {
+        Ok(CommitQuery {
+            author: self.author.clone(),
+            committer: self.committer.clone(),
+            since: self
+                .since
+                .as_deref()
+                .map(|s| parse_time(s, NaiveTime::MIN))
+                .transpose()?,
+            until: self
+                .until
+                .as_deref()
+                .map(|s| parse_time(s, NaiveTime::from_hms_opt(23, 59, 59).unwrap()))
+                .transpose()?,
+            message: self.message.clone(),
+            path: self
+                .path
+                .as_deref()
+                .map(|p| PathBuf::from(p.trim_matches('/'))),
+            limit: self.limit,
+        })
+    }
+}
+
+/// parse a RFC 3339 time or a date, which is completed with `time_of_day`
+fn parse_time(s: &str, time_of_day: NaiveTime) -> Result<NaiveDateTime, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+        return Ok(time.naive_utc());
+    }
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map(|date| date.and_time(time_of_day))
+        .map_err(|_| format!("Invalid time: {}\n", s))
+}
+
+pub async fn search_commits<T>(
+    state: State<AppState<T>>,
+    repo_path: PathBuf,
+    params: CommitSearchParams,
+) -> Result<Response<Body>, (StatusCode, String)>
+where
+    T: ObjectStorage,
+{
+    let query = params
+        .to_query()
+        .map_err(|err| (StatusCode::BAD_REQUEST, err))?;
+    let commits = state
+        .storage
+        .search_commits(&repo_path, &query)
+        .await
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+    Ok(Response::builder()
+        .header("Content-Type", "application/json")
+        .body(Body::from(serde_json::to_string(&commits).unwrap()))
+        .unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveTime;
+
+    use super::parse_time;
+
+    #[test]
+    fn test_parse_time() {
+        let time = parse_time("2023-06-01", NaiveTime::MIN).unwrap();
+        assert_eq!(1685577600, time.and_utc().timestamp());
+
+        let time = parse_time("2023-06-01T08:00:00+08:00", NaiveTime::MIN).unwrap();
+        assert_eq!(1685577600, time.and_utc().timestamp());
+
+        assert!(parse_time("last week", NaiveTime::MIN).is_err());
+    }
+}
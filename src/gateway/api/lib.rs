@@ -18,6 +18,7 @@ use hyper::{Request, Uri};
 use regex::Regex;
 use serde::Deserialize;
 
+use crate::gateway::api::{changelist, commit, signature, tree};
 use crate::git::lfs;
 use crate::git::lfs::structs::*;
 use crate::git::protocol::{http, PackProtocol, Protocol};
@@ -39,6 +40,7 @@ struct GetParams {
     pub path: Option<String>,
     pub limit: Option<String>,
     pub cursor: Option<String>,
+    pub email: Option<String>,
 }
 
 pub fn remove_git_suffix(uri: Uri, git_suffix: &str) -> PathBuf {
@@ -106,6 +108,57 @@ where
         return lfs::http::lfs_retrieve_lock(state, lock_list_query).await;
     }
 
+    // Routing changelist services.
+    let changelist_re = Regex::new(r"^(?P<repo>.+)/changelists/(?P<cl>[0-9]+)$").unwrap();
+    if let Some(caps) = changelist_re.captures(uri.path()) {
+        let cl = caps["cl"].parse::<i64>().map_err(|_| {
+            (
+                StatusCode::BAD_REQUEST,
+                String::from("Invalid changelist\n"),
+            )
+        })?;
+        let repo_path = PathBuf::from(&caps["repo"]);
+        return changelist::get_commit_by_changelist(state, repo_path, cl).await;
+    }
+    let commit_re =
+        Regex::new(r"^(?P<repo>.+)/commits/(?P<commit>[0-9a-f]{40})/changelist$").unwrap();
+    if let Some(caps) = commit_re.captures(uri.path()) {
+        let repo_path = PathBuf::from(&caps["repo"]);
+        return changelist::get_changelist_by_commit(state, repo_path, &caps["commit"]).await;
+    }
+
+    // Routing commit search.
+    let search_re = Regex::new(r"^(?P<repo>.+)/commits$").unwrap();
+    if let Some(caps) = search_re.captures(uri.path()) {
+        let Query(search_params) = Query::<commit::CommitSearchParams>::try_from_uri(&uri)
+            .map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
+        let repo_path = PathBuf::from(&caps["repo"]);
+        return commit::search_commits(state, repo_path, search_params).await;
+    }
+
+    // Routing directory listings.
+    let tree_re = Regex::new(r"^(?P<repo>.+)/tree/(?P<commit>[0-9a-f]{40})$").unwrap();
+    if let Some(caps) = tree_re.captures(uri.path()) {
+        let repo_path = PathBuf::from(&caps["repo"]);
+        let path = params.path.as_deref().unwrap_or_default();
+        return tree::list_tree(state, repo_path, &caps["commit"], path).await;
+    }
+
+    // Routing signing keys and signatures.
+    if uri.path() == "/signing-key" {
+        return signature::get_server_signing_key(state).await;
+    }
+    if uri.path() == "/keys" {
+        let email = params
+            .email
+            .ok_or((StatusCode::BAD_REQUEST, String::from("Missing email\n")))?;
+        return signature::get_signing_keys(state, &email).await;
+    }
+    let signature_re = Regex::new(r"/(commits|tags)/(?P<object>[0-9a-f]{40})/signature$").unwrap();
+    if let Some(caps) = signature_re.captures(uri.path()) {
+        return signature::get_signature(state, &caps["object"]).await;
+    }
+
     if !Regex::new(r"/info/refs$").unwrap().is_match(uri.path()) {
         return Err((
             StatusCode::FORBIDDEN,
@@ -172,6 +225,10 @@ where
         return lfs::http::lfs_process_batch(state, req).await;
     }
 
+    if uri.path() == "/keys" {
+        return signature::add_signing_key(state, req).await;
+    }
+
     if Regex::new(r"/git-upload-pack$")
         .unwrap()
         .is_match(uri.path())
@@ -3,4 +3,8 @@
 //!
 //!
 //!
+pub mod changelist;
+pub mod commit;
 pub mod lib;
+pub mod signature;
+pub mod tree;
@@ -0,0 +1,228 @@
+//!
+//! Signing key and signature API.
+//!
+//! - `POST /keys` with `{"email": "alice@example.com", "key": "<public key>"}`: register an
+//!   armored OpenPGP public key or an SSH public key line for the signatures of `email`. Only
+//!   the admin may, with `Authorization: Bearer <KEYS_ADMIN_TOKEN>`.
+//! - `GET /keys?email=alice@example.com`: the keys registered for `email`.
+//! - `GET <repo path>/commits/<id>/signature`, `GET <repo path>/tags/<id>/signature`: the
+//!   verification status of the signature of a pushed commit or annotated tag.
+//! - `GET /signing-key`: the public key of the commits signed by the server, as text to
+//!   import with `gpg --import` or to add to the `gpg.ssh.allowedSignersFile` of git.
+//!
+use axum::body::Body;
+use axum::extract::State;
+use axum::http::header::AUTHORIZATION;
+use axum::http::{HeaderMap, Request, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::git::errors::GitError;
+use crate::git::signature::PublicKey;
+use crate::gust::driver::ObjectStorage;
+use crate::lib::AppState;
+
+#[derive(Deserialize, Debug)]
+pub struct KeyRequest {
+    pub email: String,
+    pub key: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct KeyResponse {
+    pub email: String,
+    pub key_type: String,
+    pub key_id: String,
+    pub key: String,
+}
+
+impl KeyResponse {
+    fn new(email: &str, key: PublicKey) -> KeyResponse {
+        KeyResponse {
+            email: email.to_owned(),
+            key_type: key.key_type.to_string(),
+            key_id: key.key_id,
+            key: key.text,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct SignatureResponse {
+    pub object: String,
+    pub status: String,
+    pub key_id: Option<String>,
+}
+
+pub async fn add_signing_key<T>(
+    state: State<AppState<T>>,
+    req: Request<Body>,
+) -> Result<Response<Body>, (StatusCode, String)>
+where
+    T: ObjectStorage,
+{
+    authorize_admin(req.headers(), state.storage.keys_admin_token())?;
+    let body = hyper::body::to_bytes(req.into_body())
+        .await
+        .map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
+    let request: KeyRequest =
+        serde_json::from_slice(&body).map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
+    let key = state
+        .storage
+        .add_signing_key(&request.email, &request.key)
+        .await
+        .map_err(error_response)?;
+    json_response(&KeyResponse::new(&request.email, key))
+}
+
+pub async fn get_signing_keys<T>(
+    state: State<AppState<T>>,
+    email: &str,
+) -> Result<Response<Body>, (StatusCode, String)>
+where
+    T: ObjectStorage,
+{
+    let keys: Vec<KeyResponse> = state
+        .storage
+        .get_signing_keys(email)
+        .await
+        .map_err(error_response)?
+        .into_iter()
+        .map(|key| KeyResponse::new(email, key))
+        .collect();
+    json_response(&keys)
+}
+
+pub async fn get_signature<T>(
+    state: State<AppState<T>>,
+    object_id: &str,
+) -> Result<Response<Body>, (StatusCode, String)>
+where
+    T: ObjectStorage,
+{
+    let verification = state
+        .storage
+        .get_signature(object_id)
+        .await
+        .map_err(error_response)?;
+    json_response(&SignatureResponse {
+        object: object_id.to_owned(),
+        status: verification.status.to_string(),
+        key_id: verification.key_id,
+    })
+}
+
+pub async fn get_server_signing_key<T>(
+    state: State<AppState<T>>,
+) -> Result<Response<Body>, (StatusCode, String)>
+where
+    T: ObjectStorage,
+{
+    let key = state.storage.server_signing_key().ok_or((
+        StatusCode::NOT_FOUND,
+        String::from("Server commits are not signed\n"),
+    ))?;
+    Ok(Response::builder()
+        .header("Content-Type", "text/plain")
+        .body(Body::from(key.text.clone()))
+        .unwrap())
+}
+
+/// Check the request carries the admin token, no request does when there is none.
+fn authorize_admin(headers: &HeaderMap, token: Option<&str>) -> Result<(), (StatusCode, String)> {
+    let given = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match (token, given) {
+        (Some(token), Some(given)) if constant_time_eq(token.as_bytes(), given.as_bytes()) => {
+            Ok(())
+        }
+        _ => Err((
+            StatusCode::UNAUTHORIZED,
+            String::from("Registering keys requires the admin token\n"),
+        )),
+    }
+}
+
+/// compare without telling how many leading bytes match
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn json_response<B: Serialize>(body: &B) -> Result<Response<Body>, (StatusCode, String)> {
+    Ok(Response::builder()
+        .header("Content-Type", "application/json")
+        .body(Body::from(serde_json::to_string(body).unwrap()))
+        .unwrap())
+}
+
+fn error_response(err: GitError) -> (StatusCode, String) {
+    match err {
+        GitError::InvalidPublicKey(_) => (StatusCode::BAD_REQUEST, err.to_string()),
+        GitError::NotFountHashValue(_) => (StatusCode::NOT_FOUND, err.to_string()),
+        _ => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use axum::body::Body;
+    use axum::extract::State;
+    use axum::http::{Request, StatusCode};
+    use tokio_test::block_on;
+
+    use crate::gust::driver::database::mysql::storage::MysqlStorage;
+    use crate::lib::AppState;
+    use crate::ServeConfig;
+
+    use super::add_signing_key;
+
+    fn post_key(token: Option<&str>, authorization: Option<&str>) -> StatusCode {
+        let state = AppState {
+            storage: MysqlStorage {
+                keys_admin_token: token.map(|token| Arc::new(token.to_owned())),
+                ..Default::default()
+            },
+            config: ServeConfig {
+                host: String::from("127.0.0.1"),
+                port: 8000,
+                key_path: None,
+                cert_path: None,
+                lfs_content_path: PathBuf::from("lfs_content"),
+            },
+        };
+        let mut req = Request::post("/keys");
+        if let Some(authorization) = authorization {
+            req = req.header("Authorization", authorization);
+        }
+        let body = r#"{"email": "alice@example.com", "key": "ssh-ed25519 AAAA"}"#;
+        let req = req.body(Body::from(body)).unwrap();
+        match block_on(add_signing_key(State(state), req)) {
+            Ok(resp) => resp.status(),
+            Err((status, _)) => status,
+        }
+    }
+
+    #[test]
+    fn test_add_signing_key_requires_admin() {
+        assert_eq!(StatusCode::UNAUTHORIZED, post_key(Some("secret"), None));
+        assert_eq!(
+            StatusCode::UNAUTHORIZED,
+            post_key(Some("secret"), Some("Bearer other"))
+        );
+        assert_eq!(
+            StatusCode::UNAUTHORIZED,
+            post_key(Some("secret"), Some("secret"))
+        );
+        // no one registers keys without a token configured
+        assert_eq!(StatusCode::UNAUTHORIZED, post_key(None, Some("Bearer ")));
+        // the admin gets to the key, which is invalid
+        assert_eq!(
+            StatusCode::BAD_REQUEST,
+            post_key(Some("secret"), Some("Bearer secret"))
+        );
+    }
+}
@@ -0,0 +1,41 @@
+//!
+//! Tree API.
+//!
+//! `GET <repo path>/tree/<commit id>?path=src/gust` lists a directory of the tree of a
+//! commit of the repo, the root directory without `path`. Symlinks are listed with their target and
+//! gitlinks with the commit of the submodule.
+//!
+use std::path::{Path, PathBuf};
+
+use axum::body::Body;
+use axum::extract::State;
+use axum::http::{Response, StatusCode};
+
+use crate::git::errors::GitError;
+use crate::gust::driver::ObjectStorage;
+use crate::lib::AppState;
+
+pub async fn list_tree<T>(
+    state: State<AppState<T>>,
+    repo_path: PathBuf,
+    commit_id: &str,
+    path: &str,
+) -> Result<Response<Body>, (StatusCode, String)>
+where
+    T: ObjectStorage,
+{
+    let entries = state
+        .storage
+        .list_tree(&repo_path, commit_id, Path::new(path.trim_matches('/')))
+        .await
+        .map_err(|err| match err {
+            GitError::NotFountHashValue(_) | GitError::PathNotFound(_) => {
+                (StatusCode::NOT_FOUND, err.to_string())
+            }
+            err => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+        })?;
+    Ok(Response::builder()
+        .header("Content-Type", "application/json")
+        .body(Body::from(serde_json::to_string(&entries).unwrap()))
+        .unwrap())
+}
@@ -26,6 +26,9 @@ pub enum GitError {
     #[error("The `{0}` is not a valid git tag object.")]
     InvalidTagObject(String),
 
+    #[error("The `{0}` is not a valid author or committer line.")]
+    InvalidAuthorSign(String),
+
     #[error("The `{0}` is not a valid idx file.")]
     InvalidIdxFile(String),
 
@@ -49,6 +52,48 @@ pub enum GitError {
 
     #[error("Can't found Hash value :{0} from current file")]
     NotFountHashValue(String),
+
+    #[error("{0}")]
+    RefUpdateRejected(String),
+
+    #[error("The `{0}` is not a valid signature status.")]
+    InvalidSignature(String),
+
+    #[error("The pack exceeds the {0}")]
+    PackLimitExceeded(String),
+
+    #[error("Failed to write the pack: {0}")]
+    PackWriteFailed(String),
+
+    #[error("Decoding the pack stopped: {0}")]
+    PackDecodeStopped(String),
+
+    #[error("Invalid public key: {0}")]
+    InvalidPublicKey(String),
+
+    #[error("Invalid signing key: {0}")]
+    InvalidSigningKey(String),
+
+    #[error("Failed to sign with {0}")]
+    SigningFailed(String),
+
+    #[error("Invalid fsck setting: {0}")]
+    InvalidFsckConfig(String),
+
+    #[error("Missing necessary objects, {0}")]
+    MissingObjects(String),
+
+    #[error("No changelist found for {0}")]
+    ChangelistNotFound(String),
+
+    #[error("No directory {0}")]
+    PathNotFound(String),
+
+    #[error("The `{0}` is not a path of the repo.")]
+    InvalidRepoPath(String),
+
+    #[error("Database operation failed: {0}")]
+    DbError(#[from] sea_orm::DbErr),
 }
 
 #[derive(Error, Debug)]
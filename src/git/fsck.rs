@@ -0,0 +1,815 @@
+//!
+//! Validation of received objects with the checks of `git fsck`, as `git receive-pack` runs
+//! them with `receive.fsckObjects`: the warnings of fsck are errors, except for old tags
+//! without tagger.
+//!
+//! Every check is known by its git message id, e.g. `hasDotgit`, and its severity is
+//! configurable like `receive.fsck.<msg-id>`.
+//!
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use bstr::ByteSlice;
+
+use crate::git::errors::GitError;
+use crate::git::hash::Hash;
+use crate::git::object::base::tree::TreeItemType;
+use crate::git::object::metadata::MetaData;
+use crate::git::object::types::ObjectType;
+use crate::git::pack::resolve::{ObjectSink, ResolvedObject};
+
+/// objects larger than this are reported as `largeObject` by default, 100 MiB
+pub const DEFAULT_MAX_OBJECT_SIZE: usize = 100 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FsckCheck {
+    BadFilemode,
+    ZeroPaddedFilemode,
+    TreeNotSorted,
+    DuplicateEntries,
+    HasDot,
+    HasDotdot,
+    HasDotgit,
+    EmptyName,
+    FullPathname,
+    BadTree,
+    NulInHeader,
+    UnterminatedHeader,
+    MissingTree,
+    BadTreeSha1,
+    BadParentSha1,
+    MissingAuthor,
+    MultipleAuthors,
+    MissingCommitter,
+    MissingNameBeforeEmail,
+    BadName,
+    MissingEmail,
+    MissingSpaceBeforeEmail,
+    BadEmail,
+    MissingSpaceBeforeDate,
+    ZeroPaddedDate,
+    BadDateOverflow,
+    BadDate,
+    BadTimezone,
+    MissingObject,
+    BadObjectSha1,
+    MissingTypeEntry,
+    BadType,
+    MissingTagEntry,
+    MissingTaggerEntry,
+    LargeObject,
+}
+
+impl FsckCheck {
+    const ALL: [FsckCheck; 35] = [
+        FsckCheck::BadFilemode,
+        FsckCheck::ZeroPaddedFilemode,
+        FsckCheck::TreeNotSorted,
+        FsckCheck::DuplicateEntries,
+        FsckCheck::HasDot,
+        FsckCheck::HasDotdot,
+        FsckCheck::HasDotgit,
+        FsckCheck::EmptyName,
+        FsckCheck::FullPathname,
+        FsckCheck::BadTree,
+        FsckCheck::NulInHeader,
+        FsckCheck::UnterminatedHeader,
+        FsckCheck::MissingTree,
+        FsckCheck::BadTreeSha1,
+        FsckCheck::BadParentSha1,
+        FsckCheck::MissingAuthor,
+        FsckCheck::MultipleAuthors,
+        FsckCheck::MissingCommitter,
+        FsckCheck::MissingNameBeforeEmail,
+        FsckCheck::BadName,
+        FsckCheck::MissingEmail,
+        FsckCheck::MissingSpaceBeforeEmail,
+        FsckCheck::BadEmail,
+        FsckCheck::MissingSpaceBeforeDate,
+        FsckCheck::ZeroPaddedDate,
+        FsckCheck::BadDateOverflow,
+        FsckCheck::BadDate,
+        FsckCheck::BadTimezone,
+        FsckCheck::MissingObject,
+        FsckCheck::BadObjectSha1,
+        FsckCheck::MissingTypeEntry,
+        FsckCheck::BadType,
+        FsckCheck::MissingTagEntry,
+        FsckCheck::MissingTaggerEntry,
+        FsckCheck::LargeObject,
+    ];
+
+    /// the message id of git
+    pub fn id(&self) -> &'static str {
+        match self {
+            FsckCheck::BadFilemode => "badFilemode",
+            FsckCheck::ZeroPaddedFilemode => "zeroPaddedFilemode",
+            FsckCheck::TreeNotSorted => "treeNotSorted",
+            FsckCheck::DuplicateEntries => "duplicateEntries",
+            FsckCheck::HasDot => "hasDot",
+            FsckCheck::HasDotdot => "hasDotdot",
+            FsckCheck::HasDotgit => "hasDotgit",
+            FsckCheck::EmptyName => "emptyName",
+            FsckCheck::FullPathname => "fullPathname",
+            FsckCheck::BadTree => "badTree",
+            FsckCheck::NulInHeader => "nulInHeader",
+            FsckCheck::UnterminatedHeader => "unterminatedHeader",
+            FsckCheck::MissingTree => "missingTree",
+            FsckCheck::BadTreeSha1 => "badTreeSha1",
+            FsckCheck::BadParentSha1 => "badParentSha1",
+            FsckCheck::MissingAuthor => "missingAuthor",
+            FsckCheck::MultipleAuthors => "multipleAuthors",
+            FsckCheck::MissingCommitter => "missingCommitter",
+            FsckCheck::MissingNameBeforeEmail => "missingNameBeforeEmail",
+            FsckCheck::BadName => "badName",
+            FsckCheck::MissingEmail => "missingEmail",
+            FsckCheck::MissingSpaceBeforeEmail => "missingSpaceBeforeEmail",
+            FsckCheck::BadEmail => "badEmail",
+            FsckCheck::MissingSpaceBeforeDate => "missingSpaceBeforeDate",
+            FsckCheck::ZeroPaddedDate => "zeroPaddedDate",
+            FsckCheck::BadDateOverflow => "badDateOverflow",
+            FsckCheck::BadDate => "badDate",
+            FsckCheck::BadTimezone => "badTimezone",
+            FsckCheck::MissingObject => "missingObject",
+            FsckCheck::BadObjectSha1 => "badObjectSha1",
+            FsckCheck::MissingTypeEntry => "missingTypeEntry",
+            FsckCheck::BadType => "badType",
+            FsckCheck::MissingTagEntry => "missingTagEntry",
+            FsckCheck::MissingTaggerEntry => "missingTaggerEntry",
+            FsckCheck::LargeObject => "largeObject",
+        }
+    }
+
+    fn default_severity(&self) -> Severity {
+        match self {
+            FsckCheck::MissingTaggerEntry => Severity::Warn,
+            _ => Severity::Error,
+        }
+    }
+}
+
+impl Display for FsckCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.id())
+    }
+}
+
+impl FromStr for FsckCheck {
+    type Err = GitError;
+
+    /// message ids are case insensitive, as in the git config
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        FsckCheck::ALL
+            .into_iter()
+            .find(|check| check.id().eq_ignore_ascii_case(s))
+            .ok_or_else(|| GitError::InvalidFsckConfig(format!("unknown check {}", s)))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// the push is rejected
+    Error,
+    /// the problem is logged and told to the client
+    Warn,
+    Ignore,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let severity = match self {
+            Severity::Error => "error",
+            Severity::Warn => "warning",
+            Severity::Ignore => "ignore",
+        };
+        write!(f, "{}", severity)
+    }
+}
+
+impl FromStr for Severity {
+    type Err = GitError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "error" => Ok(Severity::Error),
+            "warn" | "warning" => Ok(Severity::Warn),
+            "ignore" => Ok(Severity::Ignore),
+            _ => Err(GitError::InvalidFsckConfig(format!(
+                "unknown severity {}",
+                s
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FsckConfig {
+    /// the checks whose severity isn't the default one
+    severities: HashMap<FsckCheck, Severity>,
+    /// the size above which an object is reported as `largeObject`
+    pub max_object_size: usize,
+}
+
+impl Default for FsckConfig {
+    fn default() -> Self {
+        FsckConfig {
+            severities: HashMap::new(),
+            max_object_size: DEFAULT_MAX_OBJECT_SIZE,
+        }
+    }
+}
+
+impl FsckConfig {
+    /// Set the severities of a comma separated list of `<msg-id>=<severity>`, e.g.
+    /// `zeroPaddedFilemode=warn,hasDot=ignore`.
+    pub fn configure(&mut self, settings: &str) -> Result<(), GitError> {
+        for setting in settings.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let (check, severity) = setting.split_once('=').ok_or_else(|| {
+                GitError::InvalidFsckConfig(format!("expecting <check>=<severity>: {}", setting))
+            })?;
+            self.severities
+                .insert(check.trim().parse()?, severity.trim().parse()?);
+        }
+        Ok(())
+    }
+
+    pub fn severity(&self, check: FsckCheck) -> Severity {
+        self.severities
+            .get(&check)
+            .copied()
+            .unwrap_or_else(|| check.default_severity())
+    }
+}
+
+/// A problem found in an object, which isn't ignored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FsckProblem {
+    pub id: Hash,
+    pub check: FsckCheck,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Display for FsckProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: object {}: {}: {}",
+            self.severity,
+            self.id.to_plain_str(),
+            self.check,
+            self.message
+        )
+    }
+}
+
+/// Check `objects`, the problems which aren't ignored are returned.
+pub fn fsck_objects<'a>(
+    objects: impl IntoIterator<Item = &'a MetaData>,
+    config: &FsckConfig,
+) -> Vec<FsckProblem> {
+    let mut problems = Vec::new();
+    for meta in objects {
+        let mut report = Report {
+            id: meta.id,
+            config,
+            problems: &mut problems,
+        };
+        if meta.data.len() > config.max_object_size {
+            report.report(
+                FsckCheck::LargeObject,
+                format!(
+                    "size {} exceeds the limit of {}",
+                    meta.data.len(),
+                    config.max_object_size
+                ),
+            );
+        }
+        match meta.t {
+            ObjectType::Tree => fsck_tree(&meta.data, &mut report),
+            ObjectType::Commit => fsck_commit(&meta.data, &mut report),
+            ObjectType::Tag => fsck_tag(&meta.data, &mut report),
+            _ => {}
+        }
+    }
+    problems
+}
+
+/// Checks the objects of a pack on their way to `inner`. Once an object fails a check with
+/// `Severity::Error` nothing more reaches `inner`, the rest are still checked so that every
+/// problem is reported.
+pub struct FsckSink<'a, S: ?Sized> {
+    inner: &'a mut S,
+    config: &'a FsckConfig,
+    pub problems: Vec<FsckProblem>,
+}
+
+impl<'a, S: ObjectSink + ?Sized> FsckSink<'a, S> {
+    pub fn new(inner: &'a mut S, config: &'a FsckConfig) -> Self {
+        FsckSink {
+            inner,
+            config,
+            problems: Vec::new(),
+        }
+    }
+
+    /// whether an object failed a check
+    pub fn failed(&self) -> bool {
+        self.problems.iter().any(|p| p.severity == Severity::Error)
+    }
+}
+
+#[async_trait]
+impl<S: ObjectSink + ?Sized> ObjectSink for FsckSink<'_, S> {
+    async fn accept(&mut self, objects: Vec<ResolvedObject>) -> Result<(), GitError> {
+        // the bases of a thin pack were checked when they were received
+        let received = objects
+            .iter()
+            .filter(|resolved| resolved.offset.is_some())
+            .map(|resolved| resolved.object.as_ref());
+        self.problems.extend(fsck_objects(received, self.config));
+        if self.failed() {
+            return Ok(());
+        }
+        self.inner.accept(objects).await
+    }
+}
+
+struct Report<'a> {
+    id: Hash,
+    config: &'a FsckConfig,
+    problems: &'a mut Vec<FsckProblem>,
+}
+
+impl Report<'_> {
+    fn report(&mut self, check: FsckCheck, message: impl Into<String>) {
+        let severity = self.config.severity(check);
+        if severity != Severity::Ignore {
+            self.problems.push(FsckProblem {
+                id: self.id,
+                check,
+                severity,
+                message: message.into(),
+            });
+        }
+    }
+}
+
+fn fsck_tree(data: &[u8], report: &mut Report) {
+    let mut names = HashSet::new();
+    let mut previous: Option<(&[u8], bool)> = None;
+    let (mut bad_mode, mut zero_padded, mut not_sorted, mut duplicate) =
+        (false, false, false, false);
+    let mut rest = data;
+    while !rest.is_empty() {
+        let (Some(space), Some(nul)) = (rest.find_byte(b' '), rest.find_byte(0)) else {
+            return report.report(FsckCheck::BadTree, "truncated tree entry");
+        };
+        if nul < space || rest.len() < nul + 21 {
+            return report.report(FsckCheck::BadTree, "truncated tree entry");
+        }
+        let (mode, name) = (&rest[..space], &rest[space + 1..nul]);
+        rest = &rest[nul + 21..];
+
+        let Ok(item_type) = TreeItemType::tree_item_type_from(mode) else {
+            return report.report(FsckCheck::BadTree, "malformed mode in tree entry");
+        };
+        zero_padded |= mode.starts_with(b"0");
+        // git only writes these, other permissions of regular files are normalized
+        bad_mode |= !matches!(
+            mode.trim_start_with(|c| c == '0'),
+            b"100644" | b"100755" | b"120000" | b"40000" | b"160000"
+        );
+
+        fsck_name(name, report);
+        let is_tree = item_type == TreeItemType::Tree;
+        if let Some((previous_name, previous_is_tree)) = previous {
+            if previous_name == name {
+                duplicate = true;
+            } else if entry_cmp(previous_name, previous_is_tree, name, is_tree) == Ordering::Greater
+            {
+                not_sorted = true;
+            }
+        }
+        // a file and a directory of the same name aren't next to each other
+        duplicate |= !names.insert(name);
+        previous = Some((name, is_tree));
+    }
+
+    if bad_mode {
+        report.report(FsckCheck::BadFilemode, "contains bad file modes");
+    }
+    if zero_padded {
+        report.report(
+            FsckCheck::ZeroPaddedFilemode,
+            "contains zero-padded file modes",
+        );
+    }
+    if duplicate {
+        report.report(
+            FsckCheck::DuplicateEntries,
+            "contains duplicate file entries",
+        );
+    }
+    if not_sorted {
+        report.report(FsckCheck::TreeNotSorted, "not properly sorted");
+    }
+}
+
+fn fsck_name(name: &[u8], report: &mut Report) {
+    match name {
+        b"" => report.report(FsckCheck::EmptyName, "contains empty pathname"),
+        b"." => report.report(FsckCheck::HasDot, "contains '.'"),
+        b".." => report.report(FsckCheck::HasDotdot, "contains '..'"),
+        _ if name.contains(&b'/') => {
+            report.report(FsckCheck::FullPathname, "contains full pathnames")
+        }
+        _ if is_dotgit(name) => report.report(FsckCheck::HasDotgit, "contains '.git'"),
+        _ => {}
+    }
+}
+
+/// Whether `name` is `.git` on a case insensitive file system: NTFS drops trailing dots and
+/// spaces and knows it as `git~1`, HFS+ ignores some zero width characters.
+fn is_dotgit(name: &[u8]) -> bool {
+    let name: String = name
+        .to_str_lossy()
+        .chars()
+        .filter(
+            |c| !matches!(*c as u32, 0x200C..=0x200F | 0x202A..=0x202E | 0x206A..=0x206F | 0xFEFF),
+        )
+        .collect::<String>()
+        .to_lowercase();
+    let name = name.trim_end_matches(['.', ' ']);
+    name == ".git" || name == "git~1"
+}
+
+/// The order of git trees, directories are sorted as if their name ended with `/`.
+fn entry_cmp(a: &[u8], a_is_tree: bool, b: &[u8], b_is_tree: bool) -> Ordering {
+    let suffix = |is_tree: bool| if is_tree { &b"/"[..] } else { &b""[..] };
+    a.iter()
+        .chain(suffix(a_is_tree))
+        .cmp(b.iter().chain(suffix(b_is_tree)))
+}
+
+fn fsck_commit(data: &[u8], report: &mut Report) {
+    let Some(mut lines) = header_lines(data, report) else {
+        return;
+    };
+    match lines.next().and_then(|line| line.strip_prefix(b"tree ")) {
+        None => return report.report(FsckCheck::MissingTree, "expected 'tree' line"),
+        Some(id) if Hash::from_bytes(id).is_none() => {
+            return report.report(
+                FsckCheck::BadTreeSha1,
+                "invalid 'tree' line format - bad sha1",
+            )
+        }
+        _ => {}
+    }
+    let mut line = lines.next();
+    while let Some(id) = line.and_then(|line| line.strip_prefix(b"parent ")) {
+        if Hash::from_bytes(id).is_none() {
+            return report.report(
+                FsckCheck::BadParentSha1,
+                "invalid 'parent' line format - bad sha1",
+            );
+        }
+        line = lines.next();
+    }
+    let mut authors = 0;
+    while let Some(ident) = line.and_then(|line| line.strip_prefix(b"author ")) {
+        authors += 1;
+        fsck_ident(ident, report);
+        line = lines.next();
+    }
+    match authors {
+        0 => return report.report(FsckCheck::MissingAuthor, "expected 'author' line"),
+        1 => {}
+        _ => report.report(FsckCheck::MultipleAuthors, "multiple author lines"),
+    }
+    match line.and_then(|line| line.strip_prefix(b"committer ")) {
+        Some(ident) => fsck_ident(ident, report),
+        None => report.report(FsckCheck::MissingCommitter, "expected 'committer' line"),
+    }
+}
+
+fn fsck_tag(data: &[u8], report: &mut Report) {
+    let Some(mut lines) = header_lines(data, report) else {
+        return;
+    };
+    match lines.next().and_then(|line| line.strip_prefix(b"object ")) {
+        None => return report.report(FsckCheck::MissingObject, "expected 'object' line"),
+        Some(id) if Hash::from_bytes(id).is_none() => {
+            return report.report(
+                FsckCheck::BadObjectSha1,
+                "invalid 'object' line format - bad sha1",
+            )
+        }
+        _ => {}
+    }
+    match lines.next().and_then(|line| line.strip_prefix(b"type ")) {
+        None => return report.report(FsckCheck::MissingTypeEntry, "expected 'type' line"),
+        Some(t) if !matches!(t, b"blob" | b"tree" | b"commit" | b"tag") => {
+            return report.report(FsckCheck::BadType, "invalid 'type' value")
+        }
+        _ => {}
+    }
+    if lines
+        .next()
+        .and_then(|line| line.strip_prefix(b"tag "))
+        .is_none()
+    {
+        return report.report(FsckCheck::MissingTagEntry, "expected 'tag' line");
+    }
+    match lines.next().and_then(|line| line.strip_prefix(b"tagger ")) {
+        Some(ident) => fsck_ident(ident, report),
+        None => report.report(FsckCheck::MissingTaggerEntry, "expected 'tagger' line"),
+    }
+}
+
+/// The header lines of a commit or a tag, which end with a blank line or the end of the
+/// object. `None` if the headers are malformed.
+fn header_lines<'a>(data: &'a [u8], report: &mut Report) -> Option<impl Iterator<Item = &'a [u8]>> {
+    let headers = match data.find("\n\n") {
+        Some(end) => &data[..end + 1],
+        None if data.ends_with(b"\n") => data,
+        None => {
+            report.report(FsckCheck::UnterminatedHeader, "unterminated header");
+            return None;
+        }
+    };
+    if headers.contains(&0) {
+        report.report(FsckCheck::NulInHeader, "unterminated header: NUL in header");
+        return None;
+    }
+    Some(headers.lines())
+}
+
+/// Check a `<name> <<email>> <timestamp> <timezone>` ident the way git does, the first
+/// problem found is reported.
+fn fsck_ident(ident: &[u8], report: &mut Report) {
+    let problem = ident_problem(ident);
+    if let Some((check, message)) = problem {
+        report.report(
+            check,
+            format!("invalid author/committer line - {}", message),
+        );
+    }
+}
+
+fn ident_problem(ident: &[u8]) -> Option<(FsckCheck, &'static str)> {
+    if ident.starts_with(b"<") {
+        return Some((
+            FsckCheck::MissingNameBeforeEmail,
+            "missing space before email",
+        ));
+    }
+    let email_start = match ident.find_byteset(b"<>") {
+        Some(pos) if ident[pos] == b'<' => pos,
+        Some(_) => return Some((FsckCheck::BadName, "bad name")),
+        None => return Some((FsckCheck::MissingEmail, "missing email")),
+    };
+    if ident[email_start - 1] != b' ' {
+        return Some((
+            FsckCheck::MissingSpaceBeforeEmail,
+            "missing space before email",
+        ));
+    }
+    let email_end = match ident[email_start + 1..].find_byteset(b"<>") {
+        Some(pos) if ident[email_start + 1 + pos] == b'>' => email_start + 1 + pos,
+        _ => return Some((FsckCheck::BadEmail, "bad email")),
+    };
+    let Some(date) = ident[email_end + 1..].strip_prefix(b" ") else {
+        return Some((
+            FsckCheck::MissingSpaceBeforeDate,
+            "missing space before date",
+        ));
+    };
+    let digits = date.iter().take_while(|b| b.is_ascii_digit()).count();
+    if date.starts_with(b"0") && digits > 1 {
+        return Some((FsckCheck::ZeroPaddedDate, "zero-padded date"));
+    }
+    if digits > 0 && date[..digits].to_str().unwrap().parse::<u64>().is_err() {
+        return Some((FsckCheck::BadDateOverflow, "date causes integer overflow"));
+    }
+    let Some(timezone) = date[digits..].strip_prefix(b" ").filter(|_| digits > 0) else {
+        return Some((FsckCheck::BadDate, "bad date"));
+    };
+    let valid_timezone = timezone.len() == 5
+        && matches!(timezone[0], b'+' | b'-')
+        && timezone[1..].iter().all(u8::is_ascii_digit);
+    if !valid_timezone {
+        return Some((FsckCheck::BadTimezone, "bad time zone"));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio_test::block_on;
+
+    use crate::git::hash::Hash;
+    use crate::git::object::metadata::MetaData;
+    use crate::git::object::types::ObjectType;
+    use crate::git::pack::cache::PackObjectCache;
+    use crate::git::pack::resolve::{ObjectSink, ResolvedObject};
+
+    use super::{fsck_objects, FsckCheck, FsckConfig, FsckSink, Severity};
+
+    const ID: &str = "8ab686eafeb1f44702738c8b0f24f2567c36da6d";
+
+    fn tree(entries: &[(&str, &str)]) -> MetaData {
+        let mut data = Vec::new();
+        for (mode, name) in entries {
+            data.extend_from_slice(format!("{} {}\0", mode, name).as_bytes());
+            data.extend_from_slice(&Hash::from_bytes(ID.as_bytes()).unwrap().0);
+        }
+        MetaData::new(ObjectType::Tree, &data)
+    }
+
+    fn checks(objects: &[MetaData], config: &FsckConfig) -> Vec<FsckCheck> {
+        fsck_objects(objects, config)
+            .into_iter()
+            .map(|problem| problem.check)
+            .collect()
+    }
+
+    #[test]
+    fn test_fsck_tree() {
+        let config = FsckConfig::default();
+        let good = tree(&[("100755", "a.b"), ("40000", "a"), ("120000", "a0")]);
+        assert!(checks(&[good], &config).is_empty());
+
+        for (entries, check) in [
+            (vec![("100664", "a")], FsckCheck::BadFilemode),
+            (vec![("040000", "a")], FsckCheck::ZeroPaddedFilemode),
+            (
+                vec![("100644", "b"), ("100644", "a")],
+                FsckCheck::TreeNotSorted,
+            ),
+            (
+                vec![("40000", "a"), ("100644", "a.b")],
+                FsckCheck::TreeNotSorted,
+            ),
+            (
+                vec![("100644", "a"), ("100644", "a")],
+                FsckCheck::DuplicateEntries,
+            ),
+            (
+                vec![("100644", "a"), ("100644", "a.b"), ("40000", "a")],
+                FsckCheck::DuplicateEntries,
+            ),
+            (vec![("40000", ".GIT")], FsckCheck::HasDotgit),
+            (vec![("40000", ".git. ")], FsckCheck::HasDotgit),
+            (vec![("40000", "GIT~1")], FsckCheck::HasDotgit),
+            (vec![("40000", ".g\u{200c}it")], FsckCheck::HasDotgit),
+            (vec![("40000", "..")], FsckCheck::HasDotdot),
+            (vec![("100644", "a/b")], FsckCheck::FullPathname),
+            (vec![("100644", "")], FsckCheck::EmptyName),
+            (vec![("10x644", "a")], FsckCheck::BadTree),
+        ] {
+            assert_eq!(
+                vec![check],
+                checks(&[tree(&entries)], &config),
+                "{:?}",
+                entries
+            );
+        }
+
+        let truncated = MetaData::new(ObjectType::Tree, &b"100644 a\0abc".to_vec());
+        assert_eq!(vec![FsckCheck::BadTree], checks(&[truncated], &config));
+    }
+
+    #[test]
+    fn test_fsck_commit() {
+        let config = FsckConfig::default();
+        let commit = |author: &str| {
+            let data = format!(
+                "tree {}\nparent {}\nauthor {}\ncommitter A U Thor <a@b.c> 1 +0000\n\nmsg\n",
+                ID, ID, author
+            );
+            MetaData::new(ObjectType::Commit, &data.into_bytes())
+        };
+        assert!(checks(&[commit("A U Thor <a@b.c> 1685577600 +0800")], &config).is_empty());
+        assert!(checks(&[commit(" <> 0 +0000")], &config).is_empty());
+
+        for (author, check) in [
+            ("<a@b.c> 1 +0000", FsckCheck::MissingNameBeforeEmail),
+            ("A> <a@b.c> 1 +0000", FsckCheck::BadName),
+            ("A 1 +0000", FsckCheck::MissingEmail),
+            ("A<a@b.c> 1 +0000", FsckCheck::MissingSpaceBeforeEmail),
+            ("A <a@b.c 1 +0000", FsckCheck::BadEmail),
+            ("A <a@b.c>1 +0000", FsckCheck::MissingSpaceBeforeDate),
+            ("A <a@b.c> 01 +0000", FsckCheck::ZeroPaddedDate),
+            (
+                "A <a@b.c> 99999999999999999999 +0000",
+                FsckCheck::BadDateOverflow,
+            ),
+            ("A <a@b.c> x +0000", FsckCheck::BadDate),
+            ("A <a@b.c> 1 0800", FsckCheck::BadTimezone),
+            ("A <a@b.c> 1 +08", FsckCheck::BadTimezone),
+        ] {
+            assert_eq!(
+                vec![check],
+                checks(&[commit(author)], &config),
+                "{}",
+                author
+            );
+        }
+
+        for (data, check) in [
+            (format!("parent {}\n\n", ID), FsckCheck::MissingTree),
+            ("tree 1234\n\n".to_owned(), FsckCheck::BadTreeSha1),
+            (
+                format!("tree {}\nparent x\n\n", ID),
+                FsckCheck::BadParentSha1,
+            ),
+            (format!("tree {}\n\n", ID), FsckCheck::MissingAuthor),
+            (
+                format!("tree {}\nauthor A <a> 1 +0000\n\n", ID),
+                FsckCheck::MissingCommitter,
+            ),
+            (format!("tree {}", ID), FsckCheck::UnterminatedHeader),
+            (format!("tree {}\0\n\n", ID), FsckCheck::NulInHeader),
+        ] {
+            let meta = MetaData::new(ObjectType::Commit, &data.into_bytes());
+            assert_eq!(vec![check], checks(&[meta], &config));
+        }
+    }
+
+    #[test]
+    fn test_fsck_tag() {
+        let tag = |tagger: &str| {
+            let data = format!("object {}\ntype commit\ntag v1\n{}\nmsg\n", ID, tagger);
+            MetaData::new(ObjectType::Tag, &data.into_bytes())
+        };
+        let mut config = FsckConfig::default();
+        assert!(checks(&[tag("tagger A <a> 1 +0000\n")], &config).is_empty());
+
+        let problems = fsck_objects(&[tag("")], &config);
+        assert_eq!(1, problems.len());
+        assert_eq!(FsckCheck::MissingTaggerEntry, problems[0].check);
+        assert_eq!(Severity::Warn, problems[0].severity);
+
+        config.configure("missingTaggerEntry=ignore").unwrap();
+        assert!(checks(&[tag("")], &config).is_empty());
+
+        let bad_type = format!("object {}\ntype commits\ntag v1\n\n", ID);
+        let meta = MetaData::new(ObjectType::Tag, &bad_type.into_bytes());
+        assert_eq!(vec![FsckCheck::BadType], checks(&[meta], &config));
+    }
+
+    #[test]
+    fn test_fsck_config() {
+        let mut config = FsckConfig::default();
+        config
+            .configure("hasdotgit=warn, zeroPaddedFilemode = ignore")
+            .unwrap();
+        assert_eq!(Severity::Warn, config.severity(FsckCheck::HasDotgit));
+        assert_eq!(
+            Severity::Ignore,
+            config.severity(FsckCheck::ZeroPaddedFilemode)
+        );
+        assert_eq!(Severity::Error, config.severity(FsckCheck::BadTree));
+        assert!(config.configure("noSuchCheck=warn").is_err());
+        assert!(config.configure("badTree=fatal").is_err());
+        assert!(config.configure("badTree").is_err());
+
+        config.max_object_size = 4;
+        let blob = MetaData::new(ObjectType::Blob, &b"hello".to_vec());
+        assert_eq!(vec![FsckCheck::LargeObject], checks(&[blob], &config));
+    }
+
+    #[test]
+    fn test_fsck_sink() {
+        let config = FsckConfig::default();
+        let resolved = |offset, meta: MetaData| ResolvedObject {
+            offset,
+            object: Arc::new(meta),
+            depth: 0,
+        };
+        let mut cache = PackObjectCache::default();
+        let mut sink = FsckSink::new(&mut cache, &config);
+        let good = tree(&[("100644", "a")]);
+        block_on(sink.accept(vec![resolved(Some(12), good)])).unwrap();
+        // a base of a thin pack isn't checked again
+        let base = tree(&[("100664", "b")]);
+        block_on(sink.accept(vec![resolved(None, base)])).unwrap();
+        assert!(!sink.failed());
+
+        let bad = tree(&[("100664", "c")]);
+        let after = MetaData::new(ObjectType::Blob, &b"hello".to_vec());
+        block_on(sink.accept(vec![resolved(Some(40), bad)])).unwrap();
+        block_on(sink.accept(vec![resolved(Some(80), after)])).unwrap();
+        assert!(sink.failed());
+        assert_eq!(1, sink.problems.len());
+        assert_eq!(1, cache.by_hash.len());
+        assert_eq!(1, cache.external.len());
+    }
+}
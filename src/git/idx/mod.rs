@@ -44,6 +44,8 @@ pub struct Idx {
     pub idx_items: Vec<IdxItem>,
     pub pack_signature: Hash,
     pub idx_signature: Hash,
+    /// the count of the objects whose id starts with a byte up to each index
+    pub fan_out: Vec<u32>,
     _file_data: Vec<u8>,
 }
 
@@ -78,6 +80,12 @@ impl Idx {
         let mut id_of_objects: Vec<Hash> = Vec::new();
         let mut crc32_of_objects: Vec<String> = Vec::new();
 
+        if data.len() < 8 + 256 * 4 + 40 {
+            return Err(GitError::InvalidIdxFile(format!(
+                "{} bytes is too short for an idx",
+                data.len()
+            )));
+        }
         // 4-byte Header: //FF 74 4F 63
         if data[offset..4].to_vec() != vec![255, 116, 79, 99] {
             return Err(GitError::InvalidIdxFile(format!(
@@ -99,6 +107,13 @@ impl Idx {
         for i in (offset..offset + 256 * 4).filter(|x| ((x - offset) % 4 == 0)) {
             let mut v = Cursor::new(data[i..i + 4].to_vec());
             let m = v.read_u32::<BigEndian>().unwrap() as usize;
+            if m < n {
+                return Err(GitError::InvalidIdxFile(format!(
+                    "Invalid fan-out table at {}",
+                    i
+                )));
+            }
+            self.fan_out.push(m as u32);
 
             if m != n {
                 self.map_of_prefix
@@ -108,6 +123,13 @@ impl Idx {
             }
         }
         offset += 256 * 4; // 1040
+        if data.len() < offset + 28 * n + 40 {
+            return Err(GitError::InvalidIdxFile(format!(
+                "{} objects can't fit in {} bytes",
+                n,
+                data.len()
+            )));
+        }
 
         // Layer 2:
         //  The all the SHA-1 hashes of the objects in the pack.
@@ -125,31 +147,51 @@ impl Idx {
         offset += 4 * n as usize;
 
         // Layer 4:
-        //   the object offset in the pack file.
-        let mut index = 0;
-        for (index, i) in (offset..offset + (4 * n) as usize)
-            .filter(|x| ((x - offset) % 4 == 0))
-            .enumerate()
-        {
+        //   the object offset in the pack file, an offset with the most significant bit set
+        //   is the index of the offset in the layer 5
+        let mut offsets: Vec<u32> = Vec::with_capacity(n);
+        for i in (offset..offset + (4 * n) as usize).filter(|x| ((x - offset) % 4 == 0)) {
             let mut v = Cursor::new(data[i..i + 4].to_vec());
-            let m = v.read_u32::<BigEndian>().unwrap() as usize;
+            offsets.push(v.read_u32::<BigEndian>().unwrap());
+        }
+        offset += 4 * n as usize;
 
+        // Layer 5:
+        //   the 8-byte offsets of a pack larger than 2G
+        let large_offsets = offsets.iter().filter(|m| **m & 0x8000_0000 != 0).count();
+        let large_start = offset;
+        if data.len() < large_start + 8 * large_offsets + 40 {
+            return Err(GitError::InvalidIdxFile(format!(
+                "{} large offsets can't fit in {} bytes",
+                large_offsets,
+                data.len()
+            )));
+        }
+        for (index, m) in offsets.into_iter().enumerate() {
+            let offset = if m & 0x8000_0000 == 0 {
+                m as usize
+            } else {
+                let i = large_start + 8 * (m & 0x7fff_ffff) as usize;
+                let large = data.get(i..i + 8).ok_or_else(|| {
+                    GitError::InvalidIdxFile(format!("Invalid large offset {}", m))
+                })?;
+                let mut v = Cursor::new(large);
+                v.read_u64::<BigEndian>().unwrap() as usize
+            };
             self.idx_items.push(IdxItem {
                 id: id_of_objects[index].clone(),
                 crc32: crc32_of_objects[index].clone(),
-                offset: m,
+                offset,
             });
         }
-        offset += 4 * n as usize;
-
-        // Layer 5
+        offset += 8 * large_offsets;
 
         // Layer 6:
         //  The SHA-1 hash of the pack file itself.
         //  The SHA-1 hash of the index file itself.
         self.pack_signature = Hash::from_row(&data[offset..offset + 20].to_vec());
         offset += 20;
-        self.idx_signature = Hash::from_row(&data[offset..].to_vec());
+        self.idx_signature = Hash::from_row(&data[offset..offset + 20].to_vec());
 
         /// fill the item_hash map.
         for (index, item) in self.idx_items.iter().enumerate() {
@@ -158,6 +200,21 @@ impl Idx {
         Ok(())
     }
 
+    /// Find the item of `id`, the fan-out table narrows the lookup to the ids starting with
+    /// the first byte of `id`, which are searched in order.
+    pub fn find(&self, id: &Hash) -> Option<&IdxItem> {
+        let first = id.get_first() as usize;
+        let end = *self.fan_out.get(first)? as usize;
+        let start = if first == 0 {
+            0
+        } else {
+            self.fan_out[first - 1] as usize
+        };
+        let items = self.idx_items.get(start..end)?;
+        let index = items.binary_search_by(|item| item.id.cmp(id)).ok()?;
+        Some(&items[index])
+    }
+
     #[allow(unused)]
     pub fn encode(pack: Pack) -> Self {
         let mut idx = Self::default();
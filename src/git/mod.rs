@@ -8,12 +8,14 @@
 //!
 
 pub mod errors;
+pub mod fsck;
 pub mod hash;
 pub mod idx;
 pub mod lfs;
 pub mod object;
 pub mod pack;
 pub mod protocol;
+pub mod signature;
 pub mod utils;
 
 ///
@@ -9,8 +9,6 @@ use std::sync::Arc;
 
 use bstr::ByteSlice;
 
-use crate::errors::GustError;
-
 use crate::git::errors::GitError;
 use crate::git::hash::Hash;
 use crate::git::object::base::sign::AuthorSign;
@@ -27,7 +25,14 @@ pub struct Commit {
     pub parent_tree_ids: Vec<Hash>,
     pub author: AuthorSign,
     pub committer: AuthorSign,
-    pub message: String,
+    /// every header in its original order, e.g. `author`, `encoding`, `mergetag` or `gpgsig`,
+    /// continuation lines are joined by `\n`. Only the tree and the parents are written from
+    /// their fields, the other headers are written back as received.
+    pub headers: Vec<(String, Vec<u8>)>,
+    /// the bytes after the last header, `\n\n` unless the commit has no message
+    pub header_end: &'static [u8],
+    /// the message after the blank line ending the headers, in the `encoding` of the commit
+    pub message: Vec<u8>,
 }
 
 impl Ord for Commit {
@@ -57,6 +62,11 @@ impl Commit {
 
     ///
     pub fn new(metadata: Arc<MetaData>) -> Self {
+        Commit::try_new(metadata).unwrap()
+    }
+
+    /// Decode a commit, which may be malformed when it was received.
+    pub fn try_new(metadata: Arc<MetaData>) -> Result<Self, GitError> {
         let mut a = Self {
             meta: metadata,
             tree_id: Hash::default(),
@@ -75,59 +85,105 @@ impl Commit {
                 timestamp: 0,
                 timezone: "".to_string(),
             },
-            message: "".to_string(),
+            headers: vec![],
+            header_end: b"\n\n",
+            message: vec![],
         };
-        a.decode_meta().unwrap();
-        a
+        a.decode_meta()?;
+        Ok(a)
     }
-    ///
 
-    /// Decode the Metadata.data and convert to `Commit` Class
-    // If there a
-    pub(crate) fn decode_meta(&mut self) -> Result<(), GustError> {
-        let mut data = self.meta.data.clone();
-
-        // Find the tree id and remove it from the data
-        let tree_begin = data.find_byte(0x20).unwrap();
-        let tree_end = data.find_byte(0x0a).unwrap();
-        self.tree_id = Hash::from_bytes(&data[tree_begin + 1..tree_end].to_vec()).unwrap();
-        data = data[tree_end + 1..].to_vec();
-
-        // Find the parent tree ids and remove them from the data
-        let author_begin = data.find("author").unwrap();
-        if data.find_iter("parent").count() > 0 {
-            let mut parents: Vec<Hash> = Vec::new();
-            let mut index = 0;
-
-            while index < author_begin {
-                let parent_begin = data.find_byte(0x20).unwrap();
-                let parent_end = data.find_byte(0x0a).unwrap();
-                parents
-                    .push(Hash::from_bytes(&data[parent_begin + 1..parent_end].to_vec()).unwrap());
-                index = index + parent_end + 1;
-            }
+    /// Decode the Metadata.data and convert to `Commit` Class. Every header is kept, so
+    /// `encode_metadata` gives back the same bytes.
+    pub(crate) fn decode_meta(&mut self) -> Result<(), GitError> {
+        let data = &self.meta.data;
+        let (headers, header_end, message): (_, &'static [u8], _) = match data.find("\n\n") {
+            Some(end) => (&data[..end], b"\n\n", &data[end + 2..]),
+            None => match data.strip_suffix(b"\n") {
+                Some(headers) => (headers, b"\n", &data[data.len()..]),
+                None => (&data[..], b"", &data[data.len()..]),
+            },
+        };
 
-            self.parent_tree_ids = parents;
+        let mut fields: Vec<(String, Vec<u8>)> = Vec::new();
+        for line in headers.split_str("\n").filter(|line| !line.is_empty()) {
+            // a line starting with a space continues the value of the previous header
+            if let Some(continuation) = line.strip_prefix(b" ") {
+                let (_, value) = fields.last_mut().ok_or_else(|| invalid(line))?;
+                value.push(b'\n');
+                value.extend_from_slice(continuation);
+                continue;
+            }
+            let (name, value) = line.split_once_str(" ").ok_or_else(|| invalid(line))?;
+            let name = name.to_str().map_err(|_| invalid(line))?;
+            fields.push((name.to_owned(), value.to_vec()));
         }
-        data = data[author_begin..].to_vec();
-
-        // Find the author and remove it from the data
-        let author_data = data[..data.find_byte(0x0a).unwrap()].to_vec();
-        self.author.decode_from_data(author_data)?;
-        data = data[data.find_byte(0x0a).unwrap() + 1..].to_vec();
-
-        // Find the committer and remove it from the data
-        let committer_data = data[..data.find_byte(0x0a).unwrap()].to_vec();
-        self.committer.decode_from_data(committer_data)?;
-        self.message = data[data.find_byte(0x0a).unwrap() + 1..]
-            .to_vec()
-            .to_str()
-            .unwrap()
-            .to_string();
 
+        // the first tree, author and committer are decoded, repeated ones are only kept
+        let mut tree_id = None;
+        let mut parents = Vec::new();
+        let (mut author, mut committer) = (false, false);
+        for (name, value) in fields.iter() {
+            match name.as_str() {
+                "tree" if tree_id.is_none() => {
+                    tree_id = Some(Hash::from_bytes(value).ok_or_else(|| invalid(value))?)
+                }
+                "parent" => parents.push(Hash::from_bytes(value).ok_or_else(|| invalid(value))?),
+                "author" | "committer" => {
+                    let (decoded, sign) = if name == "author" {
+                        (&mut author, &mut self.author)
+                    } else {
+                        (&mut committer, &mut self.committer)
+                    };
+                    if !*decoded {
+                        *decoded = true;
+                        sign.decode_from_data([name.as_bytes(), b" ", value].concat())?;
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.tree_id = tree_id.ok_or_else(|| invalid(b"missing tree"))?;
+        self.parent_tree_ids = parents;
+        self.headers = fields;
+        self.header_end = header_end;
+        self.message = message.to_vec();
         Ok(())
     }
 
+    /// The value of the first header `name`.
+    pub fn header(&self, name: &str) -> Option<&[u8]> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_slice())
+    }
+
+    /// the character encoding of the message, UTF-8 if `None`
+    pub fn encoding(&self) -> Option<&str> {
+        self.header("encoding")
+            .and_then(|value| value.to_str().ok())
+    }
+
+    /// the armored signature of the commit, for SHA-1 or SHA-256 repos
+    pub fn signature(&self) -> Option<&[u8]> {
+        self.header("gpgsig")
+            .or_else(|| self.header("gpgsig-sha256"))
+    }
+
+    /// the signed tags merged by the commit, each a whole tag object
+    pub fn mergetags(&self) -> impl Iterator<Item = &[u8]> {
+        self.headers
+            .iter()
+            .filter(|(name, _)| name == "mergetag")
+            .map(|(_, value)| value.as_slice())
+    }
+
+    /// The commit without its signature headers, the data the signature is made over.
+    pub fn signed_payload(&self) -> Vec<u8> {
+        self.encode_data(|name| name != "gpgsig" && name != "gpgsig-sha256")
+    }
+
     ///
     #[allow(unused)]
     pub(crate) fn write_to_file(&self, root_path: String) -> Result<String, GitError> {
@@ -137,30 +193,65 @@ impl Commit {
     ///
     #[allow(unused)]
     pub(crate) fn encode_metadata(&self) -> Result<MetaData, ()> {
-        let mut data = Vec::new();
-
-        data.extend_from_slice("tree".as_bytes());
-        data.extend_from_slice(0x20u8.to_be_bytes().as_ref());
-        data.extend_from_slice(self.tree_id.to_plain_str().as_bytes());
-        data.extend_from_slice(0x0au8.to_be_bytes().as_ref());
-
-        for parent_tree_id in &self.parent_tree_ids {
-            data.extend_from_slice("parent".as_bytes());
-            data.extend_from_slice(0x20u8.to_be_bytes().as_ref());
-            data.extend_from_slice(parent_tree_id.to_plain_str().as_bytes());
-            data.extend_from_slice(0x0au8.to_be_bytes().as_ref());
+        Ok(MetaData::new(
+            ObjectType::Commit,
+            &self.encode_data(|_| true),
+        ))
+    }
+
+    /// The object data, with the headers for which `header` returns true. The tree and the
+    /// parents take the place of their headers, parents added by a rewrite follow the last
+    /// parent. A commit built without headers gets the usual tree, parent, author and committer
+    /// layout.
+    fn encode_data(&self, header: impl Fn(&str) -> bool) -> Vec<u8> {
+        let mut lines: Vec<Vec<u8>> = Vec::new();
+        let mut parents = self.parent_tree_ids.iter();
+        let parent_line = |id: &Hash| header_line("parent", id.to_plain_str().as_bytes());
+
+        let tree_at = self.headers.iter().position(|(name, _)| name == "tree");
+        if tree_at.is_none() {
+            lines.push(header_line("tree", self.tree_id.to_plain_str().as_bytes()));
+            lines.extend(parents.by_ref().map(parent_line));
+            lines.push(self.author.encode_to_data().unwrap());
+            lines.push(self.committer.encode_to_data().unwrap());
+        }
+        let last_parent_at = self
+            .headers
+            .iter()
+            .rposition(|(name, _)| name == "parent")
+            .or(tree_at);
+
+        for (i, (name, value)) in self.headers.iter().enumerate() {
+            match name.as_str() {
+                "tree" if Some(i) == tree_at => {
+                    lines.push(header_line("tree", self.tree_id.to_plain_str().as_bytes()))
+                }
+                "parent" => lines.extend(parents.next().map(parent_line)),
+                _ if header(name) => lines.push(header_line(name, value)),
+                _ => {}
+            }
+            if Some(i) == last_parent_at {
+                lines.extend(parents.by_ref().map(parent_line));
+            }
         }
 
-        data.extend_from_slice(self.author.encode_to_data().unwrap().as_ref());
-        data.extend_from_slice(0x0au8.to_be_bytes().as_ref());
-        data.extend_from_slice(self.committer.encode_to_data().unwrap().as_ref());
-        data.extend_from_slice(0x0au8.to_be_bytes().as_ref());
-        data.extend_from_slice(self.message.as_bytes());
+        let mut data = lines.join(&b'\n');
+        data.extend_from_slice(self.header_end);
+        data.extend_from_slice(&self.message);
 
-        Ok(MetaData::new(ObjectType::Commit, &data))
+        data
     }
 }
 
+/// a `name value` header line, with the lines of the value after the first one indented
+fn header_line(name: &str, value: &[u8]) -> Vec<u8> {
+    [name.as_bytes(), b" ", &value.replace("\n", "\n ")].concat()
+}
+
+fn invalid(data: &[u8]) -> GitError {
+    GitError::InvalidCommitObject(data.to_str_lossy().into_owned())
+}
+
 impl Display for Commit {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         writeln!(f, "Tree: {}", self.tree_id)?;
@@ -171,7 +262,12 @@ impl Display for Commit {
 
         writeln!(f, "author {}", self.author)?;
         writeln!(f, "committer {}", self.committer)?;
-        writeln!(f, "Message: {}", self.message)
+        for (name, _) in self.headers.iter() {
+            if !matches!(name.as_str(), "tree" | "parent" | "author" | "committer") {
+                writeln!(f, "header: {}", name)?;
+            }
+        }
+        writeln!(f, "Message: {}", self.message.to_str_lossy())
     }
 }
 
@@ -183,6 +279,8 @@ mod tests {
     use std::str::FromStr;
     use std::sync::Arc;
 
+    use bstr::ByteSlice;
+
     use crate::git::hash::Hash;
     use crate::git::object::types::ObjectType;
 
@@ -212,7 +310,9 @@ mod tests {
                 timestamp: 0,
                 timezone: "".to_string(),
             },
-            message: "".to_string(),
+            headers: vec![],
+            header_end: b"\n\n",
+            message: vec![],
         }
     }
 
@@ -277,7 +377,14 @@ mod tests {
             ],
             author,
             committer,
-            message: "gpgsig -----BEGIN PGP SIGNATURE-----\n \n iQIzBAABCAAdFiEEanuf5/5ADLU2lvsCZL9E4tsHuXIFAmJRs88ACgkQZL9E4tsH\n uXJAmBAAtubFjLjNzIgal1/Gwy/zlpw7aQvVO2xcX3Xhbeb0UJyKvrSm/Ht19kiz\n 6Bc8ZV75mpKKip93XAljUgWgAO6Q4DUFnVA5bwF1vvhKHbgXLr+I8q+5GqmLW61U\n oBrB/3aJJ/uAxElQz5nOhgB7ztCfeKQ5egbhBXn9QGqPg/RkfQmDPYsU7evk1J0Z\n CyKinbSNe0c92qE95nURzozFb1zf0rO9NtnpYohFCEO5qyuoV4nz7npnJD4Miqy9\n IUQapeJeZC7eDvU8AWbxARrkXQkyfLSebDVcqbz7WfQz+4dhoK7jADaB48oKpR/K\n bKZDJU9a2t2nPC1ojzjQJgXZ6x4linQofBR8wE1ns3W5RoRgcBSj8dQMNH8wXa/T\n oQD6hlCJpjvbiYHuc3tSgCESI4ZU7zGpL9BAQK+C91T8CUamycF1H7TAHXdzNClR\n bWO4EeRzvwZZyIL029DYFxD2IFN7OQb5jc7JvcroIW8jUN0sMPS6jY+d0bg5pgIs\n yJjmI6qPYy7R35OElfTlw8aVSOAnVbQh7MZt6n3JUyezwK9MwbiKdAYKOLYaVaC0\n ++SY+NV4Dwe6W72KhFhxwOJQRGMfES1mRxy4n85BgqfCGy7STGSBOmon3VZEl89z\n rmvdX0JXy93hGH0oUQINsN9bzpsdaQUWVND8wAnb0+sU4LvJz90=\n =9qni\n -----END PGP SIGNATURE-----\n\nAdd gust.md and modify gitmega.md\n\nSigned-off-by: Quanyi Ma <eli@patch.sh>\n".to_string(),
+            headers: vec![(
+                "gpgsig".to_string(),
+                "-----BEGIN PGP SIGNATURE-----\n\niQIzBAABCAAdFiEEanuf5/5ADLU2lvsCZL9E4tsHuXIFAmJRs88ACgkQZL9E4tsH\nuXJAmBAAtubFjLjNzIgal1/Gwy/zlpw7aQvVO2xcX3Xhbeb0UJyKvrSm/Ht19kiz\n6Bc8ZV75mpKKip93XAljUgWgAO6Q4DUFnVA5bwF1vvhKHbgXLr+I8q+5GqmLW61U\noBrB/3aJJ/uAxElQz5nOhgB7ztCfeKQ5egbhBXn9QGqPg/RkfQmDPYsU7evk1J0Z\nCyKinbSNe0c92qE95nURzozFb1zf0rO9NtnpYohFCEO5qyuoV4nz7npnJD4Miqy9\nIUQapeJeZC7eDvU8AWbxARrkXQkyfLSebDVcqbz7WfQz+4dhoK7jADaB48oKpR/K\nbKZDJU9a2t2nPC1ojzjQJgXZ6x4linQofBR8wE1ns3W5RoRgcBSj8dQMNH8wXa/T\noQD6hlCJpjvbiYHuc3tSgCESI4ZU7zGpL9BAQK+C91T8CUamycF1H7TAHXdzNClR\nbWO4EeRzvwZZyIL029DYFxD2IFN7OQb5jc7JvcroIW8jUN0sMPS6jY+d0bg5pgIs\nyJjmI6qPYy7R35OElfTlw8aVSOAnVbQh7MZt6n3JUyezwK9MwbiKdAYKOLYaVaC0\n++SY+NV4Dwe6W72KhFhxwOJQRGMfES1mRxy4n85BgqfCGy7STGSBOmon3VZEl89z\nrmvdX0JXy93hGH0oUQINsN9bzpsdaQUWVND8wAnb0+sU4LvJz90=\n=9qni\n-----END PGP SIGNATURE-----"
+                    .as_bytes()
+                    .to_vec(),
+            )],
+            header_end: b"\n\n",
+            message: "Add gust.md and modify gitmega.md\n\nSigned-off-by: Quanyi Ma <eli@patch.sh>\n".as_bytes().to_vec(),
         };
 
         commit.meta = Arc::new(commit.encode_metadata().unwrap());
@@ -293,4 +400,142 @@ mod tests {
 
         assert!(Path::new("/tmp/3b/8bc1e152af7ed6b69f2acfa8be709d1733e1bb").exists());
     }
+
+    #[test]
+    fn test_commit_decode_merge_parents() {
+        let sign = |t: &str| AuthorSign {
+            t: t.to_string(),
+            name: "Quanyi Ma".to_string(),
+            email: "eli@patch.sh".to_string(),
+            timestamp: 1649521615,
+            timezone: "+0800".to_string(),
+        };
+        let parents = vec![
+            Hash::from_str("1b490ec04712d147bbe7c8b3a6d86ed4d3587a6a").unwrap(),
+            Hash::from_str("3b8bc1e152af7ed6b69f2acfa8be709d1733e1bb").unwrap(),
+        ];
+        let commit = super::Commit {
+            meta: Arc::new(MetaData::new(ObjectType::Commit, &vec![])),
+            tree_id: Hash::from_str("9bbe4087bedef91e50dc0c1a930c1d3e86fd5f20").unwrap(),
+            parent_tree_ids: parents.clone(),
+            author: sign("author"),
+            committer: sign("committer"),
+            headers: vec![],
+            header_end: b"\n\n",
+            message: "Merge branch 'dev'\n".as_bytes().to_vec(),
+        };
+
+        let decoded = Commit::new(Arc::new(commit.encode_metadata().unwrap()));
+
+        assert_eq!(parents, decoded.parent_tree_ids);
+        assert_eq!(commit.message, decoded.message);
+    }
+    #[test]
+    fn test_commit_round_trip_headers() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/data/test/commit-3b8bc1e152af7ed6b69f2acfa8be709d1733e1bb");
+        let meta = MetaData::read_object_from_file(path.to_str().unwrap().to_string()).unwrap();
+        let signed = Commit::new(Arc::new(meta));
+        assert!(signed
+            .signature()
+            .unwrap()
+            .starts_with(b"-----BEGIN PGP SIGNATURE-----"));
+        assert_eq!(signed.meta.id, signed.encode_metadata().unwrap().id);
+
+        let data = b"tree 9bbe4087bedef91e50dc0c1a930c1d3e86fd5f20\n\
+parent 1b490ec04712d147bbe7c8b3a6d86ed4d3587a6a\n\
+author A U Thor <author@example.com> 1649521615 +0800\n\
+committer C O Mitter <committer@example.com> 1649521615 +0800\n\
+encoding ISO-8859-1\n\
+mergetag object 1b490ec04712d147bbe7c8b3a6d86ed4d3587a6a\n type commit\n tag v1.0\n \n signed\n\
+x-extension some value\n\
+\nCaf\xe9\n";
+        let commit = Commit::new(Arc::new(MetaData::new(ObjectType::Commit, &data.to_vec())));
+        assert_eq!(Some("ISO-8859-1"), commit.encoding());
+        assert_eq!(
+            vec![&b"object 1b490ec04712d147bbe7c8b3a6d86ed4d3587a6a\ntype commit\ntag v1.0\n\nsigned"[..]],
+            commit.mergetags().collect::<Vec<_>>()
+        );
+        assert_eq!(Some(&b"some value"[..]), commit.header("x-extension"));
+        assert_eq!(b"Caf\xe9\n".to_vec(), commit.message);
+        assert_eq!(data.to_vec(), commit.encode_metadata().unwrap().data);
+    }
+
+    #[test]
+    fn test_commit_round_trip_raw() {
+        // a commit with the author line `author` and the bytes `rest` after the committer
+        let data = |author: &[u8], rest: &[u8]| {
+            [
+                b"tree 9bbe4087bedef91e50dc0c1a930c1d3e86fd5f20\n",
+                author,
+                b"\ncommitter C O Mitter <committer@example.com> 1649521615 +0800",
+                rest,
+            ]
+            .concat()
+        };
+        let author = b"author A U Thor <author@example.com> 1649521615 +0800";
+        let cases = [
+            // a Latin-1 name under an encoding header
+            data(
+                b"author Andr\xe9 <andre@example.com> 1649521615 +0800",
+                b"\nencoding ISO-8859-1\n\nCaf\xe9\n",
+            ),
+            // no space before the email
+            data(
+                b"author A U Thor<author@example.com> 1649521615 +0800",
+                b"\n\nmsg\n",
+            ),
+            // a zero padded timestamp
+            data(
+                b"author A U Thor <author@example.com> 0001649521615 +0800",
+                b"\n\nmsg\n",
+            ),
+            // no message, with and without the last newline
+            data(author, b"\n"),
+            data(author, b""),
+            // headers out of the usual order, a repeated author
+            data(
+                b"author A <a@example.com> 1649521615 +0800\nencoding UTF-8\n\
+author B <b@example.com> 1649521615 +0800",
+                b"\nparent 1b490ec04712d147bbe7c8b3a6d86ed4d3587a6a\n\nmsg\n",
+            ),
+        ];
+        for data in cases {
+            let commit = Commit::new(Arc::new(MetaData::new(ObjectType::Commit, &data)));
+            assert_eq!(
+                data,
+                commit.encode_metadata().unwrap().data,
+                "{}",
+                data.to_str_lossy()
+            );
+        }
+
+        let commit = Commit::new(Arc::new(MetaData::new(
+            ObjectType::Commit,
+            &data(
+                b"author A U Thor<author@example.com> 1649521615 +0800",
+                b"\n\nmsg\n",
+            ),
+        )));
+        assert_eq!("A U Thor", commit.author.name);
+        assert_eq!("author@example.com", commit.author.email);
+    }
+
+    #[test]
+    fn test_commit_encode_rewritten_parents() {
+        let data = b"tree 9bbe4087bedef91e50dc0c1a930c1d3e86fd5f20\n\
+author A U Thor <author@example.com> 1649521615 +0800\n\
+committer C O Mitter <committer@example.com> 1649521615 +0800\n\
+\nmsg\n";
+        let mut commit = Commit::new(Arc::new(MetaData::new(ObjectType::Commit, &data.to_vec())));
+        let parent = Hash::from_str("1b490ec04712d147bbe7c8b3a6d86ed4d3587a6a").unwrap();
+        commit.parent_tree_ids = vec![parent];
+
+        let rewritten = Commit::new(Arc::new(commit.encode_metadata().unwrap()));
+        assert_eq!(vec![parent], rewritten.parent_tree_ids);
+        assert!(rewritten
+            .meta
+            .data
+            .starts_with(b"tree 9bbe4087bedef91e50dc0c1a930c1d3e86fd5f20\nparent 1b490ec"));
+    }
 }
@@ -11,6 +11,7 @@ use std::fmt::Display;
 use bstr::ByteSlice;
 
 use crate::errors::GustError;
+use crate::git::errors::GitError;
 
 ///
 #[allow(unused)]
@@ -36,35 +37,44 @@ impl Display for AuthorSign {
 
 ///
 impl AuthorSign {
-    ///
+    /// Decode a `<type> <name> <<email>> <timestamp> <timezone>` line, a malformed line is an
+    /// error, `fsck` tells what's wrong with it. The name and the email may be in another
+    /// encoding than UTF-8, their invalid sequences are replaced.
     #[allow(unused)]
-    pub(crate) fn decode_from_data(&mut self, data: Vec<u8>) -> Result<(), GustError> {
-        let mut data = data;
-
-        let name_start = data.find_byte(0x20).unwrap();
-
-        self.t = String::from_utf8(data[..name_start].to_vec()).unwrap();
-
-        let email_start = data.find_byte(0x3C).unwrap();
-        let email_end = data.find_byte(0x3E).unwrap();
-
-        self.name = data[name_start + 1..email_start - 1]
+    pub(crate) fn decode_from_data(&mut self, data: Vec<u8>) -> Result<(), GitError> {
+        let invalid = || GitError::InvalidAuthorSign(data.to_str_lossy().into_owned());
+
+        let name_start = data.find_byte(0x20).ok_or_else(invalid)?;
+        let email_start = data.find_byte(0x3C).ok_or_else(invalid)?;
+        if email_start <= name_start {
+            return Err(invalid());
+        }
+        let email_end = data[email_start..]
+            .find_byte(0x3E)
+            .map(|end| email_start + end)
+            .ok_or_else(invalid)?;
+
+        self.t = data[..name_start]
             .to_str()
-            .unwrap()
+            .map_err(|_| invalid())?
             .to_string();
-        self.email = data[email_start + 1..email_end]
+        // the space before the email isn't part of the name
+        let name = &data[name_start + 1..email_start];
+        let name = name.strip_suffix(b" ").unwrap_or(name);
+        self.name = name.to_str_lossy().into_owned();
+        self.email = data[email_start + 1..email_end].to_str_lossy().into_owned();
+
+        let date = data.get(email_end + 2..).ok_or_else(invalid)?;
+        let timestamp_split = date.find_byte(0x20).ok_or_else(invalid)?;
+        self.timestamp = date[..timestamp_split]
             .to_str()
-            .unwrap()
-            .to_string();
-        data = data[email_end + 2..].to_vec();
-
-        let timestamp_split = data.find_byte(0x20).unwrap();
-        self.timestamp = data[0..timestamp_split]
+            .ok()
+            .and_then(|t| t.parse::<usize>().ok())
+            .ok_or_else(invalid)?;
+        self.timezone = date[timestamp_split + 1..]
             .to_str()
-            .unwrap()
-            .parse::<usize>()
-            .unwrap();
-        self.timezone = data[timestamp_split + 1..].to_str().unwrap().to_string();
+            .map_err(|_| invalid())?
+            .to_string();
 
         Ok(())
     }
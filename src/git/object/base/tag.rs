@@ -10,8 +10,6 @@ use std::sync::Arc;
 
 use bstr::ByteSlice;
 
-use crate::errors::GustError;
-
 use crate::git::errors::GitError;
 use crate::git::hash::Hash;
 use crate::git::object::base::sign::AuthorSign;
@@ -52,9 +50,14 @@ impl Tag {
     /// Tag 的构造函数 接收一个@param meta::Metadata
     /// 同时执行tag解码 -> `fn decode_metadata`
     pub fn new(meta: Arc<MetaData>) -> Self {
+        Tag::try_new(meta).unwrap()
+    }
+
+    /// Decode a tag, which may be malformed when it was received.
+    pub fn try_new(meta: Arc<MetaData>) -> Result<Self, GitError> {
         let mut a = Self {
             meta: meta.clone(),
-            object: meta.id.clone(),
+            object: meta.id,
             t: ObjectType::Commit,
             tag: "".to_string(),
             tagger: AuthorSign {
@@ -66,49 +69,62 @@ impl Tag {
             },
             message: "".to_string(),
         };
-        a.decode_metadata().unwrap();
-        a
+        a.decode_metadata()?;
+        Ok(a)
     }
 
-    ///
+    /// Decode the `object`, `type`, `tag` and `tagger` headers, the message keeps the blank
+    /// line ending them. Old tags have no tagger.
     #[allow(unused)]
-    fn decode_metadata(&mut self) -> Result<(), GustError> {
-        let mut data = self.meta.data.clone();
-
-        let object_begin = data.find_byte(0x20).unwrap();
-        let object_end = data.find_byte(0x0a).unwrap();
-        self.object = Hash::from_bytes(&data[object_begin + 1..object_end].to_vec()).unwrap();
-        data = data[object_end + 1..].to_vec();
-
-        let type_begin = data.find_byte(0x20).unwrap();
-        let type_end = data.find_byte(0x0a).unwrap();
-        self.t = ObjectType::from_string(data[type_begin + 1..type_end].to_str().unwrap()).unwrap();
-        data = data[type_end + 1..].to_vec();
-
-        let tag_begin = data.find_byte(0x20).unwrap();
-        let tag_end = data.find_byte(0x0a).unwrap();
-        self.tag = data[tag_begin + 1..tag_end]
-            .to_str()
-            .unwrap()
-            .parse()
-            .unwrap();
-        data = data[tag_end + 1..].to_vec(); //Fixed Bug: bug type_end to tag_end
-
-        let tagger_begin = data.find("tagger").unwrap();
-        let tagger_end = data.find_byte(0x0a).unwrap();
-        let tagger_data = data[tagger_begin..tagger_end].to_vec();
-        self.tagger.decode_from_data(tagger_data)?;
-        data = data[data.find_byte(0x0a).unwrap() + 1..].to_vec();
-
-        self.message = data[data.find_byte(0x0a).unwrap()..]
-            .to_vec()
-            .to_str()
-            .unwrap()
-            .to_string();
+    fn decode_metadata(&mut self) -> Result<(), GitError> {
+        let meta = self.meta.clone();
+        let data = &meta.data;
+        let invalid = || GitError::InvalidTagObject(meta.id.to_plain_str());
+        let (headers, message) = match data.find("\n\n") {
+            Some(end) => (&data[..end], &data[end + 1..]),
+            None => (&data[..], &data[data.len()..]),
+        };
+
+        let mut object = None;
+        let mut object_type = None;
+        for line in headers.split_str("\n") {
+            let Some((name, value)) = line.split_once_str(" ") else {
+                continue;
+            };
+            match name {
+                b"object" => object = Hash::from_bytes(value),
+                b"type" => {
+                    object_type = value
+                        .to_str()
+                        .ok()
+                        .and_then(|t| ObjectType::from_string(t).ok())
+                }
+                b"tag" => self.tag = value.to_str_lossy().into_owned(),
+                b"tagger" => self.tagger.decode_from_data(line.to_vec())?,
+                _ => {}
+            }
+        }
+        self.object = object.ok_or_else(invalid)?;
+        self.t = object_type.ok_or_else(invalid)?;
+        self.message = message.to_str_lossy().into_owned();
 
         Ok(())
     }
 
+    /// The signed payload and the armored signature of a signed tag, the signature is
+    /// appended to the message.
+    pub fn signature(&self) -> Option<(&[u8], &[u8])> {
+        let data = &self.meta.data;
+        [
+            "\n-----BEGIN PGP SIGNATURE-----",
+            "\n-----BEGIN SSH SIGNATURE-----",
+        ]
+        .iter()
+        .filter_map(|begin| data.rfind(begin))
+        .max()
+        .map(|pos| data.split_at(pos + 1))
+    }
+
     ///
     #[allow(unused)]
     fn encode_metadata(&self) -> Result<MetaData, ()> {
@@ -55,22 +55,22 @@ impl TreeItemType {
         }
     }
 
-    ///
+    /// The type of an entry by its octal mode. Like git, the file type bits decide it, zero
+    /// padded modes and unusual permissions are only reported by fsck.
     #[allow(unused)]
     pub(crate) fn tree_item_type_from(mode: &[u8]) -> Result<TreeItemType, GitError> {
-        Ok(match mode {
-            b"40000" => TreeItemType::Tree,
-            b"100644" => TreeItemType::Blob,
-            b"100755" => TreeItemType::BlobExecutable,
-            b"120000" => TreeItemType::Link,
-            b"160000" => TreeItemType::Commit,
-            b"100664" => TreeItemType::Blob,
-            b"100640" => TreeItemType::Blob,
-            _ => {
-                return Err(GitError::InvalidTreeItem(
-                    String::from_utf8(mode.to_vec()).unwrap(),
-                ));
-            }
+        let invalid = || GitError::InvalidTreeItem(mode.to_str_lossy().into_owned());
+        if mode.is_empty() || !mode.iter().all(|b| (b'0'..=b'7').contains(b)) {
+            return Err(invalid());
+        }
+        let bits = u32::from_str_radix(mode.to_str().unwrap(), 8).map_err(|_| invalid())?;
+        Ok(match bits & 0o170000 {
+            0o040000 => TreeItemType::Tree,
+            0o120000 => TreeItemType::Link,
+            0o160000 => TreeItemType::Commit,
+            0o100000 if bits & 0o100 != 0 => TreeItemType::BlobExecutable,
+            0o100000 => TreeItemType::Blob,
+            _ => return Err(invalid()),
         })
     }
 }
@@ -147,36 +147,45 @@ impl Tree {
     }
 
     pub fn new(metadata: Arc<MetaData>) -> Self {
+        Tree::try_new(metadata).unwrap()
+    }
+
+    /// Decode a tree, which may be malformed when it was received.
+    pub fn try_new(metadata: Arc<MetaData>) -> Result<Self, GitError> {
         let mut a = Self {
             meta: metadata,
             tree_items: vec![],
             tree_name: String::new(),
         };
-        a.decode_metadata().unwrap();
-        a
+        a.decode_metadata()?;
+        Ok(a)
     }
 
     pub(crate) fn decode_metadata(&mut self) -> Result<(), GitError> {
+        let meta = self.meta.clone();
+        let invalid = || GitError::InvalidTreeObject(meta.id.to_plain_str());
         let mut index = 0;
-        while index < self.meta.data.len() {
-            let mode_index = &self.meta.data[index..].find_byte(0x20).unwrap();
-            let mode = &self.meta.data[index..index + *mode_index];
-            let item_type = TreeItemType::tree_item_type_from(mode).unwrap();
-
-            let filename_index = &self.meta.data[index..].find_byte(0x00).unwrap();
-            let filename = String::from_utf8(
-                self.meta.data[index + mode_index + 1..index + *filename_index].to_vec(),
-            )
-            .unwrap();
+        while index < meta.data.len() {
+            let entry = &meta.data[index..];
+            let mode_index = entry.find_byte(0x20).ok_or_else(invalid)?;
+            let filename_index = entry.find_byte(0x00).ok_or_else(invalid)?;
+            if filename_index < mode_index {
+                return Err(invalid());
+            }
+            let mode = &entry[..mode_index];
+            let item_type = TreeItemType::tree_item_type_from(mode)?;
 
-            let id = Hash::from_row(
-                &self.meta.data[index + filename_index + 1..index + filename_index + 21].to_vec(),
-            );
+            let filename = String::from_utf8(entry[mode_index + 1..filename_index].to_vec())
+                .map_err(|_| invalid())?;
+
+            let id = entry
+                .get(filename_index + 1..filename_index + 21)
+                .ok_or_else(invalid)?;
 
             self.tree_items.push(TreeItem {
                 mode: mode.to_vec(),
                 item_type,
-                id,
+                id: Hash::from_row(id),
                 filename,
             });
 
@@ -206,6 +215,23 @@ impl Tree {
     pub(crate) fn write_to_file(&self, root_path: String) -> Result<String, GitError> {
         self.meta.write_to_file(root_path)
     }
+
+    /// Replace the item with the same filename, or insert it in git order, and re-encode the
+    /// tree. Git sorts items by filename with a trailing `/` on subtrees.
+    pub fn set_item(&mut self, item: TreeItem) {
+        self.tree_items.retain(|i| i.filename != item.filename);
+        let sort_key = |i: &TreeItem| {
+            let mut key = i.filename.as_bytes().to_vec();
+            if i.item_type == TreeItemType::Tree {
+                key.push(b'/');
+            }
+            key
+        };
+        let key = sort_key(&item);
+        let index = self.tree_items.partition_point(|i| sort_key(i) < key);
+        self.tree_items.insert(index, item);
+        self.meta = Arc::new(self.encode_metadata().unwrap());
+    }
 }
 
 ///
@@ -427,4 +453,35 @@ mod tests {
 
         assert_eq!(TreeItemType::Blob, tree.tree_items[1].item_type);
     }
+
+    #[test]
+    fn test_tree_set_item_in_git_order() {
+        let item = |name: &str, item_type: TreeItemType| super::TreeItem {
+            mode: item_type.to_bytes().to_vec(),
+            item_type,
+            id: Hash::default(),
+            filename: name.to_string(),
+        };
+        let mut tree = Tree {
+            tree_name: String::new(),
+            meta: Arc::new(MetaData::new(ObjectType::Tree, &vec![])),
+            tree_items: vec![
+                item("a.c", TreeItemType::Blob),
+                item("b", TreeItemType::Blob),
+            ],
+        };
+
+        // "a/" sorts after "a.c" since '/' > '.'
+        tree.set_item(item("a", TreeItemType::Tree));
+        tree.set_item(item("b", TreeItemType::BlobExecutable));
+
+        let names: Vec<&str> = tree
+            .tree_items
+            .iter()
+            .map(|i| i.filename.as_str())
+            .collect();
+        assert_eq!(vec!["a.c", "a", "b"], names);
+        assert_eq!(TreeItemType::BlobExecutable, tree.tree_items[2].item_type);
+        assert_eq!(tree.meta.id, tree.encode_metadata().unwrap().id);
+    }
 }
@@ -13,6 +13,7 @@ use flate2::read::ZlibDecoder;
 use crate::git::errors::GitError;
 use crate::git::hash::Hash;
 use crate::git::object::metadata::MetaData;
+use crate::git::pack::limits::DecodeBudget;
 use crate::git::utils;
 
 const COPY_INSTRUCTION_FLAG: u8 = 1 << 7;
@@ -20,35 +21,71 @@ const COPY_OFFSET_BYTES: u8 = 4;
 const COPY_SIZE_BYTES: u8 = 3;
 const COPY_ZERO_SIZE: usize = 0x10000;
 
-///使用delta指令
-pub fn apply_delta(pack_file: &mut File, base: &MetaData) -> Result<MetaData, GitError> {
-    utils::read_zlib_stream_exact(pack_file, |delta| {
-        let base_size = utils::read_size_encoding(delta).unwrap();
-        if base.size != base_size {
-            return Err(GitError::DeltaObjError(
-                String::from_str("Incorrect base object length").unwrap(),
-            ));
-        }
+///使用delta指令, `delta_size` is the inflated size of the delta declared by the pack, the
+/// resolved object is charged to `budget` before it's allocated
+pub fn apply_delta(
+    pack_file: &mut File,
+    base: &MetaData,
+    delta_size: usize,
+    budget: &mut DecodeBudget,
+) -> Result<MetaData, GitError> {
+    utils::read_zlib_stream_exact(pack_file, |decompressed| {
+        apply_delta_stream(decompressed, base, delta_size, budget)
+    })
+}
 
-        let result_size = utils::read_size_encoding(delta).unwrap();
-        let mut result = Vec::with_capacity(result_size);
-        while apply_delta_instruction(delta, &base.data, &mut result)? {}
-        if result.len() != result_size {
-            return Err(GitError::DeltaObjError(
-                String::from_str("Incorrect object length").unwrap(),
-            ));
-        }
+/// Apply the delta read from the inflated `decompressed` stream to `base`, the stream must
+/// end with the `delta_size` bytes of the delta.
+pub fn apply_delta_stream<R: Read>(
+    decompressed: &mut R,
+    base: &MetaData,
+    delta_size: usize,
+    budget: &mut DecodeBudget,
+) -> Result<MetaData, GitError> {
+    let mut delta = Read::take(&mut *decompressed, delta_size as u64);
+    let base_size = utils::read_size_encoding(&mut delta).map_err(invalid_delta)?;
+    if base.size != base_size {
+        return Err(GitError::DeltaObjError(
+            String::from_str("Incorrect base object length").unwrap(),
+        ));
+    }
 
-        // The object type is the same as the base object
-        Ok(MetaData::new(base.t, &result))
-    })
+    let result_size = utils::read_size_encoding(&mut delta).map_err(invalid_delta)?;
+    budget.charge(result_size)?;
+    let mut result = Vec::with_capacity(result_size);
+    while apply_delta_instruction(&mut delta, &base.data, &mut result, result_size)? {}
+    if result.len() != result_size {
+        return Err(GitError::DeltaObjError(
+            String::from_str("Incorrect object length").unwrap(),
+        ));
+    }
+    // the zlib stream must end with the declared size
+    if decompressed.read(&mut [0]).map_err(invalid_delta)? != 0 {
+        return Err(GitError::DeltaObjError(
+            String::from_str("Incorrect delta length").unwrap(),
+        ));
+    }
+
+    // The object type is the same as the base object
+    Ok(MetaData::new(base.t, &result))
+}
+
+/// The size of the object resolved by a delta, read from the start of its inflated data.
+pub fn delta_result_size<R: Read>(decompressed: &mut R) -> Result<usize, GitError> {
+    utils::read_size_encoding(decompressed).map_err(invalid_delta)?;
+    utils::read_size_encoding(decompressed).map_err(invalid_delta)
+}
+
+fn invalid_delta(err: std::io::Error) -> GitError {
+    GitError::DeltaObjError(format!("Invalid delta: {}", err))
 }
 
-///执行单个delta指令
+///执行单个delta指令, the result never grows past `result_size`
 fn apply_delta_instruction<R: Read>(
     stream: &mut R,
     base: &[u8],
     result: &mut Vec<u8>,
+    result_size: usize,
 ) -> Result<bool, GitError> {
     // Check if the stream has ended, meaning the new object is done
     let instruction = match utils::read_bytes(stream) {
@@ -69,22 +106,32 @@ fn apply_delta_instruction<R: Read>(
                 String::from_str("Invalid data instruction").unwrap(),
             ));
         }
+        if result.len() + instruction as usize > result_size {
+            return Err(GitError::DeltaObjError(String::from(
+                "Delta exceeds the object length",
+            )));
+        }
 
         // Append the provided bytes
         let mut data = vec![0; instruction as usize];
-        stream.read_exact(&mut data).unwrap();
+        stream.read_exact(&mut data).map_err(invalid_delta)?;
         result.extend_from_slice(&data);
     } else {
         // Copy instruction
         let mut nonzero_bytes = instruction;
-        let offset =
-            utils::read_partial_int(stream, COPY_OFFSET_BYTES, &mut nonzero_bytes).unwrap();
-        let mut size =
-            utils::read_partial_int(stream, COPY_SIZE_BYTES, &mut nonzero_bytes).unwrap();
+        let offset = utils::read_partial_int(stream, COPY_OFFSET_BYTES, &mut nonzero_bytes)
+            .map_err(invalid_delta)?;
+        let mut size = utils::read_partial_int(stream, COPY_SIZE_BYTES, &mut nonzero_bytes)
+            .map_err(invalid_delta)?;
         if size == 0 {
             // Copying 0 bytes doesn't make sense, so git assumes a different size
             size = COPY_ZERO_SIZE;
         }
+        if result.len() + size > result_size {
+            return Err(GitError::DeltaObjError(String::from(
+                "Delta exceeds the object length",
+            )));
+        }
         // Copy bytes from the base object
         let base_data = base
             .get(offset..(offset + size))
@@ -1,214 +1,279 @@
 //!
+//! Binary deltas in the pack format, computed like git's `diff-delta.c`: the blocks of the
+//! base are indexed by a rolling hash of their bytes, then the target is scanned for the
+//! blocks it shares with the base. Moved blocks are found and the time is linear in the
+//! size of the two objects.
 //!
-//!
-//!
-//!
-use std::vec;
-
-use diffs::myers;
-use diffs::Diff;
+use std::borrow::Cow;
 
 use crate::git::object::metadata::MetaData;
 use crate::git::utils;
 
-const DATA_INS_LEN: usize = 0x7f;
-
-#[allow(dead_code)]
-#[derive(Debug)]
-pub struct DeltaDiff {
-    ops: Vec<DeltaOp>,
-    old_data: MetaData,
-    new_data: MetaData,
-    ssam: usize,
-    ssam_r: f64,
+/// bytes hashed by the rolling hash, the shortest block copied from the base
+const WINDOW: usize = 16;
+/// multiplier of the rolling hash
+const HASH_PRIME: u32 = 0x0100_0193;
+/// the weight of the byte leaving the window of the rolling hash
+const OUT_WEIGHT: u32 = HASH_PRIME.wrapping_pow(WINDOW as u32 - 1);
+/// the most bytes of an insert instruction
+const MAX_INSERT_SIZE: usize = 0x7f;
+/// the most bytes of a copy instruction written, as git does
+const MAX_COPY_SIZE: usize = 0x10000;
+
+/// How hard deltas are searched for.
+#[derive(Debug, Clone)]
+pub struct DeltaOptions {
+    /// the most blocks of the base compared at each position of the target, the quality and
+    /// speed knob
+    pub max_chain: usize,
+    /// the largest base or target, larger objects are sent whole
+    pub max_object_size: usize,
 }
 
-impl DeltaDiff {
-    /// Diff the two Metadata , Type should be same.
-    /// Return the DeltaDiff struct.
-    pub fn new(old_md: MetaData, new_md: MetaData) -> Self {
-        let mut delta_diff = DeltaDiff {
-            ops: vec![],
-            old_data: old_md.clone(),
-            new_data: new_md.clone(),
-
-            ssam: 0,
-            ssam_r: 0.00,
-        };
-
-        myers::diff(
-            &mut delta_diff,
-            &old_md.data,
-            0,
-            old_md.data.len(),
-            &new_md.data,
-            0,
-            new_md.data.len(),
-        )
-        .unwrap();
-
-        delta_diff
+impl Default for DeltaOptions {
+    fn default() -> Self {
+        DeltaOptions {
+            max_chain: 64,
+            max_object_size: 64 * 1024 * 1024,
+        }
     }
+}
 
-    ///
-    ///
-    pub fn get_delta_metadata(&self) -> Vec<u8> {
-        let mut result: Vec<u8> = vec![];
+impl DeltaOptions {
+    /// Whether an object of `size` bytes may be a base or a target.
+    pub fn fits(&self, size: usize) -> bool {
+        size <= self.max_object_size && size <= u32::MAX as usize
+    }
+}
 
-        result.append(&mut utils::write_size_encoding(self.old_data.size));
-        result.append(&mut utils::write_size_encoding(self.new_data.size));
+/// The blocks of a base by the hash of their bytes, the base is borrowed or owned by the index.
+pub struct DeltaIndex<'a> {
+    base: Cow<'a, [u8]>,
+    /// the blocks of bucket `b` are `offsets[starts[b]..starts[b + 1]]`
+    starts: Vec<u32>,
+    offsets: Vec<u32>,
+    bits: u32,
+}
 
-        for op in &self.ops {
-            result.append(&mut self.decode_op(op));
+impl<'a> DeltaIndex<'a> {
+    /// Index the blocks of `base` every `WINDOW` bytes, a bucket keeps at most
+    /// `options.max_chain` blocks spread over the base.
+    pub fn new(base: impl Into<Cow<'a, [u8]>>, options: &DeltaOptions) -> DeltaIndex<'a> {
+        let base = base.into();
+        // copy instructions have 4 bytes of offset
+        let indexed = &base[..base.len().min(u32::MAX as usize)];
+        let mut blocks = Vec::with_capacity(indexed.len() / WINDOW);
+        let mut previous = None;
+        for offset in (0..indexed.len().saturating_sub(WINDOW - 1)).step_by(WINDOW) {
+            let hash = window_hash(&indexed[offset..offset + WINDOW]);
+            // a run of the same bytes is indexed once
+            if previous != Some(hash) {
+                blocks.push((hash, offset as u32));
+            }
+            previous = Some(hash);
+        }
+        let bits = (blocks.len() / 4)
+            .next_power_of_two()
+            .trailing_zeros()
+            .max(4);
+        let mut counts = vec![0u32; 1 << bits];
+        for (hash, _) in &blocks {
+            counts[bucket(*hash, bits)] += 1;
+        }
+        let mut starts = Vec::with_capacity(counts.len() + 1);
+        let mut total = 0;
+        starts.push(0);
+        for count in &counts {
+            total += (*count as usize).min(options.max_chain.max(1)) as u32;
+            starts.push(total);
+        }
+        let mut offsets = vec![0; total as usize];
+        let mut seen = vec![0u32; counts.len()];
+        for (hash, offset) in blocks {
+            let b = bucket(hash, bits);
+            let (count, kept) = (counts[b] as usize, (starts[b + 1] - starts[b]) as usize);
+            let n = seen[b] as usize;
+            seen[b] += 1;
+            // keep the blocks evenly spread when a bucket is culled
+            if n * kept / count != (n + 1) * kept / count {
+                offsets[starts[b] as usize + n * kept / count] = offset;
+            }
+        }
+        DeltaIndex {
+            base,
+            starts,
+            offsets,
+            bits,
         }
-
-        result
     }
 
-    ///
-    /// Decode the DeltaOp to `Vec<u8>`
-    fn decode_op(&self, op: &DeltaOp) -> Vec<u8> {
-        let mut op_data = vec![];
-
-        match op.ins {
-            Optype::DATA => {
-                let instruct = (op.len & 0x7f) as u8;
-                op_data.push(instruct);
-                op_data.append(&mut self.new_data.data[op.begin..op.begin + op.len].to_vec());
-            }
+    /// The delta turning the base into `target`, None if it's longer than `max_delta_size`.
+    pub fn delta(&self, target: &[u8], max_delta_size: usize) -> Option<Vec<u8>> {
+        self.encode(target, max_delta_size).map(|(delta, _)| delta)
+    }
 
-            Optype::COPY => {
-                let mut instruct: u8 = 0x80;
-                let mut offset = op.begin;
-                let mut size = op.len;
-                let mut copy_data = vec![];
-
-                for i in 0..4 {
-                    let _bit = (offset & 0xff) as u8;
-                    if _bit != 0 {
-                        instruct |= (1 << i) as u8;
-                        copy_data.push(_bit)
-                    }
-                    offset >>= 8;
+    /// The delta to `target` and the bytes it copies from the base.
+    fn encode(&self, target: &[u8], max_delta_size: usize) -> Option<(Vec<u8>, usize)> {
+        let mut delta = utils::write_size_encoding(self.base.len());
+        delta.append(&mut utils::write_size_encoding(target.len()));
+        let mut copied = 0;
+        // the bytes of target[insert..pos] are not written yet
+        let mut insert = 0;
+        let mut pos = 0;
+        let mut hash = 0;
+        if target.len() >= WINDOW {
+            hash = window_hash(&target[..WINDOW]);
+        }
+        while pos < target.len() {
+            let (mut from, mut len) = if pos + WINDOW <= target.len() {
+                self.longest_match(hash, &target[pos..])
+            } else {
+                (0, 0)
+            };
+            if len == 0 {
+                if delta.len() + pos + 1 - insert > max_delta_size {
+                    return None;
                 }
-
-                for i in 4..7 {
-                    let _bit = (size & 0xff) as u8;
-                    if _bit != 0 {
-                        instruct |= (1 << i) as u8;
-                        copy_data.push(_bit)
-                    }
-                    size >>= 8;
+                if pos + WINDOW < target.len() {
+                    hash = roll_hash(hash, target[pos], target[pos + WINDOW]);
                 }
-
-                op_data.push(instruct);
-                op_data.append(&mut copy_data);
+                pos += 1;
+                continue;
+            }
+            // the match may start in the bytes not written yet
+            while pos > insert && from > 0 && self.base[from - 1] == target[pos - 1] {
+                from -= 1;
+                pos -= 1;
+                len += 1;
+            }
+            write_insert(&mut delta, &target[insert..pos]);
+            write_copy(&mut delta, from, len);
+            copied += len;
+            pos += len;
+            insert = pos;
+            if delta.len() > max_delta_size {
+                return None;
+            }
+            if pos + WINDOW <= target.len() {
+                hash = window_hash(&target[pos..pos + WINDOW]);
             }
         }
-
-        op_data
+        write_insert(&mut delta, &target[insert..]);
+        if delta.len() > max_delta_size {
+            return None;
+        }
+        Some((delta, copied))
     }
 
-    ///
-    pub fn get_ssam_rate(&self) -> f64 {
-        self.ssam_r
+    /// The offset and length of the longest block of the base `target` starts with, among
+    /// the blocks of `hash`. The length is 0 if none matches.
+    fn longest_match(&self, hash: u32, target: &[u8]) -> (usize, usize) {
+        let b = bucket(hash, self.bits);
+        let mut best = (0, 0);
+        for &offset in &self.offsets[self.starts[b] as usize..self.starts[b + 1] as usize] {
+            let base = &self.base[offset as usize..];
+            if base[..WINDOW] != target[..WINDOW] {
+                continue;
+            }
+            let len = WINDOW
+                + base[WINDOW..]
+                    .iter()
+                    .zip(&target[WINDOW..])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+            if len > best.1 {
+                best = (offset as usize, len);
+                if len == target.len() {
+                    break;
+                }
+            }
+        }
+        best
     }
 }
 
-impl Diff for DeltaDiff {
-    type Error = ();
+fn window_hash(window: &[u8]) -> u32 {
+    window.iter().fold(0u32, |hash, &byte| {
+        hash.wrapping_mul(HASH_PRIME).wrapping_add(byte as u32)
+    })
+}
 
-    ///
-    fn equal(&mut self, _old: usize, _new: usize, _len: usize) -> Result<(), Self::Error> {
-        self.ssam += _len;
-        if let Some(tail) = self.ops.last_mut() {
-            if tail.begin + tail.len == _old && tail.ins == Optype::COPY {
-                tail.len += _len;
-            } else {
-                self.ops.push(DeltaOp {
-                    ins: Optype::COPY,
-                    begin: _old,
-                    len: _len,
-                });
-            }
-        } else {
-            self.ops.push(DeltaOp {
-                ins: Optype::COPY,
-                begin: _old,
-                len: _len,
-            });
-        }
+/// The hash of the window moved by one byte, `out` leaving it and `new` entering it.
+fn roll_hash(hash: u32, out: u8, new: u8) -> u32 {
+    hash.wrapping_sub((out as u32).wrapping_mul(OUT_WEIGHT))
+        .wrapping_mul(HASH_PRIME)
+        .wrapping_add(new as u32)
+}
 
-        Ok(())
+fn bucket(hash: u32, bits: u32) -> usize {
+    (hash.wrapping_mul(0x9e37_79b1) >> (32 - bits)) as usize
+}
+
+fn write_insert(delta: &mut Vec<u8>, data: &[u8]) {
+    for chunk in data.chunks(MAX_INSERT_SIZE) {
+        delta.push(chunk.len() as u8);
+        delta.extend_from_slice(chunk);
     }
+}
 
-    ///
-    ///
-    fn insert(&mut self, _old: usize, _new: usize, _len: usize) -> Result<(), ()> {
-        let mut len = _len;
-        let mut new = _new;
-
-        if _len > DATA_INS_LEN {
-            while len > DATA_INS_LEN {
-                self.ops.push(DeltaOp {
-                    ins: Optype::DATA,
-                    begin: new,
-                    len: DATA_INS_LEN,
-                });
-
-                len -= DATA_INS_LEN;
-                new += DATA_INS_LEN;
+fn write_copy(delta: &mut Vec<u8>, mut offset: usize, mut len: usize) {
+    while len > 0 {
+        let size = len.min(MAX_COPY_SIZE);
+        let mut instruction = 0x80;
+        let mut args = vec![];
+        for i in 0..4 {
+            let byte = (offset >> (8 * i)) as u8;
+            if byte != 0 {
+                instruction |= 1 << i;
+                args.push(byte);
             }
-
-            self.ops.push(DeltaOp {
-                ins: Optype::DATA,
-                begin: new,
-                len,
-            });
-        } else {
-            if let Some(tail) = self.ops.last_mut() {
-                if tail.begin + tail.len == _new
-                    && tail.ins == Optype::DATA
-                    && tail.len + _len < DATA_INS_LEN
-                {
-                    tail.len += _len;
-                } else {
-                    self.ops.push(DeltaOp {
-                        ins: Optype::DATA,
-                        begin: new,
-                        len: len,
-                    });
-                }
-            } else {
-                self.ops.push(DeltaOp {
-                    ins: Optype::DATA,
-                    begin: new,
-                    len: len,
-                });
+        }
+        for i in 0..3 {
+            let byte = (size >> (8 * i)) as u8;
+            if byte != 0 {
+                instruction |= 1 << (4 + i);
+                args.push(byte);
             }
         }
-
-        Ok(())
-    }
-
-    fn finish(&mut self) -> Result<(), Self::Error> {
-        // compute the ssam rate when finish the diff process.
-        self.ssam_r = self.ssam as f64 / self.new_data.data.len() as f64;
-        Ok(())
+        delta.push(instruction);
+        delta.append(&mut args);
+        offset += size;
+        len -= size;
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
-enum Optype {
-    DATA,
-    COPY,
+/// The delta between two objects of the same type.
+#[derive(Debug)]
+pub struct DeltaDiff {
+    delta: Vec<u8>,
+    ssam_r: f64,
 }
 
-#[derive(Debug, Clone, Copy)]
-struct DeltaOp {
-    ins: Optype,
-    begin: usize,
-    len: usize,
+impl DeltaDiff {
+    /// Diff the two Metadata , Type should be same.
+    /// Return the DeltaDiff struct.
+    pub fn new(old_md: MetaData, new_md: MetaData) -> Self {
+        let options = DeltaOptions::default();
+        let (delta, copied) = DeltaIndex::new(&old_md.data, &options)
+            .encode(&new_md.data, usize::MAX)
+            .unwrap();
+        let ssam_r = if new_md.data.is_empty() {
+            0.0
+        } else {
+            copied as f64 / new_md.data.len() as f64
+        };
+        DeltaDiff { delta, ssam_r }
+    }
+
+    /// the delta data, sizes then instructions
+    pub fn get_delta_metadata(&self) -> Vec<u8> {
+        self.delta.clone()
+    }
+
+    /// the share of the new object copied from the old one
+    pub fn get_ssam_rate(&self) -> f64 {
+        self.ssam_r
+    }
 }
 
 #[cfg(test)]
@@ -217,10 +282,91 @@ mod tests {
     use std::path::PathBuf;
 
     use bstr::ByteSlice;
+    use rand::rngs::StdRng;
+    use rand::{RngCore, SeedableRng};
 
+    use crate::git::object::delta::apply_delta_stream;
+    use crate::git::pack::limits::{DecodeBudget, PackLimits};
     use crate::git::{object::metadata::MetaData, object::types::ObjectType, pack::Pack, utils};
 
-    use super::DeltaDiff;
+    use super::{DeltaDiff, DeltaIndex, DeltaOptions};
+
+    fn random_bytes(rng: &mut StdRng, len: usize) -> Vec<u8> {
+        let mut data = vec![0; len];
+        rng.fill_bytes(&mut data);
+        data
+    }
+
+    /// Apply `delta` to `base` like a received pack does.
+    fn apply(base: &[u8], delta: &[u8]) -> Vec<u8> {
+        let base = MetaData::new(ObjectType::Blob, &base.to_vec());
+        let mut budget = DecodeBudget::new(PackLimits::default(), 0);
+        apply_delta_stream(&mut &delta[..], &base, delta.len(), &mut budget)
+            .unwrap()
+            .data
+    }
+
+    #[test]
+    fn test_delta_round_trip() {
+        let mut rng = StdRng::seed_from_u64(50);
+        let text = "fn main() {\n    println!(\"hello\");\n}\n".repeat(50);
+        let binary = random_bytes(&mut rng, 300_000);
+        let mut edited = binary.clone();
+        edited[1000..1010].copy_from_slice(&[0; 10]);
+        edited.splice(150_000..150_000, random_bytes(&mut rng, 500));
+        let cases: Vec<(Vec<u8>, Vec<u8>)> = vec![
+            (
+                text.as_bytes().to_vec(),
+                text.replacen("hello", "hi", 3).into_bytes(),
+            ),
+            (binary.clone(), edited),
+            // a copy longer than a copy instruction
+            (binary.clone(), binary.clone()),
+            (binary.clone(), random_bytes(&mut rng, 1000)),
+            (vec![0; 100_000], vec![0; 120_000]),
+            (b"short".to_vec(), b"shorter".to_vec()),
+            (vec![], b"new".to_vec()),
+            (b"old".to_vec(), vec![]),
+        ];
+        for max_chain in [1, 64] {
+            let options = DeltaOptions {
+                max_chain,
+                ..Default::default()
+            };
+            for (base, target) in &cases {
+                let delta = DeltaIndex::new(base, &options)
+                    .delta(target, usize::MAX)
+                    .unwrap();
+                assert_eq!(*target, apply(base, &delta));
+            }
+        }
+    }
+
+    #[test]
+    fn test_delta_moved_blocks() {
+        let mut rng = StdRng::seed_from_u64(50);
+        let a = random_bytes(&mut rng, 4096);
+        let b = random_bytes(&mut rng, 4096);
+        let base = [a.clone(), b.clone()].concat();
+        let target = [b, a].concat();
+        let delta = DeltaIndex::new(&base, &DeltaOptions::default())
+            .delta(&target, usize::MAX)
+            .unwrap();
+        assert!(delta.len() < 32);
+        assert_eq!(target, apply(&base, &delta));
+
+        let diff = DeltaDiff::new(
+            MetaData::new(ObjectType::Blob, &base),
+            MetaData::new(ObjectType::Blob, &target),
+        );
+        assert_eq!(1.0, diff.get_ssam_rate());
+
+        // a delta longer than the limit is given up
+        let unrelated = random_bytes(&mut rng, 4096);
+        let index = DeltaIndex::new(&base, &DeltaOptions::default());
+        assert!(index.delta(&unrelated, 2048).is_none());
+        assert!(index.delta(&target, 32).is_some());
+    }
 
     /// 通过两个metadata 来进行对后者No.2的压缩
     /// 首先，需要两个是相同的类型(ObjectType)
@@ -4,17 +4,29 @@
 use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 
+use async_trait::async_trait;
+
+use crate::git::errors::GitError;
 use crate::git::hash::Hash;
 use crate::git::object::metadata::MetaData;
+use crate::git::pack::resolve::{ObjectSink, ResolvedObject};
 
 /// #### Build Cache Info for the decode packed object
 /// There are two hashmap for object ,<br>
 /// the keys is `hash value` of The object
+///
+/// The cache is the whole content of a pack decoded in memory, it's only filled for packs read
+/// locally. A pack received by a push is decoded into an `ObjectSink` of the storage, the
+/// decode only keeps its objects in the byte bounded `ObjectCache` of the storage.
 #[derive(Default, Clone)]
 pub struct PackObjectCache {
     pub by_hash: BTreeMap<Hash, Arc<MetaData>>,
     pub by_offset: HashMap<Hash, u64>,
     pub offset_hash: BTreeMap<u64, Hash>,
+    /// bases of a thin pack, which are not in the pack but were resolved from the storage
+    pub external: BTreeMap<Hash, Arc<MetaData>>,
+    /// the length of the delta chains of the deltas, bases are left out
+    pub delta_depths: HashMap<Hash, usize>,
     pub base: u32,
     pub delta: u32,
 }
@@ -33,6 +45,8 @@ impl PackObjectCache {
         self.by_hash.clear();
         self.by_offset.clear();
         self.offset_hash.clear();
+        self.external.clear();
+        self.delta_depths.clear();
     }
 
     pub fn offset_object(&mut self, offset: u64) -> Option<&mut Arc<MetaData>> {
@@ -41,7 +55,37 @@ impl PackObjectCache {
         self.by_hash.get_mut(_hash)
     }
 
+    /// the length of the delta chain leading to `hash`, 0 for a base
+    pub fn delta_depth(&self, hash: &Hash) -> usize {
+        self.delta_depths.get(hash).copied().unwrap_or(0)
+    }
+
+    pub fn set_delta_depth(&mut self, hash: Hash, depth: usize) {
+        self.delta_depths.insert(hash, depth);
+    }
+
     pub fn hash_object(&mut self, hash: Hash) -> Option<&Arc<MetaData>> {
-        self.by_hash.get(&hash)
+        self.by_hash.get(&hash).or_else(|| self.external.get(&hash))
+    }
+}
+
+/// Keeps every object of the pack, the bases of a thin pack go to `external`.
+#[async_trait]
+impl ObjectSink for PackObjectCache {
+    async fn accept(&mut self, objects: Vec<ResolvedObject>) -> Result<(), GitError> {
+        for resolved in objects {
+            let Some(offset) = resolved.offset else {
+                self.external.insert(resolved.object.id, resolved.object);
+                continue;
+            };
+            if resolved.depth == 0 {
+                self.base += 1;
+            } else {
+                self.delta += 1;
+                self.set_delta_depth(resolved.object.id, resolved.depth);
+            }
+            self.update(resolved.object, offset);
+        }
+        Ok(())
     }
 }
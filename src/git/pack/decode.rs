@@ -11,7 +11,6 @@ use colored::Colorize;
 use obj::base::ObjectClass;
 use obj::base::{blob, commit, tag, tree};
 use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
-use rayon::ThreadPoolBuilder;
 
 use crate::git::errors::GitError;
 use crate::git::hash::Hash;
@@ -38,18 +37,18 @@ pub struct ObjDecodedMap {
 
 //在解析完object后执行的进一步的解码过程
 impl ObjDecodedMap {
-    /// 通过cache对不同结构进行进一步解析
+    /// 通过cache对不同结构进行进一步解析, a malformed object is an error
     #[allow(unused)]
-    pub fn update_from_cache(&mut self, cache: &PackObjectCache) {
-        let builder = ThreadPoolBuilder::new().num_threads(8);
-        let pool = builder.build().unwrap();
+    pub fn update_from_cache(&mut self, cache: &PackObjectCache) -> Result<(), GitError> {
         let mut blobs: Arc<RwLock<Vec<Blob>>> = Default::default();
         let mut commits: Arc<RwLock<Vec<Commit>>> = Default::default();
         let mut trees: Arc<RwLock<Vec<Tree>>> = Default::default();
         let mut tags: Arc<RwLock<Vec<Tag>>> = Default::default();
 
-        pool.install(|| {
-            cache.by_hash.par_iter().for_each(|(key, value)| {
+        cache
+            .by_hash
+            .par_iter()
+            .try_for_each(|(key, value)| -> Result<(), GitError> {
                 let metadata = value.clone();
                 match value.t {
                     // 交给各自的new函数,通过metadata来解码
@@ -57,22 +56,23 @@ impl ObjDecodedMap {
                         blobs.write().unwrap().push(Blob::new(metadata));
                     }
                     ObjectType::Commit => {
-                        commits.write().unwrap().push(Commit::new(metadata));
+                        commits.write().unwrap().push(Commit::try_new(metadata)?);
                     }
                     ObjectType::Tag => {
-                        tags.write().unwrap().push(Tag::new(metadata));
+                        tags.write().unwrap().push(Tag::try_new(metadata)?);
                     }
                     ObjectType::Tree => {
-                        trees.write().unwrap().push(Tree::new(metadata));
+                        trees.write().unwrap().push(Tree::try_new(metadata)?);
                     }
                     _ => panic!("src/git/pack/decode.rs: 33 invalid type in encoded metadata"),
                 }
-            });
-        });
+                Ok(())
+            })?;
         self.blobs = blobs.read().unwrap().to_vec();
         self.commits = commits.read().unwrap().to_vec();
         self.trees = trees.read().unwrap().to_vec();
         self.tags = tags.read().unwrap().to_vec();
+        Ok(())
 
         // for (key, value) in cache.by_hash.iter() {
         //     let metadata = MetaData::new(value.t, &value.data);
@@ -220,7 +220,7 @@ mod tests {
             decoded_pack.signature.to_plain_str()
         );
         let mut result = ObjDecodedMap::default();
-        result.update_from_cache(&decoded_pack.result);
+        result.update_from_cache(&decoded_pack.result).unwrap();
         result.check_completeness().unwrap();
         result.print_vec();
     }
@@ -2,6 +2,7 @@
 //!
 //!
 //!
+use std::collections::{HashMap, VecDeque};
 use std::fs::File;
 use std::io::Write;
 use std::path::PathBuf;
@@ -11,14 +12,16 @@ use bstr::ByteSlice;
 
 use crate::git::errors::GitError;
 use crate::git::hash::Hash;
-use crate::git::object::diff::DeltaDiff;
+use crate::git::object::diff::{DeltaIndex, DeltaOptions};
 use crate::git::object::metadata::MetaData;
 use crate::git::object::types::ObjectType;
 use crate::git::pack::decode::ObjDecodedMap;
 use crate::git::pack::Pack;
 use crate::git::utils;
 
-const SLIDING_WINDOW: i32 = 10;
+const SLIDING_WINDOW: usize = 10;
+/// the longest chain of offset deltas written, as git does
+const MAX_DELTA_DEPTH: usize = 50;
 
 ///
 /// Pack类的encode函数，将解析出的pack或其他途径生成的pack生成对应的文件
@@ -80,10 +83,16 @@ impl Pack {
                     offset = result.len() as u64;
                 }
             }
+            // the bases of a thin pack are added back, the pack is complete on its own
             None => {
-                self.number_of_objects = self.result.by_hash.len();
+                self.number_of_objects = self.result.by_hash.len() + self.result.external.len();
                 result = self.encode_header();
-                for (key, value) in self.result.by_hash.iter() {
+                for value in self
+                    .result
+                    .by_hash
+                    .values()
+                    .chain(self.result.external.values())
+                {
                     result.append(&mut value.convert_to_vec().unwrap());
                 }
             }
@@ -93,6 +102,24 @@ impl Pack {
         result
     }
 
+    /// Encode `objects` as a thin pack: an object with a base in `bases` is sent as a ref
+    /// delta against it when that saves space. The bases are objects the receiver has, they
+    /// are left out of the pack.
+    pub fn encode_thin(
+        &mut self,
+        objects: Vec<MetaData>,
+        bases: &HashMap<Hash, MetaData>,
+    ) -> Vec<u8> {
+        let objects = objects
+            .into_iter()
+            .map(|object| match bases.get(&object.id) {
+                Some(base) => ref_delta(base, object, &DeltaOptions::default()),
+                None => object,
+            })
+            .collect();
+        self.encode(Some(objects))
+    }
+
     /// 仅支持offset delta
     /// 一次通过metadata的完整data输出
     /// 从decode的 `vec_sliding_window` 来
@@ -104,47 +131,14 @@ impl Pack {
         let mut code_meta = vec![];
         assert_eq!(result.len(), 12);
 
-        let mut offset: Vec<u64> = vec![]; //记录已完成的metadata的offset
-
-        for i in 0.._pack.number_of_objects as i32 {
-            let mut new_meta = meta_vec[i as usize].clone();
-            let mut best_j: i32 = 11;
-            let mut best_ssam_rate: f64 = 0.0;
-            for j in 1..SLIDING_WINDOW {
-                if i - j < 0 {
-                    break;
-                }
-                let _base = meta_vec[(i - j) as usize].clone();
-                // 若两个对象类型不相同则不进行delta
-                if new_meta.t != _base.t {
-                    break;
-                }
-                let diff = DeltaDiff::new(_base.clone(), new_meta.clone());
-                let _rate = diff.get_ssam_rate();
-                if (_rate > best_ssam_rate) && _rate > 0.5 {
-                    best_ssam_rate = _rate;
-                    best_j = j;
-                }
-            }
-
-            let mut final_meta = new_meta.clone();
-            if best_j != 11 {
-                let _base = meta_vec[(i - best_j) as usize].clone();
-                let diff = DeltaDiff::new(_base.clone(), new_meta.clone());
-                let zlib_data = diff.get_delta_metadata();
-                let offset_head = utils::write_offset_encoding(
-                    result.len() as u64 - offset[(i - best_j) as usize],
-                );
-                final_meta.change_to_delta(ObjectType::OffsetDelta, zlib_data, offset_head);
-            }
-            code_meta.push(final_meta.clone());
-            // TODO:update the offset and write
-            offset.push(result.len() as u64);
+        let mut window = DeltaWindow::new(DeltaOptions::default());
+        for new_meta in meta_vec {
+            let offset = result.len() as u64;
+            let delta = window.delta(&new_meta, offset);
+            let final_meta = delta.as_ref().unwrap_or(&new_meta);
             result.append(&mut final_meta.convert_to_vec().unwrap());
-            println!();
-            println!("Hash :{}", final_meta.id);
-            println!("type: {}", final_meta.t);
-            println!("Offset: {}", offset.last().unwrap());
+            code_meta.push(final_meta.clone());
+            window.push(new_meta, offset);
         }
         let mut _hash = _pack.append_hash_signature(&result);
         result.append(&mut _hash);
@@ -332,6 +326,87 @@ impl Pack {
     }
 }
 
+/// `object` as a delta against `base`, or unchanged if the delta isn't under half of it.
+pub(crate) fn ref_delta(base: &MetaData, object: MetaData, options: &DeltaOptions) -> MetaData {
+    if base.t != object.t
+        || object.data.is_empty()
+        || !options.fits(object.size)
+        || !options.fits(base.size)
+    {
+        return object;
+    }
+    let Some(delta) = DeltaIndex::new(&base.data, options).delta(&object.data, object.size / 2)
+    else {
+        return object;
+    };
+    let mut object = object;
+    object.change_to_delta(ObjectType::HashDelta, delta, base.id.0.to_vec());
+    object
+}
+
+/// The last objects written to a pack. An object is written as an offset delta against the one
+/// of them giving the smallest delta, if it's under half of the object.
+pub(crate) struct DeltaWindow {
+    options: DeltaOptions,
+    /// the offset, type and delta depth of the objects indexed, the latest last
+    bases: VecDeque<(u64, ObjectType, usize, DeltaIndex<'static>)>,
+    /// the delta depth of the object searched last
+    depth: usize,
+}
+
+impl DeltaWindow {
+    pub(crate) fn new(options: DeltaOptions) -> DeltaWindow {
+        DeltaWindow {
+            options,
+            bases: VecDeque::with_capacity(SLIDING_WINDOW),
+            depth: 0,
+        }
+    }
+
+    /// `object` as an offset delta to be written at `offset`, None if it's better sent whole.
+    pub(crate) fn delta(&mut self, object: &MetaData, offset: u64) -> Option<MetaData> {
+        self.depth = 0;
+        if object.data.is_empty() || !self.options.fits(object.size) {
+            return None;
+        }
+        let mut best: Option<(u64, usize, Vec<u8>)> = None;
+        for (base_offset, t, depth, index) in self.bases.iter().rev() {
+            if *t != object.t || *depth >= MAX_DELTA_DEPTH {
+                continue;
+            }
+            let max_size = best
+                .as_ref()
+                .map_or(object.size / 2, |(_, _, delta)| delta.len() - 1);
+            if let Some(delta) = index.delta(&object.data, max_size) {
+                best = Some((*base_offset, *depth, delta));
+            }
+        }
+        let (base_offset, depth, delta) = best?;
+        self.depth = depth + 1;
+        Some(MetaData {
+            t: ObjectType::OffsetDelta,
+            h: object.h,
+            id: object.id,
+            size: delta.len(),
+            data: delta,
+            delta_header: utils::write_offset_encoding(offset - base_offset),
+        })
+    }
+
+    /// Index `object`, written at `offset` right after it was searched, as a base of the next
+    /// objects. The oldest base leaves a full window.
+    pub(crate) fn push(&mut self, object: MetaData, offset: u64) {
+        if !self.options.fits(object.size) {
+            return;
+        }
+        if self.bases.len() == SLIDING_WINDOW {
+            self.bases.pop_front();
+        }
+        let index = DeltaIndex::new(object.data, &self.options);
+        self.bases.push_back((offset, object.t, self.depth, index));
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::io::Write;
@@ -339,7 +414,13 @@ mod tests {
     use bstr::ByteSlice;
     use tokio_test::block_on;
 
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use crate::git::object::metadata::MetaData;
+    use crate::git::object::types::ObjectType;
     use crate::git::pack::{decode::ObjDecodedMap, Pack};
+    use crate::gust::driver::database::mysql::storage::MysqlStorage;
 
     const TEST_DIR: &str = "./test_dir";
 
@@ -363,7 +444,7 @@ mod tests {
             "./resources/test2/pack-8c81e90db37ef77494efe4f31daddad8b494e099.pack",
         ));
         let mut map = ObjDecodedMap::default();
-        map.update_from_cache(&decoded_pack.get_cache());
+        map.update_from_cache(&decoded_pack.get_cache()).unwrap();
         Pack::write(&mut map, TEST_DIR).unwrap();
 
         Pack::decode_file("./test_dir/pack-83df56e42ca705892f7fd64f96ecb9870b5c5ed8.pack");
@@ -379,8 +460,8 @@ mod tests {
         ));
 
         let mut map = ObjDecodedMap::default();
-        map.update_from_cache(&pack_1.get_cache());
-        map.update_from_cache(&pack_2.get_cache());
+        map.update_from_cache(&pack_1.get_cache()).unwrap();
+        map.update_from_cache(&pack_2.get_cache()).unwrap();
 
         Pack::write(&mut map, TEST_DIR).unwrap();
 
@@ -440,7 +521,7 @@ mod tests {
             decoded_pack.signature.to_plain_str()
         );
         let mut result = ObjDecodedMap::default();
-        result.update_from_cache(&decoded_pack.result);
+        result.update_from_cache(&decoded_pack.result).unwrap();
         result.check_completeness().unwrap();
         let meta_vec = result.vec_sliding_window();
         let (_pack, data_write) = Pack::encode_delta(meta_vec);
@@ -459,6 +540,36 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_thin_pack_round_trip() {
+        let content = "fn main() {\n    println!(\"hello\");\n}\n".repeat(20);
+        let base = MetaData::new(ObjectType::Blob, &content.as_bytes().to_vec());
+        let changed = content.replacen("hello", "hello, world", 1);
+        let object = MetaData::new(ObjectType::Blob, &changed.as_bytes().to_vec());
+        let bases = HashMap::from([(object.id, base.clone())]);
+        let data = Pack::default().encode_thin(vec![object.clone()], &bases);
+        // a ref delta right after the header
+        assert_eq!(7, (data[12] >> 4) & 0x7);
+
+        let path = std::env::temp_dir().join(format!("thin-{}.pack", object.id.to_plain_str()));
+        std::fs::write(&path, data).unwrap();
+        // the receiver has the base
+        let storage = MysqlStorage::default();
+        storage.cache.insert(Arc::new(base.clone()));
+        let mut pack = block_on(Pack::decode(
+            &mut std::fs::File::open(&path).unwrap(),
+            &storage,
+        ))
+        .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(object.data, pack.result.by_hash[&object.id].data);
+        assert!(pack.result.external.contains_key(&base.id));
+
+        // the fixed pack holds the base
+        pack.encode(None);
+        assert_eq!(2, pack.get_object_number());
+    }
+
     // #[test]
     // fn test_vec(){
     //     let mut arr = vec! [1,2,3,4,5];
@@ -0,0 +1,134 @@
+//!
+//! Limits of pack decoding, a pack received from a client is untrusted: the sizes it declares
+//! are checked before anything is allocated for them.
+//!
+
+use crate::git::errors::GitError;
+
+/// The bounds of the objects of a pack.
+#[derive(Debug, Clone)]
+pub struct PackLimits {
+    /// the largest object once inflated, or resolved for a delta
+    pub max_object_size: usize,
+    /// the longest chain of deltas leading to an object
+    pub max_delta_depth: usize,
+    /// the inflated size of all the objects of a pack per byte of the pack. A pack may always
+    /// inflate to `max_object_size`, the delta of a small change to a large file is tiny.
+    pub max_inflate_ratio: u64,
+    /// the most objects in a pack
+    pub max_objects: usize,
+}
+
+impl Default for PackLimits {
+    fn default() -> Self {
+        PackLimits {
+            max_object_size: 512 * 1024 * 1024,
+            max_delta_depth: 4095,
+            max_inflate_ratio: 1000,
+            max_objects: 10_000_000,
+        }
+    }
+}
+
+impl PackLimits {
+    /// Check the object count of the header of a pack of `pack_len` bytes, an object takes
+    /// at least 2 bytes after the 12 bytes of the header.
+    pub fn check_object_count(&self, count: usize, pack_len: u64) -> Result<(), GitError> {
+        if count > self.max_objects {
+            return Err(GitError::PackLimitExceeded(format!(
+                "limit of {} objects with {} objects",
+                self.max_objects, count
+            )));
+        }
+        if count as u64 * 2 > pack_len.saturating_sub(32) {
+            return Err(GitError::InvalidPackFile(format!(
+                "{} objects can't fit in {} bytes",
+                count, pack_len
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn check_delta_depth(&self, depth: usize) -> Result<(), GitError> {
+        if depth > self.max_delta_depth {
+            return Err(GitError::PackLimitExceeded(format!(
+                "delta depth limit of {}",
+                self.max_delta_depth
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// What a decode may still inflate.
+#[derive(Debug, Clone)]
+pub struct DecodeBudget {
+    pub limits: PackLimits,
+    /// bytes left to inflate
+    remaining: u64,
+}
+
+impl DecodeBudget {
+    pub fn new(limits: PackLimits, pack_len: u64) -> DecodeBudget {
+        let remaining = limits
+            .max_inflate_ratio
+            .saturating_mul(pack_len)
+            .max(limits.max_object_size as u64);
+        DecodeBudget { limits, remaining }
+    }
+
+    /// Take `size` bytes of the budget for an object, before it's inflated.
+    pub fn charge(&mut self, size: usize) -> Result<(), GitError> {
+        if size > self.limits.max_object_size {
+            return Err(GitError::PackLimitExceeded(format!(
+                "object size limit of {} bytes with an object of {} bytes",
+                self.limits.max_object_size, size
+            )));
+        }
+        self.remaining = self.remaining.checked_sub(size as u64).ok_or_else(|| {
+            GitError::PackLimitExceeded(format!(
+                "inflate ratio limit of {}",
+                self.limits.max_inflate_ratio
+            ))
+        })?;
+        Ok(())
+    }
+
+    pub fn check_delta_depth(&self, depth: usize) -> Result<(), GitError> {
+        self.limits.check_delta_depth(depth)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DecodeBudget, PackLimits};
+
+    #[test]
+    fn test_decode_budget() {
+        let limits = PackLimits {
+            max_object_size: 100,
+            max_delta_depth: 2,
+            max_inflate_ratio: 10,
+            max_objects: 3,
+        };
+        // a small pack may still inflate to one large object
+        let mut budget = DecodeBudget::new(limits.clone(), 4);
+        budget.charge(60).unwrap();
+        budget.charge(40).unwrap();
+        assert!(budget.charge(1).is_err());
+
+        let mut budget = DecodeBudget::new(limits.clone(), 50);
+        assert!(budget.charge(101).is_err());
+        for _ in 0..5 {
+            budget.charge(100).unwrap();
+        }
+        assert!(budget.charge(1).is_err());
+
+        assert!(budget.check_delta_depth(2).is_ok());
+        assert!(budget.check_delta_depth(3).is_err());
+
+        assert!(limits.check_object_count(3, 100).is_ok());
+        assert!(limits.check_object_count(4, 100).is_err());
+        assert!(limits.check_object_count(3, 36).is_err());
+    }
+}
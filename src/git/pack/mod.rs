@@ -1,7 +1,5 @@
 //!Encode and Decode The Pack File ,which is in the dir:`.git/object/pack/*.pack`
 //!
-use std::convert::TryFrom;
-use std::convert::TryInto;
 use std::fs::File;
 use std::io::Read;
 use std::path::Path;
@@ -10,19 +8,25 @@ use std::sync::Arc;
 
 use crate::git::errors::GitError;
 use crate::git::hash::Hash;
-use crate::git::idx::Idx;
-use crate::git::object::delta::*;
-use crate::git::object::metadata::MetaData;
+use crate::git::idx::{Idx, IdxItem};
 use crate::git::pack::cache::PackObjectCache;
+use crate::git::pack::limits::DecodeBudget;
+use crate::git::pack::reader::{ObjectReader, PackMap};
+use crate::git::pack::resolve::ObjectSink;
 use crate::git::utils;
 use crate::gust::driver::database::mysql::storage::MysqlStorage;
 use crate::gust::driver::ObjectStorage;
-use async_recursion::async_recursion;
+use crc::{Crc, CRC_32_ISO_HDLC};
+use sha1::{Digest, Sha1};
 
 pub mod cache;
 pub mod decode;
 pub mod encode;
+pub mod limits;
 pub mod multidecode;
+pub mod reader;
+pub mod resolve;
+pub mod writer;
 
 /// ### Pack文件结构<br>
 ///  `head`: always = "PACK" <br>
@@ -47,42 +51,59 @@ impl Pack {
     /// ## Decode the Pack File without the `.idx` File
     ///  - in: pack_file: &mut File
     ///  - out: The `Pack` Struct
+    ///
+    /// The pack is untrusted, it's decoded within the limits of `storage` and a malformed
+    /// pack is an error.
     #[allow(unused)]
     pub async fn decode<T: ObjectStorage>(
         pack_file: &mut File,
         storage: &T,
     ) -> Result<Self, GitError> {
-        // Check the Header of Pack File
-        let mut _pack = Self::check_header(pack_file)?;
-
-        // Init the cache for follow object parse
         let mut cache = PackObjectCache::default();
-
-        for i in 0.._pack.number_of_objects {
-            if i % 1000 == 0 {
-                tracing::info!(
-                    "Unpacking: Idx/Total:{}/{}, Hash/Offset Map Size:{}/{}, Obj/Delta count, {}/{}",
-                    i,
-                    _pack.number_of_objects,
-                    cache.by_hash.len(),
-                    cache.by_offset.len(),
-                    cache.base,
-                    cache.delta,
-                );
-            }
-            //update offset of the Object
-            let offset = utils::get_offset(pack_file).unwrap();
-            //Get the next Object by the Pack::next_object() func
-            let object = Pack::next_object(pack_file, offset, &mut cache, storage).await?;
-            // Larger offsets would require a version-2 pack index
-            let offset = u32::try_from(offset)
-                .map_err(|_| GitError::InvalidObjectInfo(format!("Packfile is too large")))
-                .unwrap();
-        }
+        let mut _pack = Pack::decode_into(pack_file, storage, &mut cache).await?;
         _pack.result = Arc::new(cache);
-        // CheckSum sha-1
-        let _id: [u8; 20] = utils::read_bytes(pack_file).unwrap();
-        _pack.signature = Hash::from_row(&_id[..]);
+        Ok(_pack)
+    }
+
+    /// Decode the pack file like `decode`, but its objects are handed to `sink` as they're
+    /// resolved instead of kept in `result`.
+    pub async fn decode_into<T, S>(
+        pack_file: &mut File,
+        storage: &T,
+        sink: &mut S,
+    ) -> Result<Self, GitError>
+    where
+        T: ObjectStorage,
+        S: ObjectSink + ?Sized,
+    {
+        let pack_len = pack_file.metadata().map_err(utils::corrupt_pack)?.len();
+        let signature = Pack::verify_checksum(pack_file, pack_len)?;
+        utils::seek(pack_file, 0).map_err(utils::corrupt_pack)?;
+
+        // Check the Header of Pack File
+        let mut _pack = Self::check_header(pack_file)?;
+        storage
+            .pack_limits()
+            .check_object_count(_pack.number_of_objects, pack_len)?;
+        let budget = DecodeBudget::new(storage.pack_limits().clone(), pack_len);
+
+        let map = PackMap::open(pack_file).map_err(utils::corrupt_pack)?;
+        let stats = resolve::resolve_pack(
+            Arc::new(map),
+            pack_len as usize - 20,
+            _pack.number_of_objects,
+            storage,
+            budget,
+            sink,
+        )
+        .await?;
+        tracing::info!(
+            "Unpacked {} objects, {} deltas, {} bases of a thin pack",
+            _pack.number_of_objects,
+            stats.deltas,
+            stats.external
+        );
+        _pack.signature = signature;
 
         Ok(_pack)
     }
@@ -101,7 +122,7 @@ impl Pack {
         };
 
         // Get the Pack Head 4 b ,which should be the "PACK"
-        let magic = utils::read_bytes(pack_file).unwrap();
+        let magic = utils::read_bytes(pack_file).map_err(utils::corrupt_pack)?;
         if magic != *b"PACK" {
             return Err(GitError::InvalidPackHeader(format!(
                 "{},{},{},{}",
@@ -111,44 +132,131 @@ impl Pack {
         _pack.head = magic;
 
         //Get the Version Number
-        let version = utils::read_u32(pack_file).unwrap();
+        let version = utils::read_u32(pack_file).map_err(utils::corrupt_pack)?;
         if version != 2 {
             return Err(GitError::InvalidPackFile(format!("Current File")));
         }
         _pack.version = version;
 
-        let object_num = utils::read_u32(pack_file).unwrap();
+        let object_num = utils::read_u32(pack_file).map_err(utils::corrupt_pack)?;
         _pack.number_of_objects = object_num as usize;
 
         Ok(_pack)
     }
 
-    /// Decode the pack file helped by the according decoded idx file
+    /// Check the trailing checksum of the `pack_len` bytes of the pack file against its
+    /// content, which is read in chunks. Return the checksum.
+    fn verify_checksum(pack_file: &mut File, pack_len: u64) -> Result<Hash, GitError> {
+        if pack_len < 32 {
+            return Err(GitError::InvalidPackFile(format!(
+                "{} bytes is too short for a pack",
+                pack_len
+            )));
+        }
+        utils::seek(pack_file, 0).map_err(utils::corrupt_pack)?;
+        let mut content = Read::take(&mut *pack_file, pack_len - 20);
+        let mut sha1 = Sha1::new();
+        let mut chunk = vec![0; 64 * 1024];
+        loop {
+            let n = content.read(&mut chunk).map_err(utils::corrupt_pack)?;
+            if n == 0 {
+                break;
+            }
+            sha1.update(&chunk[..n]);
+        }
+        let signature = utils::read_hash(pack_file).map_err(utils::corrupt_pack)?;
+        if Hash::from_row(&sha1.finalize()) != signature {
+            return Err(GitError::InvalidPackFile(format!(
+                "checksum mismatch, expected {}",
+                signature.to_plain_str()
+            )));
+        }
+        Ok(signature)
+    }
+
+    /// Decode the pack file helped by the according decoded idx file, the pack must match the
+    /// checksum and the CRC32 of every object recorded by the idx, and every object must hash
+    /// to its id. The objects are read one by one with an `ObjectReader`, only the bases of its
+    /// bounded cache are kept. The pack is decoded within the limits of `storage`.
     #[allow(unused)]
-    pub async fn decode_by_idx(idx: &mut Idx, pack_file: &mut File) -> Result<Self, GitError> {
+    pub async fn decode_by_idx<T: ObjectStorage>(
+        idx: Idx,
+        pack_file: &mut File,
+        storage: &T,
+    ) -> Result<Self, GitError> {
+        let pack_len = pack_file.metadata().map_err(utils::corrupt_pack)?.len();
+        let signature = Pack::verify_checksum(pack_file, pack_len)?;
+        if signature != idx.pack_signature {
+            return Err(GitError::InvalidIdxFile(format!(
+                "idx of pack {}, not {}",
+                idx.pack_signature.to_plain_str(),
+                signature.to_plain_str()
+            )));
+        }
+        utils::seek(pack_file, 0).map_err(utils::corrupt_pack)?;
         let mut _pack = Self::check_header(pack_file)?;
-        let object_num = idx.number_of_objects;
-        _pack.number_of_objects = u32::try_from(object_num)
-            .map_err(|_| GitError::InvalidObjectInfo(format!("Packfile is too large")))
-            .unwrap() as usize;
-        let mut cache = PackObjectCache::default();
-
-        for idx_item in idx.idx_items.iter() {
-            Pack::next_object(
-                pack_file,
-                idx_item.offset.try_into().unwrap(),
-                &mut cache,
-                &MysqlStorage::default(),
-            )
-            .await
-            .unwrap();
+        let limits = storage.pack_limits().clone();
+        limits.check_object_count(idx.number_of_objects, pack_len)?;
+        _pack.number_of_objects = idx.number_of_objects;
+        Pack::verify_crc(&idx, pack_file, pack_len)?;
+
+        let reader = ObjectReader::new(pack_file, idx, limits)?;
+        for (id, _) in reader.objects() {
+            let object = reader.read(id)?;
+            if object.id != *id {
+                return Err(GitError::InvalidIdxFile(format!(
+                    "object {} hashes to {}",
+                    id.to_plain_str(),
+                    object.id.to_plain_str()
+                )));
+            }
         }
-        let mut result = decode::ObjDecodedMap::default();
-        result.update_from_cache(&mut cache);
-        _pack.signature = idx.pack_signature.clone();
+        _pack.signature = signature;
         Ok(_pack)
     }
 
+    /// Check the CRC32 of the packed data of every object of `idx`, an object ends where the
+    /// next one starts.
+    fn verify_crc(idx: &Idx, pack_file: &mut File, pack_len: u64) -> Result<(), GitError> {
+        let mut items: Vec<&IdxItem> = idx.idx_items.iter().collect();
+        items.sort_by_key(|item| item.offset);
+        let ends = items
+            .iter()
+            .skip(1)
+            .map(|item| item.offset as u64)
+            .chain([pack_len - 20]);
+        let crc = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+        for (item, end) in items.iter().zip(ends) {
+            let start = item.offset as u64;
+            if start < 12 || end <= start {
+                return Err(GitError::InvalidIdxFile(format!(
+                    "invalid offset {} of {}",
+                    start,
+                    item.id.to_plain_str()
+                )));
+            }
+            utils::seek(pack_file, start).map_err(utils::corrupt_pack)?;
+            let mut digest = crc.digest();
+            let mut data = Read::take(&mut *pack_file, end - start);
+            let mut chunk = vec![0; 64 * 1024];
+            loop {
+                let n = data.read(&mut chunk).map_err(utils::corrupt_pack)?;
+                if n == 0 {
+                    break;
+                }
+                digest.update(&chunk[..n]);
+            }
+            let actual = format!("{:08x}", digest.finalize());
+            if actual != item.crc32 {
+                return Err(GitError::InvalidPackFile(format!(
+                    "CRC mismatch of object {}",
+                    item.id.to_plain_str()
+                )));
+            }
+        }
+        Ok(())
+    }
+
     /// Decode the object info from the pack file, <br>
     /// but we don't decode the object  further info ,<br>
     /// Instead, it stores **all un decoded object information** to a `Vec<u8>`. <br>
@@ -162,84 +270,6 @@ impl Pack {
         raw_pack.signature = _hash;
         (raw_pack, raw_info)
     }
-    /// Get the Object from File by the Give Offset<br>
-    /// By the way , the cache can hold the fount object
-    #[async_recursion]
-    pub async fn next_object<T: ObjectStorage>(
-        pack_file: &mut File,
-        offset: u64,
-        cache: &mut PackObjectCache,
-        storage: &T,
-    ) -> Result<Arc<MetaData>, GitError> {
-        use super::object::types::ObjectType;
-        utils::seek(pack_file, offset).unwrap();
-        let (type_num, size) = utils::read_type_and_size(pack_file).unwrap();
-        //Get the Object according to the Types Enum
-        let object = match type_num {
-            // Undelta representation
-            1..=4 => utils::read_zlib_stream_exact(pack_file, |decompressed| {
-                let mut contents = Vec::with_capacity(size);
-                decompressed.read_to_end(&mut contents).unwrap();
-                if contents.len() != size {
-                    return Err(GitError::InvalidObjectInfo(format!(
-                        "Incorrect object size"
-                    )));
-                }
-                cache.base += 1;
-                Ok(MetaData::new(ObjectType::number_type(type_num), &contents))
-            }),
-            // Delta; base object is at an offset in the same packfile
-            6 => {
-                let delta_offset = utils::read_offset_encoding(pack_file).unwrap();
-                let base_offset = offset.checked_sub(delta_offset).ok_or_else(|| {
-                    GitError::InvalidObjectInfo(format!("Invalid OffsetDelta offset"))
-                })?;
-                let offset = utils::get_offset(pack_file).unwrap();
-
-                let base_object = if let Some(object) = cache.offset_object(base_offset) {
-                    Arc::clone(object)
-                } else {
-                    //递归调用 找出base object
-                    Pack::next_object(pack_file, base_offset, cache, storage).await?
-                };
-                utils::seek(pack_file, offset).unwrap();
-                let base_obj = base_object.as_ref();
-                let objs = apply_delta(pack_file, base_obj)?;
-                cache.delta += 1;
-                Ok(objs)
-            }
-            // Delta; base object is given by a hash outside the packfile
-            //TODO : This Type need to be completed ，对应多文件的todo
-            7 => {
-                let hash = utils::read_hash(pack_file).unwrap();
-                //let object;
-                let base_object = if let Some(object) = cache.hash_object(hash) {
-                    object.to_owned()
-                } else {
-                    // object = read_object(hash)?;
-                    // &object
-                    Arc::new(storage.get_hash_object(&hash.to_plain_str()).await.unwrap())
-                };
-                apply_delta(pack_file, &base_object)
-            }
-            _ => {
-                return Err(GitError::InvalidObjectType(
-                    ObjectType::number_type(type_num).to_string(),
-                ));
-            }
-        }?;
-
-        // //Debug Code: Print the hash & type of the parsed object
-        // match PackObjectType::type_number2_type(object_type) {
-        //     Some(a) => println!("Hash:{} \t Types: {:?}",object.hash(), a),
-        //     None =>{},
-        // }
-
-        let obj = Arc::new(object);
-        cache.update(Arc::clone(&obj), offset);
-        Ok(obj)
-    }
-
     pub fn get_object_number(&self) -> usize {
         return self.number_of_objects as usize;
     }
@@ -285,10 +315,186 @@ mod tests {
     use std::path::Path;
     use tokio_test::block_on;
 
+    use std::io::Write;
+    use std::sync::Arc;
+
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+
+    use crate::git::errors::GitError;
+    use crate::git::hash::Hash;
     use crate::git::idx::Idx;
+    use crate::git::pack::limits::PackLimits;
+    use crate::git::utils;
+    use crate::gust::driver::database::mysql::storage::MysqlStorage;
 
     use super::Pack;
 
+    fn zlib(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// the header of a pack entry, type and inflated size
+    fn entry_header(type_num: u8, size: usize) -> Vec<u8> {
+        let mut header = vec![(type_num << 4) | (size & 0xf) as u8];
+        let mut size = size >> 4;
+        while size > 0 {
+            *header.last_mut().unwrap() |= 0x80;
+            header.push((size & 0x7f) as u8);
+            size >>= 7;
+        }
+        header
+    }
+
+    /// A pack of `count` objects with the given entries and a valid trailer, the offsets of
+    /// the entries are returned too.
+    fn build_pack(count: u32, entries: &[Vec<u8>]) -> (Vec<u8>, Vec<u64>) {
+        let mut pack = b"PACK".to_vec();
+        pack.extend(2u32.to_be_bytes());
+        pack.extend(count.to_be_bytes());
+        let mut offsets = Vec::new();
+        for entry in entries {
+            offsets.push(pack.len() as u64);
+            pack.extend(entry);
+        }
+        let trailer = Hash::new(&pack);
+        pack.extend(trailer.0);
+        (pack, offsets)
+    }
+
+    fn blob_entry(data: &[u8]) -> Vec<u8> {
+        let mut entry = entry_header(3, data.len());
+        entry.extend(zlib(data));
+        entry
+    }
+
+    /// an OFS_DELTA entry at `offset` against the base at `base_offset`
+    fn ofs_delta_entry(offset: u64, base_offset: u64, delta: &[u8]) -> Vec<u8> {
+        let mut entry = entry_header(6, delta.len());
+        entry.extend(utils::write_offset_encoding(offset - base_offset));
+        entry.extend(zlib(delta));
+        entry
+    }
+
+    fn decode(pack: &[u8], limits: PackLimits) -> Result<Pack, GitError> {
+        let path = std::env::temp_dir().join(format!(
+            "gust-test-{}-{}.pack",
+            std::process::id(),
+            Hash::new(&pack.to_vec()).to_plain_str()
+        ));
+        std::fs::write(&path, pack).unwrap();
+        let storage = MysqlStorage {
+            pack_limits: Arc::new(limits),
+            ..Default::default()
+        };
+        let result = block_on(Pack::decode(&mut File::open(&path).unwrap(), &storage));
+        std::fs::remove_file(path).unwrap();
+        result
+    }
+
+    /// "hello world" and a delta of it to "hello!"
+    fn delta_pack() -> Vec<u8> {
+        let base = blob_entry(b"hello world");
+        let delta = [11, 6, 0x90, 5, 1, b'!'];
+        let delta = ofs_delta_entry(12 + base.len() as u64, 12, &delta);
+        build_pack(2, &[base, delta]).0
+    }
+
+    #[test]
+    fn test_decode_delta_pack() {
+        let pack = decode(&delta_pack(), PackLimits::default()).unwrap();
+        let data: Vec<&[u8]> = pack
+            .result
+            .by_hash
+            .values()
+            .map(|meta| meta.data.as_slice())
+            .collect();
+        assert_eq!(2, data.len());
+        assert!(data.contains(&&b"hello!"[..]));
+    }
+
+    #[test]
+    fn test_decode_malformed_packs() {
+        let mut corrupt = delta_pack();
+        let index = corrupt.len() - 30;
+        corrupt[index] ^= 0xff;
+        assert!(decode(&corrupt, PackLimits::default()).is_err());
+
+        // more objects declared than found, then more data than declared
+        let (truncated, _) = build_pack(3, &[blob_entry(b"a"), blob_entry(b"b")]);
+        assert!(decode(&truncated, PackLimits::default()).is_err());
+        let (garbage, _) = build_pack(1, &[blob_entry(b"a"), vec![0; 8]]);
+        assert!(decode(&garbage, PackLimits::default()).is_err());
+
+        // a size larger than the data
+        let mut lying = entry_header(3, 100);
+        lying.extend(zlib(b"short"));
+        assert!(decode(&build_pack(1, &[lying]).0, PackLimits::default()).is_err());
+
+        // a delta based on itself
+        let mut own_base = entry_header(6, 6);
+        own_base.push(0);
+        own_base.extend(zlib(&[11, 6, 0x90, 5, 1, b'!']));
+        assert!(decode(&build_pack(1, &[own_base]).0, PackLimits::default()).is_err());
+
+        // a copy beyond the result size
+        let base = blob_entry(b"hello world");
+        let delta = ofs_delta_entry(12 + base.len() as u64, 12, &[11, 2, 0x90, 5]);
+        assert!(decode(&build_pack(2, &[base, delta]).0, PackLimits::default()).is_err());
+    }
+
+    #[test]
+    fn test_decode_limits() {
+        let limits = PackLimits {
+            max_object_size: 8,
+            ..Default::default()
+        };
+        let (pack, _) = build_pack(1, &[blob_entry(b"more than 8 bytes")]);
+        assert!(matches!(
+            decode(&pack, limits),
+            Err(GitError::PackLimitExceeded(_))
+        ));
+
+        // a tiny delta claiming a huge object isn't allocated
+        let base = blob_entry(b"hello world");
+        let mut bomb = vec![11];
+        bomb.extend(utils::write_size_encoding(1 << 40));
+        let delta = ofs_delta_entry(12 + base.len() as u64, 12, &bomb);
+        let (pack, _) = build_pack(2, &[base, delta]);
+        assert!(matches!(
+            decode(&pack, PackLimits::default()),
+            Err(GitError::PackLimitExceeded(_))
+        ));
+
+        // a chain of two deltas
+        let base = blob_entry(b"hello world");
+        let first = 12 + base.len() as u64;
+        let delta = ofs_delta_entry(first, 12, &[11, 6, 0x90, 5, 1, b'!']);
+        let second = first + delta.len() as u64;
+        let delta2 = ofs_delta_entry(second, first, &[6, 7, 0x90, 6, 1, b'!']);
+        let (pack, _) = build_pack(3, &[base, delta, delta2]);
+        let limits = PackLimits {
+            max_delta_depth: 1,
+            ..Default::default()
+        };
+        assert!(matches!(
+            decode(&pack, limits),
+            Err(GitError::PackLimitExceeded(_))
+        ));
+        assert!(decode(&pack, PackLimits::default()).is_ok());
+
+        let limits = PackLimits {
+            max_objects: 2,
+            ..Default::default()
+        };
+        assert!(matches!(
+            decode(&pack, limits),
+            Err(GitError::PackLimitExceeded(_))
+        ));
+    }
+
     /// Test the pack File decode standalone
     #[test]
     fn test_decode_pack_file1() {
@@ -366,7 +572,12 @@ mod tests {
 
         let mut idx = Idx::default();
         idx.decode(buffer).unwrap();
-        let decoded_pack = block_on(Pack::decode_by_idx(&mut idx, &mut pack_file)).unwrap();
+        let decoded_pack = block_on(Pack::decode_by_idx(
+            idx,
+            &mut pack_file,
+            &MysqlStorage::default(),
+        ))
+        .unwrap();
         assert_eq!(*b"PACK", decoded_pack.head);
         assert_eq!(2, decoded_pack.version);
         assert_eq!(
@@ -3,15 +3,14 @@
 //!
 //!
 use std::cmp::Ordering;
-use std::convert::TryFrom;
 use std::{fs::File, sync::Arc};
 
 use crate::git::errors::GitError;
-use crate::git::hash::Hash;
 use crate::git::pack::cache::PackObjectCache;
+use crate::git::pack::reader::ObjectReader;
 use crate::git::pack::Pack;
 use crate::git::utils;
-use crate::gust::driver::database::mysql::storage::MysqlStorage;
+use crate::gust::driver::ObjectStorage;
 
 impl Eq for Pack {}
 
@@ -44,34 +43,28 @@ impl PartialEq for Pack {
 }
 
 impl Pack {
+    /// Read the objects of the pack into `cache` with the `.idx` next to it, within the limits
+    /// of `storage`.
     #[allow(unused)]
-    pub async fn decode_with_cache(&self, cache: &mut PackObjectCache) -> Result<Self, GitError> {
-        let mut pack_file = File::open(self.pack_file.clone()).unwrap();
+    pub async fn decode_with_cache<T: ObjectStorage>(
+        &self,
+        cache: &mut PackObjectCache,
+        storage: &T,
+    ) -> Result<Self, GitError> {
+        let mut pack_file = File::open(&self.pack_file).map_err(utils::corrupt_pack)?;
         // Check the Header of Pack File
-        let mut _pack = Self::check_header(&mut pack_file).unwrap();
-
-        for _ in 0.._pack.number_of_objects {
-            //update offset of the Object
-            let offset = utils::get_offset(&mut pack_file).unwrap();
-            //Get the next Object by the Pack::next_object() func
-            let object = Pack::next_object(&mut pack_file, offset, cache, &MysqlStorage::default())
-                .await
-                .unwrap();
-            // Larger offsets would require a version-2 pack index
-            let offset = u32::try_from(offset)
-                .map_err(|_| GitError::InvalidObjectInfo(format!("Packfile is too large")))
-                .unwrap();
+        let mut _pack = Self::check_header(&mut pack_file)?;
+        let reader = ObjectReader::open(&self.pack_file, storage.pack_limits().clone())?;
+        for (id, offset) in reader.objects() {
+            cache.update(reader.read(id)?, offset);
         }
-
-        // CheckSum sha-1
-        let _id: [u8; 20] = utils::read_bytes(&mut pack_file).unwrap();
-        _pack.signature = Hash::from_row(&_id[..]);
+        _pack.signature = reader.signature();
         print!("{}", cache.by_hash.len());
         Ok(_pack)
     }
 
     #[allow(dead_code)]
-    pub async fn multi_decode(root: &str) -> Result<Self, GitError> {
+    pub async fn multi_decode<T: ObjectStorage>(root: &str, storage: &T) -> Result<Self, GitError> {
         let mut total_pack = Self::default();
         total_pack.number_of_objects = 0;
         let (files, _hash_vec) = utils::find_all_pack_file(root);
@@ -84,7 +77,7 @@ impl Pack {
         pack_vec.sort();
         let mut cache = PackObjectCache::default();
         for _pack_ in pack_vec.iter_mut() {
-            _pack_.decode_with_cache(&mut cache).await?;
+            _pack_.decode_with_cache(&mut cache, storage).await?;
             total_pack.number_of_objects += _pack_.number_of_objects;
         }
         total_pack.result = Arc::new(cache);
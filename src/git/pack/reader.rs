@@ -0,0 +1,413 @@
+//!
+//! Random access to the objects of a pack on disk: the pack is mapped in memory and objects
+//! are found with its `.idx`, so reading one object only inflates the object and the deltas
+//! leading to it, not the whole pack.
+//!
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, Read};
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use flate2::read::ZlibDecoder;
+use memmap2::Mmap;
+
+use crate::git::errors::GitError;
+use crate::git::hash::Hash;
+use crate::git::idx::Idx;
+use crate::git::object::delta::{apply_delta_stream, delta_result_size};
+use crate::git::object::metadata::MetaData;
+use crate::git::object::types::ObjectType;
+use crate::git::pack::limits::{DecodeBudget, PackLimits};
+use crate::git::utils;
+
+/// the bytes of resolved delta bases kept by a reader, like `core.deltaBaseCacheLimit` of git
+pub const DELTA_BASE_CACHE_LIMIT: usize = 96 * 1024 * 1024;
+
+/// The type and the inflated size of an object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ObjectHeader {
+    pub t: ObjectType,
+    pub size: usize,
+}
+
+/// Reads the objects of a pack by their id, a reader can be shared by threads.
+pub struct ObjectReader {
+    map: PackMap,
+    idx: Idx,
+    limits: PackLimits,
+    cache: Mutex<DeltaBaseCache>,
+}
+
+/// where the base of an entry is
+pub(crate) enum EntryBase {
+    Offset(u64),
+    Hash(Hash),
+}
+
+/// An entry of the pack at `offset`, its zlib data starts at `data`.
+pub(crate) struct Entry {
+    pub offset: u64,
+    pub type_num: u8,
+    pub size: usize,
+    pub base: Option<EntryBase>,
+    pub data: usize,
+}
+
+impl ObjectReader {
+    /// Open the pack at `pack_path` and the `.idx` next to it, the objects are decoded within
+    /// `limits`.
+    pub fn open(pack_path: &Path, limits: PackLimits) -> Result<ObjectReader, GitError> {
+        let mut idx_data = Vec::new();
+        File::open(pack_path.with_extension("idx"))
+            .and_then(|mut file| file.read_to_end(&mut idx_data))
+            .map_err(|err| GitError::InvalidIdxFile(err.to_string()))?;
+        let mut idx = Idx::default();
+        idx.decode(idx_data)?;
+        let file = File::open(pack_path).map_err(utils::corrupt_pack)?;
+        ObjectReader::new(&file, idx, limits)
+    }
+
+    /// A reader of the pack `file` with its decoded `idx`, the pack must match the idx.
+    pub fn new(file: &File, idx: Idx, limits: PackLimits) -> Result<ObjectReader, GitError> {
+        let map = PackMap::open(file).map_err(utils::corrupt_pack)?;
+        if map.len() < 32 {
+            return Err(GitError::InvalidPackFile(format!(
+                "{} bytes is too short for a pack",
+                map.len()
+            )));
+        }
+        if map[0..4] != *b"PACK" {
+            return Err(GitError::InvalidPackHeader(format!("{:?}", &map[0..4])));
+        }
+        let mut header = &map[4..12];
+        let version = utils::read_u32(&mut header).map_err(utils::corrupt_pack)?;
+        let count = utils::read_u32(&mut header).map_err(utils::corrupt_pack)? as usize;
+        if version != 2 {
+            return Err(GitError::InvalidPackFile(format!("version {}", version)));
+        }
+        limits.check_object_count(count, map.len() as u64)?;
+        if count != idx.number_of_objects {
+            return Err(GitError::InvalidIdxFile(format!(
+                "{} objects in the idx, {} in the pack",
+                idx.number_of_objects, count
+            )));
+        }
+        let signature = Hash::from_row(&map[map.len() - 20..]);
+        if signature != idx.pack_signature {
+            return Err(GitError::InvalidIdxFile(format!(
+                "idx of pack {}, not {}",
+                idx.pack_signature.to_plain_str(),
+                signature.to_plain_str()
+            )));
+        }
+
+        Ok(ObjectReader {
+            map,
+            idx,
+            limits,
+            cache: Mutex::new(DeltaBaseCache::new(DELTA_BASE_CACHE_LIMIT)),
+        })
+    }
+
+    /// the ids of the objects of the pack and their offset, in the order of the idx
+    pub fn objects(&self) -> impl Iterator<Item = (&Hash, u64)> {
+        self.idx
+            .idx_items
+            .iter()
+            .map(|item| (&item.id, item.offset as u64))
+    }
+
+    /// the checksum of the pack
+    pub fn signature(&self) -> Hash {
+        self.idx.pack_signature
+    }
+
+    pub fn contains(&self, id: &Hash) -> bool {
+        self.idx.find(id).is_some()
+    }
+
+    /// The type and size of the object `id`, without resolving it when it's a delta.
+    pub fn header(&self, id: &Hash) -> Result<ObjectHeader, GitError> {
+        let mut offset = self.offset(id)?;
+        if let Some(object) = self.cache.lock().unwrap().get(offset) {
+            return Ok(ObjectHeader {
+                t: object.t,
+                size: object.size,
+            });
+        }
+        let mut entry = self.entry(offset)?;
+        let size = match entry.base {
+            None => entry.size,
+            Some(_) => delta_result_size(&mut ZlibDecoder::new(&self.content()[entry.data..]))?,
+        };
+        // the type of a delta is the type of its base
+        let mut depth = 0;
+        while let Some(base) = &entry.base {
+            depth += 1;
+            self.limits.check_delta_depth(depth)?;
+            offset = match base {
+                EntryBase::Offset(offset) => *offset,
+                EntryBase::Hash(hash) => self.offset(hash)?,
+            };
+            entry = self.entry(offset)?;
+        }
+        Ok(ObjectHeader {
+            t: ObjectType::number_type(entry.type_num),
+            size,
+        })
+    }
+
+    /// Read the object `id`, resolved when it's a delta.
+    pub fn read(&self, id: &Hash) -> Result<Arc<MetaData>, GitError> {
+        let offset = self.offset(id)?;
+        self.read_at(offset)
+    }
+
+    /// Resolve the object at `offset`: the deltas leading to it are walked down to a base in
+    /// the cache or in the pack, then applied from the base up. Every base of a delta is kept
+    /// in the cache.
+    fn read_at(&self, offset: u64) -> Result<Arc<MetaData>, GitError> {
+        let mut budget = DecodeBudget::new(self.limits.clone(), self.map.len() as u64);
+        let mut chain: Vec<Entry> = Vec::new();
+        let mut current = offset;
+        let mut object = loop {
+            if let Some(object) = self.cache.lock().unwrap().get(current) {
+                break object;
+            }
+            budget.check_delta_depth(chain.len())?;
+            let entry = self.entry(current)?;
+            let base = match entry.base {
+                None => {
+                    budget.charge(entry.size)?;
+                    break Arc::new(self.inflate(&entry)?);
+                }
+                Some(EntryBase::Offset(base)) => base,
+                Some(EntryBase::Hash(hash)) => self.offset(&hash)?,
+            };
+            chain.push(entry);
+            current = base;
+        };
+
+        for entry in chain.into_iter().rev() {
+            self.cache
+                .lock()
+                .unwrap()
+                .insert(current, Arc::clone(&object));
+            let mut decompressed = ZlibDecoder::new(&self.content()[entry.data..]);
+            object = Arc::new(apply_delta_stream(
+                &mut decompressed,
+                &object,
+                entry.size,
+                &mut budget,
+            )?);
+            current = entry.offset;
+        }
+        Ok(object)
+    }
+
+    fn offset(&self, id: &Hash) -> Result<u64, GitError> {
+        self.idx
+            .find(id)
+            .map(|item| item.offset as u64)
+            .ok_or_else(|| GitError::NotFountHashValue(id.to_plain_str()))
+    }
+
+    /// the pack without its trailing checksum
+    fn content(&self) -> &[u8] {
+        &self.map[..self.map.len() - 20]
+    }
+
+    /// Read the header of the entry at `offset`.
+    fn entry(&self, offset: u64) -> Result<Entry, GitError> {
+        if offset < 12 || offset >= self.content().len() as u64 {
+            return Err(GitError::InvalidIdxFile(format!(
+                "invalid offset {}",
+                offset
+            )));
+        }
+        read_entry(self.content(), offset)
+    }
+
+    fn inflate(&self, entry: &Entry) -> Result<MetaData, GitError> {
+        inflate_entry(self.content(), entry)
+    }
+}
+
+/// Read the header of the entry at `offset` of `content`, which starts with the header of
+/// the pack.
+pub(crate) fn read_entry(content: &[u8], offset: u64) -> Result<Entry, GitError> {
+    let mut stream = &content[offset as usize..];
+    let (type_num, size) = utils::read_type_and_size(&mut stream).map_err(utils::corrupt_pack)?;
+    let base = match type_num {
+        1..=4 => None,
+        6 => {
+            let delta_offset =
+                utils::read_offset_encoding(&mut stream).map_err(utils::corrupt_pack)?;
+            let base = offset
+                .checked_sub(delta_offset)
+                .filter(|base| delta_offset > 0 && *base >= 12)
+                .ok_or_else(|| {
+                    GitError::InvalidObjectInfo(String::from("Invalid OffsetDelta offset"))
+                })?;
+            Some(EntryBase::Offset(base))
+        }
+        7 => Some(EntryBase::Hash(
+            utils::read_hash(&mut stream).map_err(utils::corrupt_pack)?,
+        )),
+        _ => return Err(GitError::InvalidObjectType(format!("type {}", type_num))),
+    };
+    Ok(Entry {
+        offset,
+        type_num,
+        size,
+        base,
+        data: content.len() - stream.len(),
+    })
+}
+
+/// Inflate the data of the undeltified `entry` of `content`.
+pub(crate) fn inflate_entry(content: &[u8], entry: &Entry) -> Result<MetaData, GitError> {
+    let decompressed = ZlibDecoder::new(&content[entry.data..]);
+    let mut contents = Vec::with_capacity(entry.size);
+    decompressed
+        .take(entry.size as u64 + 1)
+        .read_to_end(&mut contents)
+        .map_err(utils::corrupt_pack)?;
+    if contents.len() != entry.size {
+        return Err(GitError::InvalidObjectInfo(String::from(
+            "Incorrect object size",
+        )));
+    }
+    Ok(MetaData::new(
+        ObjectType::number_type(entry.type_num),
+        &contents,
+    ))
+}
+
+/// The objects resolved lately by their offset, up to `limit` bytes of data. The least
+/// recently used object is dropped first.
+struct DeltaBaseCache {
+    limit: usize,
+    size: usize,
+    tick: u64,
+    objects: HashMap<u64, (Arc<MetaData>, u64)>,
+    /// the offsets of the objects by their last use
+    uses: BTreeMap<u64, u64>,
+}
+
+impl DeltaBaseCache {
+    fn new(limit: usize) -> DeltaBaseCache {
+        DeltaBaseCache {
+            limit,
+            size: 0,
+            tick: 0,
+            objects: HashMap::new(),
+            uses: BTreeMap::new(),
+        }
+    }
+
+    fn get(&mut self, offset: u64) -> Option<Arc<MetaData>> {
+        self.tick += 1;
+        let (object, used) = self.objects.get_mut(&offset)?;
+        self.uses.remove(used);
+        *used = self.tick;
+        self.uses.insert(self.tick, offset);
+        Some(Arc::clone(object))
+    }
+
+    fn insert(&mut self, offset: u64, object: Arc<MetaData>) {
+        if object.size > self.limit || self.get(offset).is_some() {
+            return;
+        }
+        while self.size + object.size > self.limit {
+            let Some((_, oldest)) = self.uses.pop_first() else {
+                break;
+            };
+            if let Some((evicted, _)) = self.objects.remove(&oldest) {
+                self.size -= evicted.size;
+            }
+        }
+        self.size += object.size;
+        self.uses.insert(self.tick, offset);
+        self.objects.insert(offset, (object, self.tick));
+    }
+}
+
+/// A read-only map of a file in memory.
+pub(crate) struct PackMap(Mmap);
+
+impl PackMap {
+    pub fn open(file: &File) -> io::Result<PackMap> {
+        // SAFETY: the map is read only, packs are never modified once written
+        let map = unsafe { Mmap::map(file)? };
+        Ok(PackMap(map))
+    }
+}
+
+impl Deref for PackMap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use crate::git::hash::Hash;
+    use crate::git::object::metadata::MetaData;
+    use crate::git::object::types::ObjectType;
+    use crate::git::pack::limits::PackLimits;
+
+    use super::{DeltaBaseCache, ObjectReader};
+
+    #[test]
+    fn test_object_reader() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/data/test/pack-8d36a6464e1f284e5e9d06683689ee751d4b2687.pack");
+        let reader = ObjectReader::open(&path, PackLimits::default()).unwrap();
+
+        let ids: Vec<Hash> = reader.idx.idx_items.iter().map(|item| item.id).collect();
+        assert_eq!(614, ids.len());
+        for id in ids.iter().rev() {
+            assert!(reader.contains(id));
+            let header = reader.header(id).unwrap();
+            let object = reader.read(id).unwrap();
+            assert_eq!(*id, object.id);
+            assert_eq!(header.t, object.t);
+            assert_eq!(header.size, object.size);
+        }
+        let missing = Hash::new(&b"missing".to_vec());
+        assert!(!reader.contains(&missing));
+        assert!(reader.read(&missing).is_err());
+
+        let limits = PackLimits {
+            max_delta_depth: 0,
+            ..PackLimits::default()
+        };
+        let reader = ObjectReader::open(&path, limits).unwrap();
+        assert!(ids.iter().any(|id| reader.read(id).is_err()));
+    }
+
+    #[test]
+    fn test_delta_base_cache() {
+        let object = |data: &[u8]| Arc::new(MetaData::new(ObjectType::Blob, &data.to_vec()));
+        let mut cache = DeltaBaseCache::new(10);
+        cache.insert(12, object(b"1234"));
+        cache.insert(20, object(b"5678"));
+        assert!(cache.get(12).is_some());
+        // 20 is the least recently used
+        cache.insert(30, object(b"90ab"));
+        assert!(cache.get(20).is_none());
+        assert!(cache.get(12).is_some());
+        assert!(cache.get(30).is_some());
+        assert_eq!(8, cache.size);
+        cache.insert(40, object(b"too large to cache"));
+        assert!(cache.get(40).is_none());
+    }
+}
@@ -0,0 +1,525 @@
+//!
+//! Decode a received pack like `git index-pack`: a first pass records the header and the
+//! offset of every entry, then the deltas are resolved as trees rooted at their bases, the
+//! trees in parallel on the pack threads of the storage. A base is inflated once for all of
+//! its deltas. The objects are handed to an [`ObjectSink`] in batches as they're resolved, the
+//! decode itself keeps none of them.
+//!
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::io::{self, Read};
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+
+use async_trait::async_trait;
+use flate2::read::ZlibDecoder;
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::git::errors::GitError;
+use crate::git::hash::Hash;
+use crate::git::object::delta::{apply_delta_stream, delta_result_size};
+use crate::git::object::metadata::MetaData;
+use crate::git::pack::limits::{DecodeBudget, PackLimits};
+use crate::git::pack::reader::{inflate_entry, read_entry, Entry, EntryBase, PackMap};
+use crate::git::utils;
+use crate::gust::driver::object_cache::ObjectCache;
+use crate::gust::driver::ObjectStorage;
+
+/// the inflated bytes of the objects of a batch handed to the sink
+const BATCH_BYTES: usize = 4 * 1024 * 1024;
+
+/// the batches resolved ahead of the sink before the pack threads wait for it
+const QUEUED_BATCHES: usize = 32;
+
+/// The threads resolving the objects of received packs, a pool of their own so a decode
+/// neither blocks the async runtime nor takes the global rayon pool.
+#[derive(Debug, Default)]
+pub struct PackThreads {
+    /// the size of the pool, 0 for one thread per core
+    threads: usize,
+    pool: OnceLock<ThreadPool>,
+}
+
+impl PackThreads {
+    pub fn new(threads: usize) -> PackThreads {
+        PackThreads {
+            threads,
+            pool: OnceLock::new(),
+        }
+    }
+
+    /// The pool, started on first use.
+    pub fn pool(&self) -> &ThreadPool {
+        self.pool.get_or_init(|| {
+            ThreadPoolBuilder::new()
+                .num_threads(self.threads)
+                .thread_name(|i| format!("pack-{}", i))
+                .build()
+                .expect("Failed to start the pack threads")
+        })
+    }
+}
+
+/// An object of a pack once resolved.
+pub struct ResolvedObject {
+    /// where the object is in the pack, None for a base of a thin pack read from the storage
+    pub offset: Option<u64>,
+    pub object: Arc<MetaData>,
+    /// the length of the delta chain leading to the object, 0 for a base
+    pub depth: usize,
+}
+
+/// Takes the objects of a pack as they're resolved, in no particular order. The decode stops
+/// at the first error of the sink.
+#[async_trait]
+pub trait ObjectSink: Send {
+    async fn accept(&mut self, objects: Vec<ResolvedObject>) -> Result<(), GitError>;
+}
+
+/// The counts of a decoded pack.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ResolveStats {
+    pub deltas: usize,
+    /// bases of a thin pack read from the storage
+    pub external: usize,
+}
+
+/// The base a tree of deltas is rooted at.
+enum Root {
+    /// an undeltified entry of the pack
+    Entry(usize),
+    /// a base of a thin pack read from the storage
+    External(Arc<MetaData>),
+}
+
+/// The entries of a pack and its deltas by their base.
+struct Resolver {
+    map: Arc<PackMap>,
+    /// the length of the pack without its trailing checksum
+    len: usize,
+    entries: Vec<Entry>,
+    by_offset: HashMap<u64, Vec<usize>>,
+    by_hash: HashMap<Hash, Vec<usize>>,
+    /// whether each delta was resolved by a tree
+    claimed: Vec<AtomicBool>,
+    limits: PackLimits,
+}
+
+/// Decode the `count` objects of the first `len` bytes of `map`, the pack without its
+/// trailing checksum, on the pack threads of `storage` and hand them to `sink`. Bases of a
+/// thin pack missing from the pack are read from `storage`.
+pub(crate) async fn resolve_pack<T, S>(
+    map: Arc<PackMap>,
+    len: usize,
+    count: usize,
+    storage: &T,
+    mut budget: DecodeBudget,
+    sink: &mut S,
+) -> Result<ResolveStats, GitError>
+where
+    T: ObjectStorage,
+    S: ObjectSink + ?Sized,
+{
+    let pool = storage.pack_threads().pool();
+    let limits = budget.limits.clone();
+    let (sender, receiver) = oneshot::channel();
+    let scanned = Arc::clone(&map);
+    spawn_job(
+        pool,
+        move || scan_entries(&scanned[..len], count, &mut budget),
+        move |entries| {
+            let _ = sender.send(entries);
+        },
+    );
+    let entries = receiver.await.map_err(|_| stopped())??;
+
+    let mut resolver = Resolver {
+        map,
+        len,
+        claimed: entries.iter().map(|_| AtomicBool::new(false)).collect(),
+        entries: Vec::new(),
+        by_offset: HashMap::new(),
+        by_hash: HashMap::new(),
+        limits,
+    };
+    let mut roots = Vec::new();
+    for (i, entry) in entries.iter().enumerate() {
+        match &entry.base {
+            None => roots.push(Root::Entry(i)),
+            Some(EntryBase::Offset(offset)) => {
+                resolver.by_offset.entry(*offset).or_default().push(i)
+            }
+            Some(EntryBase::Hash(hash)) => resolver.by_hash.entry(*hash).or_default().push(i),
+        }
+    }
+    resolver.entries = entries;
+    let resolver = Arc::new(resolver);
+
+    let mut stats = ResolveStats::default();
+    let cache = storage.object_cache();
+    stream_trees(pool, &resolver, roots, cache, sink, &mut stats).await?;
+
+    // the deltas left are based on objects outside of the pack, or on deltas based on them
+    let mut fetched = HashSet::new();
+    loop {
+        let missing: BTreeSet<Hash> = resolver
+            .unclaimed()
+            .filter_map(|entry| match &entry.base {
+                Some(EntryBase::Hash(hash)) if !fetched.contains(hash) => Some(*hash),
+                _ => None,
+            })
+            .collect();
+        let mut bases = Vec::new();
+        for hash in missing {
+            fetched.insert(hash);
+            // a base not stored may be the result of a delta based on another one
+            if let Some(object) = storage.get_hash_object(&hash.to_plain_str()).await? {
+                bases.push(Root::External(Arc::new(object)));
+            }
+        }
+        if bases.is_empty() {
+            break;
+        }
+        stream_trees(pool, &resolver, bases, cache, sink, &mut stats).await?;
+    }
+
+    let mut unclaimed: Vec<&Entry> = resolver.unclaimed().collect();
+    unclaimed.sort_by_key(|entry| matches!(entry.base, Some(EntryBase::Offset(_))));
+    if let Some(entry) = unclaimed.first() {
+        return Err(match &entry.base {
+            Some(EntryBase::Hash(hash)) => GitError::NotFountHashValue(hash.to_plain_str()),
+            _ => GitError::InvalidObjectInfo(format!(
+                "Invalid OffsetDelta offset of {}",
+                entry.offset
+            )),
+        });
+    }
+    Ok(stats)
+}
+
+/// Resolve the trees of `roots` on `pool` and hand their objects to `sink` as they come, the
+/// pool waits once `QUEUED_BATCHES` batches are ahead of the sink. The objects are added to
+/// `cache`, which keeps them within its budget.
+async fn stream_trees<S: ObjectSink + ?Sized>(
+    pool: &ThreadPool,
+    resolver: &Arc<Resolver>,
+    roots: Vec<Root>,
+    cache: &ObjectCache,
+    sink: &mut S,
+    stats: &mut ResolveStats,
+) -> Result<(), GitError> {
+    let (sender, mut receiver) = mpsc::channel(QUEUED_BATCHES);
+    let resolver = Arc::clone(resolver);
+    let failed = sender.clone();
+    spawn_job(
+        pool,
+        move || {
+            roots.par_iter().try_for_each_with(sender, |sender, root| {
+                // the receiver is gone once the sink failed
+                resolver.resolve_tree(root, &mut |batch| {
+                    sender.blocking_send(Ok(batch)).map_err(|_| stopped())
+                })
+            })
+        },
+        move |result| {
+            if let Err(err) = result {
+                let _ = failed.blocking_send(Err(err));
+            }
+        },
+    );
+    while let Some(batch) = receiver.recv().await {
+        let mut batch = batch?;
+        // small trees are handed over together
+        let mut bytes: usize = batch.iter().map(|resolved| resolved.object.size).sum();
+        while bytes < BATCH_BYTES {
+            let Ok(more) = receiver.try_recv() else {
+                break;
+            };
+            let more = more?;
+            bytes += more
+                .iter()
+                .map(|resolved| resolved.object.size)
+                .sum::<usize>();
+            batch.extend(more);
+        }
+        for resolved in &batch {
+            cache.insert(Arc::clone(&resolved.object));
+            match resolved.offset {
+                None => stats.external += 1,
+                Some(_) if resolved.depth > 0 => stats.deltas += 1,
+                Some(_) => {}
+            }
+        }
+        sink.accept(batch).await?;
+    }
+    Ok(())
+}
+
+/// Run `op` on `pool` and hand its result to `done`, a panic of `op` is an error.
+fn spawn_job<R, F, D>(pool: &ThreadPool, op: F, done: D)
+where
+    R: Send + 'static,
+    F: FnOnce() -> Result<R, GitError> + Send + 'static,
+    D: FnOnce(Result<R, GitError>) + Send + 'static,
+{
+    pool.spawn(move || {
+        let result = panic::catch_unwind(AssertUnwindSafe(op)).unwrap_or_else(|_| {
+            tracing::error!("a pack thread panicked");
+            Err(stopped())
+        });
+        done(result);
+    });
+}
+
+fn stopped() -> GitError {
+    GitError::PackDecodeStopped(String::from("the decode was abandoned"))
+}
+
+/// Record the header of the `count` entries of `content` and check that their data
+/// inflates to their size, the inflated sizes are charged to `budget`.
+fn scan_entries(
+    content: &[u8],
+    count: usize,
+    budget: &mut DecodeBudget,
+) -> Result<Vec<Entry>, GitError> {
+    let mut entries = Vec::with_capacity(count);
+    let mut offset = 12;
+    for i in 0..count {
+        if offset >= content.len() {
+            return Err(GitError::InvalidPackFile(format!(
+                "{} objects declared, {} found",
+                count, i
+            )));
+        }
+        let entry = read_entry(content, offset as u64)?;
+        let mut decompressed = ZlibDecoder::new(&content[entry.data..]);
+        let mut data = Read::take(&mut decompressed, entry.size as u64 + 1);
+        budget.charge(entry.size)?;
+        if entry.base.is_some() {
+            budget.charge(delta_result_size(&mut data)?)?;
+        }
+        io::copy(&mut data, &mut io::sink()).map_err(utils::corrupt_pack)?;
+        if decompressed.total_out() != entry.size as u64 {
+            return Err(GitError::InvalidObjectInfo(String::from(
+                "Incorrect object size",
+            )));
+        }
+        offset = entry.data + decompressed.total_in() as usize;
+        entries.push(entry);
+    }
+    if offset != content.len() {
+        return Err(GitError::InvalidPackFile(format!(
+            "{} bytes of garbage after the objects",
+            content.len().saturating_sub(offset)
+        )));
+    }
+    Ok(entries)
+}
+
+impl Resolver {
+    fn content(&self) -> &[u8] {
+        &self.map[..self.len]
+    }
+
+    /// Resolve the deltas based on `root` and on their results, a delta is claimed by the
+    /// first tree reaching it. The objects of the tree are handed to `emit` in batches of
+    /// about `BATCH_BYTES`, an object is emitted once its deltas are resolved.
+    fn resolve_tree(
+        &self,
+        root: &Root,
+        emit: &mut dyn FnMut(Vec<ResolvedObject>) -> Result<(), GitError>,
+    ) -> Result<(), GitError> {
+        let (offset, base) = match root {
+            Root::Entry(i) => {
+                let entry = &self.entries[*i];
+                let base = inflate_entry(self.content(), entry)?;
+                (Some(entry.offset), Arc::new(base))
+            }
+            Root::External(base) => (None, Arc::clone(base)),
+        };
+        // the inflated sizes were charged by the first pass, the budget of a tree only bounds
+        // its objects
+        let mut budget = DecodeBudget::new(self.limits.clone(), self.len as u64);
+        let mut batch = Vec::new();
+        let mut bytes = 0;
+        let mut stack = vec![ResolvedObject {
+            offset,
+            object: base,
+            depth: 0,
+        }];
+        while let Some(base) = stack.pop() {
+            let children = base
+                .offset
+                .and_then(|offset| self.by_offset.get(&offset))
+                .into_iter()
+                .chain(self.by_hash.get(&base.object.id))
+                .flatten();
+            for &i in children {
+                if self.claimed[i].swap(true, Ordering::Relaxed) {
+                    continue;
+                }
+                let entry = &self.entries[i];
+                budget.check_delta_depth(base.depth + 1)?;
+                let mut decompressed = ZlibDecoder::new(&self.content()[entry.data..]);
+                let object =
+                    apply_delta_stream(&mut decompressed, &base.object, entry.size, &mut budget)?;
+                stack.push(ResolvedObject {
+                    offset: Some(entry.offset),
+                    object: Arc::new(object),
+                    depth: base.depth + 1,
+                });
+            }
+            bytes += base.object.size;
+            batch.push(base);
+            if bytes >= BATCH_BYTES {
+                emit(mem::take(&mut batch))?;
+                bytes = 0;
+            }
+        }
+        if !batch.is_empty() {
+            emit(batch)?;
+        }
+        Ok(())
+    }
+
+    /// the deltas no tree reached
+    fn unclaimed(&self) -> impl Iterator<Item = &Entry> {
+        self.entries
+            .iter()
+            .zip(&self.claimed)
+            .filter(|(entry, claimed)| entry.base.is_some() && !claimed.load(Ordering::Relaxed))
+            .map(|(entry, _)| entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::path::PathBuf;
+    use std::sync::{Arc, Weak};
+
+    use async_trait::async_trait;
+    use tokio_test::block_on;
+
+    use super::{ObjectSink, ResolvedObject};
+    use crate::git::errors::GitError;
+    use crate::git::object::metadata::MetaData;
+    use crate::git::pack::limits::PackLimits;
+    use crate::git::pack::reader::ObjectReader;
+    use crate::git::pack::Pack;
+    use crate::gust::driver::database::mysql::storage::MysqlStorage;
+    use crate::gust::driver::object_cache::ObjectCache;
+
+    #[test]
+    fn test_resolve_pack() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/data/test/pack-8d36a6464e1f284e5e9d06683689ee751d4b2687.pack");
+        let storage = MysqlStorage::default();
+        let pack = block_on(Pack::decode(&mut File::open(&path).unwrap(), &storage)).unwrap();
+        let cache = pack.result;
+        assert_eq!(614, cache.by_hash.len());
+        assert_eq!(614, (cache.base + cache.delta) as usize);
+        assert!(cache.delta > 0);
+
+        let reader = ObjectReader::open(&path, PackLimits::default()).unwrap();
+        for (id, object) in cache.by_hash.iter() {
+            assert_eq!(object.data, reader.read(id).unwrap().data);
+            assert_eq!(*id, cache.offset_hash[&cache.by_offset[id]]);
+        }
+    }
+
+    /// fails on every batch
+    struct FailingSink {
+        batches: usize,
+    }
+
+    #[async_trait]
+    impl ObjectSink for FailingSink {
+        async fn accept(&mut self, _: Vec<ResolvedObject>) -> Result<(), GitError> {
+            self.batches += 1;
+            Err(GitError::PackWriteFailed(String::from("sink is full")))
+        }
+    }
+
+    #[test]
+    fn test_resolve_pack_sink_error() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/data/test/pack-8d36a6464e1f284e5e9d06683689ee751d4b2687.pack");
+        let storage = MysqlStorage::default();
+        let mut sink = FailingSink { batches: 0 };
+        let result = block_on(Pack::decode_into(
+            &mut File::open(&path).unwrap(),
+            &storage,
+            &mut sink,
+        ));
+        assert!(matches!(result, Err(GitError::PackWriteFailed(_))));
+        assert_eq!(1, sink.batches);
+    }
+
+    /// only watches the objects it's handed
+    #[derive(Default)]
+    struct WatchingSink {
+        objects: Vec<Weak<MetaData>>,
+    }
+
+    #[async_trait]
+    impl ObjectSink for WatchingSink {
+        async fn accept(&mut self, objects: Vec<ResolvedObject>) -> Result<(), GitError> {
+            let watched = objects
+                .iter()
+                .map(|resolved| Arc::downgrade(&resolved.object));
+            self.objects.extend(watched);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_resolve_pack_keeps_no_objects() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/data/test/pack-8d36a6464e1f284e5e9d06683689ee751d4b2687.pack");
+        let storage = MysqlStorage {
+            cache: Arc::new(ObjectCache::new(0)),
+            ..Default::default()
+        };
+        let mut sink = WatchingSink::default();
+        block_on(Pack::decode_into(
+            &mut File::open(&path).unwrap(),
+            &storage,
+            &mut sink,
+        ))
+        .unwrap();
+        assert_eq!(614, sink.objects.len());
+        // the memory of a decode is what the sink and the object cache keep
+        assert!(sink.objects.iter().all(|object| object.upgrade().is_none()));
+    }
+
+    #[test]
+    fn test_resolve_pack_within_cache_budget() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/data/test/pack-8d36a6464e1f284e5e9d06683689ee751d4b2687.pack");
+        let budget = 256 * 1024;
+        assert!(budget < std::fs::metadata(&path).unwrap().len() as usize);
+        let storage = MysqlStorage {
+            cache: Arc::new(ObjectCache::new(budget)),
+            ..Default::default()
+        };
+        let mut sink = WatchingSink::default();
+        block_on(Pack::decode_into(
+            &mut File::open(&path).unwrap(),
+            &storage,
+            &mut sink,
+        ))
+        .unwrap();
+        assert_eq!(614, sink.objects.len());
+
+        let stats = storage.cache.stats();
+        assert!(stats.entries > 0);
+        assert!(stats.evictions > 0);
+        assert!(stats.bytes <= budget);
+        let kept = sink.objects.iter().filter(|o| o.upgrade().is_some());
+        assert_eq!(stats.entries, kept.count());
+    }
+}
@@ -0,0 +1,175 @@
+//!
+//! Write a pack entry by entry to an `AsyncWrite`, the SHA-1 trailer is computed as the
+//! bytes go out so the pack is never held whole in memory.
+//!
+use sha1::{Digest, Sha1};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::git::errors::GitError;
+use crate::git::hash::Hash;
+use crate::git::object::metadata::MetaData;
+
+/// Writes a pack of a known number of objects.
+pub struct PackWriter<W> {
+    writer: W,
+    sha1: Sha1,
+    count: usize,
+    written: usize,
+    /// the bytes written so far, the offset of the next entry
+    offset: u64,
+}
+
+impl<W: AsyncWrite + Unpin> PackWriter<W> {
+    /// Write the header of a pack of `count` objects.
+    pub async fn new(writer: W, count: usize) -> Result<PackWriter<W>, GitError> {
+        let count_u32 = u32::try_from(count).map_err(|_| {
+            GitError::PackWriteFailed(format!("{} objects don't fit in a pack", count))
+        })?;
+        let mut pack = PackWriter {
+            writer,
+            sha1: Sha1::new(),
+            count,
+            written: 0,
+            offset: 0,
+        };
+        let mut header = b"PACK".to_vec();
+        header.extend(2u32.to_be_bytes());
+        header.extend(count_u32.to_be_bytes());
+        pack.write(&header).await?;
+        Ok(pack)
+    }
+
+    /// Write the entry of `object`, a delta keeps the type and the base of its delta header.
+    pub async fn write_object(&mut self, object: &MetaData) -> Result<(), GitError> {
+        if self.written == self.count {
+            return Err(GitError::PackWriteFailed(format!(
+                "more than the {} objects declared",
+                self.count
+            )));
+        }
+        let entry = object
+            .convert_to_vec()
+            .map_err(|err| GitError::PackWriteFailed(err.to_string()))?;
+        self.write(&entry).await?;
+        self.written += 1;
+        Ok(())
+    }
+
+    /// The offset the next entry is written at, an offset delta counts back from it.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Write the trailer once every object is written, returns the checksum of the pack.
+    pub async fn finish(mut self) -> Result<Hash, GitError> {
+        if self.written != self.count {
+            return Err(GitError::PackWriteFailed(format!(
+                "{} objects declared, {} written",
+                self.count, self.written
+            )));
+        }
+        let signature = Hash::from_row(&self.sha1.finalize_reset());
+        self.writer
+            .write_all(&signature.0)
+            .await
+            .map_err(|err| GitError::PackWriteFailed(err.to_string()))?;
+        self.writer
+            .flush()
+            .await
+            .map_err(|err| GitError::PackWriteFailed(err.to_string()))?;
+        Ok(signature)
+    }
+
+    async fn write(&mut self, data: &[u8]) -> Result<(), GitError> {
+        self.sha1.update(data);
+        self.offset += data.len() as u64;
+        self.writer
+            .write_all(data)
+            .await
+            .map_err(|err| GitError::PackWriteFailed(err.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+
+    use tokio_test::block_on;
+
+    use crate::git::object::diff::DeltaOptions;
+    use crate::git::object::metadata::MetaData;
+    use crate::git::object::types::ObjectType;
+    use crate::git::pack::encode::DeltaWindow;
+    use crate::git::pack::Pack;
+    use crate::gust::driver::database::mysql::storage::MysqlStorage;
+
+    use super::PackWriter;
+
+    #[test]
+    fn test_pack_writer() {
+        let objects = vec![
+            MetaData::new(ObjectType::Blob, &b"hello\n".to_vec()),
+            MetaData::new(ObjectType::Blob, &b"world\n".to_vec()),
+        ];
+        let mut streamed = Vec::new();
+        let signature = block_on(async {
+            let mut writer = PackWriter::new(&mut streamed, 2).await.unwrap();
+            for object in &objects {
+                writer.write_object(object).await.unwrap();
+            }
+            writer.finish().await.unwrap()
+        });
+        let mut pack = Pack::default();
+        assert_eq!(pack.encode(Some(objects.clone())), streamed);
+        assert_eq!(pack.signature, signature);
+
+        // the count of the header must match the objects written
+        let mut streamed = Vec::new();
+        block_on(async {
+            let mut writer = PackWriter::new(&mut streamed, 1).await.unwrap();
+            writer.write_object(&objects[0]).await.unwrap();
+            assert!(writer.write_object(&objects[1]).await.is_err());
+        });
+        let mut streamed = Vec::new();
+        block_on(async {
+            let writer = PackWriter::new(&mut streamed, 1).await.unwrap();
+            assert!(writer.finish().await.is_err());
+        });
+    }
+
+    #[test]
+    fn test_pack_writer_offset_deltas() {
+        let text = "fn main() {\n    println!(\"hello\");\n}\n".repeat(100);
+        let mut objects = vec![MetaData::new(ObjectType::Tree, &b"not alike".to_vec())];
+        for i in 0..5 {
+            let data = text.replacen("hello", &format!("hello {}", i), i + 1);
+            objects.push(MetaData::new(ObjectType::Blob, &data.into_bytes()));
+        }
+        let mut streamed = Vec::new();
+        block_on(async {
+            let mut writer = PackWriter::new(&mut streamed, objects.len()).await.unwrap();
+            let mut window = DeltaWindow::new(DeltaOptions::default());
+            for object in objects.clone() {
+                let offset = writer.offset();
+                match window.delta(&object, offset) {
+                    Some(delta) => writer.write_object(&delta).await.unwrap(),
+                    None => writer.write_object(&object).await.unwrap(),
+                }
+                window.push(object, offset);
+            }
+            writer.finish().await.unwrap();
+        });
+        let path = std::env::temp_dir().join("test_pack_writer_offset_deltas.pack");
+        fs::write(&path, &streamed).unwrap();
+        let storage = MysqlStorage::default();
+        let pack = block_on(Pack::decode(&mut File::open(&path).unwrap(), &storage)).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        // every blob but the first is a delta of an earlier blob
+        assert_eq!(2, pack.result.base);
+        assert_eq!(4, pack.result.delta);
+        for object in &objects {
+            assert_eq!(object.data, pack.result.by_hash[&object.id].data);
+        }
+    }
+}
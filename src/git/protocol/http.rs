@@ -1,21 +1,19 @@
 use std::collections::HashMap;
+use std::convert::Infallible;
 
 use anyhow::Result;
 use axum::body::Body;
 use axum::http::response::Builder;
 use axum::http::{Response, StatusCode};
 
-use bytes::{BufMut, Bytes, BytesMut};
+use bytes::{Bytes, BytesMut};
 
-use futures::StreamExt;
-use hyper::body::Sender;
+use futures::{stream, StreamExt};
 use hyper::Request;
 
-use tokio::io::{AsyncReadExt, BufReader};
-
 use crate::gust::driver::ObjectStorage;
 
-use super::{pack, PackProtocol};
+use super::PackProtocol;
 
 pub fn build_res_header(content_type: String) -> Builder {
     let mut headers = HashMap::new();
@@ -32,29 +30,6 @@ pub fn build_res_header(content_type: String) -> Builder {
     resp
 }
 
-pub async fn send_pack<T: ObjectStorage>(
-    mut sender: Sender,
-    result: Vec<u8>,
-    pack_protocol: PackProtocol<T>,
-) -> Result<(), (StatusCode, &'static str)> {
-    let mut reader = BufReader::new(result.as_slice());
-    loop {
-        let mut temp = BytesMut::new();
-        temp.reserve(65500);
-        let length = reader.read_buf(&mut temp).await.unwrap();
-        if temp.is_empty() {
-            let mut bytes_out = BytesMut::new();
-            bytes_out.put_slice(pack::PKT_LINE_END_MARKER);
-            tracing::info!("send: bytes_out: {:?}", bytes_out.clone().freeze());
-            sender.send_data(bytes_out.freeze()).await.unwrap();
-            return Ok(());
-        }
-        let bytes_out = pack_protocol.build_side_band_format(temp, length);
-        tracing::info!("send: bytes_out: {:?}", bytes_out.clone().freeze());
-        sender.send_data(bytes_out.freeze()).await.unwrap();
-    }
-}
-
 pub async fn git_upload_pack<T: ObjectStorage + 'static>(
     req: Request<Body>,
     mut pack_protocol: PackProtocol<T>,
@@ -69,7 +44,7 @@ pub async fn git_upload_pack<T: ObjectStorage + 'static>(
         upload_request.extend_from_slice(&bytes);
     }
 
-    let (send_pack_data, buf) = pack_protocol
+    let (pack_request, buf) = pack_protocol
         .git_upload_pack(&mut upload_request.freeze())
         .await
         .unwrap();
@@ -77,10 +52,9 @@ pub async fn git_upload_pack<T: ObjectStorage + 'static>(
 
     tracing::info!("send buf: {:?}", buf);
 
-    let (mut sender, body) = Body::channel();
-    sender.send_data(buf.freeze()).await.unwrap();
-
-    tokio::spawn(send_pack(sender, send_pack_data, pack_protocol));
+    let packets =
+        stream::once(async { buf.freeze() }).chain(pack_protocol.pack_stream(pack_request));
+    let body = Body::wrap_stream(packets.map(Ok::<Bytes, Infallible>));
     Ok(resp.body(body).unwrap())
 }
 
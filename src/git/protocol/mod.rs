@@ -13,7 +13,7 @@ use crate::{
     gust::driver::{ObjectStorage, ZERO_ID},
 };
 
-use super::pack::Pack;
+use super::pack::{resolve::ObjectSink, Pack};
 pub mod http;
 pub mod pack;
 pub mod ssh;
@@ -77,6 +77,7 @@ pub enum Capability {
     OfsDelta,
     DeepenSince,
     DeepenNot,
+    ThinPack,
 }
 
 impl FromStr for Capability {
@@ -94,6 +95,7 @@ impl FromStr for Capability {
             "no-done" => Ok(Capability::NoDone),
             "deepen-since" => Ok(Capability::DeepenSince),
             "deepen-not" => Ok(Capability::DeepenNot),
+            "thin-pack" => Ok(Capability::ThinPack),
             _ => Err(()),
         }
     }
@@ -161,12 +163,18 @@ impl RefCommand {
         }
     }
 
-    pub async fn unpack<T: ObjectStorage>(
+    /// Decode the pack of the command and hand its objects to `sink`.
+    pub async fn unpack<T, S>(
         &mut self,
         pack_file: &mut File,
         storage: &T,
-    ) -> Result<Pack, anyhow::Error> {
-        match Pack::decode(pack_file, storage).await {
+        sink: &mut S,
+    ) -> Result<Pack, anyhow::Error>
+    where
+        T: ObjectStorage,
+        S: ObjectSink + ?Sized,
+    {
+        match Pack::decode_into(pack_file, storage, sink).await {
             Ok(decoded_pack) => {
                 self.status = RefCommand::OK_STATUS.to_owned();
                 Ok(decoded_pack)
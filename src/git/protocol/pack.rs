@@ -12,13 +12,18 @@ use anyhow::Result;
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 use chrono::Utc;
 
+use futures::{stream, Stream};
+use tokio::io::{AsyncReadExt, AsyncWrite};
+
+use crate::git::errors::GitError;
+use crate::git::fsck::FsckSink;
 use crate::git::hash::Hash;
 use crate::git::object::base::blob::Blob;
 use crate::git::object::base::commit::Commit;
 use crate::git::object::base::tree::{Tree, TreeItemType};
 use crate::git::object::metadata::MetaData;
 use crate::git::protocol::{PackProtocol, RefCommand};
-use crate::gust::driver::{ObjectStorage, ZERO_ID};
+use crate::gust::driver::{ObjectStorage, PackReceiver, ZERO_ID};
 
 use super::{Capability, Protocol, ServiceType, SideBind};
 
@@ -39,8 +44,21 @@ const RECEIVE_CAP_LIST: &str = "report-status report-status-v2 delete-refs quiet
 const CAP_LIST: &str = "side-band-64k ofs-delta object-format=sha1";
 
 // All other capabilities are only recognized by the upload-pack (fetch from server) process.
+// allow-reachable-sha1-in-want lets clients fetch the commit of a changelist, which is not
+// advertised as a ref. With thin-pack the pack may hold deltas against objects the client has.
 const UPLOAD_CAP_LIST: &str =
-    "shallow deepen-since deepen-not deepen-relative multi_ack_detailed no-done ";
+    "shallow deepen-since deepen-not deepen-relative multi_ack_detailed no-done allow-reachable-sha1-in-want thin-pack ";
+
+/// the data of a side-band-64k packet
+const PACK_CHUNK: usize = 65515;
+
+/// The objects an upload-pack request is answered with, the whole repo when `have` is empty.
+#[derive(Debug, Clone)]
+pub struct UploadPackRequest {
+    pub want: HashSet<String>,
+    pub have: HashSet<String>,
+    pub thin: bool,
+}
 
 impl<T: ObjectStorage> PackProtocol<T> {
     pub async fn git_info_refs(&mut self) -> BytesMut {
@@ -70,10 +88,12 @@ impl<T: ObjectStorage> PackProtocol<T> {
         pkt_line_stream
     }
 
+    /// Negotiate the pack of an upload-pack request, returns the pack to send, written by
+    /// `pack_stream`, and the acknowledgements to send before it.
     pub async fn git_upload_pack(
         &mut self,
         upload_request: &mut Bytes,
-    ) -> Result<(Vec<u8>, BytesMut)> {
+    ) -> Result<(Option<UploadPackRequest>, BytesMut)> {
         let mut want: HashSet<String> = HashSet::new();
         let mut have: HashSet<String> = HashSet::new();
 
@@ -113,11 +133,15 @@ impl<T: ObjectStorage> PackProtocol<T> {
             self.capabilities
         );
 
-        let mut send_pack_data = vec![];
+        let mut pack_request = None;
         let mut buf = BytesMut::new();
 
         if have.is_empty() {
-            send_pack_data = self.storage.get_full_pack_data(&self.path).await.unwrap();
+            pack_request = Some(UploadPackRequest {
+                want,
+                have,
+                thin: false,
+            });
             add_pkt_line_string(&mut buf, String::from("NAK\n"));
         } else {
             // let object_root = self.path.join(".git/objects");
@@ -132,20 +156,14 @@ impl<T: ObjectStorage> PackProtocol<T> {
                 // it is ready to send data with ACK obj-id ready lines,
                 // and signals the identified common commits with ACK obj-id common lines
                 for hash in &have {
-                    if self.storage.get_commit_by_hash(hash).await.is_ok() {
+                    if self.storage.get_commit_by_hash(hash).await?.is_some() {
                         add_pkt_line_string(&mut buf, format!("ACK {} common\n", hash));
                     }
                     // no need to send NAK in this mode if missing commit?
                 }
 
-                send_pack_data = self
-                    .storage
-                    .get_incremental_pack_data(&self.path, &want, &have)
-                    .await
-                    .unwrap();
-
                 for hash in &want {
-                    if self.storage.get_commit_by_hash(hash).await.is_ok() {
+                    if self.storage.get_commit_by_hash(hash).await?.is_some() {
                         add_pkt_line_string(&mut buf, format!("ACK {} common\n", hash));
                     }
                     if self.capabilities.contains(&Capability::NoDone) {
@@ -154,6 +172,11 @@ impl<T: ObjectStorage> PackProtocol<T> {
                         add_pkt_line_string(&mut buf, format!("ACK {} ready\n", hash));
                     }
                 }
+                pack_request = Some(UploadPackRequest {
+                    want,
+                    have,
+                    thin: self.capabilities.contains(&Capability::ThinPack),
+                });
             } else {
                 tracing::error!("capability unsupported");
             }
@@ -163,7 +186,7 @@ impl<T: ObjectStorage> PackProtocol<T> {
                 format!("ACK {} \n", "27dd8d4cf39f3868c6eee38b601bc9e9939304f5"),
             );
         }
-        Ok((send_pack_data, buf))
+        Ok((pack_request, buf))
     }
 
     pub async fn git_receive_pack(&mut self, mut body_bytes: Bytes) -> Result<Bytes> {
@@ -180,33 +203,65 @@ impl<T: ObjectStorage> PackProtocol<T> {
                 .open(&temp_file)
                 .unwrap();
             file.write_all(&body_bytes).unwrap();
-            let decoded_pack = command
-                .unpack(
-                    &mut std::fs::File::open(&temp_file).unwrap(),
-                    self.storage.as_ref(),
-                )
-                .await
-                .unwrap();
-            let pack_result = self.storage.save_packfile(decoded_pack, &self.path).await;
-            if pack_result.is_ok() {
-                self.storage.handle_refs(command, &self.path).await;
-            } else {
-                tracing::error!("{}", pack_result.err().unwrap());
-                command.failed(String::from("db operation failed"));
-            }
+            let mut messages = BytesMut::new();
+            let unpack_status = match self.storage.receive_pack(&self.path).await {
+                Ok(mut receiver) => {
+                    // nothing is stored when a received object fails a check
+                    let mut fsck = FsckSink::new(receiver.as_mut(), self.storage.fsck_config());
+                    let unpacked = command
+                        .unpack(
+                            &mut std::fs::File::open(&temp_file).unwrap(),
+                            self.storage.as_ref(),
+                            &mut fsck,
+                        )
+                        .await;
+                    let failed = fsck.failed();
+                    for problem in &fsck.problems {
+                        tracing::warn!("{}", problem);
+                        messages.put(self.build_side_band_message(
+                            SideBind::ProgressInfo,
+                            &format!("{}\n", problem),
+                        ));
+                    }
+                    match unpacked {
+                        Ok(_) if failed => Err(String::from("fsck failed")),
+                        Ok(_) => {
+                            self.finish_received_pack(receiver).await;
+                            Ok(())
+                        }
+                        Err(err) => {
+                            tracing::error!("{}", err);
+                            Err(err.to_string().replace('\n', " "))
+                        }
+                    }
+                }
+                Err(err) => {
+                    tracing::error!("{}", err);
+                    Err(push_failure_reason(&err))
+                }
+            };
             fs::remove_file(temp_file).unwrap();
 
             // After receiving the pack data from the sender, the receiver sends a report
             let mut report_status = BytesMut::new();
-            // TODO: replace this hard code "unpack ok\n"
-            add_pkt_line_string(&mut report_status, "unpack ok\n".to_owned());
+            match unpack_status {
+                Ok(()) => add_pkt_line_string(&mut report_status, "unpack ok\n".to_owned()),
+                Err(err) => {
+                    add_pkt_line_string(&mut report_status, format!("unpack {}\n", err));
+                    // like git, no ref is updated once the pack is rejected
+                    for command in &mut self.command_list {
+                        command.failed(String::from("unpacker error"));
+                    }
+                }
+            }
             for command in &self.command_list {
                 add_pkt_line_string(&mut report_status, command.get_status());
             }
             report_status.put(&PKT_LINE_END_MARKER[..]);
 
             let length = report_status.len();
-            let mut buf = self.build_side_band_format(report_status, length);
+            messages.put(self.build_side_band_format(report_status, length));
+            let mut buf = messages;
             buf.put(&PKT_LINE_END_MARKER[..]);
             Ok(buf.into())
         } else {
@@ -222,6 +277,41 @@ impl<T: ObjectStorage> PackProtocol<T> {
         }
     }
 
+    /// Save the rest of the push received by `receiver` and update the ref of every command
+    /// whose objects are connected, the other commands are reported as failed. Every command
+    /// fails if the push can't be saved.
+    async fn finish_received_pack(&mut self, receiver: Box<dyn PackReceiver>) {
+        match receiver.finish(&self.command_list).await {
+            Ok(results) => {
+                for (command, result) in self.command_list.iter_mut().zip(results) {
+                    if let Err(err) = result {
+                        tracing::error!("{}: {}", command.ref_name, err);
+                        command.failed(push_failure_reason(&err));
+                    }
+                }
+            }
+            Err(err) => {
+                tracing::error!("{}", err);
+                for command in &mut self.command_list {
+                    command.failed(push_failure_reason(&err));
+                }
+            }
+        }
+    }
+
+    /// A message for the client on `band`, dropped if sideband isn't negotiated.
+    fn build_side_band_message(&self, band: SideBind, message: &str) -> BytesMut {
+        let mut to_bytes = BytesMut::new();
+        if self.capabilities.contains(&Capability::SideBand)
+            || self.capabilities.contains(&Capability::SideBand64k)
+        {
+            to_bytes.put(Bytes::from(format!("{:04x}", message.len() + 5)));
+            to_bytes.put_u8(band.value());
+            to_bytes.put(message.as_bytes());
+        }
+        to_bytes
+    }
+
     // if SideBand/64k capability is enabled, pack data should send with sideband format
     pub fn build_side_band_format(&self, from_bytes: BytesMut, length: usize) -> BytesMut {
         let capabilities = &self.capabilities;
@@ -272,6 +362,65 @@ impl<T: ObjectStorage> PackProtocol<T> {
     }
 }
 
+impl<T: ObjectStorage + 'static> PackProtocol<T> {
+    /// Write the pack of `request` to `writer`.
+    pub async fn write_pack(
+        &self,
+        request: &UploadPackRequest,
+        writer: &mut (dyn AsyncWrite + Unpin + Send),
+    ) -> Result<(), GitError> {
+        if request.have.is_empty() {
+            self.storage.write_full_pack(&self.path, writer).await
+        } else {
+            self.storage
+                .write_incremental_pack(
+                    &self.path,
+                    &request.want,
+                    &request.have,
+                    request.thin,
+                    writer,
+                )
+                .await
+        }
+    }
+
+    /// The packets of the pack of `request`, ended by a flush packet. The pack is written by a
+    /// task while the packets are read, so it's never held whole in memory. A pack failing
+    /// midway ends with an error message.
+    pub fn pack_stream(
+        &self,
+        request: Option<UploadPackRequest>,
+    ) -> impl Stream<Item = Bytes> + Send + 'static {
+        let (mut writer, reader) = tokio::io::duplex(PACK_CHUNK);
+        let protocol = self.clone();
+        let task = tokio::spawn(async move {
+            match request {
+                Some(request) => protocol.write_pack(&request, &mut writer).await,
+                None => Ok(()),
+            }
+        });
+        let state = Some((reader, task, self.clone()));
+        stream::unfold(state, |state| async move {
+            let (mut reader, task, protocol) = state?;
+            let mut chunk = BytesMut::with_capacity(PACK_CHUNK);
+            if let Ok(length @ 1..) = reader.read_buf(&mut chunk).await {
+                let packet = protocol.build_side_band_format(chunk, length).freeze();
+                return Some((packet, Some((reader, task, protocol))));
+            }
+            let mut end = BytesMut::new();
+            let result = task
+                .await
+                .unwrap_or_else(|err| Err(GitError::PackWriteFailed(err.to_string())));
+            if let Err(err) = result {
+                tracing::error!("{}", err);
+                end.put(protocol.build_side_band_message(SideBind::Error, &format!("{}\n", err)));
+            }
+            end.put(&PKT_LINE_END_MARKER[..]);
+            Some((end.freeze(), None))
+        })
+    }
+}
+
 fn read_until_white_space(bytes: &mut Bytes) -> String {
     let mut buf = Vec::new();
     while bytes.has_remaining() {
@@ -359,7 +508,7 @@ fn parse_tree(
             continue;
         }
         match tree_item.item_type {
-            TreeItemType::Blob => {
+            TreeItemType::Blob | TreeItemType::BlobExecutable | TreeItemType::Link => {
                 if !init_basic {
                     let blob = Blob::parse_from_file(
                         object_root
@@ -369,12 +518,11 @@ fn parse_tree(
                     result.insert(blob.meta.id, Arc::try_unwrap(blob.meta).unwrap());
                 }
             }
-            TreeItemType::BlobExecutable => todo!(),
             TreeItemType::Tree => {
                 parse_tree(object_root, tree_item.id, result, basic_objects, init_basic);
             }
-            TreeItemType::Commit => todo!(),
-            TreeItemType::Link => todo!(),
+            // submodule commits live in other repos
+            TreeItemType::Commit => {}
         }
         basic_objects.insert(tree_item.id);
     }
@@ -404,11 +552,101 @@ pub fn read_pkt_line(bytes: &mut Bytes) -> (usize, Bytes) {
     (pkt_length, pkt_line)
 }
 
+/// The reason sent to the client for a push failing with `err`. Only rejections and missing
+/// objects are the client's to fix, anything else is reported as a server failure.
+fn push_failure_reason(err: &GitError) -> String {
+    match err {
+        // a truncated or corrupt push must not leave the ref pointing at missing objects
+        GitError::MissingObjects(_) => String::from("missing necessary objects"),
+        GitError::RefUpdateRejected(reason) => reason.clone(),
+        GitError::DbError(_) => String::from("db operation failed"),
+        _ => String::from("internal server error"),
+    }
+}
+
 #[cfg(test)]
 pub mod test {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
     use bytes::{Bytes, BytesMut};
+    use tokio_test::block_on;
+
+    use crate::git::errors::GitError;
+    use crate::git::pack::resolve::{ObjectSink, ResolvedObject};
+    use crate::git::protocol::{PackProtocol, Protocol, RefCommand};
+    use crate::gust::driver::database::mysql::storage::MysqlStorage;
+    use crate::gust::driver::PackReceiver;
+
+    use super::{add_pkt_line_string, push_failure_reason, read_pkt_line};
 
-    use super::{add_pkt_line_string, read_pkt_line};
+    /// fails the commands to refs named `missing`
+    struct MissingReceiver;
+
+    #[async_trait]
+    impl ObjectSink for MissingReceiver {
+        async fn accept(&mut self, _: Vec<ResolvedObject>) -> Result<(), GitError> {
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl PackReceiver for MissingReceiver {
+        async fn finish(
+            self: Box<Self>,
+            commands: &[RefCommand],
+        ) -> Result<Vec<Result<(), GitError>>, GitError> {
+            Ok(commands
+                .iter()
+                .map(|command| match command.ref_name.as_str() {
+                    "missing" => Err(GitError::MissingObjects(command.new_id.clone())),
+                    _ => Ok(()),
+                })
+                .collect())
+        }
+    }
+
+    #[test]
+    pub fn test_finish_received_pack_reports_every_command() {
+        let mut pack_protocol = PackProtocol::new(
+            PathBuf::from("/root/repo"),
+            "",
+            Arc::new(MysqlStorage::default()),
+            Protocol::Http,
+        );
+        let old = "0".repeat(40);
+        let new = "1".repeat(40);
+        for ref_name in ["missing", "refs/heads/master"] {
+            pack_protocol.command_list.push(RefCommand::new(
+                old.clone(),
+                new.clone(),
+                ref_name.to_owned(),
+            ));
+        }
+
+        block_on(pack_protocol.finish_received_pack(Box::new(MissingReceiver)));
+
+        let statuses: Vec<String> = pack_protocol
+            .command_list
+            .iter()
+            .map(|command| command.get_status())
+            .collect();
+        assert!(statuses[0].starts_with("ng missing missing necessary objects"));
+        assert!(statuses[1].starts_with("ok refs/heads/master"));
+    }
+
+    #[test]
+    pub fn test_push_failure_reason() {
+        let missing = GitError::MissingObjects("blob 1234".to_owned());
+        assert_eq!(push_failure_reason(&missing), "missing necessary objects");
+        let rejected = GitError::RefUpdateRejected("fetch first".to_owned());
+        assert_eq!(push_failure_reason(&rejected), "fetch first");
+        let invalid = GitError::InvalidHashValue("xyz".to_owned());
+        assert_eq!(push_failure_reason(&invalid), "internal server error");
+        let db = GitError::DbError(sea_orm::DbErr::Custom("gone".to_owned()));
+        assert_eq!(push_failure_reason(&db), "db operation failed");
+    }
 
     #[test]
     pub fn test_read_pkt_line() {
@@ -4,7 +4,8 @@
 //!
 
 use async_trait::async_trait;
-use bytes::{BufMut, Bytes, BytesMut};
+use bytes::Bytes;
+use futures::StreamExt;
 use russh::server::{Auth, Msg, Session};
 use russh::*;
 use russh_keys::*;
@@ -12,12 +13,10 @@ use std::collections::HashMap;
 
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
-use tokio::io::{AsyncReadExt, BufReader};
 
 use crate::git::protocol::ServiceType;
 use crate::gust::driver::ObjectStorage;
 
-use super::pack::{self};
 use super::{PackProtocol, Protocol};
 
 #[derive(Clone)]
@@ -30,7 +29,7 @@ pub struct SshServer<T: ObjectStorage> {
     pub pack_protocol: Option<PackProtocol<T>>,
 }
 
-impl<T: ObjectStorage> server::Server for SshServer<T> {
+impl<T: ObjectStorage + 'static> server::Server for SshServer<T> {
     type Handler = Self;
     fn new_client(&mut self, _: Option<std::net::SocketAddr>) -> Self {
         let s = self.clone();
@@ -40,7 +39,7 @@ impl<T: ObjectStorage> server::Server for SshServer<T> {
 }
 
 #[async_trait]
-impl<T: ObjectStorage> server::Handler for SshServer<T> {
+impl<T: ObjectStorage + 'static> server::Handler for SshServer<T> {
     type Error = anyhow::Error;
 
     async fn channel_open_session(
@@ -137,7 +136,7 @@ impl<T: ObjectStorage> server::Handler for SshServer<T> {
     // }
 }
 
-impl<T: ObjectStorage> SshServer<T> {
+impl<T: ObjectStorage + 'static> SshServer<T> {
     async fn handle_git_command(&mut self, command: &str) -> String {
         let command: Vec<_> = command.split(' ').collect();
         // command:
@@ -159,7 +158,7 @@ impl<T: ObjectStorage> SshServer<T> {
     async fn handle_upload_pack(&mut self, channel: ChannelId, data: &[u8], session: &mut Session) {
         let pack_protocol = self.pack_protocol.as_mut().unwrap();
 
-        let (send_pack_data, buf) = pack_protocol
+        let (pack_request, buf) = pack_protocol
             .git_upload_pack(&mut Bytes::copy_from_slice(data))
             .await
             .unwrap();
@@ -167,21 +166,18 @@ impl<T: ObjectStorage> SshServer<T> {
         tracing::info!("buf is {:?}", buf);
         session.data(channel, String::from_utf8(buf.to_vec()).unwrap().into());
 
-        let mut reader = BufReader::new(send_pack_data.as_slice());
-        loop {
-            let mut temp = BytesMut::new();
-            let length = reader.read_buf(&mut temp).await.unwrap();
-            if temp.is_empty() {
-                let mut bytes_out = BytesMut::new();
-                bytes_out.put_slice(pack::PKT_LINE_END_MARKER);
-                tracing::info!("send: ends: {:?}", bytes_out.clone().freeze());
-                session.data(channel, bytes_out.to_vec().into());
-                return;
+        // the session only sends the data of a handler once it returns, the pack is sent
+        // through a handle as it's written
+        let mut packets = Box::pin(pack_protocol.pack_stream(pack_request));
+        let handle = session.handle();
+        tokio::spawn(async move {
+            while let Some(packet) = packets.next().await {
+                if handle.data(channel, packet.to_vec().into()).await.is_err() {
+                    tracing::error!("channel {:?} closed while sending the pack", channel);
+                    return;
+                }
             }
-            let bytes_out = pack_protocol.build_side_band_format(temp, length);
-            tracing::info!("send: bytes_out: {:?}", bytes_out.clone().freeze());
-            session.data(channel, bytes_out.to_vec().into());
-        }
+        });
     }
 
     async fn handle_receive_pack(
@@ -0,0 +1,1136 @@
+//!
+//! OpenPGP signatures (RFC 4880), as made by `gpg --detach-sign` for git. Only v4 keys and
+//! signatures are read, with Ed25519 or RSA keys and SHA-2 digests. Signatures are made with
+//! SHA-256.
+//!
+//! The self signatures of a key may also be SHA-1 digests, as older gpg made them. A collision
+//! only lets the holder of the key forge what they sign themselves, while a SHA-1 signature of
+//! a commit or tag could be moved to a colliding one, so those are still rejected.
+//!
+
+use ed25519_dalek::{Signer, Verifier};
+use rsa::traits::PublicKeyParts;
+use rsa::{BigUint, Pkcs1v15Sign};
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha384, Sha512};
+
+use super::{
+    armor, dearmor, KeyType, PublicKey, Reader, SecretKey, SignatureStatus, SignerKey, SigningKey,
+    Verification, VerifyingKey,
+};
+use crate::git::errors::GitError;
+
+const SIGNATURE_PACKET: u8 = 2;
+const SECRET_KEY_PACKET: u8 = 5;
+const PUBLIC_KEY_PACKET: u8 = 6;
+const SECRET_SUBKEY_PACKET: u8 = 7;
+const USER_ID_PACKET: u8 = 13;
+const PUBLIC_SUBKEY_PACKET: u8 = 14;
+const USER_ATTRIBUTE_PACKET: u8 = 17;
+
+const BINARY_SIGNATURE: u8 = 0x00;
+const TEXT_SIGNATURE: u8 = 0x01;
+const SUBKEY_BINDING_SIGNATURE: u8 = 0x18;
+const PRIMARY_KEY_BINDING_SIGNATURE: u8 = 0x19;
+const DIRECT_KEY_SIGNATURE: u8 = 0x1F;
+const KEY_REVOCATION_SIGNATURE: u8 = 0x20;
+const SUBKEY_REVOCATION_SIGNATURE: u8 = 0x28;
+
+const RSA: [u8; 3] = [1, 2, 3];
+const ELGAMAL: u8 = 16;
+const DSA: u8 = 17;
+const ECDH: u8 = 18;
+const ECDSA: u8 = 19;
+const EDDSA_LEGACY: u8 = 22;
+const X25519: u8 = 25;
+const ED25519: u8 = 27;
+
+const SHA1: u8 = 2;
+const SHA256: u8 = 8;
+const SHA384: u8 = 9;
+const SHA512: u8 = 10;
+
+/// the curve OID of Ed25519 keys of the legacy EdDSA algorithm
+const ED25519_OID: &[u8] = &[0x2B, 0x06, 0x01, 0x04, 0x01, 0xDA, 0x47, 0x0F, 0x01];
+
+const CREATION_TIME_SUBPACKET: u8 = 2;
+const SIGNATURE_EXPIRATION_SUBPACKET: u8 = 3;
+const KEY_EXPIRATION_SUBPACKET: u8 = 9;
+const ISSUER_SUBPACKET: u8 = 16;
+const KEY_FLAGS_SUBPACKET: u8 = 27;
+const EMBEDDED_SIGNATURE_SUBPACKET: u8 = 32;
+const ISSUER_FINGERPRINT_SUBPACKET: u8 = 33;
+
+/// the key flag of keys signing data
+const SIGN_FLAG: u8 = 0x02;
+
+/// how far in the future of the server a signature may be made, as clocks drift
+const CLOCK_SKEW: u32 = 5 * 60;
+
+/// Parse an armored public key block. The primary key must be certified by one of its self
+/// signatures, and a subkey bound by the primary key and, as it signs, by a back signature.
+/// The keys without the sign flag in their latest self signature are left out.
+pub(super) fn parse_public_key(text: &str) -> Result<PublicKey, GitError> {
+    let invalid = |msg: &str| GitError::InvalidPublicKey(msg.to_owned());
+    let data = dearmor(text.as_bytes(), "PGP PUBLIC KEY BLOCK")
+        .ok_or_else(|| invalid("malformed armor"))?;
+    let packets = packets(&data).ok_or_else(|| invalid("malformed packets"))?;
+    let mut packets = packets.into_iter();
+    let primary = match packets.next() {
+        Some((PUBLIC_KEY_PACKET, body)) => body,
+        _ => return Err(invalid("no primary key")),
+    };
+    let primary_key = parse_key_packet(primary)
+        .ok_or_else(|| invalid("the primary key is not an Ed25519 or RSA key"))?;
+    let primary_data = key_data(primary);
+
+    // the packets a signature is about, the signatures follow them
+    enum Component<'a> {
+        Primary,
+        UserId(&'a [u8]),
+        Subkey(usize),
+        Other,
+    }
+    let mut component = Component::Primary;
+    let mut self_signature: Option<Signature> = None;
+    let mut revoked = false;
+    let mut subkeys: Vec<(&[u8], Option<Signature>, bool)> = Vec::new();
+    for (tag, body) in packets {
+        match tag {
+            PUBLIC_KEY_PACKET => return Err(invalid("expecting a single primary key")),
+            USER_ID_PACKET => component = Component::UserId(body),
+            PUBLIC_SUBKEY_PACKET => {
+                subkeys.push((body, None, false));
+                component = Component::Subkey(subkeys.len() - 1);
+            }
+            USER_ATTRIBUTE_PACKET => component = Component::Other,
+            SIGNATURE_PACKET => {
+                let Some(signature) = Signature::parse(body).filter(|sig| sig.version == 4) else {
+                    continue;
+                };
+                let signed = match &component {
+                    Component::Primary => primary_data.clone(),
+                    Component::UserId(user_id) => {
+                        [&primary_data[..], &user_id_data(user_id)].concat()
+                    }
+                    Component::Subkey(i) => [&primary_data[..], &key_data(subkeys[*i].0)].concat(),
+                    Component::Other => continue,
+                };
+                // only the signatures of the primary key bind its components
+                if signature.check_data(&signed, &primary_key, true) != Some(true) {
+                    continue;
+                }
+                match (&component, signature.sig_type) {
+                    (Component::Primary, DIRECT_KEY_SIGNATURE)
+                    | (Component::UserId(_), 0x10..=0x13)
+                        if is_later(&signature, &self_signature) =>
+                    {
+                        self_signature = Some(signature);
+                    }
+                    (Component::Primary, KEY_REVOCATION_SIGNATURE) => revoked = true,
+                    (Component::Subkey(i), SUBKEY_BINDING_SIGNATURE)
+                        if is_later(&signature, &subkeys[*i].1) =>
+                    {
+                        subkeys[*i].1 = Some(signature);
+                    }
+                    (Component::Subkey(i), SUBKEY_REVOCATION_SIGNATURE) => subkeys[*i].2 = true,
+                    _ => {}
+                }
+            }
+            // trust packets and the like don't change what signatures are about
+            _ => {}
+        }
+    }
+
+    let self_signature = self_signature.ok_or_else(|| invalid("no valid self signature"))?;
+    let key_id = hex::encode_upper(fingerprint(primary));
+    let mut keys = Vec::new();
+    if self_signature.can_sign() {
+        keys.push(signer_key(
+            primary,
+            primary_key.clone(),
+            &self_signature,
+            revoked,
+        ));
+    }
+    for (body, binding, subkey_revoked) in subkeys {
+        let (Some(binding), Some(key)) = (binding, parse_key_packet(body)) else {
+            continue;
+        };
+        // a signing subkey signs its binding back, else anyone could bind the key of
+        // someone else to theirs
+        let signed = [&primary_data[..], &key_data(body)].concat();
+        let back_signed = binding.embedded_signature().is_some_and(|back| {
+            back.sig_type == PRIMARY_KEY_BINDING_SIGNATURE
+                && back.check_data(&signed, &key, true) == Some(true)
+        });
+        if binding.can_sign() && back_signed {
+            // a revoked primary key revokes its subkeys
+            keys.push(signer_key(body, key, &binding, revoked || subkey_revoked));
+        }
+    }
+    if keys.is_empty() {
+        return Err(invalid("no Ed25519 or RSA key able to sign"));
+    }
+    Ok(PublicKey {
+        key_type: KeyType::OpenPgp,
+        key_id,
+        text: text.to_owned(),
+        keys,
+    })
+}
+
+/// The key of the key packet `body` with the validity of its latest self signature.
+fn signer_key(
+    body: &[u8],
+    key: VerifyingKey,
+    self_signature: &Signature,
+    revoked: bool,
+) -> SignerKey {
+    let created = key_created(body);
+    SignerKey {
+        id: fingerprint(body).to_vec(),
+        key,
+        created,
+        expires: self_signature
+            .u32_subpacket(KEY_EXPIRATION_SUBPACKET)
+            .filter(|seconds| *seconds != 0)
+            .map(|seconds| created.saturating_add(seconds)),
+        revoked,
+    }
+}
+
+fn is_later(signature: &Signature, than: &Option<Signature>) -> bool {
+    than.as_ref()
+        .is_none_or(|than| signature.created() >= than.created())
+}
+
+/// Parse an armored secret key block whose secret keys aren't protected, as exported by
+/// `gpg --export-secret-keys` for a key without passphrase. The primary key signs if it's an
+/// Ed25519 or RSA key able to sign, else the first such subkey.
+pub(super) fn parse_signing_key(text: &str) -> Result<SigningKey, GitError> {
+    let invalid = |msg: &str| GitError::InvalidSigningKey(msg.to_owned());
+    let data = dearmor(text.as_bytes(), "PGP PRIVATE KEY BLOCK")
+        .ok_or_else(|| invalid("malformed armor"))?;
+    let packets = packets(&data).ok_or_else(|| invalid("malformed packets"))?;
+    // the public key block of the key, the public part of the keys with their user ids and
+    // signatures
+    let mut public = Vec::new();
+    let mut secrets = Vec::new();
+    let mut skipping = false;
+    for (tag, body) in packets {
+        match tag {
+            SECRET_KEY_PACKET | SECRET_SUBKEY_PACKET => {
+                // the signatures of a key left out are left out too
+                let Some(len) = public_len(body) else {
+                    skipping = true;
+                    continue;
+                };
+                skipping = false;
+                let (public_part, secret_part) = body.split_at(len);
+                let public_tag = if tag == SECRET_KEY_PACKET {
+                    PUBLIC_KEY_PACKET
+                } else {
+                    PUBLIC_SUBKEY_PACKET
+                };
+                put_packet(&mut public, public_tag, public_part);
+                if let Some(secret) = parse_secret(public_part, secret_part)? {
+                    secrets.push((public_part.to_vec(), secret));
+                }
+            }
+            USER_ID_PACKET | SIGNATURE_PACKET if !skipping => put_packet(&mut public, tag, body),
+            _ => {}
+        }
+    }
+    let checksum = crc24(&public);
+    let public_key = parse_public_key(&armor("PGP PUBLIC KEY BLOCK", &public, 64, Some(checksum)))?;
+    // the first key the public key lets sign
+    let (id, secret) = secrets
+        .into_iter()
+        .find(|(id, _)| {
+            let fingerprint = fingerprint(id);
+            public_key.keys.iter().any(|key| key.id == fingerprint)
+        })
+        .ok_or_else(|| invalid("no Ed25519 or RSA secret key able to sign"))?;
+    Ok(SigningKey {
+        public_key,
+        id,
+        secret,
+    })
+}
+
+/// Sign `payload` as a binary document made at `time`, the armored signature is returned.
+pub(super) fn sign(payload: &[u8], time: u32, key: &SigningKey) -> Option<String> {
+    // gpg rejects signatures older than their key
+    let time = time.max(key_created(&key.id));
+    let packet = signature_packet(key, BINARY_SIGNATURE, &issued(key, time), payload)?;
+    Some(armor("PGP SIGNATURE", &packet, 64, Some(crc24(&packet))))
+}
+
+/// The hashed subpackets of a signature by `key` made at `time`.
+fn issued(key: &SigningKey, time: u32) -> Vec<u8> {
+    let mut subpackets = Vec::new();
+    put_subpacket(
+        &mut subpackets,
+        ISSUER_FINGERPRINT_SUBPACKET,
+        &[&[4][..], &fingerprint(&key.id)].concat(),
+    );
+    put_subpacket(
+        &mut subpackets,
+        CREATION_TIME_SUBPACKET,
+        &time.to_be_bytes(),
+    );
+    subpackets
+}
+
+/// The packet of a signature of `sig_type` by `key` over `data` with SHA-256.
+fn signature_packet(
+    key: &SigningKey,
+    sig_type: u8,
+    hashed_subpackets: &[u8],
+    data: &[u8],
+) -> Option<Vec<u8>> {
+    let algorithm = *key.id.get(5)?;
+    let fingerprint = fingerprint(&key.id);
+    let mut hashed = vec![4, sig_type, algorithm, SHA256];
+    hashed.extend_from_slice(&(hashed_subpackets.len() as u16).to_be_bytes());
+    hashed.extend_from_slice(hashed_subpackets);
+    let mut trailer = vec![4, 0xFF];
+    trailer.extend_from_slice(&(hashed.len() as u32).to_be_bytes());
+    let digest = digest::<Sha256>(&[data, &hashed, &trailer]);
+
+    let mut material = Vec::new();
+    match &key.secret {
+        SecretKey::Ed25519(keypair) => {
+            let signature = keypair.sign(&digest).to_bytes();
+            if algorithm == ED25519 {
+                material.extend_from_slice(&signature);
+            } else {
+                put_mpi(&mut material, &signature[..32]);
+                put_mpi(&mut material, &signature[32..]);
+            }
+        }
+        SecretKey::Rsa(private) => {
+            let padding = Pkcs1v15Sign::new::<Sha256>();
+            put_mpi(&mut material, &private.sign(padding, &digest).ok()?);
+        }
+        SecretKey::Ssh(_) => return None,
+    }
+
+    let mut unhashed_subpackets = Vec::new();
+    put_subpacket(
+        &mut unhashed_subpackets,
+        ISSUER_SUBPACKET,
+        &fingerprint[12..],
+    );
+    let mut body = hashed;
+    body.extend_from_slice(&(unhashed_subpackets.len() as u16).to_be_bytes());
+    body.extend_from_slice(&unhashed_subpackets);
+    body.extend_from_slice(&digest[..2]);
+    body.extend_from_slice(&material);
+    let mut packet = Vec::new();
+    put_packet(&mut packet, SIGNATURE_PACKET, &body);
+    Some(packet)
+}
+
+/// Verify the signature `armored` over `payload` as of `now`.
+pub(super) fn verify(payload: &[u8], armored: &[u8], keys: &[PublicKey], now: u32) -> Verification {
+    let data = dearmor(armored, "PGP SIGNATURE").unwrap_or_default();
+    let body = packets(&data).and_then(|packets| {
+        packets
+            .into_iter()
+            .find(|(tag, _)| *tag == SIGNATURE_PACKET)
+            .map(|(_, body)| body)
+    });
+    let Some(body) = body else {
+        return Verification::status(SignatureStatus::Bad);
+    };
+    if body.first() != Some(&4) {
+        return Verification::status(SignatureStatus::Unsupported);
+    }
+    let Some(signature) = Signature::parse(body) else {
+        return Verification::status(SignatureStatus::Bad);
+    };
+    let Some(issuer) = signature.issuer() else {
+        return Verification::status(SignatureStatus::Bad);
+    };
+    let found = keys
+        .iter()
+        .filter(|key| key.key_type == KeyType::OpenPgp)
+        .find_map(|key| {
+            key.keys
+                .iter()
+                .find(|signer| signer.id.ends_with(&issuer))
+                .map(|signer| (key, signer))
+        });
+    let Some((key, signer)) = found else {
+        return Verification::status(SignatureStatus::UnknownKey);
+    };
+    let status = match signature.check(payload, &signer.key) {
+        Some(true) => validity(&signature, signer, now),
+        Some(false) => SignatureStatus::Bad,
+        None => SignatureStatus::Unsupported,
+    };
+    Verification {
+        status,
+        key_id: Some(key.key_id.clone()),
+    }
+}
+
+/// The status of a signature by `signer` matching its data, as of `now`.
+fn validity(signature: &Signature, signer: &SignerKey, now: u32) -> SignatureStatus {
+    // a signature must tell when it was made to be checked against the life of its key
+    let Some(created) = signature.created() else {
+        return SignatureStatus::Bad;
+    };
+    if created < signer.created || created > now.saturating_add(CLOCK_SKEW) {
+        return SignatureStatus::Bad;
+    }
+    if signer.revoked {
+        return SignatureStatus::RevokedKey;
+    }
+    if signer.expires.is_some_and(|expires| expires <= now) {
+        return SignatureStatus::ExpiredKey;
+    }
+    let expired = signature
+        .u32_subpacket(SIGNATURE_EXPIRATION_SUBPACKET)
+        .filter(|seconds| *seconds != 0)
+        .is_some_and(|seconds| created.saturating_add(seconds) <= now);
+    if expired {
+        return SignatureStatus::Expired;
+    }
+    SignatureStatus::Verified
+}
+
+/// A v4 signature packet.
+struct Signature<'a> {
+    version: u8,
+    sig_type: u8,
+    public_key_algorithm: u8,
+    hash_algorithm: u8,
+    /// the leading fields and the hashed subpackets, all covered by the digest
+    hashed: &'a [u8],
+    hashed_subpackets: &'a [u8],
+    unhashed_subpackets: &'a [u8],
+    digest_prefix: &'a [u8],
+    material: &'a [u8],
+}
+
+impl<'a> Signature<'a> {
+    fn parse(body: &'a [u8]) -> Option<Signature<'a>> {
+        let mut reader = Reader::new(body);
+        let version = reader.u8()?;
+        let sig_type = reader.u8()?;
+        let public_key_algorithm = reader.u8()?;
+        let hash_algorithm = reader.u8()?;
+        let hashed_len = reader.u16()? as usize;
+        let hashed_subpackets = reader.take(hashed_len)?;
+        let unhashed_len = reader.u16()? as usize;
+        let unhashed_subpackets = reader.take(unhashed_len)?;
+        Some(Signature {
+            version,
+            sig_type,
+            public_key_algorithm,
+            hash_algorithm,
+            hashed: &body[..6 + hashed_len],
+            hashed_subpackets,
+            unhashed_subpackets,
+            digest_prefix: reader.take(2)?,
+            material: reader.rest(),
+        })
+    }
+
+    /// The key id of the issuer, the low 64 bits of its fingerprint.
+    fn issuer(&self) -> Option<Vec<u8>> {
+        let subpackets = subpackets(self.hashed_subpackets)?
+            .into_iter()
+            .chain(subpackets(self.unhashed_subpackets)?);
+        let mut issuer = None;
+        for (kind, data) in subpackets {
+            match kind {
+                ISSUER_FINGERPRINT_SUBPACKET if data.len() == 21 => {
+                    return Some(data[13..].to_vec());
+                }
+                ISSUER_SUBPACKET if data.len() == 8 => issuer = Some(data.to_vec()),
+                _ => {}
+            }
+        }
+        issuer
+    }
+
+    /// The data of the hashed subpacket `kind`, subpackets outside of the hashed area are
+    /// not covered by the signature.
+    fn subpacket(&self, kind: u8) -> Option<&'a [u8]> {
+        subpackets(self.hashed_subpackets)?
+            .into_iter()
+            .find(|(k, _)| *k == kind)
+            .map(|(_, data)| data)
+    }
+
+    fn u32_subpacket(&self, kind: u8) -> Option<u32> {
+        let data: [u8; 4] = self.subpacket(kind)?.try_into().ok()?;
+        Some(u32::from_be_bytes(data))
+    }
+
+    fn created(&self) -> Option<u32> {
+        self.u32_subpacket(CREATION_TIME_SUBPACKET)
+    }
+
+    /// Whether the key flags of a self signature let the key sign data.
+    fn can_sign(&self) -> bool {
+        self.subpacket(KEY_FLAGS_SUBPACKET)
+            .and_then(|flags| flags.first())
+            .is_some_and(|flags| flags & SIGN_FLAG != 0)
+    }
+
+    /// The back signature of a subkey binding signature, it may be in either area as it's
+    /// signed by itself.
+    fn embedded_signature(&self) -> Option<Signature<'a>> {
+        let subpackets = subpackets(self.hashed_subpackets)?
+            .into_iter()
+            .chain(subpackets(self.unhashed_subpackets)?);
+        for (kind, data) in subpackets {
+            if kind == EMBEDDED_SIGNATURE_SUBPACKET {
+                return Signature::parse(data).filter(|sig| sig.version == 4);
+            }
+        }
+        None
+    }
+
+    /// Whether the signature is valid for the document `payload` made with `key`, `None` for
+    /// algorithms not supported.
+    fn check(&self, payload: &[u8], key: &VerifyingKey) -> Option<bool> {
+        match self.sig_type {
+            BINARY_SIGNATURE => self.check_data(payload, key, false),
+            // text signatures are over the lines ended by CRLF
+            TEXT_SIGNATURE => self.check_data(&canonical_text(payload), key, false),
+            _ => Some(false),
+        }
+    }
+
+    /// Whether the signature is valid for `data`, the document or the key packets signed. SHA-1
+    /// digests are only accepted for the `self_signed` key packets.
+    fn check_data(&self, data: &[u8], key: &VerifyingKey, self_signed: bool) -> Option<bool> {
+        let mut trailer = vec![4, 0xFF];
+        trailer.extend_from_slice(&(self.hashed.len() as u32).to_be_bytes());
+        let parts = [data, self.hashed, &trailer];
+        let (digest, padding) = match self.hash_algorithm {
+            SHA1 if self_signed => (digest::<Sha1>(&parts), Pkcs1v15Sign::new::<Sha1>()),
+            SHA256 => (digest::<Sha256>(&parts), Pkcs1v15Sign::new::<Sha256>()),
+            SHA384 => (digest::<Sha384>(&parts), Pkcs1v15Sign::new::<Sha384>()),
+            SHA512 => (digest::<Sha512>(&parts), Pkcs1v15Sign::new::<Sha512>()),
+            _ => return None,
+        };
+        if !digest.starts_with(self.digest_prefix) {
+            return Some(false);
+        }
+
+        // a malformed signature is as bad as a wrong one
+        Some(self.check_material(&digest, padding, key).unwrap_or(false))
+    }
+
+    fn check_material(
+        &self,
+        digest: &[u8],
+        padding: Pkcs1v15Sign,
+        key: &VerifyingKey,
+    ) -> Option<bool> {
+        let mut material = Reader::new(self.material);
+        match (self.public_key_algorithm, key) {
+            (EDDSA_LEGACY | ED25519, VerifyingKey::Ed25519(public)) => {
+                let bytes = if self.public_key_algorithm == ED25519 {
+                    material.take(64)?.to_vec()
+                } else {
+                    // r and s are MPIs, leading zero bytes are left out
+                    let r = left_pad(mpi(&mut material)?, 32)?;
+                    let s = left_pad(mpi(&mut material)?, 32)?;
+                    [r, s].concat()
+                };
+                let signature = ed25519_dalek::Signature::from_bytes(&bytes).ok()?;
+                Some(public.verify(digest, &signature).is_ok())
+            }
+            (algorithm, VerifyingKey::Rsa(public)) if RSA.contains(&algorithm) => {
+                let signature = left_pad(mpi(&mut material)?, public.size())?;
+                Some(public.verify(padding, digest, &signature).is_ok())
+            }
+            _ => Some(false),
+        }
+    }
+}
+
+/// The key of a v4 public key or subkey packet, `None` for other versions and algorithms.
+fn parse_key_packet(body: &[u8]) -> Option<VerifyingKey> {
+    let mut reader = Reader::new(body);
+    if reader.u8()? != 4 {
+        return None;
+    }
+    let _created = reader.u32()?;
+    match reader.u8()? {
+        algorithm if RSA.contains(&algorithm) => {
+            let n = BigUint::from_bytes_be(mpi(&mut reader)?);
+            let e = BigUint::from_bytes_be(mpi(&mut reader)?);
+            rsa::RsaPublicKey::new(n, e).ok().map(VerifyingKey::Rsa)
+        }
+        EDDSA_LEGACY => {
+            let oid_len = reader.u8()? as usize;
+            if reader.take(oid_len)? != ED25519_OID {
+                return None;
+            }
+            // the point is prefixed by 0x40, the native encoding
+            let point = mpi(&mut reader)?;
+            let key = point.strip_prefix(&[0x40])?;
+            ed25519_dalek::PublicKey::from_bytes(key)
+                .ok()
+                .map(VerifyingKey::Ed25519)
+        }
+        ED25519 => ed25519_dalek::PublicKey::from_bytes(reader.take(32)?)
+            .ok()
+            .map(VerifyingKey::Ed25519),
+        _ => None,
+    }
+}
+
+/// The length of the public fields of a v4 key packet, `None` for other versions and
+/// algorithms not known.
+fn public_len(body: &[u8]) -> Option<usize> {
+    let mut reader = Reader::new(body);
+    if reader.u8()? != 4 {
+        return None;
+    }
+    let _created = reader.u32()?;
+    let mpis = match reader.u8()? {
+        algorithm if RSA.contains(&algorithm) => 2,
+        ELGAMAL => 3,
+        DSA => 4,
+        ECDSA | ECDH | EDDSA_LEGACY => {
+            let oid_len = reader.u8()? as usize;
+            reader.take(oid_len)?;
+            1
+        }
+        X25519 | ED25519 => {
+            reader.take(32)?;
+            0
+        }
+        _ => return None,
+    };
+    for _ in 0..mpis {
+        mpi(&mut reader)?;
+    }
+    // the key derivation parameters of ECDH keys
+    if body[5] == ECDH {
+        let kdf_len = reader.u8()? as usize;
+        reader.take(kdf_len)?;
+    }
+    Some(body.len() - reader.data.len())
+}
+
+/// The secret key of a secret key packet split at the end of its public fields, `None` for
+/// algorithms not supported.
+fn parse_secret(public: &[u8], secret: &[u8]) -> Result<Option<SecretKey>, GitError> {
+    let Some(key) = parse_key_packet(public) else {
+        return Ok(None);
+    };
+    let mut reader = Reader::new(secret);
+    if reader.u8() != Some(0) {
+        return Err(GitError::InvalidSigningKey(
+            "protected secret keys are not supported, export the key without passphrase".to_owned(),
+        ));
+    }
+    let secret = match key {
+        VerifyingKey::Ed25519(public_key) => {
+            let seed = if public[5] == ED25519 {
+                reader.take(32).map(|seed| seed.to_vec())
+            } else {
+                mpi(&mut reader).and_then(|seed| left_pad(seed, 32))
+            };
+            seed.and_then(|seed| ed25519_dalek::SecretKey::from_bytes(&seed).ok())
+                .filter(|secret| ed25519_dalek::PublicKey::from(secret) == public_key)
+                .map(|secret| {
+                    SecretKey::Ed25519(ed25519_dalek::Keypair {
+                        secret,
+                        public: public_key,
+                    })
+                })
+        }
+        VerifyingKey::Rsa(public_key) => (|| {
+            let d = BigUint::from_bytes_be(mpi(&mut reader)?);
+            let p = BigUint::from_bytes_be(mpi(&mut reader)?);
+            let q = BigUint::from_bytes_be(mpi(&mut reader)?);
+            let n = public_key.n().clone();
+            let e = public_key.e().clone();
+            let private = rsa::RsaPrivateKey::from_components(n, e, d, vec![p, q]).ok()?;
+            private.validate().ok()?;
+            Some(SecretKey::Rsa(private))
+        })(),
+        VerifyingKey::Ssh(_) => None,
+    };
+    secret
+        .map(Some)
+        .ok_or_else(|| GitError::InvalidSigningKey("malformed secret key".to_owned()))
+}
+
+/// the v4 fingerprint of a key packet
+fn fingerprint(body: &[u8]) -> [u8; 20] {
+    let mut hasher = Sha1::new();
+    hasher.update([0x99]);
+    hasher.update((body.len() as u16).to_be_bytes());
+    hasher.update(body);
+    hasher.finalize().into()
+}
+
+/// the creation time of a v4 key packet
+fn key_created(body: &[u8]) -> u32 {
+    body.get(1..5).map_or(0, |time| {
+        u32::from_be_bytes([time[0], time[1], time[2], time[3]])
+    })
+}
+
+/// a key packet as covered by the signatures about it
+fn key_data(body: &[u8]) -> Vec<u8> {
+    let mut data = vec![0x99];
+    data.extend_from_slice(&(body.len() as u16).to_be_bytes());
+    data.extend_from_slice(body);
+    data
+}
+
+/// a user id packet as covered by its certifications
+fn user_id_data(body: &[u8]) -> Vec<u8> {
+    let mut data = vec![0xB4];
+    data.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    data.extend_from_slice(body);
+    data
+}
+
+/// The packets of `data` as `(tag, body)`, partial body lengths are not supported.
+fn packets(data: &[u8]) -> Option<Vec<(u8, &[u8])>> {
+    let mut reader = Reader::new(data);
+    let mut packets = Vec::new();
+    while !reader.data.is_empty() {
+        let header = reader.u8()?;
+        if header & 0x80 == 0 {
+            return None;
+        }
+        let (tag, len) = if header & 0x40 != 0 {
+            let tag = header & 0x3F;
+            let len = match reader.u8()? as usize {
+                len @ 0..=191 => len,
+                first @ 192..=223 => ((first - 192) << 8) + reader.u8()? as usize + 192,
+                255 => reader.u32()? as usize,
+                _ => return None,
+            };
+            (tag, len)
+        } else {
+            let tag = (header >> 2) & 0x0F;
+            let len = match header & 0x03 {
+                0 => reader.u8()? as usize,
+                1 => reader.u16()? as usize,
+                2 => reader.u32()? as usize,
+                _ => reader.data.len(),
+            };
+            (tag, len)
+        };
+        packets.push((tag, reader.take(len)?));
+    }
+    Some(packets)
+}
+
+/// Append a packet in the new format.
+fn put_packet(out: &mut Vec<u8>, tag: u8, body: &[u8]) {
+    out.push(0xC0 | tag);
+    match body.len() {
+        len @ 0..=191 => out.push(len as u8),
+        len @ 192..=8383 => {
+            out.push((((len - 192) >> 8) + 192) as u8);
+            out.push((len - 192) as u8);
+        }
+        len => {
+            out.push(0xFF);
+            out.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+    }
+    out.extend_from_slice(body);
+}
+
+fn put_subpacket(out: &mut Vec<u8>, kind: u8, data: &[u8]) {
+    out.push(data.len() as u8 + 1);
+    out.push(kind);
+    out.extend_from_slice(data);
+}
+
+/// The subpackets of a signature as `(type, data)`, the critical bit is dropped.
+fn subpackets(data: &[u8]) -> Option<Vec<(u8, &[u8])>> {
+    let mut reader = Reader::new(data);
+    let mut subpackets = Vec::new();
+    while !reader.data.is_empty() {
+        let len = match reader.u8()? as usize {
+            len @ 0..=191 => len,
+            first @ 192..=254 => ((first - 192) << 8) + reader.u8()? as usize + 192,
+            _ => reader.u32()? as usize,
+        };
+        let body = reader.take(len)?;
+        let (kind, data) = body.split_first()?;
+        subpackets.push((kind & 0x7F, data));
+    }
+    Some(subpackets)
+}
+
+/// a multiprecision integer, its length in bits followed by its big endian bytes
+fn mpi<'a>(reader: &mut Reader<'a>) -> Option<&'a [u8]> {
+    let bits = reader.u16()? as usize;
+    reader.take(bits.div_ceil(8))
+}
+
+fn put_mpi(out: &mut Vec<u8>, data: &[u8]) {
+    let start = data
+        .iter()
+        .position(|byte| *byte != 0)
+        .unwrap_or(data.len());
+    let data = &data[start..];
+    let bits = data
+        .first()
+        .map_or(0, |first| data.len() * 8 - first.leading_zeros() as usize);
+    out.extend_from_slice(&(bits as u16).to_be_bytes());
+    out.extend_from_slice(data);
+}
+
+fn left_pad(data: &[u8], len: usize) -> Option<Vec<u8>> {
+    let padding = len.checked_sub(data.len())?;
+    let mut padded = vec![0; padding];
+    padded.extend_from_slice(data);
+    Some(padded)
+}
+
+fn canonical_text(payload: &[u8]) -> Vec<u8> {
+    let mut text = Vec::with_capacity(payload.len());
+    for (i, byte) in payload.iter().enumerate() {
+        if *byte == b'\n' && (i == 0 || payload[i - 1] != b'\r') {
+            text.push(b'\r');
+        }
+        text.push(*byte);
+    }
+    text
+}
+
+fn digest<D: Digest>(parts: &[&[u8]]) -> Vec<u8> {
+    let mut hasher = D::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().to_vec()
+}
+
+/// the CRC-24 checksum of OpenPGP armor
+fn crc24(data: &[u8]) -> [u8; 3] {
+    let mut crc: u32 = 0xB704CE;
+    for byte in data {
+        crc ^= (*byte as u32) << 16;
+        for _ in 0..8 {
+            crc <<= 1;
+            if crc & 0x1000000 != 0 {
+                crc ^= 0x1864CFB;
+            }
+        }
+    }
+    let [_, a, b, c] = crc.to_be_bytes();
+    [a, b, c]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use super::{
+        armor, crc24, fingerprint, issued, key_data, put_packet, put_subpacket, sign,
+        signature_packet, user_id_data, verify, BINARY_SIGNATURE, CREATION_TIME_SUBPACKET, ED25519,
+        EMBEDDED_SIGNATURE_SUBPACKET, ISSUER_FINGERPRINT_SUBPACKET, KEY_EXPIRATION_SUBPACKET,
+        KEY_FLAGS_SUBPACKET, KEY_REVOCATION_SIGNATURE, PRIMARY_KEY_BINDING_SIGNATURE,
+        PUBLIC_KEY_PACKET, PUBLIC_SUBKEY_PACKET, SIGNATURE_EXPIRATION_SUBPACKET, SIGN_FLAG,
+        SUBKEY_BINDING_SIGNATURE, SUBKEY_REVOCATION_SIGNATURE, USER_ID_PACKET,
+    };
+    use crate::git::signature::{KeyType, PublicKey, SecretKey, SignatureStatus, SigningKey};
+
+    const CERTIFY_FLAG: u8 = 0x01;
+    const CREATED: u32 = 1_600_000_000;
+    const PAYLOAD: &[u8] = b"tree 4b825dc642cb6eb9a060e54bf8d69288fbfa4904\n";
+
+    fn now() -> u32 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as u32
+    }
+
+    /// An Ed25519 key of `seed` made at `CREATED`.
+    fn key(seed: u8) -> SigningKey {
+        let secret = ed25519_dalek::SecretKey::from_bytes(&[seed; 32]).unwrap();
+        let public = ed25519_dalek::PublicKey::from(&secret);
+        let mut id = vec![4];
+        id.extend_from_slice(&CREATED.to_be_bytes());
+        id.push(ED25519);
+        id.extend_from_slice(public.as_bytes());
+        SigningKey {
+            public_key: PublicKey {
+                key_type: KeyType::OpenPgp,
+                key_id: String::new(),
+                text: String::new(),
+                keys: Vec::new(),
+            },
+            id,
+            secret: SecretKey::Ed25519(ed25519_dalek::Keypair { secret, public }),
+        }
+    }
+
+    fn flags_and_expiry(flags: u8, expiry: Option<u32>) -> Vec<u8> {
+        let mut subpackets = issued_at_creation();
+        put_subpacket(&mut subpackets, KEY_FLAGS_SUBPACKET, &[flags]);
+        if let Some(expiry) = expiry {
+            put_subpacket(
+                &mut subpackets,
+                KEY_EXPIRATION_SUBPACKET,
+                &expiry.to_be_bytes(),
+            );
+        }
+        subpackets
+    }
+
+    /// creation time only, the issuer is in the unhashed area
+    fn issued_at_creation() -> Vec<u8> {
+        let mut subpackets = Vec::new();
+        put_subpacket(
+            &mut subpackets,
+            CREATION_TIME_SUBPACKET,
+            &CREATED.to_be_bytes(),
+        );
+        subpackets
+    }
+
+    /// How a subkey is bound to its primary key.
+    enum Binding {
+        Missing,
+        /// without the back signature of the subkey
+        Primary,
+        /// signed by the subkey instead of the primary key
+        Forged,
+        Full,
+        Revoked,
+    }
+
+    /// The public key block of `primary` certified with `flags` and `expiry`, with `subkey`
+    /// bound as a signing key.
+    fn key_block(
+        primary: &SigningKey,
+        flags: u8,
+        expiry: Option<u32>,
+        revoked: bool,
+        subkey: Option<(&SigningKey, Binding)>,
+    ) -> String {
+        let primary_data = key_data(&primary.id);
+        let user_id = b"alice <alice@example.com>";
+        let mut block = Vec::new();
+        put_packet(&mut block, PUBLIC_KEY_PACKET, &primary.id);
+        if revoked {
+            block.extend(
+                signature_packet(
+                    primary,
+                    KEY_REVOCATION_SIGNATURE,
+                    &issued_at_creation(),
+                    &primary_data,
+                )
+                .unwrap(),
+            );
+        }
+        put_packet(&mut block, USER_ID_PACKET, user_id);
+        let certified = [&primary_data[..], &user_id_data(user_id)].concat();
+        block.extend(
+            signature_packet(primary, 0x13, &flags_and_expiry(flags, expiry), &certified).unwrap(),
+        );
+        if let Some((subkey, binding)) = subkey {
+            put_packet(&mut block, PUBLIC_SUBKEY_PACKET, &subkey.id);
+            let bound = [&primary_data[..], &key_data(&subkey.id)].concat();
+            let back = signature_packet(
+                subkey,
+                PRIMARY_KEY_BINDING_SIGNATURE,
+                &issued_at_creation(),
+                &bound,
+            )
+            .unwrap();
+            let mut subpackets = flags_and_expiry(SIGN_FLAG, None);
+            // the body of the back signature packet, after its 2 bytes of header
+            put_subpacket(&mut subpackets, EMBEDDED_SIGNATURE_SUBPACKET, &back[2..]);
+            let signer = match binding {
+                Binding::Forged => subkey,
+                _ => primary,
+            };
+            match binding {
+                Binding::Missing => {}
+                Binding::Primary => block.extend(
+                    signature_packet(
+                        primary,
+                        SUBKEY_BINDING_SIGNATURE,
+                        &flags_and_expiry(SIGN_FLAG, None),
+                        &bound,
+                    )
+                    .unwrap(),
+                ),
+                Binding::Forged | Binding::Full | Binding::Revoked => block.extend(
+                    signature_packet(signer, SUBKEY_BINDING_SIGNATURE, &subpackets, &bound)
+                        .unwrap(),
+                ),
+            }
+            if let Binding::Revoked = binding {
+                block.extend(
+                    signature_packet(
+                        primary,
+                        SUBKEY_REVOCATION_SIGNATURE,
+                        &issued_at_creation(),
+                        &bound,
+                    )
+                    .unwrap(),
+                );
+            }
+        }
+        armor("PGP PUBLIC KEY BLOCK", &block, 64, Some(crc24(&block)))
+    }
+
+    fn status(block: &str, signer: &SigningKey, time: u32) -> SignatureStatus {
+        let public: PublicKey = block.parse().unwrap();
+        let signature = sign(PAYLOAD, time, signer).unwrap();
+        verify(PAYLOAD, signature.as_bytes(), &[public], now()).status
+    }
+
+    fn armored_signature(signer: &SigningKey, hashed_subpackets: &[u8]) -> String {
+        let packet =
+            signature_packet(signer, BINARY_SIGNATURE, hashed_subpackets, PAYLOAD).unwrap();
+        armor("PGP SIGNATURE", &packet, 64, Some(crc24(&packet)))
+    }
+
+    #[test]
+    fn test_primary_key_validity() {
+        let (primary, time) = (key(1), now() - 60);
+        let block = key_block(&primary, CERTIFY_FLAG | SIGN_FLAG, None, false, None);
+        assert_eq!(SignatureStatus::Verified, status(&block, &primary, time));
+
+        // expired a day after it was made
+        let block = key_block(&primary, SIGN_FLAG, Some(86400), false, None);
+        assert_eq!(SignatureStatus::ExpiredKey, status(&block, &primary, time));
+        // expiring in the future
+        let expiry = now() - CREATED + 86400;
+        let block = key_block(&primary, SIGN_FLAG, Some(expiry), false, None);
+        assert_eq!(SignatureStatus::Verified, status(&block, &primary, time));
+
+        let block = key_block(&primary, SIGN_FLAG, None, true, None);
+        assert_eq!(SignatureStatus::RevokedKey, status(&block, &primary, time));
+
+        // a key which can't sign data is left out, a key with no such key is refused
+        let block = key_block(&primary, CERTIFY_FLAG, None, false, None);
+        assert!(block.parse::<PublicKey>().is_err());
+        let subkey = key(2);
+        let block = key_block(
+            &primary,
+            CERTIFY_FLAG,
+            None,
+            false,
+            Some((&subkey, Binding::Full)),
+        );
+        assert_eq!(SignatureStatus::UnknownKey, status(&block, &primary, time));
+        assert_eq!(SignatureStatus::Verified, status(&block, &subkey, time));
+
+        // a certification by another key doesn't make a self signature
+        let user_id = b"alice <alice@example.com>";
+        let certified = [&key_data(&primary.id)[..], &user_id_data(user_id)].concat();
+        let mut block = Vec::new();
+        put_packet(&mut block, PUBLIC_KEY_PACKET, &primary.id);
+        put_packet(&mut block, USER_ID_PACKET, user_id);
+        block.extend(
+            signature_packet(
+                &key(3),
+                0x13,
+                &flags_and_expiry(SIGN_FLAG, None),
+                &certified,
+            )
+            .unwrap(),
+        );
+        let forged = armor("PGP PUBLIC KEY BLOCK", &block, 64, Some(crc24(&block)));
+        assert!(forged.parse::<PublicKey>().is_err());
+    }
+
+    #[test]
+    fn test_subkey_binding() {
+        let (primary, subkey, time) = (key(1), key(2), now() - 60);
+        for (binding, expected) in [
+            (Binding::Full, SignatureStatus::Verified),
+            (Binding::Missing, SignatureStatus::UnknownKey),
+            (Binding::Primary, SignatureStatus::UnknownKey),
+            (Binding::Forged, SignatureStatus::UnknownKey),
+            (Binding::Revoked, SignatureStatus::RevokedKey),
+        ] {
+            let block = key_block(&primary, SIGN_FLAG, None, false, Some((&subkey, binding)));
+            assert_eq!(expected, status(&block, &subkey, time));
+        }
+        // a revoked primary key revokes its subkeys
+        let block = key_block(
+            &primary,
+            SIGN_FLAG,
+            None,
+            true,
+            Some((&subkey, Binding::Full)),
+        );
+        assert_eq!(SignatureStatus::RevokedKey, status(&block, &subkey, time));
+    }
+
+    #[test]
+    fn test_signature_creation_time() {
+        let primary = key(1);
+        let block = key_block(&primary, SIGN_FLAG, None, false, None);
+        let public: PublicKey = block.parse().unwrap();
+        let check = |signature: String| {
+            verify(
+                PAYLOAD,
+                signature.as_bytes(),
+                std::slice::from_ref(&public),
+                now(),
+            )
+            .status
+        };
+
+        assert_eq!(
+            SignatureStatus::Verified,
+            check(armored_signature(&primary, &issued(&primary, now())))
+        );
+        // made in the future
+        assert_eq!(
+            SignatureStatus::Bad,
+            check(armored_signature(
+                &primary,
+                &issued(&primary, now() + 86400)
+            ))
+        );
+        // made before the key
+        assert_eq!(
+            SignatureStatus::Bad,
+            check(armored_signature(&primary, &issued(&primary, CREATED - 1)))
+        );
+        // not telling when it was made
+        let mut subpackets = Vec::new();
+        put_subpacket(
+            &mut subpackets,
+            ISSUER_FINGERPRINT_SUBPACKET,
+            &[&[4][..], &fingerprint(&primary.id)].concat(),
+        );
+        assert_eq!(
+            SignatureStatus::Bad,
+            check(armored_signature(&primary, &subpackets))
+        );
+        // valid for an hour, two hours ago
+        let mut subpackets = issued(&primary, now() - 7200);
+        put_subpacket(
+            &mut subpackets,
+            SIGNATURE_EXPIRATION_SUBPACKET,
+            &3600u32.to_be_bytes(),
+        );
+        assert_eq!(
+            SignatureStatus::Expired,
+            check(armored_signature(&primary, &subpackets))
+        );
+    }
+}
@@ -0,0 +1,101 @@
+//!
+//! SSH signatures, the `SSHSIG` format of `ssh-keygen -Y sign` which git uses for
+//! `gpg.format=ssh`. Keys and signatures are read and checked by the `ssh-key` crate, with
+//! Ed25519, ECDSA and RSA keys.
+//!
+
+use ssh_key::{HashAlg, LineEnding, PrivateKey, SshSig};
+
+use super::{
+    KeyType, PublicKey, SecretKey, SignatureStatus, SignerKey, SigningKey, Verification,
+    VerifyingKey,
+};
+use crate::git::errors::GitError;
+
+/// the namespace git signs objects in, a signature made for another use doesn't count
+const GIT_NAMESPACE: &str = "git";
+
+/// Parse a public key line of `authorized_keys`, `<type> <base64 key> [comment]`.
+pub(super) fn parse_public_key(text: &str) -> Result<PublicKey, GitError> {
+    let key = ssh_key::PublicKey::from_openssh(text)
+        .map_err(|err| GitError::InvalidPublicKey(format!("{}: {}", text, err)))?;
+    let blob = key
+        .to_bytes()
+        .map_err(|err| GitError::InvalidPublicKey(err.to_string()))?;
+    Ok(PublicKey {
+        key_type: KeyType::Ssh,
+        key_id: key.fingerprint(HashAlg::Sha256).to_string(),
+        text: text.to_owned(),
+        // SSH keys have no validity period, certificates are not supported
+        keys: vec![SignerKey {
+            id: blob,
+            key: VerifyingKey::Ssh(Box::new(key)),
+            created: 0,
+            expires: None,
+            revoked: false,
+        }],
+    })
+}
+
+/// Parse an unencrypted OpenSSH private key, the `openssh-key-v1` format of `ssh-keygen`.
+pub(super) fn parse_signing_key(text: &str) -> Result<SigningKey, GitError> {
+    let invalid = |msg: String| GitError::InvalidSigningKey(msg);
+    let private = PrivateKey::from_openssh(text).map_err(|err| invalid(err.to_string()))?;
+    if private.is_encrypted() {
+        return Err(invalid("encrypted keys are not supported".to_owned()));
+    }
+    let public = private
+        .public_key()
+        .to_openssh()
+        .map_err(|err| invalid(err.to_string()))?;
+    Ok(SigningKey {
+        public_key: parse_public_key(&public)?,
+        id: Vec::new(),
+        secret: SecretKey::Ssh(Box::new(private)),
+    })
+}
+
+/// Sign `payload` in the git namespace, the armored signature is returned.
+pub(super) fn sign(payload: &[u8], key: &SigningKey) -> Option<String> {
+    let SecretKey::Ssh(private) = &key.secret else {
+        return None;
+    };
+    private
+        .sign(GIT_NAMESPACE, HashAlg::Sha512, payload)
+        .and_then(|signature| signature.to_pem(LineEnding::LF))
+        .ok()
+}
+
+pub(super) fn verify(payload: &[u8], armored: &[u8], keys: &[PublicKey]) -> Verification {
+    let signature = std::str::from_utf8(armored)
+        .ok()
+        .and_then(|armored| SshSig::from_pem(armored).ok());
+    let Some(signature) = signature else {
+        return Verification::status(SignatureStatus::Bad);
+    };
+    let found = keys
+        .iter()
+        .filter(|key| key.key_type == KeyType::Ssh)
+        .find_map(|key| {
+            key.keys.iter().find_map(|signer| match &signer.key {
+                VerifyingKey::Ssh(public) if public.key_data() == signature.public_key() => {
+                    Some((key, public))
+                }
+                _ => None,
+            })
+        });
+    let Some((key, public)) = found else {
+        return Verification::status(SignatureStatus::UnknownKey);
+    };
+    let status = match public.verify(GIT_NAMESPACE, payload, &signature) {
+        Ok(()) => SignatureStatus::Verified,
+        Err(ssh_key::Error::AlgorithmUnknown | ssh_key::Error::AlgorithmUnsupported { .. }) => {
+            SignatureStatus::Unsupported
+        }
+        Err(_) => SignatureStatus::Bad,
+    };
+    Verification {
+        status,
+        key_id: Some(key.key_id.clone()),
+    }
+}
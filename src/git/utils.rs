@@ -93,13 +93,17 @@ pub fn read_size_encoding<R: Read>(stream: &mut R) -> io::Result<usize> {
     let mut length = 0;
 
     loop {
-        let (byte_value, more_bytes) = read_var_int_byte(stream).unwrap();
-        value |= (byte_value as usize) << length;
+        let (byte_value, more_bytes) = read_var_int_byte(stream)?;
+        let bits = (byte_value as usize)
+            .checked_shl(length)
+            .filter(|bits| bits >> length == byte_value as usize)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "size overflow"))?;
+        value |= bits;
         if !more_bytes {
             return Ok(value);
         }
 
-        length += VAR_INT_ENCODING_BITS;
+        length += VAR_INT_ENCODING_BITS as u32;
     }
 }
 
@@ -154,6 +158,12 @@ pub fn read_offset_encoding<R: Read>(stream: &mut R) -> io::Result<u64> {
     loop {
         let (byte_value, more_bytes) = read_var_int_byte(stream)?;
 
+        if value >> (u64::BITS - VAR_INT_ENCODING_BITS as u32) != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "offset overflow",
+            ));
+        }
         value = (value << VAR_INT_ENCODING_BITS) | byte_value as u64;
         if !more_bytes {
             return Ok(value);
@@ -226,6 +236,11 @@ pub fn get_offset(file: &mut File) -> io::Result<u64> {
     file.stream_position()
 }
 
+/// The error of a pack that can't be read, e.g. truncated or with a broken zlib stream.
+pub fn corrupt_pack(err: io::Error) -> GitError {
+    GitError::InvalidPackFile(format!("corrupt or truncated pack: {}", err))
+}
+
 /// Call reader() to process a zlib stream from a file.
 /// Reset the file offset afterwards to the end of the zlib stream,
 /// since ZlibDecoder uses BufReader, which may consume extra bytes.
@@ -234,12 +249,12 @@ pub fn read_zlib_stream_exact<T, F>(file: &mut File, reader: F) -> Result<T, Git
 where
     F: FnOnce(&mut ZlibDecoder<&mut File>) -> Result<T, GitError>,
 {
-    let offset = get_offset(file).unwrap();
+    let offset = get_offset(file).map_err(corrupt_pack)?;
     let mut decompressed = ZlibDecoder::new(file);
     let result = reader(&mut decompressed);
     let zlib_end = offset + decompressed.total_in();
 
-    seek(decompressed.into_inner(), zlib_end).unwrap();
+    seek(decompressed.into_inner(), zlib_end).map_err(corrupt_pack)?;
 
     result
 }
@@ -306,4 +321,17 @@ pub fn find_all_pack_file(pack_dir: &str) -> (Vec<PathBuf>, Vec<Hash>) {
 }
 
 #[cfg(test)]
-mod test {}
+mod test {
+    use std::io::Cursor;
+
+    use super::{read_offset_encoding, read_size_encoding, write_size_encoding};
+
+    #[test]
+    fn test_read_encoding_overflow() {
+        let size = read_size_encoding(&mut Cursor::new(write_size_encoding(1 << 40))).unwrap();
+        assert_eq!(1 << 40, size);
+        assert!(read_size_encoding(&mut Cursor::new([0xff; 11])).is_err());
+        assert!(read_offset_encoding(&mut Cursor::new([0xff; 11])).is_err());
+        assert!(read_size_encoding(&mut Cursor::new([0x80])).is_err());
+    }
+}
@@ -0,0 +1,219 @@
+//!
+//! Reachability bitmaps, in the spirit of git's `.bitmap` files.
+//!
+//! Every object gets a position, and the bitmap of a commit has the positions of all objects
+//! reachable from it set. The object set of a pack is then computed with bitmap operations
+//! instead of a walk over commits and trees. Bitmaps are stored EWAH compressed, in the layout
+//! git uses.
+//!
+
+use crate::git::errors::GitError;
+
+const RUNNING_LEN_BITS: u32 = 32;
+const LITERAL_BITS: u32 = 31;
+const MAX_RUNNING_LEN: u64 = (1 << RUNNING_LEN_BITS) - 1;
+const MAX_LITERALS: usize = (1 << LITERAL_BITS) - 1;
+
+/// An uncompressed bitmap of object positions.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Bitmap {
+    words: Vec<u64>,
+}
+
+impl Bitmap {
+    pub fn new() -> Bitmap {
+        Bitmap::default()
+    }
+
+    pub fn set(&mut self, pos: usize) {
+        let word = pos / 64;
+        if word >= self.words.len() {
+            self.words.resize(word + 1, 0);
+        }
+        self.words[word] |= 1 << (pos % 64);
+    }
+
+    pub fn contains(&self, pos: usize) -> bool {
+        self.words
+            .get(pos / 64)
+            .is_some_and(|word| word & (1 << (pos % 64)) != 0)
+    }
+
+    /// add the positions of `other`
+    pub fn or(&mut self, other: &Bitmap) {
+        if other.words.len() > self.words.len() {
+            self.words.resize(other.words.len(), 0);
+        }
+        for (word, other) in self.words.iter_mut().zip(&other.words) {
+            *word |= other;
+        }
+    }
+
+    /// remove the positions of `other`
+    pub fn and_not(&mut self, other: &Bitmap) {
+        for (word, other) in self.words.iter_mut().zip(&other.words) {
+            *word &= !other;
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.words.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    /// the positions set, in ascending order
+    pub fn positions(&self) -> impl Iterator<Item = usize> + '_ {
+        self.words.iter().enumerate().flat_map(|(i, word)| {
+            let mut word = *word;
+            std::iter::from_fn(move || {
+                if word == 0 {
+                    return None;
+                }
+                let bit = word.trailing_zeros() as usize;
+                word &= word - 1;
+                Some(i * 64 + bit)
+            })
+        })
+    }
+
+    /// Compress the bitmap: the bit size, the number of words, the words and the position of
+    /// the last marker word, all big endian. Every marker word holds a run of empty or full
+    /// words and is followed by the literal words up to the next marker.
+    pub fn encode_ewah(&self) -> Vec<u8> {
+        let len = self
+            .words
+            .iter()
+            .rposition(|w| *w != 0)
+            .map_or(0, |i| i + 1);
+        let words = &self.words[..len];
+
+        let mut compressed: Vec<u64> = Vec::new();
+        let mut last_marker;
+        let mut i = 0;
+        loop {
+            let mut run_bit = false;
+            let mut run_len = 0;
+            if i < len && (words[i] == 0 || words[i] == u64::MAX) {
+                let run_word = words[i];
+                run_bit = run_word == u64::MAX;
+                while i < len && words[i] == run_word && run_len < MAX_RUNNING_LEN {
+                    run_len += 1;
+                    i += 1;
+                }
+            }
+            let start = i;
+            while i < len && words[i] != 0 && words[i] != u64::MAX && i - start < MAX_LITERALS {
+                i += 1;
+            }
+            last_marker = compressed.len();
+            compressed.push(
+                run_bit as u64 | run_len << 1 | ((i - start) as u64) << (RUNNING_LEN_BITS + 1),
+            );
+            compressed.extend_from_slice(&words[start..i]);
+            if i >= len {
+                break;
+            }
+        }
+
+        let mut data = Vec::with_capacity(12 + compressed.len() * 8);
+        data.extend_from_slice(&((len * 64) as u32).to_be_bytes());
+        data.extend_from_slice(&(compressed.len() as u32).to_be_bytes());
+        for word in compressed {
+            data.extend_from_slice(&word.to_be_bytes());
+        }
+        data.extend_from_slice(&(last_marker as u32).to_be_bytes());
+        data
+    }
+
+    pub fn decode_ewah(data: &[u8]) -> Result<Bitmap, GitError> {
+        let invalid = || GitError::InvalidObjectInfo("invalid EWAH bitmap".to_string());
+        let read_u32 = |at: usize| -> Result<u32, GitError> {
+            data.get(at..at + 4)
+                .map(|b| u32::from_be_bytes(b.try_into().unwrap()))
+                .ok_or_else(invalid)
+        };
+        let bit_size = read_u32(0)? as usize;
+        let word_count = read_u32(4)? as usize;
+        if data.len() != 12 + word_count * 8 {
+            return Err(invalid());
+        }
+        let compressed: Vec<u64> = data[8..8 + word_count * 8]
+            .chunks_exact(8)
+            .map(|b| u64::from_be_bytes(b.try_into().unwrap()))
+            .collect();
+
+        let size = bit_size.div_ceil(64);
+        let mut words = Vec::with_capacity(size);
+        let mut i = 0;
+        while i < compressed.len() {
+            let marker = compressed[i];
+            let run_word = if marker & 1 == 1 { u64::MAX } else { 0 };
+            let run_len = ((marker >> 1) & MAX_RUNNING_LEN) as usize;
+            let literals = (marker >> (RUNNING_LEN_BITS + 1)) as usize;
+            if words.len() + run_len + literals > size || i + 1 + literals > compressed.len() {
+                return Err(invalid());
+            }
+            words.resize(words.len() + run_len, run_word);
+            words.extend_from_slice(&compressed[i + 1..i + 1 + literals]);
+            i += 1 + literals;
+        }
+        words.resize(size, 0);
+        Ok(Bitmap { words })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Bitmap;
+
+    fn bitmap(positions: &[usize]) -> Bitmap {
+        let mut bitmap = Bitmap::new();
+        for pos in positions {
+            bitmap.set(*pos);
+        }
+        bitmap
+    }
+
+    #[test]
+    fn test_bitmap_ops() {
+        let mut a = bitmap(&[1, 3, 64, 200]);
+        let b = bitmap(&[3, 65, 500]);
+        assert!(a.contains(64));
+        assert!(!a.contains(65));
+        assert!(!a.contains(10_000));
+
+        a.or(&b);
+        assert_eq!(
+            vec![1, 3, 64, 65, 200, 500],
+            a.positions().collect::<Vec<_>>()
+        );
+        a.and_not(&bitmap(&[3, 500, 700]));
+        assert_eq!(vec![1, 64, 65, 200], a.positions().collect::<Vec<_>>());
+        assert_eq!(4, a.count());
+    }
+
+    #[test]
+    fn test_ewah_round_trip() {
+        // a run of full words, literal words and a long run of empty words
+        let mut positions: Vec<usize> = (0..64 * 3).collect();
+        positions.extend([64 * 3 + 5, 64 * 4 + 63, 64 * 1000 + 1]);
+        let bitmap = bitmap(&positions);
+        let data = bitmap.encode_ewah();
+        // header, 2 markers, 3 literal words, marker position
+        assert_eq!(8 + 5 * 8 + 4, data.len());
+        let decoded = Bitmap::decode_ewah(&data).unwrap();
+        assert_eq!(
+            bitmap.positions().collect::<Vec<_>>(),
+            decoded.positions().collect::<Vec<_>>()
+        );
+
+        let empty = Bitmap::decode_ewah(&Bitmap::new().encode_ewah()).unwrap();
+        assert_eq!(0, empty.count());
+    }
+
+    #[test]
+    fn test_ewah_invalid() {
+        let data = bitmap(&[1, 100]).encode_ewah();
+        assert!(Bitmap::decode_ewah(&data[..data.len() - 1]).is_err());
+        assert!(Bitmap::decode_ewah(&[0, 0]).is_err());
+    }
+}
@@ -0,0 +1,171 @@
+//!
+//! Changed-path Bloom filters, laid out like the `BIDX/BDAT` chunks of git's commit-graph.
+//!
+//! Every commit gets a filter of the paths changed against its first parent, a directory is
+//! changed if anything below it is. A filter never misses a changed path, so a path-limited
+//! history walk only needs to diff the trees of commits whose filter matches the path.
+//!
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use async_trait::async_trait;
+
+use crate::git::errors::GitError;
+use crate::git::hash::Hash;
+use crate::gust::driver::commit_graph::CommitGraph;
+
+/// Commits changing more paths get no filter, every path may match them.
+pub const MAX_CHANGED_PATHS: usize = 512;
+
+const BITS_PER_ENTRY: usize = 10;
+const NUM_HASHES: u32 = 7;
+const SEED_0: u32 = 0x293a_e76f;
+const SEED_1: u32 = 0x7e64_6e2c;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BloomFilter {
+    bits: Vec<u8>,
+}
+
+impl BloomFilter {
+    /// Build the filter of the changed paths, `None` if there are more than
+    /// [`MAX_CHANGED_PATHS`] of them.
+    pub fn from_paths<P: AsRef<Path>>(paths: &[P]) -> Option<BloomFilter> {
+        if paths.len() > MAX_CHANGED_PATHS {
+            return None;
+        }
+        let mut filter = BloomFilter {
+            bits: vec![0; (paths.len() * BITS_PER_ENTRY).div_ceil(8)],
+        };
+        for path in paths {
+            for bit in filter.bit_positions(path.as_ref()) {
+                filter.bits[bit / 8] |= 1 << (bit % 8);
+            }
+        }
+        Some(filter)
+    }
+
+    pub fn from_bytes(bits: Vec<u8>) -> BloomFilter {
+        BloomFilter { bits }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bits
+    }
+
+    /// An empty filter matches nothing, the commit changes no path.
+    pub fn is_empty(&self) -> bool {
+        self.bits.is_empty()
+    }
+
+    /// `false` if `path` is certainly not changed, `true` if it may be.
+    pub fn might_contain(&self, path: &Path) -> bool {
+        !self.is_empty()
+            && self
+                .bit_positions(path)
+                .iter()
+                .all(|bit| self.bits[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    fn bit_positions(&self, path: &Path) -> Vec<usize> {
+        let len = self.bits.len() * 8;
+        let key = path.to_str().unwrap().trim_end_matches('/').as_bytes();
+        let h0 = murmur3_32(SEED_0, key);
+        let h1 = murmur3_32(SEED_1, key);
+        (0..NUM_HASHES)
+            .map(|i| h0.wrapping_add(i.wrapping_mul(h1)) as usize % len)
+            .collect()
+    }
+}
+
+#[async_trait]
+pub trait ChangedPaths: CommitGraph {
+    /// Look up the filters of a batch of commits, commits without a filter are left out.
+    async fn bloom_filters(&self, ids: &[Hash]) -> Result<HashMap<Hash, BloomFilter>, GitError>;
+
+    /// Whether the commit `id` changes `path` against its first parent, checked on the trees.
+    async fn touches(
+        &self,
+        id: Hash,
+        first_parent: Option<Hash>,
+        path: &Path,
+    ) -> Result<bool, GitError>;
+}
+
+/// 32-bit MurmurHash3, the hash git uses for changed-path filters.
+fn murmur3_32(seed: u32, data: &[u8]) -> u32 {
+    const C1: u32 = 0xcc9e_2d51;
+    const C2: u32 = 0x1b87_3593;
+
+    let mut hash = seed;
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        let mut k = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        k = k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+        hash ^= k;
+        hash = hash
+            .rotate_left(13)
+            .wrapping_mul(5)
+            .wrapping_add(0xe654_6b64);
+    }
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        let mut k = 0u32;
+        for (i, byte) in tail.iter().enumerate() {
+            k |= (*byte as u32) << (8 * i);
+        }
+        k = k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+        hash ^= k;
+    }
+
+    hash ^= data.len() as u32;
+    hash ^= hash >> 16;
+    hash = hash.wrapping_mul(0x85eb_ca6b);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(0xc2b2_ae35);
+    hash ^= hash >> 16;
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{murmur3_32, BloomFilter, MAX_CHANGED_PATHS};
+
+    #[test]
+    fn test_murmur3_32() {
+        assert_eq!(0, murmur3_32(0, b""));
+        assert_eq!(
+            0x2e4f_f723,
+            murmur3_32(0, b"The quick brown fox jumps over the lazy dog")
+        );
+    }
+
+    #[test]
+    fn test_bloom_filter() {
+        let paths = ["src", "src/gust", "src/gust/main.rs", "README.md"];
+        let filter = BloomFilter::from_paths(&paths).unwrap();
+        assert_eq!(5, filter.as_bytes().len());
+        for path in paths {
+            assert!(filter.might_contain(Path::new(path)));
+        }
+        assert!(filter.might_contain(Path::new("src/gust/")));
+
+        let filter = BloomFilter::from_bytes(filter.as_bytes().to_vec());
+        assert!(filter.might_contain(Path::new("src/gust/main.rs")));
+
+        let empty = BloomFilter::from_paths::<&str>(&[]).unwrap();
+        assert!(empty.is_empty());
+        assert!(!empty.might_contain(Path::new("src")));
+    }
+
+    #[test]
+    fn test_bloom_filter_too_many_paths() {
+        let paths: Vec<PathBuf> = (0..=MAX_CHANGED_PATHS)
+            .map(|i| PathBuf::from(format!("file-{}", i)))
+            .collect();
+        assert_eq!(None, BloomFilter::from_paths(&paths));
+    }
+}
@@ -0,0 +1,536 @@
+//!
+//! Ancestry queries over the commit graph, in the spirit of git's commit-graph file.
+//!
+//! Every commit has a generation number, 1 for root commits and 1 + the max generation of its
+//! parents otherwise. A commit can only reach commits with a lower generation, so walks are
+//! cut off early and visit commits in an order where all descendants come first.
+//!
+
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::path::Path;
+
+use async_trait::async_trait;
+
+use crate::git::errors::GitError;
+use crate::git::hash::Hash;
+use crate::gust::driver::changed_paths::{BloomFilter, ChangedPaths};
+
+/// The graph data of a commit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GraphEntry {
+    pub generation: u64,
+    /// committer timestamp, orders commits with the same generation
+    pub commit_time: i64,
+    pub parents: Vec<Hash>,
+}
+
+/// the parents a walk looks ahead when it reaches a commit it hasn't loaded
+const WALK_DEPTH: usize = 64;
+
+#[async_trait]
+pub trait CommitGraph: Sync {
+    /// Look up a batch of commits, commits missing from the graph are left out.
+    async fn lookup(&self, ids: &[Hash]) -> Result<HashMap<Hash, GraphEntry>, GitError>;
+
+    /// Look up `ids` and their ancestors up to `depth` parents away, so a walk loads the
+    /// commits it's about to visit at once. The default looks up one generation of parents
+    /// per batch.
+    async fn lookup_ancestors(
+        &self,
+        ids: &[Hash],
+        depth: usize,
+    ) -> Result<HashMap<Hash, GraphEntry>, GitError> {
+        let mut entries: HashMap<Hash, GraphEntry> = HashMap::new();
+        let mut level = ids.to_vec();
+        for _ in 0..=depth {
+            if level.is_empty() {
+                break;
+            }
+            let found = self.lookup(&level).await?;
+            let parents: HashSet<Hash> = found
+                .values()
+                .flat_map(|entry| entry.parents.iter().copied())
+                .filter(|id| !entries.contains_key(id) && !found.contains_key(id))
+                .collect();
+            entries.extend(found);
+            level = parents.into_iter().collect();
+        }
+        Ok(entries)
+    }
+}
+
+/// Compute the generation of new commits ordered parents first, `stored` gives the
+/// generation of parents outside of `commits`. Unknown parents count as generation 0.
+pub fn compute_generations(
+    commits: &[(Hash, Vec<Hash>)],
+    stored: &HashMap<Hash, u64>,
+) -> HashMap<Hash, u64> {
+    let mut generations = HashMap::new();
+    for (id, parents) in commits {
+        let max_parent = parents
+            .iter()
+            .map(|p| {
+                generations
+                    .get(p)
+                    .or_else(|| stored.get(p))
+                    .copied()
+                    .unwrap_or(0)
+            })
+            .max()
+            .unwrap_or(0);
+        generations.insert(*id, max_parent + 1);
+    }
+    generations
+}
+
+/// Commits with the graph data loaded so far, walked from the highest generation down.
+struct GraphWalk<'a, G: CommitGraph> {
+    graph: &'a G,
+    entries: HashMap<Hash, GraphEntry>,
+    queue: BinaryHeap<(u64, i64, Hash)>,
+    queued: HashSet<Hash>,
+}
+
+impl<'a, G: CommitGraph> GraphWalk<'a, G> {
+    fn new(graph: &'a G) -> Self {
+        GraphWalk {
+            graph,
+            entries: HashMap::new(),
+            queue: BinaryHeap::new(),
+            queued: HashSet::new(),
+        }
+    }
+
+    /// load the commits not loaded yet with their ancestors up to `WALK_DEPTH` parents away
+    async fn load(&mut self, ids: &[Hash]) -> Result<(), GitError> {
+        let missing: Vec<Hash> = ids
+            .iter()
+            .filter(|id| !self.entries.contains_key(id))
+            .copied()
+            .collect();
+        if !missing.is_empty() {
+            let loaded = self.graph.lookup_ancestors(&missing, WALK_DEPTH).await?;
+            for (id, entry) in loaded {
+                self.entries.entry(id).or_insert(entry);
+            }
+        }
+        Ok(())
+    }
+
+    /// queue the commits once, commits missing from the graph are skipped
+    async fn push(&mut self, ids: &[Hash]) -> Result<(), GitError> {
+        self.load(ids).await?;
+        for id in ids {
+            if let Some(entry) = self.entries.get(id) {
+                if self.queued.insert(*id) {
+                    self.queue.push((entry.generation, entry.commit_time, *id));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Option<Hash> {
+        self.queue.pop().map(|(_, _, id)| id)
+    }
+
+    fn parents(&self, id: &Hash) -> Vec<Hash> {
+        self.entries[id].parents.clone()
+    }
+}
+
+/// Whether `ancestor` is reachable from `descendant`, a commit is its own ancestor.
+pub async fn is_ancestor<G: CommitGraph>(
+    graph: &G,
+    ancestor: Hash,
+    descendant: Hash,
+) -> Result<bool, GitError> {
+    if ancestor == descendant {
+        return Ok(true);
+    }
+    let mut walk = GraphWalk::new(graph);
+    walk.load(&[ancestor]).await?;
+    let generation = match walk.entries.get(&ancestor) {
+        Some(entry) => entry.generation,
+        None => return Ok(false),
+    };
+    walk.push(&[descendant]).await?;
+    while let Some(id) = walk.pop() {
+        if id == ancestor {
+            return Ok(true);
+        }
+        // the parents can't reach a commit of the same or a higher generation
+        if walk.entries[&id].generation <= generation {
+            continue;
+        }
+        let parents = walk.parents(&id);
+        walk.push(&parents).await?;
+    }
+    Ok(false)
+}
+
+/// The best common ancestor of `a` and `b`, which is not an ancestor of another common
+/// ancestor. `None` if the histories are unrelated.
+pub async fn merge_base<G: CommitGraph>(
+    graph: &G,
+    a: Hash,
+    b: Hash,
+) -> Result<Option<Hash>, GitError> {
+    const FROM_A: u8 = 1;
+    const FROM_B: u8 = 2;
+    const STALE: u8 = 4;
+
+    let mut walk = GraphWalk::new(graph);
+    let mut flags: HashMap<Hash, u8> = HashMap::from([(a, FROM_A)]);
+    *flags.entry(b).or_default() |= FROM_B;
+    walk.push(&[a, b]).await?;
+    while let Some(id) = walk.pop() {
+        let mut flag = flags[&id];
+        if flag & (FROM_A | FROM_B) == FROM_A | FROM_B && flag & STALE == 0 {
+            // all the descendants were visited first, so no other common ancestor can
+            // reach this one
+            return Ok(Some(id));
+        }
+        if flag & (FROM_A | FROM_B) == FROM_A | FROM_B {
+            flag |= STALE;
+        }
+        let parents = walk.parents(&id);
+        for parent in &parents {
+            let parent_flag = flags.entry(*parent).or_default();
+            *parent_flag |= flag;
+        }
+        walk.push(&parents).await?;
+        // once only stale commits are left, the remaining ones are below a merge base
+        if walk.queue.iter().all(|(_, _, id)| flags[id] & STALE != 0) {
+            break;
+        }
+    }
+    Ok(None)
+}
+
+/// Commits reachable from `include` but not from `exclude`, descendants before their
+/// ancestors, at most `limit` of them.
+pub async fn rev_list<G: CommitGraph>(
+    graph: &G,
+    include: &[Hash],
+    exclude: &[Hash],
+    limit: Option<usize>,
+) -> Result<Vec<Hash>, GitError> {
+    let mut walk = GraphWalk::new(graph);
+    let mut uninteresting: HashSet<Hash> = exclude.iter().copied().collect();
+    let mut result = Vec::new();
+    walk.push(include).await?;
+    walk.push(exclude).await?;
+    while let Some(id) = walk.pop() {
+        let parents = walk.parents(&id);
+        // every commit reaching this one was popped before, the flag is final
+        if uninteresting.contains(&id) {
+            uninteresting.extend(parents.iter().copied());
+        } else {
+            result.push(id);
+            if limit.is_some_and(|limit| result.len() >= limit) {
+                break;
+            }
+        }
+        walk.push(&parents).await?;
+        if walk
+            .queue
+            .iter()
+            .all(|(_, _, id)| uninteresting.contains(id))
+        {
+            break;
+        }
+    }
+    Ok(result)
+}
+
+/// Commits reachable from `heads` that change `path` against their first parent, descendants
+/// before their ancestors, at most `limit` of them. The trees are only compared for commits
+/// whose changed-path filter matches `path`.
+pub async fn path_history<G: ChangedPaths>(
+    graph: &G,
+    heads: &[Hash],
+    path: &Path,
+    limit: Option<usize>,
+) -> Result<Vec<Hash>, GitError> {
+    let mut walk = GraphWalk::new(graph);
+    // `None` for commits without a filter, which may change any path
+    let mut filters: HashMap<Hash, Option<BloomFilter>> = HashMap::new();
+    let mut result = Vec::new();
+    walk.push(heads).await?;
+    while let Some(id) = walk.pop() {
+        if !filters.contains_key(&id) {
+            // load the filters of every commit seen so far in one go
+            let ids: Vec<Hash> = walk
+                .entries
+                .keys()
+                .filter(|id| !filters.contains_key(id))
+                .copied()
+                .collect();
+            let mut loaded = graph.bloom_filters(&ids).await?;
+            for id in ids {
+                filters.insert(id, loaded.remove(&id));
+            }
+        }
+        let parents = walk.parents(&id);
+        let maybe_changed = filters[&id]
+            .as_ref()
+            .is_none_or(|filter| filter.might_contain(path));
+        if maybe_changed && graph.touches(id, parents.first().copied(), path).await? {
+            result.push(id);
+            if limit.is_some_and(|limit| result.len() >= limit) {
+                break;
+            }
+        }
+        walk.push(&parents).await?;
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use async_trait::async_trait;
+
+    use super::{
+        compute_generations, is_ancestor, merge_base, path_history, rev_list, CommitGraph,
+        GraphEntry,
+    };
+    use crate::git::errors::GitError;
+    use crate::git::hash::Hash;
+    use crate::gust::driver::changed_paths::{BloomFilter, ChangedPaths};
+
+    struct MemoryGraph(HashMap<Hash, GraphEntry>);
+
+    #[async_trait]
+    impl CommitGraph for MemoryGraph {
+        async fn lookup(&self, ids: &[Hash]) -> Result<HashMap<Hash, GraphEntry>, GitError> {
+            Ok(ids
+                .iter()
+                .filter_map(|id| self.0.get(id).map(|e| (*id, e.clone())))
+                .collect())
+        }
+    }
+
+    fn id(n: u8) -> Hash {
+        Hash([n; 20])
+    }
+
+    /// 1 - 2 - 3 - 5 - 6
+    ///      \     /
+    ///       --4--
+    /// 7 (unrelated root)
+    fn graph() -> MemoryGraph {
+        let edges: Vec<(Hash, Vec<Hash>)> = vec![
+            (id(1), vec![]),
+            (id(2), vec![id(1)]),
+            (id(3), vec![id(2)]),
+            (id(4), vec![id(2)]),
+            (id(5), vec![id(3), id(4)]),
+            (id(6), vec![id(5)]),
+            (id(7), vec![]),
+        ];
+        let generations = compute_generations(&edges, &HashMap::new());
+        MemoryGraph(
+            edges
+                .into_iter()
+                .enumerate()
+                .map(|(time, (commit, parents))| {
+                    let entry = GraphEntry {
+                        generation: generations[&commit],
+                        commit_time: time as i64,
+                        parents,
+                    };
+                    (commit, entry)
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_compute_generations() {
+        let graph = graph();
+        assert_eq!(1, graph.0[&id(1)].generation);
+        assert_eq!(3, graph.0[&id(4)].generation);
+        assert_eq!(4, graph.0[&id(5)].generation);
+
+        let stored = HashMap::from([(id(6), 5)]);
+        let generations = compute_generations(&[(id(8), vec![id(6), id(9)])], &stored);
+        assert_eq!(6, generations[&id(8)]);
+    }
+
+    #[tokio::test]
+    async fn test_is_ancestor() {
+        let graph = graph();
+        assert!(is_ancestor(&graph, id(4), id(6)).await.unwrap());
+        assert!(is_ancestor(&graph, id(1), id(5)).await.unwrap());
+        assert!(is_ancestor(&graph, id(6), id(6)).await.unwrap());
+        assert!(!is_ancestor(&graph, id(3), id(4)).await.unwrap());
+        assert!(!is_ancestor(&graph, id(6), id(1)).await.unwrap());
+        assert!(!is_ancestor(&graph, id(7), id(6)).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_merge_base() {
+        let graph = graph();
+        assert_eq!(Some(id(2)), merge_base(&graph, id(3), id(4)).await.unwrap());
+        assert_eq!(Some(id(4)), merge_base(&graph, id(6), id(4)).await.unwrap());
+        assert_eq!(None, merge_base(&graph, id(6), id(7)).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_rev_list() {
+        let graph = graph();
+        let commits = rev_list(&graph, &[id(6)], &[id(3)], None).await.unwrap();
+        assert_eq!(vec![id(6), id(5), id(4)], commits);
+
+        let commits = rev_list(&graph, &[id(6)], &[], Some(2)).await.unwrap();
+        assert_eq!(vec![id(6), id(5)], commits);
+
+        let commits = rev_list(&graph, &[id(6), id(7)], &[id(5)], None)
+            .await
+            .unwrap();
+        assert_eq!(vec![id(6), id(7)], commits);
+    }
+
+    /// `graph()` with the paths each commit changes, commit 3 has no filter
+    struct PathGraph {
+        graph: MemoryGraph,
+        changed: HashMap<Hash, Vec<&'static str>>,
+        tree_diffs: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl CommitGraph for PathGraph {
+        async fn lookup(&self, ids: &[Hash]) -> Result<HashMap<Hash, GraphEntry>, GitError> {
+            self.graph.lookup(ids).await
+        }
+    }
+
+    #[async_trait]
+    impl ChangedPaths for PathGraph {
+        async fn bloom_filters(
+            &self,
+            ids: &[Hash],
+        ) -> Result<HashMap<Hash, BloomFilter>, GitError> {
+            Ok(ids
+                .iter()
+                .filter(|commit| **commit != id(3))
+                .map(|commit| {
+                    let filter = BloomFilter::from_paths(&self.changed[commit]).unwrap();
+                    (*commit, filter)
+                })
+                .collect())
+        }
+
+        async fn touches(
+            &self,
+            id: Hash,
+            _first_parent: Option<Hash>,
+            path: &Path,
+        ) -> Result<bool, GitError> {
+            self.tree_diffs.fetch_add(1, Ordering::SeqCst);
+            Ok(self.changed[&id].contains(&path.to_str().unwrap()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_path_history() {
+        let changed = HashMap::from([
+            (id(1), vec!["src", "src/lib.rs", "README.md"]),
+            (id(2), vec!["src", "src/lib.rs"]),
+            (id(3), vec!["docs", "docs/guide.md"]),
+            (id(4), vec!["README.md"]),
+            (id(5), vec!["README.md"]),
+            (id(6), vec![]),
+        ]);
+        let graph = PathGraph {
+            graph: graph(),
+            changed,
+            tree_diffs: AtomicUsize::new(0),
+        };
+        let commits = path_history(&graph, &[id(6)], Path::new("src/lib.rs"), None)
+            .await
+            .unwrap();
+        assert_eq!(vec![id(2), id(1)], commits);
+        // commit 3 has no filter, the filters rule out the other commits
+        assert_eq!(3, graph.tree_diffs.load(Ordering::SeqCst));
+
+        let commits = path_history(&graph, &[id(6)], Path::new("README.md"), Some(2))
+            .await
+            .unwrap();
+        assert_eq!(vec![id(5), id(4)], commits);
+    }
+
+    /// a history of `len` commits in a row, counting the batches looked up
+    struct ChainGraph {
+        graph: MemoryGraph,
+        batches: AtomicUsize,
+    }
+
+    impl ChainGraph {
+        fn new(len: u16) -> Self {
+            let chain_id = |n: u16| {
+                let mut id = [0; 20];
+                id[..2].copy_from_slice(&n.to_be_bytes());
+                Hash(id)
+            };
+            let entries = (1..=len).map(|n| {
+                let entry = GraphEntry {
+                    generation: n as u64,
+                    commit_time: n as i64,
+                    parents: (n > 1).then(|| chain_id(n - 1)).into_iter().collect(),
+                };
+                (chain_id(n), entry)
+            });
+            ChainGraph {
+                graph: MemoryGraph(entries.collect()),
+                batches: AtomicUsize::new(0),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl CommitGraph for ChainGraph {
+        async fn lookup(&self, ids: &[Hash]) -> Result<HashMap<Hash, GraphEntry>, GitError> {
+            self.graph.lookup(ids).await
+        }
+
+        async fn lookup_ancestors(
+            &self,
+            ids: &[Hash],
+            depth: usize,
+        ) -> Result<HashMap<Hash, GraphEntry>, GitError> {
+            self.batches.fetch_add(1, Ordering::SeqCst);
+            let mut entries = HashMap::new();
+            let mut next: Vec<Hash> = ids.to_vec();
+            for _ in 0..=depth {
+                let found = self.graph.lookup(&next).await?;
+                next = found.values().flat_map(|e| e.parents.clone()).collect();
+                entries.extend(found);
+            }
+            Ok(entries)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_walk_loads_ancestors_in_batches() {
+        let graph = ChainGraph::new(200);
+        let head = *graph
+            .graph
+            .0
+            .iter()
+            .max_by_key(|(_, e)| e.generation)
+            .unwrap()
+            .0;
+
+        let commits = rev_list(&graph, &[head], &[], None).await.unwrap();
+
+        assert_eq!(200, commits.len());
+        // 65 commits per batch instead of a lookup per commit
+        assert_eq!(4, graph.batches.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,107 @@
+//!
+//! Commit search, filters commits of a repo by the metadata columns extracted on save and by
+//! the paths they change.
+//!
+
+use std::path::PathBuf;
+
+use chrono::NaiveDateTime;
+use entity::commit;
+use serde::Serialize;
+
+/// Filters of a commit search, all of them are optional and combined with AND.
+#[derive(Debug, Default, Clone)]
+pub struct CommitQuery {
+    /// prefix of the author `name <email>`
+    pub author: Option<String>,
+    /// prefix of the committer `name <email>`
+    pub committer: Option<String>,
+    /// committed at or after, UTC
+    pub since: Option<NaiveDateTime>,
+    /// committed at or before, UTC
+    pub until: Option<NaiveDateTime>,
+    /// substring of the commit message
+    pub message: Option<String>,
+    /// only commits changing this file or directory against their first parent, relative to
+    /// the repo root
+    pub path: Option<PathBuf>,
+    pub limit: Option<u64>,
+}
+
+impl CommitQuery {
+    pub const DEFAULT_LIMIT: u64 = 100;
+
+    pub const MAX_LIMIT: u64 = 1000;
+
+    pub fn limit(&self) -> u64 {
+        self.limit
+            .unwrap_or(Self::DEFAULT_LIMIT)
+            .min(Self::MAX_LIMIT)
+    }
+
+    /// whether any filter other than `path` is set
+    pub fn has_metadata_filters(&self) -> bool {
+        self.author.is_some()
+            || self.committer.is_some()
+            || self.since.is_some()
+            || self.until.is_some()
+            || self.message.is_some()
+    }
+}
+
+/// A commit in search results, times are unix timestamps.
+#[derive(Debug, Clone, Serialize)]
+pub struct CommitInfo {
+    pub id: String,
+    pub tree: String,
+    pub parents: Vec<String>,
+    pub author: Option<String>,
+    pub author_time: Option<i64>,
+    pub committer: Option<String>,
+    pub committer_time: Option<i64>,
+    pub message: Option<String>,
+    pub changelist: Option<i64>,
+}
+
+impl From<commit::Model> for CommitInfo {
+    fn from(model: commit::Model) -> Self {
+        CommitInfo {
+            id: model.git_id,
+            tree: model.tree,
+            parents: model
+                .pid
+                .map(|pid| pid.split(' ').map(String::from).collect())
+                .unwrap_or_default(),
+            author: model.author,
+            author_time: model.author_time.map(|t| t.and_utc().timestamp()),
+            committer: model.committer,
+            committer_time: model.committer_time.map(|t| t.and_utc().timestamp()),
+            message: model.content,
+            changelist: model.cl,
+        }
+    }
+}
+
+/// Escape the wildcards of `text` to match it literally in a `LIKE` pattern, with the default
+/// escape character `\\`.
+pub(crate) fn like_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::like_escape;
+
+    #[test]
+    fn test_like_escape() {
+        assert_eq!("fix bug", like_escape("fix bug"));
+        assert_eq!("100\\% \\_done\\\\", like_escape("100% _done\\"));
+    }
+}
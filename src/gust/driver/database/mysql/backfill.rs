@@ -0,0 +1,28 @@
+//!
+//! Data backfills run on start up, e.g. the rows of a table for the data stored before the
+//! table existed. A finished backfill is recorded so later boots skip it.
+//!
+use entity::backfill;
+use sea_orm::{ConnectionTrait, DbErr, EntityTrait, Set};
+
+/// rows read per page of a backfill
+pub(super) const BACKFILL_PAGE: usize = 1000;
+
+/// Whether the backfill `name` has finished.
+pub(super) async fn is_done<C: ConnectionTrait>(conn: &C, name: &str) -> Result<bool, DbErr> {
+    let row = backfill::Entity::find_by_id(name.to_owned())
+        .one(conn)
+        .await?;
+    Ok(row.is_some())
+}
+
+/// Record that the backfill `name` has finished.
+pub(super) async fn mark_done<C: ConnectionTrait>(conn: &C, name: &str) -> Result<(), DbErr> {
+    backfill::Entity::insert(backfill::ActiveModel {
+        name: Set(name.to_owned()),
+        finished_at: Set(chrono::Utc::now().naive_utc()),
+    })
+    .exec(conn)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,353 @@
+//! Reachability bitmaps in MySQL. An object's bit position is its id in the `object_index`
+//! table, which is shared by all repos as objects are content addressed. Bitmaps are built on
+//! demand for the commits packs are requested for, and for every commit whose generation is a
+//! multiple of [`BITMAP_INTERVAL`] on the way, so later builds stop early in the history.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use entity::{commit, node_data, object_index, reachability_bitmap};
+use sea_orm::sea_query::OnConflict;
+use sea_orm::ActiveValue::NotSet;
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, QuerySelect, Set};
+
+use crate::git::errors::GitError;
+use crate::git::hash::Hash;
+use crate::git::object::base::tree::TreeItemType;
+use crate::git::object::metadata::MetaData;
+use crate::git::object::types::ObjectType;
+use crate::gust::driver::bitmap::Bitmap;
+use crate::gust::driver::commit_graph::{CommitGraph, GraphEntry};
+
+use super::storage::MysqlStorage;
+use super::tree_walk::{TreeWalk, TreeWalkOptions};
+
+/// generations between the history points that get a bitmap
+const BITMAP_INTERVAL: u64 = 1000;
+
+/// ids per `IN (...)` lookup
+const LOOKUP_CHUNK: usize = 1000;
+
+impl MysqlStorage {
+    /// The object index positions of the objects reachable from `wants` but not from
+    /// `haves`, haves unknown to the server are ignored.
+    pub(crate) async fn bitmap_pack_positions(
+        &self,
+        repo_path: &Path,
+        wants: &[Hash],
+        haves: &[Hash],
+    ) -> Result<Vec<i64>, GitError> {
+        let mut objects = Bitmap::new();
+        for want in wants {
+            objects.or(&self.reachability_bitmap(repo_path, *want).await?);
+        }
+        let known = self.lookup(haves).await?;
+        for have in haves.iter().filter(|id| known.contains_key(id)) {
+            objects.and_not(&self.reachability_bitmap(repo_path, *have).await?);
+        }
+        tracing::info!("{} objects to pack for {:?}", objects.count(), repo_path);
+        Ok(objects.positions().map(|pos| pos as i64).collect())
+    }
+
+    /// The bitmap of the objects reachable from the commit `tip`, built if not stored yet.
+    async fn reachability_bitmap(&self, repo_path: &Path, tip: Hash) -> Result<Bitmap, GitError> {
+        if let Some(bitmap) = self.get_bitmaps(repo_path, &[tip]).await?.remove(&tip) {
+            return Ok(bitmap);
+        }
+        let (walked, reached) = self.walk_to_bitmaps(repo_path, tip).await?;
+        let mut points: Vec<(u64, Hash)> = walked
+            .iter()
+            .filter(|(id, entry)| **id != tip && entry.generation % BITMAP_INTERVAL == 0)
+            .map(|(id, entry)| (entry.generation, *id))
+            .collect();
+        if points.is_empty() {
+            return self.build_bitmap(repo_path, tip, walked, reached).await;
+        }
+        // oldest first, every build stops at the bitmaps of the ones before
+        points.sort();
+        for (_, point) in points {
+            let (walked, reached) = self.walk_to_bitmaps(repo_path, point).await?;
+            self.build_bitmap(repo_path, point, walked, reached).await?;
+        }
+        let (walked, reached) = self.walk_to_bitmaps(repo_path, tip).await?;
+        self.build_bitmap(repo_path, tip, walked, reached).await
+    }
+
+    /// Walk the history of `tip` down to the commits with a bitmap. Returns the commits walked
+    /// and the union of the bitmaps reached.
+    async fn walk_to_bitmaps(
+        &self,
+        repo_path: &Path,
+        tip: Hash,
+    ) -> Result<(HashMap<Hash, GraphEntry>, Bitmap), GitError> {
+        let mut walked = HashMap::new();
+        let mut reached = Bitmap::new();
+        let mut seen = HashSet::from([tip]);
+        let mut frontier = vec![tip];
+        while !frontier.is_empty() {
+            let bitmaps = self.get_bitmaps(repo_path, &frontier).await?;
+            for bitmap in bitmaps.values() {
+                reached.or(bitmap);
+            }
+            frontier.retain(|id| !bitmaps.contains_key(id));
+            let entries = self.lookup(&frontier).await?;
+            if let Some(missing) = frontier.iter().find(|id| !entries.contains_key(id)) {
+                return Err(GitError::InvalidCommitObject(missing.to_plain_str()));
+            }
+            frontier = Vec::new();
+            for (id, entry) in entries {
+                for parent in &entry.parents {
+                    if seen.insert(*parent) {
+                        frontier.push(*parent);
+                    }
+                }
+                walked.insert(id, entry);
+            }
+        }
+        Ok((walked, reached))
+    }
+
+    /// Add the objects of the commits walked to the bitmaps reached and store the result as
+    /// the bitmap of `tip`. Trees are walked one level at a time, a tree already in the bitmap
+    /// is skipped together with everything below it.
+    async fn build_bitmap(
+        &self,
+        repo_path: &Path,
+        tip: Hash,
+        walked: HashMap<Hash, GraphEntry>,
+        mut bitmap: Bitmap,
+    ) -> Result<Bitmap, GitError> {
+        let commits: Vec<Hash> = walked.into_keys().collect();
+        let commit_objects: Vec<(Hash, ObjectType)> =
+            commits.iter().map(|id| (*id, ObjectType::Commit)).collect();
+        for pos in self.index_objects(&commit_objects).await?.values() {
+            bitmap.set(*pos);
+        }
+
+        let roots: HashSet<Hash> = self
+            .get_commit_trees(&self.connection, &commits)
+            .await?
+            .into_values()
+            .collect();
+        let root_objects: Vec<(Hash, ObjectType)> =
+            roots.into_iter().map(|id| (id, ObjectType::Tree)).collect();
+        let positions = self.index_objects(&root_objects).await?;
+        let mut fresh = HashSet::new();
+        for (id, _) in &root_objects {
+            if !bitmap.contains(positions[id]) {
+                bitmap.set(positions[id]);
+                fresh.insert(*id);
+            }
+        }
+        let roots = fresh.iter().map(|id| (*id, PathBuf::new())).collect();
+        let mut walk = TreeWalk::new(self, &self.connection, roots, TreeWalkOptions::default());
+        while let Some(entries) = walk.next_level().await? {
+            let mut level = HashSet::new();
+            for entry in entries {
+                let object_type = match entry.item.item_type {
+                    TreeItemType::Tree => ObjectType::Tree,
+                    TreeItemType::Blob | TreeItemType::BlobExecutable | TreeItemType::Link => {
+                        ObjectType::Blob
+                    }
+                    // submodule commits live in other repos
+                    TreeItemType::Commit => continue,
+                };
+                level.insert((entry.item.id, object_type));
+            }
+            let level: Vec<(Hash, ObjectType)> = level.into_iter().collect();
+            let positions = self.index_objects(&level).await?;
+            fresh.clear();
+            for (id, _) in &level {
+                if !bitmap.contains(positions[id]) {
+                    bitmap.set(positions[id]);
+                    fresh.insert(*id);
+                }
+            }
+            // a subtree is walked once, wherever it appears
+            walk.retain_pending(|id, _| fresh.remove(id));
+        }
+
+        let model = reachability_bitmap::ActiveModel {
+            id: NotSet,
+            repo_path: Set(repo_path.to_str().unwrap().to_owned()),
+            commit_id: Set(tip.to_plain_str()),
+            bitmap: Set(bitmap.encode_ewah()),
+            created_at: Set(chrono::Utc::now().naive_utc()),
+        };
+        reachability_bitmap::Entity::insert(model)
+            .on_conflict(
+                OnConflict::columns([
+                    reachability_bitmap::Column::RepoPath,
+                    reachability_bitmap::Column::CommitId,
+                ])
+                .update_column(reachability_bitmap::Column::CommitId)
+                .to_owned(),
+            )
+            .exec(&self.connection)
+            .await?;
+        Ok(bitmap)
+    }
+
+    async fn get_bitmaps(
+        &self,
+        repo_path: &Path,
+        ids: &[Hash],
+    ) -> Result<HashMap<Hash, Bitmap>, GitError> {
+        let mut bitmaps = HashMap::new();
+        for chunk in ids.chunks(LOOKUP_CHUNK) {
+            let models = reachability_bitmap::Entity::find()
+                .filter(reachability_bitmap::Column::RepoPath.eq(repo_path.to_str().unwrap()))
+                .filter(
+                    reachability_bitmap::Column::CommitId
+                        .is_in(chunk.iter().map(|id| id.to_plain_str())),
+                )
+                .all(&self.connection)
+                .await?;
+            for model in models {
+                bitmaps.insert(
+                    Hash::from_str(&model.commit_id)?,
+                    Bitmap::decode_ewah(&model.bitmap)?,
+                );
+            }
+        }
+        Ok(bitmaps)
+    }
+
+    /// The bit positions of the objects, objects seen for the first time are appended to the
+    /// index.
+    async fn index_objects(
+        &self,
+        objects: &[(Hash, ObjectType)],
+    ) -> Result<HashMap<Hash, usize>, GitError> {
+        let mut positions = HashMap::new();
+        for chunk in objects.chunks(LOOKUP_CHUNK) {
+            let ids: Vec<Hash> = chunk.iter().map(|(id, _)| *id).collect();
+            positions.extend(self.get_positions(&ids).await?);
+            let missing: Vec<object_index::ActiveModel> = chunk
+                .iter()
+                .filter(|(id, _)| !positions.contains_key(id))
+                .map(|(id, object_type)| object_index::ActiveModel {
+                    id: NotSet,
+                    git_id: Set(id.to_plain_str()),
+                    object_type: Set(object_type.to_string()),
+                })
+                .collect();
+            if missing.is_empty() {
+                continue;
+            }
+            object_index::Entity::insert_many(missing)
+                .on_conflict(
+                    // indexed by a concurrent build in the meantime
+                    OnConflict::column(object_index::Column::GitId)
+                        .update_column(object_index::Column::GitId)
+                        .to_owned(),
+                )
+                .exec(&self.connection)
+                .await?;
+            let ids: Vec<Hash> = ids
+                .into_iter()
+                .filter(|id| !positions.contains_key(id))
+                .collect();
+            positions.extend(self.get_positions(&ids).await?);
+        }
+        Ok(positions)
+    }
+
+    async fn get_positions(&self, ids: &[Hash]) -> Result<HashMap<Hash, usize>, GitError> {
+        let rows: Vec<(i64, String)> = object_index::Entity::find()
+            .select_only()
+            .column(object_index::Column::Id)
+            .column(object_index::Column::GitId)
+            .filter(object_index::Column::GitId.is_in(ids.iter().map(|id| id.to_plain_str())))
+            .into_tuple()
+            .all(&self.connection)
+            .await?;
+        rows.into_iter()
+            .map(|(pos, id)| Ok((Hash::from_str(&id)?, pos as usize)))
+            .collect()
+    }
+
+    /// The ids of the objects at `positions` of the object index.
+    pub(crate) async fn object_ids(&self, positions: &[i64]) -> Result<HashSet<Hash>, GitError> {
+        let mut ids = HashSet::with_capacity(positions.len());
+        for chunk in positions.chunks(LOOKUP_CHUNK) {
+            let rows: Vec<String> = object_index::Entity::find()
+                .select_only()
+                .column(object_index::Column::GitId)
+                .filter(object_index::Column::Id.is_in(chunk.to_vec()))
+                .into_tuple()
+                .all(&self.connection)
+                .await?;
+            for id in rows {
+                ids.insert(Hash::from_str(&id)?);
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Load the objects at `positions` of the object index.
+    pub(crate) async fn load_objects(&self, positions: &[i64]) -> Result<Vec<MetaData>, GitError> {
+        let mut objects = Vec::with_capacity(positions.len());
+        for chunk in positions.chunks(LOOKUP_CHUNK) {
+            let rows = object_index::Entity::find()
+                .filter(object_index::Column::Id.is_in(chunk.to_vec()))
+                .all(&self.connection)
+                .await?;
+            if rows.len() != chunk.len() {
+                return Err(GitError::InvalidObjectInfo(
+                    "bitmap position missing from the object index".to_string(),
+                ));
+            }
+            let mut uncached = Vec::new();
+            for row in rows {
+                match self.cache.get(&Hash::from_str(&row.git_id)?) {
+                    Some(object) => objects.push(object.as_ref().clone()),
+                    None => uncached.push(row),
+                }
+            }
+            let (commit_ids, data_ids): (Vec<_>, Vec<_>) = uncached
+                .into_iter()
+                .partition(|row| row.object_type == ObjectType::Commit.to_string());
+
+            let commit_ids: Vec<String> = commit_ids.into_iter().map(|row| row.git_id).collect();
+            let commits = commit::Entity::find()
+                .filter(commit::Column::GitId.is_in(commit_ids.clone()))
+                .all(&self.connection)
+                .await?;
+            // the same commit may be stored for more than one repo path
+            let mut loaded = HashSet::new();
+            for model in commits {
+                if loaded.insert(model.git_id) {
+                    let object = MetaData::new(ObjectType::Commit, &model.meta);
+                    self.cache.insert(Arc::new(object.clone()));
+                    objects.push(object);
+                }
+            }
+            if let Some(missing) = commit_ids.iter().find(|id| !loaded.contains(*id)) {
+                return Err(GitError::InvalidCommitObject(missing.to_owned()));
+            }
+
+            let data_ids: Vec<String> = data_ids.into_iter().map(|row| row.git_id).collect();
+            let datas = node_data::Entity::find()
+                .filter(node_data::Column::GitId.is_in(data_ids.clone()))
+                .all(&self.connection)
+                .await?;
+            let found: HashSet<&String> = datas.iter().map(|d| &d.git_id).collect();
+            if let Some(missing) = data_ids.iter().find(|id| !found.contains(id)) {
+                return Err(GitError::NotFountHashValue(missing.to_owned()));
+            }
+            for data in datas {
+                let object_type = ObjectType::from_string(&data.node_type)?;
+                let object = MetaData::new(object_type, &data.data);
+                // the blobs of a clone would flush the trees other requests keep reading
+                if object_type == ObjectType::Tree {
+                    self.cache.insert(Arc::new(object.clone()));
+                }
+                objects.push(object);
+            }
+        }
+        Ok(objects)
+    }
+}
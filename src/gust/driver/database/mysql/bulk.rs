@@ -0,0 +1,122 @@
+//! Bulk inserts. Rows are grouped into statements by their encoded size instead of a fixed
+//! row count, so a statement stays under the packet limit of sqlx whatever the rows hold, and
+//! the statements of a batch run concurrently. Every failed statement is returned to the
+//! caller, who decides whether the surrounding transaction is rolled back.
+
+use entity::node_data;
+use futures::{stream, StreamExt, TryStreamExt};
+use sea_orm::sea_query::OnConflict;
+use sea_orm::{ActiveModelTrait, ConnectionTrait, DbErr, EntityTrait, Iterable, Value};
+
+/// sqlx doesn't support packets larger than 16MB, half of it leaves room for the statement
+/// text and the protocol framing
+const MAX_STATEMENT_BYTES: usize = 8 * 1024 * 1024;
+
+/// placeholders per prepared statement allowed by MySQL
+const MAX_PLACEHOLDERS: usize = 65535;
+
+/// estimated bytes a value takes in a statement besides its content
+const VALUE_OVERHEAD: usize = 16;
+
+/// statements in flight per batch, a transaction runs them one after another on its
+/// connection
+const CONCURRENT_STATEMENTS: usize = 4;
+
+/// Insert `models`, updating the conflicting rows with `on_conflict` if given.
+pub(super) async fn batch_save_model<C, A>(
+    conn: &C,
+    models: Vec<A>,
+    on_conflict: Option<OnConflict>,
+) -> Result<(), DbErr>
+where
+    C: ConnectionTrait,
+    A: ActiveModelTrait + Send,
+{
+    let columns = <A::Entity as EntityTrait>::Column::iter().count();
+    let batches = split_batches(models.iter().map(row_size), columns);
+    let mut models = models.into_iter();
+    let statements: Vec<Vec<A>> = batches
+        .into_iter()
+        .map(|rows| models.by_ref().take(rows).collect())
+        .collect();
+    stream::iter(statements)
+        .map(|rows| {
+            let mut insert = <A::Entity as EntityTrait>::insert_many(rows);
+            if let Some(on_conflict) = &on_conflict {
+                insert = insert.on_conflict(on_conflict.clone());
+            }
+            insert.exec(conn)
+        })
+        .buffer_unordered(CONCURRENT_STATEMENTS)
+        .try_for_each(|_| async { Ok(()) })
+        .await
+}
+
+/// Content is stored once per hash, a hash already written by a concurrent push is left
+/// unchanged.
+pub(super) async fn save_node_data<C: ConnectionTrait>(
+    conn: &C,
+    node_datas: Vec<node_data::ActiveModel>,
+) -> Result<(), DbErr> {
+    // MySQL has no `DO NOTHING`, rewriting the key is a no-op update
+    let on_conflict = OnConflict::column(node_data::Column::GitId)
+        .update_column(node_data::Column::GitId)
+        .to_owned();
+    batch_save_model(conn, node_datas, Some(on_conflict)).await
+}
+
+/// The number of rows of each statement, for rows of the given encoded sizes. A row larger
+/// than the limit gets a statement of its own.
+fn split_batches(sizes: impl Iterator<Item = usize>, columns: usize) -> Vec<usize> {
+    let max_rows = (MAX_PLACEHOLDERS / columns.max(1)).max(1);
+    let mut batches = Vec::new();
+    let mut rows = 0;
+    let mut bytes = 0;
+    for size in sizes {
+        if rows > 0 && (rows == max_rows || bytes + size > MAX_STATEMENT_BYTES) {
+            batches.push(rows);
+            rows = 0;
+            bytes = 0;
+        }
+        rows += 1;
+        bytes += size;
+    }
+    if rows > 0 {
+        batches.push(rows);
+    }
+    batches
+}
+
+fn row_size<A: ActiveModelTrait>(model: &A) -> usize {
+    <A::Entity as EntityTrait>::Column::iter()
+        .map(|column| {
+            let content = match model.get(column).into_value() {
+                Some(Value::String(Some(s))) => s.len(),
+                Some(Value::Bytes(Some(b))) => b.len(),
+                _ => 8,
+            };
+            content + VALUE_OVERHEAD
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{split_batches, MAX_PLACEHOLDERS, MAX_STATEMENT_BYTES};
+
+    #[test]
+    fn test_split_batches() {
+        assert!(split_batches(std::iter::empty(), 5).is_empty());
+
+        let mb = 1024 * 1024;
+        let sizes = [3 * mb, 3 * mb, 3 * mb, 20 * mb, mb];
+        assert_eq!(vec![2, 1, 1, 1], split_batches(sizes.into_iter(), 5));
+
+        // small rows are limited by the placeholders
+        let sizes = std::iter::repeat_n(100, MAX_PLACEHOLDERS);
+        let batches = split_batches(sizes, 10);
+        assert_eq!(MAX_PLACEHOLDERS / 10, batches[0]);
+        assert_eq!(MAX_PLACEHOLDERS, batches.iter().sum::<usize>());
+        assert!(100 * batches[0] <= MAX_STATEMENT_BYTES);
+    }
+}
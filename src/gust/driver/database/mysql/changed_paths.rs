@@ -0,0 +1,243 @@
+//! Changed-path Bloom filters in MySQL, one `commit_bloom` row per commit computed when the
+//! commit is saved. Commits saved before the table existed have no row and are treated as
+//! changing every path.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use entity::{commit, commit_bloom};
+use sea_orm::sea_query::OnConflict;
+use sea_orm::{ColumnTrait, ConnectionTrait, EntityTrait, QueryFilter, QuerySelect, Set};
+
+use crate::git::errors::GitError;
+use crate::git::hash::Hash;
+use crate::git::object::base::commit::Commit;
+use crate::git::object::base::tree::{Tree, TreeItemType};
+use crate::gust::driver::changed_paths::{BloomFilter, ChangedPaths, MAX_CHANGED_PATHS};
+
+use super::storage::MysqlStorage;
+use super::tree_walk::{TreeCache, TreeWalk, TreeWalkOptions};
+
+/// ids per `IN (...)` lookup
+const LOOKUP_CHUNK: usize = 1000;
+
+#[async_trait]
+impl ChangedPaths for MysqlStorage {
+    async fn bloom_filters(&self, ids: &[Hash]) -> Result<HashMap<Hash, BloomFilter>, GitError> {
+        let mut filters = HashMap::new();
+        for chunk in ids.chunks(LOOKUP_CHUNK) {
+            let models = commit_bloom::Entity::find()
+                .filter(commit_bloom::Column::GitId.is_in(chunk.iter().map(|id| id.to_plain_str())))
+                .all(&self.connection)
+                .await?;
+            for model in models {
+                if let Some(bits) = model.bloom_filter {
+                    filters.insert(
+                        Hash::from_str(&model.git_id)?,
+                        BloomFilter::from_bytes(bits),
+                    );
+                }
+            }
+        }
+        Ok(filters)
+    }
+
+    async fn touches(
+        &self,
+        id: Hash,
+        first_parent: Option<Hash>,
+        path: &Path,
+    ) -> Result<bool, GitError> {
+        let ids: Vec<Hash> = std::iter::once(id).chain(first_parent).collect();
+        let roots = self.get_commit_trees(&self.connection, &ids).await?;
+        if let Some(missing) = ids.iter().find(|id| !roots.contains_key(id)) {
+            return Err(GitError::InvalidCommitObject(missing.to_plain_str()));
+        }
+        let entry = self.get_path_entry(roots[&id], path).await?;
+        let parent_entry = match first_parent {
+            Some(parent) => self.get_path_entry(roots[&parent], path).await?,
+            None => None,
+        };
+        Ok(entry != parent_entry)
+    }
+}
+
+impl MysqlStorage {
+    /// Compute and save the changed-path filters of the commits which have none yet. The trees
+    /// of the commits and of their first parents must be visible to `conn` already.
+    pub(crate) async fn save_changed_paths<C: ConnectionTrait>(
+        &self,
+        conn: &C,
+        commits: &[Commit],
+    ) -> Result<(), GitError> {
+        let ids: Vec<String> = commits.iter().map(|c| c.meta.id.to_plain_str()).collect();
+        let mut stored: HashSet<String> = HashSet::new();
+        for chunk in ids.chunks(LOOKUP_CHUNK) {
+            let git_ids: Vec<String> = commit_bloom::Entity::find()
+                .select_only()
+                .column(commit_bloom::Column::GitId)
+                .filter(commit_bloom::Column::GitId.is_in(chunk.to_vec()))
+                .into_tuple()
+                .all(conn)
+                .await?;
+            stored.extend(git_ids);
+        }
+        let commits: Vec<&Commit> = commits
+            .iter()
+            .filter(|c| !stored.contains(&c.meta.id.to_plain_str()))
+            .collect();
+        if commits.is_empty() {
+            return Ok(());
+        }
+
+        let mut roots: HashMap<Hash, Hash> =
+            commits.iter().map(|c| (c.meta.id, c.tree_id)).collect();
+        let outside_parents: Vec<Hash> = commits
+            .iter()
+            .filter_map(|c| c.parent_tree_ids.first().copied())
+            .filter(|id| !roots.contains_key(id))
+            .collect();
+        roots.extend(self.get_commit_trees(conn, &outside_parents).await?);
+
+        let mut trees = TreeCache::new();
+        let mut models = Vec::new();
+        for c in commits {
+            let parent_root = c.parent_tree_ids.first().map(|parent| roots.get(parent));
+            let filter = match parent_root {
+                // the first parent isn't stored, leave the commit without a filter
+                Some(None) => None,
+                _ => self
+                    .diff_trees(conn, parent_root.flatten().copied(), c.tree_id, &mut trees)
+                    .await?
+                    .and_then(|paths| BloomFilter::from_paths(&paths)),
+            };
+            models.push(commit_bloom::ActiveModel {
+                git_id: Set(c.meta.id.to_plain_str()),
+                bloom_filter: Set(filter.map(|f| f.as_bytes().to_vec())),
+            });
+        }
+        for chunk in models.chunks(100) {
+            commit_bloom::Entity::insert_many(chunk.iter().cloned())
+                .on_conflict(
+                    OnConflict::column(commit_bloom::Column::GitId)
+                        .update_column(commit_bloom::Column::GitId)
+                        .to_owned(),
+                )
+                .exec(conn)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// The paths changed from the tree `old` to the tree `new`, directories included. Both
+    /// trees are walked one level at a time and only subtrees that differ are loaded.
+    /// Returns `None` once more than [`MAX_CHANGED_PATHS`] paths changed.
+    async fn diff_trees<C: ConnectionTrait>(
+        &self,
+        conn: &C,
+        old: Option<Hash>,
+        new: Hash,
+        trees: &mut TreeCache,
+    ) -> Result<Option<Vec<PathBuf>>, GitError> {
+        let mut changed = Vec::new();
+        let mut level: Vec<(PathBuf, Option<Hash>, Option<Hash>)> =
+            vec![(PathBuf::new(), old, Some(new))];
+        while !level.is_empty() {
+            let ids: Vec<Hash> = level
+                .iter()
+                .flat_map(|(_, old, new)| old.iter().chain(new.iter()).copied())
+                .collect();
+            self.load_trees(conn, &ids, trees).await?;
+            let mut next_level = Vec::new();
+            for (dir, old, new) in level {
+                let old_items = tree_entries(old.map(|id| &trees[&id]));
+                let new_items = tree_entries(new.map(|id| &trees[&id]));
+                let mut names: Vec<&String> =
+                    old_items.keys().chain(new_items.keys()).copied().collect();
+                names.sort();
+                names.dedup();
+                for name in names {
+                    let old_item = old_items.get(name).copied();
+                    let new_item = new_items.get(name).copied();
+                    if old_item == new_item {
+                        continue;
+                    }
+                    let path = dir.join(name);
+                    changed.push(path.clone());
+                    if changed.len() > MAX_CHANGED_PATHS {
+                        return Ok(None);
+                    }
+                    let old_tree = old_item.filter(|(_, t)| *t == TreeItemType::Tree);
+                    let new_tree = new_item.filter(|(_, t)| *t == TreeItemType::Tree);
+                    if old_tree.is_some() || new_tree.is_some() {
+                        next_level.push((path, old_tree.map(|t| t.0), new_tree.map(|t| t.0)));
+                    }
+                }
+            }
+            level = next_level;
+        }
+        Ok(Some(changed))
+    }
+
+    /// The id and type of the entry at `path` in the tree `root`.
+    async fn get_path_entry(
+        &self,
+        root: Hash,
+        path: &Path,
+    ) -> Result<Option<(Hash, TreeItemType)>, GitError> {
+        if path.as_os_str().is_empty() {
+            return Ok(Some((root, TreeItemType::Tree)));
+        }
+        let options = TreeWalkOptions {
+            max_depth: Some(path.iter().count()),
+            prefix: Some(path.to_path_buf()),
+        };
+        let mut walk = TreeWalk::new(
+            self,
+            &self.connection,
+            vec![(root, PathBuf::new())],
+            options,
+        );
+        while let Some(entries) = walk.next_level().await? {
+            if let Some(entry) = entries.iter().find(|e| e.path == path) {
+                return Ok(Some((entry.item.id, entry.item.item_type)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The root tree ids of stored commits, commits not found are left out.
+    pub(super) async fn get_commit_trees<C: ConnectionTrait>(
+        &self,
+        conn: &C,
+        ids: &[Hash],
+    ) -> Result<HashMap<Hash, Hash>, GitError> {
+        let mut roots = HashMap::new();
+        for chunk in ids.chunks(LOOKUP_CHUNK) {
+            let rows: Vec<(String, String)> = commit::Entity::find()
+                .select_only()
+                .column(commit::Column::GitId)
+                .column(commit::Column::Tree)
+                .filter(commit::Column::GitId.is_in(chunk.iter().map(|id| id.to_plain_str())))
+                .into_tuple()
+                .all(conn)
+                .await?;
+            for (git_id, tree) in rows {
+                roots.insert(Hash::from_str(&git_id)?, Hash::from_str(&tree)?);
+            }
+        }
+        Ok(roots)
+    }
+}
+
+fn tree_entries(tree: Option<&Tree>) -> HashMap<&String, (Hash, TreeItemType)> {
+    tree.map(|tree| {
+        tree.tree_items
+            .iter()
+            .map(|item| (&item.filename, (item.id, item.item_type)))
+            .collect()
+    })
+    .unwrap_or_default()
+}
@@ -0,0 +1,215 @@
+//! Changelist numbers: every commit landing on the trunk of a repo gets a number from a
+//! single global sequence, numbers are gap-free and follow the order commits landed in.
+//!
+//! The trunk commits are the first-parent chain of the trunk ref, the commits of a merged
+//! branch are not numbered, so every number is a state the trunk was in.
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+
+use entity::{cl_sequence, commit, commit_mapping};
+use sea_orm::sea_query::Expr;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DbErr, EntityTrait, QueryFilter, QueryOrder,
+    QuerySelect, Set,
+};
+
+use crate::git::errors::GitError;
+use crate::git::hash::Hash;
+use crate::git::object::base::commit::Commit;
+use crate::git::object::metadata::MetaData;
+use crate::git::object::types::ObjectType;
+
+use super::storage::MysqlStorage;
+
+/// the ref whose commits are numbered unless `TRUNK_REF` is set
+pub const TRUNK_REF: &str = "refs/heads/master";
+
+/// Number the commits of the first-parent chain of `head` in `repo_path` that have no
+/// changelist yet, parents before children. The sequence row stays locked until the
+/// transaction of `conn` ends, so concurrent pushes are numbered one after another and a
+/// rolled back push doesn't leave a gap.
+pub(super) async fn assign_changelists<C: ConnectionTrait>(
+    conn: &C,
+    repo_path: &str,
+    head: Hash,
+) -> Result<(), DbErr> {
+    let sequence = cl_sequence::Entity::find_by_id(1)
+        .lock_exclusive()
+        .one(conn)
+        .await?
+        .ok_or_else(|| DbErr::RecordNotFound("cl_sequence".to_owned()))?;
+
+    // the unnumbered commits reachable from head, with their first parent
+    let mut commits: HashMap<Hash, Option<Hash>> = HashMap::new();
+    let mut visited: HashSet<Hash> = HashSet::new();
+    let mut frontier: HashSet<Hash> = HashSet::from([head]);
+    while !frontier.is_empty() {
+        let models = commit::Entity::find()
+            .filter(commit::Column::RepoPath.eq(repo_path))
+            .filter(commit::Column::GitId.is_in(frontier.iter().map(|id| id.to_plain_str())))
+            .all(conn)
+            .await?;
+        visited.extend(frontier.drain());
+        let mut parents = HashSet::new();
+        for model in models {
+            let c = Commit::new(Arc::new(MetaData::new(ObjectType::Commit, &model.meta)));
+            if model.cl.is_some() || commits.contains_key(&c.meta.id) {
+                continue;
+            }
+            parents.extend(c.parent_tree_ids.iter().copied());
+            commits.insert(c.meta.id, c.parent_tree_ids.first().copied());
+        }
+        frontier = parents
+            .into_iter()
+            .filter(|id| !visited.contains(id))
+            .collect();
+    }
+
+    let mut value = sequence.value;
+    for id in first_parent_chain(head, &commits) {
+        value += 1;
+        commit::Entity::update_many()
+            .col_expr(commit::Column::Cl, Expr::value(value))
+            .filter(commit::Column::RepoPath.eq(repo_path))
+            .filter(commit::Column::GitId.eq(id.to_plain_str()))
+            .exec(conn)
+            .await?;
+    }
+    if value != sequence.value {
+        let mut sequence: cl_sequence::ActiveModel = sequence.into();
+        sequence.value = Set(value);
+        sequence.update(conn).await?;
+    }
+    Ok(())
+}
+
+/// The commits of `first_parents` on the first-parent chain of `head`, oldest first. The
+/// chain ends at the first commit missing from `first_parents`.
+fn first_parent_chain(head: Hash, first_parents: &HashMap<Hash, Option<Hash>>) -> Vec<Hash> {
+    let mut chain = Vec::new();
+    let mut next = Some(head);
+    while let Some(id) = next {
+        match first_parents.get(&id) {
+            Some(parent) => {
+                chain.push(id);
+                next = *parent;
+            }
+            None => break,
+        }
+    }
+    chain.reverse();
+    chain
+}
+
+impl MysqlStorage {
+    /// The trunk commit of `repo_path` as of changelist `cl`, that is the last one landed at
+    /// or before `cl`, and its own changelist number. For a subdirectory it's the split
+    /// commit of that monorepo commit.
+    pub(crate) async fn get_commit_by_cl(
+        &self,
+        repo_path: &Path,
+        cl: i64,
+    ) -> Result<(i64, String), GitError> {
+        let not_found = || GitError::ChangelistNotFound(format!("{:?} at {}", repo_path, cl));
+        let root_ref = self.get_root_ref(repo_path).await?.ok_or_else(not_found)?;
+        let model = commit::Entity::find()
+            .filter(commit::Column::RepoPath.eq(&root_ref.repo_path))
+            .filter(commit::Column::Cl.lte(cl))
+            .order_by_desc(commit::Column::Cl)
+            .one(&self.connection)
+            .await?
+            .ok_or_else(not_found)?;
+        if root_ref.repo_path == repo_path.to_str().unwrap() {
+            return Ok((model.cl.ok_or_else(not_found)?, model.git_id));
+        }
+        // make sure the mapping covers the history up to the trunk head
+        self.update_split(&root_ref, repo_path).await?;
+        let mapping = commit_mapping::Entity::find()
+            .filter(commit_mapping::Column::RepoPath.eq(repo_path.to_str().unwrap()))
+            .filter(commit_mapping::Column::MonoId.eq(&model.git_id))
+            .one(&self.connection)
+            .await?;
+        match mapping.and_then(|m| m.split_id) {
+            Some(split_id) => Ok((model.cl.ok_or_else(not_found)?, split_id)),
+            None => Err(not_found()),
+        }
+    }
+
+    /// The changelist a trunk commit of `repo_path` landed in. A split commit of a
+    /// subdirectory landed with the first trunk commit mapped to it.
+    pub(crate) async fn get_cl_by_commit(
+        &self,
+        repo_path: &Path,
+        commit_id: &str,
+    ) -> Result<i64, GitError> {
+        let not_found = || GitError::ChangelistNotFound(commit_id.to_owned());
+        let root_ref = self.get_root_ref(repo_path).await?.ok_or_else(not_found)?;
+        let mono_ids: Vec<String> = if root_ref.repo_path == repo_path.to_str().unwrap() {
+            vec![commit_id.to_owned()]
+        } else {
+            commit_mapping::Entity::find()
+                .select_only()
+                .column(commit_mapping::Column::MonoId)
+                .filter(commit_mapping::Column::RepoPath.eq(repo_path.to_str().unwrap()))
+                .filter(commit_mapping::Column::SplitId.eq(commit_id))
+                .into_tuple()
+                .all(&self.connection)
+                .await?
+        };
+        if mono_ids.is_empty() {
+            return Err(not_found());
+        }
+        // the commits of merged branches map to split commits too, but have no changelist
+        let cl: Option<Option<i64>> = commit::Entity::find()
+            .select_only()
+            .column(commit::Column::Cl)
+            .filter(commit::Column::RepoPath.eq(&root_ref.repo_path))
+            .filter(commit::Column::GitId.is_in(mono_ids))
+            .filter(commit::Column::Cl.is_not_null())
+            .order_by_asc(commit::Column::Cl)
+            .into_tuple()
+            .one(&self.connection)
+            .await?;
+        cl.flatten().ok_or_else(not_found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::str::FromStr;
+
+    use crate::git::hash::Hash;
+
+    use super::first_parent_chain;
+
+    fn id(n: u8) -> Hash {
+        Hash::from_str(&format!("{:040x}", n)).unwrap()
+    }
+
+    #[test]
+    fn test_first_parent_chain_skips_merged_branch() {
+        // 1 is numbered already, 2 - 4 landed on the trunk, 3 merges the branch 5 - 6 forked
+        // from 1
+        let first_parents = HashMap::from([
+            (id(2), Some(id(1))),
+            (id(3), Some(id(2))),
+            (id(4), Some(id(3))),
+            (id(5), Some(id(1))),
+            (id(6), Some(id(5))),
+        ]);
+
+        assert_eq!(
+            vec![id(2), id(3), id(4)],
+            first_parent_chain(id(4), &first_parents)
+        );
+        // a root commit ends the chain too
+        assert_eq!(
+            vec![id(7)],
+            first_parent_chain(id(7), &HashMap::from([(id(7), None)]))
+        );
+        assert!(first_parent_chain(id(1), &first_parents).is_empty());
+    }
+}
@@ -0,0 +1,224 @@
+//! Connectivity check of a push: a ref only moves to a commit whose history, trees and blobs
+//! are all stored. Like git, everything reachable from the refs of the repo is trusted, so only
+//! the commits new to the repo are walked, and in their trees only the entries differing from
+//! the trees of their parents. A ref to an annotated tag is checked from the object the tag
+//! chain ends at.
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use entity::node_data;
+use sea_orm::{ColumnTrait, ConnectionTrait, EntityTrait, QueryFilter};
+
+use crate::git::errors::GitError;
+use crate::git::hash::Hash;
+use crate::git::object::base::tag::Tag;
+use crate::git::object::base::tree::TreeItemType;
+use crate::git::object::metadata::MetaData;
+use crate::git::object::types::ObjectType;
+use crate::git::protocol::{Command, RefCommand};
+use crate::gust::driver::commit_graph::{self, CommitGraph};
+use crate::gust::driver::{ObjectStorage, ZERO_ID};
+
+use super::graph::GraphView;
+use super::storage::MysqlStorage;
+use super::tree_walk::TreeCache;
+
+impl MysqlStorage {
+    /// Check that everything reachable from the new id of `command` is visible to `conn`,
+    /// deletions are always connected.
+    pub(crate) async fn check_ref_connectivity<C: ConnectionTrait>(
+        &self,
+        conn: &C,
+        command: &RefCommand,
+        repo_path: &Path,
+    ) -> Result<(), GitError> {
+        if let Command::Delete = command.command_type {
+            return Ok(());
+        }
+        let known = self.known_tips(command, repo_path).await?;
+        match self.peel(conn, Hash::from_str(&command.new_id)?).await? {
+            (tip, ObjectType::Commit) => self.check_connectivity(conn, tip, &known).await,
+            (tree, ObjectType::Tree) => self.check_trees(conn, vec![(tree, Vec::new())]).await,
+            // the tag chain ended at an id that is no stored tag
+            (tag, ObjectType::Tag) => Err(missing("tag", &tag)),
+            (blob, _) => {
+                let stored = self.get_stored_data_ids(conn, vec![blob]).await?;
+                if stored.contains(&blob) {
+                    Ok(())
+                } else {
+                    Err(missing("blob", &blob))
+                }
+            }
+        }
+    }
+
+    /// Follow the annotated tags starting at `id` to the object they end at, with its type
+    /// as named by the last tag. An id that is no stored tag is taken as a commit.
+    async fn peel<C: ConnectionTrait>(
+        &self,
+        conn: &C,
+        mut id: Hash,
+    ) -> Result<(Hash, ObjectType), GitError> {
+        let mut object_type = ObjectType::Commit;
+        // a tag can't point to a tag created after it, the chain ends
+        loop {
+            let tag = node_data::Entity::find_by_id(id.to_plain_str())
+                .filter(node_data::Column::NodeType.eq("tag"))
+                .one(conn)
+                .await?;
+            let Some(tag) = tag else {
+                return Ok((id, object_type));
+            };
+            let tag = Tag::try_new(Arc::new(MetaData::new(ObjectType::Tag, &tag.data)))?;
+            id = tag.object;
+            object_type = tag.t;
+        }
+    }
+
+    /// The tips the history of the repo is trusted from, its refs and the old id of `command`.
+    pub(super) async fn known_tips(
+        &self,
+        command: &RefCommand,
+        repo_path: &Path,
+    ) -> Result<Vec<Hash>, GitError> {
+        let mut known: Vec<Hash> = self
+            .get_ref_object_id(repo_path)
+            .await
+            .into_keys()
+            .chain([command.old_id.clone()])
+            .filter(|id| id != ZERO_ID)
+            .map(|id| Hash::from_str(&id))
+            .collect::<Result<_, _>>()?;
+        known.sort();
+        known.dedup();
+        Ok(known)
+    }
+
+    /// Check that the commits, trees and blobs reachable from `tip` are stored, trusting
+    /// those reachable from `known`.
+    async fn check_connectivity<C: ConnectionTrait>(
+        &self,
+        conn: &C,
+        tip: Hash,
+        known: &[Hash],
+    ) -> Result<(), GitError> {
+        let graph = GraphView(conn);
+        if graph.lookup(&[tip]).await?.is_empty() {
+            return Err(missing("commit", &tip));
+        }
+        let commits = commit_graph::rev_list(&graph, &[tip], known, None).await?;
+        let entries = graph.lookup(&commits).await?;
+        let new: HashSet<Hash> = commits.iter().copied().collect();
+
+        // the walk skips commits missing from the graph, so a missing parent is only seen
+        // from its children
+        let boundary: Vec<Hash> = entries
+            .values()
+            .flat_map(|entry| entry.parents.iter().copied())
+            .filter(|id| !new.contains(id))
+            .collect::<HashSet<Hash>>()
+            .into_iter()
+            .collect();
+        let stored = graph.lookup(&boundary).await?;
+        if let Some(id) = boundary.iter().find(|id| !stored.contains_key(id)) {
+            return Err(missing("commit", id));
+        }
+
+        let ids: Vec<Hash> = commits.iter().chain(&boundary).copied().collect();
+        let roots = self.get_commit_trees(conn, &ids).await?;
+        let mut level = Vec::new();
+        for id in &commits {
+            let root = *roots.get(id).ok_or_else(|| missing("commit", id))?;
+            let bases = entries[id]
+                .parents
+                .iter()
+                .filter_map(|parent| roots.get(parent).copied())
+                .collect();
+            level.push((root, bases));
+        }
+        self.check_trees(conn, level).await?;
+        tracing::info!(
+            "{} is connected, {} new commits",
+            tip.to_plain_str(),
+            commits.len()
+        );
+        Ok(())
+    }
+
+    /// Check the trees of `level`, each given with the trees at the same path in the parent
+    /// commits. An entry equal to the entry of the same name in one of these is covered by the
+    /// check of the parent.
+    async fn check_trees<C: ConnectionTrait>(
+        &self,
+        conn: &C,
+        mut level: Vec<(Hash, Vec<Hash>)>,
+    ) -> Result<(), GitError> {
+        let mut checked = HashSet::new();
+        while !level.is_empty() {
+            level.retain(|(id, bases)| !bases.contains(id) && checked.insert(*id));
+            let ids: Vec<Hash> = level
+                .iter()
+                .flat_map(|(id, bases)| bases.iter().chain([id]))
+                .copied()
+                .collect();
+            let mut trees = TreeCache::new();
+            self.load_trees(conn, &ids, &mut trees)
+                .await
+                .map_err(|err| match err {
+                    GitError::InvalidTreeObject(id) => {
+                        GitError::MissingObjects(format!("tree {}", id))
+                    }
+                    err => err,
+                })?;
+
+            let mut next = Vec::new();
+            let mut blobs = Vec::new();
+            for (id, bases) in &level {
+                let mut base_items: HashMap<&String, Vec<(Hash, TreeItemType)>> = HashMap::new();
+                for base in bases {
+                    for item in &trees[base].tree_items {
+                        base_items
+                            .entry(&item.filename)
+                            .or_default()
+                            .push((item.id, item.item_type));
+                    }
+                }
+                for item in &trees[id].tree_items {
+                    let same_path = base_items.get(&item.filename);
+                    if same_path.is_some_and(|items| items.iter().any(|(id, _)| *id == item.id)) {
+                        continue;
+                    }
+                    match item.item_type {
+                        TreeItemType::Tree => {
+                            let bases = same_path
+                                .into_iter()
+                                .flatten()
+                                .filter(|(_, item_type)| *item_type == TreeItemType::Tree)
+                                .map(|(id, _)| *id)
+                                .collect();
+                            next.push((item.id, bases));
+                        }
+                        // submodule commits live in other repos
+                        TreeItemType::Commit => {}
+                        _ => blobs.push(item.id),
+                    }
+                }
+            }
+            blobs.sort();
+            blobs.dedup();
+            let stored = self.get_stored_data_ids(conn, blobs.clone()).await?;
+            if let Some(id) = blobs.iter().find(|id| !stored.contains(id)) {
+                return Err(missing("blob", id));
+            }
+            level = next;
+        }
+        Ok(())
+    }
+}
+
+fn missing(kind: &str, id: &Hash) -> GitError {
+    GitError::MissingObjects(format!("{} {}", kind, id.to_plain_str()))
+}
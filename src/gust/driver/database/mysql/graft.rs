@@ -0,0 +1,256 @@
+//! Write back of pushes to a subdirectory: every pushed commit is translated into a monorepo
+//! commit whose root tree has the pushed tree grafted at the subdirectory path.
+//!
+//! The translated commits are recorded in the `commit_mapping` table like split commits, so a
+//! later split of the subdirectory returns the pushed commits unchanged.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use entity::{commit, commit_mapping, refs};
+use sea_orm::sea_query::Expr;
+use sea_orm::ActiveValue::NotSet;
+use sea_orm::{ColumnTrait, DatabaseTransaction, EntityTrait, QueryFilter, QueryOrder, Set};
+
+use crate::git::errors::GitError;
+use crate::git::hash::Hash;
+use crate::git::object::base::tree::{Tree, TreeItem, TreeItemType};
+use crate::git::object::metadata::MetaData;
+use crate::git::object::types::ObjectType;
+use crate::git::protocol::{Command, RefCommand};
+use crate::gust::driver::structure::GitNodeObject;
+use crate::gust::driver::ZERO_ID;
+
+use super::bulk::{batch_save_model, save_node_data};
+use super::changelist::assign_changelists;
+use super::split::upsert_ref;
+use super::storage::MysqlStorage;
+
+impl MysqlStorage {
+    /// Translate the commits pushed to the subdirectory `repo_path` into monorepo commits on
+    /// top of `root_ref` and advance both refs. The push is rejected if it's not based on the
+    /// current split head, or if the root ref moved while the push was translated. Everything
+    /// is written with `txn`, which holds the pushed objects.
+    pub(crate) async fn push_to_subtree(
+        &self,
+        txn: &DatabaseTransaction,
+        root_ref: &refs::Model,
+        command: &RefCommand,
+        repo_path: &Path,
+    ) -> Result<(), GitError> {
+        if let Command::Delete = command.command_type {
+            return Err(GitError::RefUpdateRejected(
+                "deleting a subdirectory ref is not supported".to_owned(),
+            ));
+        }
+        let sub_path = repo_path
+            .strip_prefix(&root_ref.repo_path)
+            .map_err(|_| GitError::InvalidRepoPath(repo_path.display().to_string()))?;
+        let repo_path_str = repo_path.to_str().unwrap();
+        let root_head = Hash::from_str(&root_ref.ref_git_id)?;
+
+        let split_head = self.split_subtree(txn, root_ref, repo_path).await?;
+        if command.old_id != split_head.map_or(ZERO_ID.to_string(), |id| id.to_plain_str()) {
+            return Err(GitError::RefUpdateRejected("fetch first".to_owned()));
+        }
+
+        // the monorepo commit each split commit is translated back to
+        let mut origins = self.get_split_origins(txn, repo_path_str).await?;
+        if let Some(split_head) = split_head {
+            origins.insert(split_head, root_head);
+        }
+        let sub_commits = self.get_new_commits(txn, &command.new_id, &origins).await?;
+
+        let mut mono_trees: HashMap<Hash, Hash> = HashMap::new();
+        let mut new_trees: HashMap<Hash, Tree> = HashMap::new();
+        let mut root_trees = Vec::new();
+        let mut mono_commits = Vec::new();
+        let mut mapping_models = Vec::new();
+        for sub_commit in &sub_commits {
+            let mut parents = Vec::new();
+            for parent in &sub_commit.parent_tree_ids {
+                let mono_parent = origins
+                    .get(parent)
+                    .ok_or_else(|| GitError::InvalidCommitObject(parent.to_plain_str()))?;
+                parents.push(*mono_parent);
+            }
+            // a new history is added to the subdirectory of the current root
+            if parents.is_empty() {
+                parents.push(root_head);
+            }
+            let base_tree = self.get_mono_tree(txn, parents[0], &mut mono_trees).await?;
+            let root_tree = self
+                .graft_tree(txn, base_tree, sub_path, sub_commit.tree_id, &mut new_trees)
+                .await?;
+            let mono_commit =
+                sub_commit.rewrite(root_tree, parents, self.signing_key.as_deref())?;
+            let mono_id = mono_commit.meta.id;
+
+            origins.insert(sub_commit.meta.id, mono_id);
+            mono_trees.insert(mono_id, root_tree);
+            root_trees.push(root_tree);
+            mapping_models.push(commit_mapping::ActiveModel {
+                id: NotSet,
+                repo_path: Set(repo_path_str.to_owned()),
+                mono_id: Set(mono_id.to_plain_str()),
+                split_id: Set(Some(sub_commit.meta.id.to_plain_str())),
+                created_at: Set(chrono::Utc::now().naive_utc()),
+            });
+            mono_commits.push(mono_commit);
+        }
+        let new_head = Hash::from_str(&command.new_id)?;
+        let new_root_head = origins[&new_head];
+
+        // trees and nodes are content addressed, saving them before the ref update is safe
+        let mut node_datas = Vec::new();
+        let mut nodes = Vec::new();
+        for tree in new_trees.values() {
+            let node = tree.convert_to_node(None, "", PathBuf::new());
+            node_datas.extend(node.convert_to_data_model());
+            if root_trees.contains(&tree.meta.id) {
+                nodes.push(node.convert_to_model());
+            }
+        }
+        save_node_data(txn, node_datas).await?;
+        let stored_trees = root_trees.iter().map(|id| (*id, PathBuf::new())).collect();
+        nodes.extend(self.expand_stored_trees(txn, stored_trees).await?);
+        batch_save_model(txn, nodes, None).await?;
+        self.save_commits(txn, &mono_commits, Path::new(&root_ref.repo_path))
+            .await?;
+        self.index_commits(txn, &mono_commits).await?;
+        batch_save_model(txn, mapping_models, None).await?;
+        let advanced = refs::Entity::update_many()
+            .col_expr(
+                refs::Column::RefGitId,
+                Expr::value(new_root_head.to_plain_str()),
+            )
+            .col_expr(
+                refs::Column::UpdatedAt,
+                Expr::value(chrono::Utc::now().naive_utc()),
+            )
+            .filter(refs::Column::Id.eq(root_ref.id))
+            .filter(refs::Column::RefGitId.eq(&root_ref.ref_git_id))
+            .exec(txn)
+            .await?;
+        if advanced.rows_affected != 1 {
+            return Err(GitError::RefUpdateRejected(
+                "the monorepo was updated during the push, fetch first".to_owned(),
+            ));
+        }
+        upsert_ref(txn, repo_path_str, &command.ref_name, new_head).await?;
+        if command.ref_name == self.trunk_ref() {
+            assign_changelists(txn, &root_ref.repo_path, new_root_head).await?;
+        }
+        Ok(())
+    }
+
+    /// For every split commit of `repo_path`, the latest monorepo commit mapped to it.
+    async fn get_split_origins(
+        &self,
+        txn: &DatabaseTransaction,
+        repo_path: &str,
+    ) -> Result<HashMap<Hash, Hash>, GitError> {
+        let models = commit_mapping::Entity::find()
+            .filter(commit_mapping::Column::RepoPath.eq(repo_path))
+            .filter(commit_mapping::Column::SplitId.is_not_null())
+            .order_by_asc(commit_mapping::Column::Id)
+            .all(txn)
+            .await?;
+        let mut origins = HashMap::new();
+        for model in models {
+            if let Some(split_id) = model.split_id {
+                origins.insert(Hash::from_str(&split_id)?, Hash::from_str(&model.mono_id)?);
+            }
+        }
+        Ok(origins)
+    }
+
+    async fn get_mono_tree(
+        &self,
+        txn: &DatabaseTransaction,
+        mono_id: Hash,
+        mono_trees: &mut HashMap<Hash, Hash>,
+    ) -> Result<Hash, GitError> {
+        if let Some(tree) = mono_trees.get(&mono_id) {
+            return Ok(*tree);
+        }
+        let model = commit::Entity::find()
+            .filter(commit::Column::GitId.eq(mono_id.to_plain_str()))
+            .one(txn)
+            .await?
+            .ok_or_else(|| GitError::InvalidCommitObject(mono_id.to_plain_str()))?;
+        let tree = Hash::from_str(&model.tree)?;
+        mono_trees.insert(mono_id, tree);
+        Ok(tree)
+    }
+
+    /// Replace the tree at `path` under `root` with `subtree`, missing directories are
+    /// created. Returns the new root, the rewritten trees are added to `new_trees`.
+    async fn graft_tree(
+        &self,
+        txn: &DatabaseTransaction,
+        root: Hash,
+        path: &Path,
+        subtree: Hash,
+        new_trees: &mut HashMap<Hash, Tree>,
+    ) -> Result<Hash, GitError> {
+        let names: Vec<String> = path
+            .components()
+            .map(|c| c.as_os_str().to_str().unwrap().to_owned())
+            .collect();
+        let mut trees = Vec::new();
+        let mut current = Some(root);
+        for name in &names {
+            let tree = match current {
+                Some(id) => Some(self.load_tree(txn, id, new_trees).await?),
+                None => None,
+            };
+            current = tree.as_ref().and_then(|t| {
+                t.tree_items
+                    .iter()
+                    .find(|i| &i.filename == name && i.item_type == TreeItemType::Tree)
+                    .map(|i| i.id)
+            });
+            trees.push(tree);
+        }
+
+        let mut id = subtree;
+        for (tree, name) in trees.into_iter().zip(names).rev() {
+            let mut tree = tree.unwrap_or_else(|| {
+                Tree::new(Arc::new(MetaData::new(ObjectType::Tree, &Vec::new())))
+            });
+            tree.set_item(TreeItem {
+                mode: TreeItemType::Tree.to_bytes().to_vec(),
+                item_type: TreeItemType::Tree,
+                id,
+                filename: name,
+            });
+            id = tree.meta.id;
+            new_trees.insert(id, tree);
+        }
+        Ok(id)
+    }
+
+    /// The tree `id`, rewritten by the graft or stored, read with `txn` as it may have been
+    /// pushed with it.
+    async fn load_tree(
+        &self,
+        txn: &DatabaseTransaction,
+        id: Hash,
+        new_trees: &HashMap<Hash, Tree>,
+    ) -> Result<Tree, GitError> {
+        if let Some(tree) = new_trees.get(&id) {
+            return Ok(tree.clone());
+        }
+        let model = self
+            .get_node_by_id(txn, &id.to_plain_str())
+            .await?
+            .ok_or_else(|| GitError::InvalidTreeObject(id.to_plain_str()))?;
+        Ok(Tree::new(Arc::new(MetaData::new(
+            ObjectType::Tree,
+            &model.data,
+        ))))
+    }
+}
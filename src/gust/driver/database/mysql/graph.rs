@@ -0,0 +1,324 @@
+//! The commit graph in MySQL: a generation number per commit and one edge row per parent,
+//! so ancestry walks never have to load and decode commit objects.
+
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use entity::{commit, commit_edge, commit_graph};
+use sea_orm::sea_query::OnConflict;
+use sea_orm::{
+    ColumnTrait, ConnectionTrait, DatabaseBackend, EntityTrait, FromQueryResult, QueryFilter,
+    QueryOrder, QuerySelect, Set, Statement,
+};
+
+use crate::git::errors::GitError;
+use crate::git::hash::Hash;
+use crate::git::object::base::commit::Commit;
+use crate::git::object::metadata::MetaData;
+use crate::git::object::types::ObjectType;
+use crate::gust::driver::commit_graph::{compute_generations, CommitGraph, GraphEntry};
+
+use super::backfill::{self, BACKFILL_PAGE};
+use super::split::topo_order;
+use super::storage::MysqlStorage;
+
+/// ids per `IN (...)` lookup
+const LOOKUP_CHUNK: usize = 1000;
+
+/// the name the backfill of the commit graph is recorded by
+const GRAPH_BACKFILL: &str = "commit_graph";
+
+/// The commit graph as seen by a connection, a transaction sees the commits it saved.
+pub(super) struct GraphView<'a, C>(pub &'a C);
+
+#[async_trait]
+impl CommitGraph for MysqlStorage {
+    async fn lookup(&self, ids: &[Hash]) -> Result<HashMap<Hash, GraphEntry>, GitError> {
+        lookup_entries(&self.connection, ids).await
+    }
+
+    async fn lookup_ancestors(
+        &self,
+        ids: &[Hash],
+        depth: usize,
+    ) -> Result<HashMap<Hash, GraphEntry>, GitError> {
+        let ids = ancestor_ids(&self.connection, ids, depth).await?;
+        lookup_entries(&self.connection, &ids).await
+    }
+}
+
+#[async_trait]
+impl<C: ConnectionTrait + Sync> CommitGraph for GraphView<'_, C> {
+    async fn lookup(&self, ids: &[Hash]) -> Result<HashMap<Hash, GraphEntry>, GitError> {
+        lookup_entries(self.0, ids).await
+    }
+
+    async fn lookup_ancestors(
+        &self,
+        ids: &[Hash],
+        depth: usize,
+    ) -> Result<HashMap<Hash, GraphEntry>, GitError> {
+        let ids = ancestor_ids(self.0, ids, depth).await?;
+        lookup_entries(self.0, &ids).await
+    }
+}
+
+/// `ids` and their ancestors up to `depth` parents away, following the edges in one query per
+/// chunk of `ids`.
+async fn ancestor_ids<C: ConnectionTrait>(
+    conn: &C,
+    ids: &[Hash],
+    depth: usize,
+) -> Result<Vec<Hash>, GitError> {
+    #[derive(FromQueryResult)]
+    struct Ancestor {
+        git_id: String,
+    }
+    let mut ancestors = HashSet::new();
+    for chunk in ids.chunks(LOOKUP_CHUNK) {
+        let placeholders = vec!["?"; chunk.len()].join(", ");
+        let mut values: Vec<sea_orm::Value> =
+            chunk.iter().map(|id| id.to_plain_str().into()).collect();
+        values.push((depth as u64).into());
+        let rows = Ancestor::find_by_statement(Statement::from_sql_and_values(
+            DatabaseBackend::MySql,
+            &format!(
+                r#"WITH RECURSIVE ancestors (git_id, depth) AS (
+                       SELECT git_id, 0 FROM commit_graph WHERE git_id IN ({})
+                       UNION
+                       SELECT e.parent_id, a.depth + 1 FROM ancestors a
+                       JOIN commit_edge e ON e.commit_id = a.git_id WHERE a.depth < ?
+                   )
+                   SELECT DISTINCT git_id FROM ancestors"#,
+                placeholders
+            ),
+            values,
+        ))
+        .all(conn)
+        .await?;
+        for row in rows {
+            ancestors.insert(Hash::from_str(&row.git_id)?);
+        }
+    }
+    Ok(ancestors.into_iter().collect())
+}
+
+/// The graph entries of `ids` visible to `conn`.
+async fn lookup_entries<C: ConnectionTrait>(
+    conn: &C,
+    ids: &[Hash],
+) -> Result<HashMap<Hash, GraphEntry>, GitError> {
+    let mut entries = HashMap::new();
+    for chunk in ids.chunks(LOOKUP_CHUNK) {
+        let git_ids: Vec<String> = chunk.iter().map(|id| id.to_plain_str()).collect();
+        let rows = commit_graph::Entity::find()
+            .filter(commit_graph::Column::GitId.is_in(git_ids.clone()))
+            .all(conn)
+            .await?;
+        let edges = commit_edge::Entity::find()
+            .filter(commit_edge::Column::CommitId.is_in(git_ids))
+            .order_by_asc(commit_edge::Column::Seq)
+            .all(conn)
+            .await?;
+        let mut parents: HashMap<String, Vec<Hash>> = HashMap::new();
+        for edge in edges {
+            parents
+                .entry(edge.commit_id)
+                .or_default()
+                .push(Hash::from_str(&edge.parent_id)?);
+        }
+        for row in rows {
+            let entry = GraphEntry {
+                generation: row.generation as u64,
+                commit_time: row.commit_time,
+                parents: parents.remove(&row.git_id).unwrap_or_default(),
+            };
+            entries.insert(Hash::from_str(&row.git_id)?, entry);
+        }
+    }
+    Ok(entries)
+}
+
+impl MysqlStorage {
+    /// Add the commits missing from the commit graph. Parents outside of `commits` must be in
+    /// the graph already, which holds for a push as its history is stored before the commits.
+    pub(crate) async fn save_commit_graph<C: ConnectionTrait>(
+        &self,
+        conn: &C,
+        commits: &[Commit],
+    ) -> Result<(), GitError> {
+        let stored = self
+            .get_generations(conn, commits.iter().map(|c| c.meta.id))
+            .await?;
+        let new_commits: HashMap<Hash, Commit> = commits
+            .iter()
+            .filter(|c| !stored.contains_key(&c.meta.id))
+            .map(|c| (c.meta.id, c.clone()))
+            .collect();
+        if new_commits.is_empty() {
+            return Ok(());
+        }
+        let commits = topo_order(new_commits);
+        let ids: HashSet<Hash> = commits.iter().map(|c| c.meta.id).collect();
+        let outside_parents = commits
+            .iter()
+            .flat_map(|c| c.parent_tree_ids.iter().copied())
+            .filter(|id| !ids.contains(id))
+            .collect::<HashSet<Hash>>();
+        let parent_generations = self
+            .get_generations(conn, outside_parents.into_iter())
+            .await?;
+        let edges: Vec<(Hash, Vec<Hash>)> = commits
+            .iter()
+            .map(|c| (c.meta.id, c.parent_tree_ids.clone()))
+            .collect();
+        let generations = compute_generations(&edges, &parent_generations);
+
+        let mut edge_models = Vec::new();
+        let mut graph_models = Vec::new();
+        for c in &commits {
+            for (seq, parent) in c.parent_tree_ids.iter().enumerate() {
+                edge_models.push(commit_edge::ActiveModel {
+                    commit_id: Set(c.meta.id.to_plain_str()),
+                    parent_id: Set(parent.to_plain_str()),
+                    seq: Set(seq as i32),
+                    ..Default::default()
+                });
+            }
+            graph_models.push(commit_graph::ActiveModel {
+                git_id: Set(c.meta.id.to_plain_str()),
+                generation: Set(generations[&c.meta.id] as i64),
+                commit_time: Set(c.committer.timestamp as i64),
+            });
+        }
+        // edges go first, a commit is only visible to walks once its graph row exists
+        for chunk in edge_models.chunks(100) {
+            commit_edge::Entity::insert_many(chunk.iter().cloned())
+                .on_conflict(
+                    OnConflict::columns([commit_edge::Column::CommitId, commit_edge::Column::Seq])
+                        .update_column(commit_edge::Column::CommitId)
+                        .to_owned(),
+                )
+                .exec(conn)
+                .await?;
+        }
+        for chunk in graph_models.chunks(100) {
+            commit_graph::Entity::insert_many(chunk.iter().cloned())
+                .on_conflict(
+                    OnConflict::column(commit_graph::Column::GitId)
+                        .update_column(commit_graph::Column::GitId)
+                        .to_owned(),
+                )
+                .exec(conn)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Build the graph rows of commits stored before the commit graph existed, a page of
+    /// commits at a time in the order they were stored. The stored ancestors of a page missing
+    /// from the graph are added with it, so a parent always has its generation before its
+    /// children. Once done it's recorded and later boots skip it.
+    pub(crate) async fn backfill_commit_graph(&self) -> Result<(), GitError> {
+        if backfill::is_done(&self.connection, GRAPH_BACKFILL).await? {
+            return Ok(());
+        }
+        #[derive(FromQueryResult)]
+        struct MissingCommit {
+            id: i32,
+            meta: Vec<u8>,
+        }
+        let mut last_id = 0;
+        loop {
+            let page = MissingCommit::find_by_statement(Statement::from_sql_and_values(
+                DatabaseBackend::MySql,
+                r#"SELECT c.id, c.meta FROM commit c LEFT JOIN commit_graph g ON c.git_id = g.git_id
+                   WHERE g.git_id IS NULL AND c.id > ? ORDER BY c.id LIMIT ?"#,
+                [last_id.into(), (BACKFILL_PAGE as u64).into()],
+            ))
+            .all(&self.connection)
+            .await?;
+            let Some(last) = page.last() else {
+                break;
+            };
+            last_id = last.id;
+            let mut commits = HashMap::new();
+            for missing in page {
+                let meta = MetaData::new(ObjectType::Commit, &missing.meta);
+                let commit = Commit::try_new(Arc::new(meta))?;
+                commits.insert(commit.meta.id, commit);
+            }
+            // parents looked up already, in the graph or not stored at all, e.g. of a shallow
+            // push, which stay unknown
+            let mut checked = HashSet::new();
+            loop {
+                let parents: Vec<Hash> = outside_parents(&commits)
+                    .into_iter()
+                    .filter(|id| !checked.contains(id))
+                    .collect();
+                if parents.is_empty() {
+                    break;
+                }
+                checked.extend(parents.iter().copied());
+                let stored = self
+                    .get_generations(&self.connection, parents.iter().copied())
+                    .await?;
+                let missing: Vec<String> = parents
+                    .iter()
+                    .filter(|id| !stored.contains_key(id))
+                    .map(|id| id.to_plain_str())
+                    .collect();
+                for chunk in missing.chunks(LOOKUP_CHUNK) {
+                    let rows = commit::Entity::find()
+                        .filter(commit::Column::GitId.is_in(chunk.to_vec()))
+                        .all(&self.connection)
+                        .await?;
+                    for row in rows {
+                        let meta = MetaData::new(ObjectType::Commit, &row.meta);
+                        let commit = Commit::try_new(Arc::new(meta))?;
+                        commits.insert(commit.meta.id, commit);
+                    }
+                }
+            }
+            tracing::info!("backfilling the commit graph of {} commits", commits.len());
+            let commits: Vec<Commit> = commits.into_values().collect();
+            self.save_commit_graph(&self.connection, &commits).await?;
+        }
+        backfill::mark_done(&self.connection, GRAPH_BACKFILL).await?;
+        Ok(())
+    }
+
+    async fn get_generations<C: ConnectionTrait>(
+        &self,
+        conn: &C,
+        ids: impl Iterator<Item = Hash>,
+    ) -> Result<HashMap<Hash, u64>, GitError> {
+        let ids: Vec<String> = ids.map(|id| id.to_plain_str()).collect();
+        let mut generations = HashMap::new();
+        for chunk in ids.chunks(LOOKUP_CHUNK) {
+            let rows: Vec<(String, i64)> = commit_graph::Entity::find()
+                .select_only()
+                .column(commit_graph::Column::GitId)
+                .column(commit_graph::Column::Generation)
+                .filter(commit_graph::Column::GitId.is_in(chunk.to_vec()))
+                .into_tuple()
+                .all(conn)
+                .await?;
+            for (id, generation) in rows {
+                generations.insert(Hash::from_str(&id)?, generation as u64);
+            }
+        }
+        Ok(generations)
+    }
+}
+
+/// The parents of `commits` which aren't in `commits`.
+fn outside_parents(commits: &HashMap<Hash, Commit>) -> HashSet<Hash> {
+    commits
+        .values()
+        .flat_map(|c| c.parent_tree_ids.iter().copied())
+        .filter(|id| !commits.contains_key(id))
+        .collect()
+}
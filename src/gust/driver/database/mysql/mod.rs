@@ -1,13 +1,160 @@
+mod backfill;
+mod bitmap;
+mod bulk;
+mod changed_paths;
+mod changelist;
+mod connectivity;
+mod graft;
+mod graph;
+mod receive;
+mod signature;
+mod split;
 pub mod storage;
+mod thin_pack;
+mod tree_walk;
+mod upload_pack;
 
-use std::{env, time::Duration};
+use std::sync::Arc;
+use std::{env, fs, time::Duration};
 
-use sea_orm::{ConnectOptions, Database};
+use migration::{Migrator, MigratorTrait};
+use sea_orm::{ConnectOptions, Database, DatabaseConnection, DbErr};
 use tracing::log;
 
 use self::storage::MysqlStorage;
+use crate::git::fsck::FsckConfig;
+use crate::git::object::diff::DeltaOptions;
+use crate::git::pack::limits::PackLimits;
+use crate::git::pack::resolve::PackThreads;
+use crate::git::signature::SigningKey;
+use crate::gust::driver::object_cache::DEFAULT_CACHE_BYTES;
+use crate::{MigrateConfig, MigrateDirection};
 
 pub async fn init() -> MysqlStorage {
+    let connection = connect().await;
+    // budget of the object cache in MB, 0 disables it
+    let cache_bytes = env::var("OBJECT_CACHE_MB")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .map_or(DEFAULT_CACHE_BYTES, |mb| mb * 1024 * 1024);
+    let mut storage = MysqlStorage::new(connection, cache_bytes);
+    // refs only updated to commits with a verified signature, full names or prefixes ended
+    // by `*` separated by commas
+    if let Ok(refs) = env::var("SIGNED_REFS") {
+        let refs = refs
+            .split(',')
+            .map(|r| r.trim().to_owned())
+            .filter(|r| !r.is_empty());
+        storage.signed_refs = Arc::new(refs.collect());
+    }
+    // the ref of the monorepos whose commits are numbered and split, `refs/heads/master` by
+    // default
+    if let Ok(trunk_ref) = env::var("TRUNK_REF") {
+        if !trunk_ref.is_empty() {
+            storage.trunk_ref = Some(Arc::new(trunk_ref));
+        }
+    }
+    // the secret key signing the commits created by the server, an OpenPGP secret key block
+    // or an OpenSSH private key without passphrase
+    if let Ok(path) = env::var("SIGNING_KEY") {
+        let text = fs::read_to_string(&path).expect("Failed to read SIGNING_KEY");
+        let key: SigningKey = text.parse().expect("Invalid SIGNING_KEY");
+        tracing::info!(
+            "signing server commits with {} key {}",
+            key.public_key.key_type,
+            key.public_key.key_id
+        );
+        storage.signing_key = Some(Arc::new(key));
+    }
+    // the bearer token of the admin registering signing keys, as a key registered for an email
+    // verifies the commits of that email
+    if let Ok(token) = env::var("KEYS_ADMIN_TOKEN") {
+        if !token.is_empty() {
+            storage.keys_admin_token = Some(Arc::new(token));
+        }
+    }
+    // the severities of the checks of received objects, `<check>=<error|warn|ignore>` pairs
+    // separated by commas, and the size in MiB above which an object is too large
+    let mut fsck = FsckConfig::default();
+    if let Ok(settings) = env::var("FSCK") {
+        fsck.configure(&settings).expect("Invalid FSCK");
+    }
+    if let Ok(size) = env::var("FSCK_MAX_OBJECT_MB") {
+        let size: usize = size.parse().expect("Invalid FSCK_MAX_OBJECT_MB");
+        fsck.max_object_size = size * 1024 * 1024;
+    }
+    storage.fsck = Arc::new(fsck);
+    // bounds of the packs pushed by clients, a pack beyond them is rejected
+    let mut limits = PackLimits::default();
+    if let Ok(size) = env::var("PACK_MAX_OBJECT_MB") {
+        let size: usize = size.parse().expect("Invalid PACK_MAX_OBJECT_MB");
+        limits.max_object_size = size * 1024 * 1024;
+    }
+    if let Ok(depth) = env::var("PACK_MAX_DELTA_DEPTH") {
+        limits.max_delta_depth = depth.parse().expect("Invalid PACK_MAX_DELTA_DEPTH");
+    }
+    if let Ok(ratio) = env::var("PACK_MAX_INFLATE_RATIO") {
+        limits.max_inflate_ratio = ratio.parse().expect("Invalid PACK_MAX_INFLATE_RATIO");
+    }
+    if let Ok(count) = env::var("PACK_MAX_OBJECTS") {
+        limits.max_objects = count.parse().expect("Invalid PACK_MAX_OBJECTS");
+    }
+    storage.pack_limits = Arc::new(limits);
+    // the blocks compared per byte when deltas of the packs sent are searched, and the size
+    // in MiB above which an object is sent whole
+    let mut delta = DeltaOptions::default();
+    if let Ok(chain) = env::var("PACK_DELTA_CHAIN") {
+        delta.max_chain = chain.parse().expect("Invalid PACK_DELTA_CHAIN");
+    }
+    if let Ok(size) = env::var("PACK_DELTA_MAX_OBJECT_MB") {
+        let size: usize = size.parse().expect("Invalid PACK_DELTA_MAX_OBJECT_MB");
+        delta.max_object_size = size * 1024 * 1024;
+    }
+    storage.delta_options = Arc::new(delta);
+    // threads resolving the objects of received packs, one per core by default
+    if let Ok(threads) = env::var("PACK_THREADS") {
+        let threads: usize = threads.parse().expect("Invalid PACK_THREADS");
+        storage.pack_threads = Arc::new(PackThreads::new(threads));
+    }
+    // started now rather than by the first push
+    storage.pack_threads.pool();
+    // pending migrations are applied on start up unless AUTO_MIGRATE is set to false
+    let auto_migrate = env::var("AUTO_MIGRATE").map_or(true, |v| v != "false");
+    if auto_migrate {
+        Migrator::up(&storage.connection, None)
+            .await
+            .expect("Database migration failed");
+        // commits stored before the commit graph table existed have no graph rows yet
+        storage
+            .backfill_commit_graph()
+            .await
+            .expect("Commit graph backfill failed");
+        // nodes stored before nodes had a path can't be found by path
+        storage
+            .backfill_node_paths()
+            .await
+            .expect("Node path backfill failed");
+        // commits stored before the metadata columns existed can't be searched
+        storage
+            .backfill_commit_metadata()
+            .await
+            .expect("Commit metadata backfill failed");
+    }
+    storage
+}
+
+/// run the `gust db migrate` subcommand against the configured database
+pub async fn migrate(config: &MigrateConfig) -> Result<(), DbErr> {
+    let connection = connect().await;
+    match config.direction {
+        None => Migrator::up(&connection, None).await,
+        Some(MigrateDirection::Up { steps }) => Migrator::up(&connection, steps).await,
+        Some(MigrateDirection::Down { steps }) => Migrator::down(&connection, Some(steps)).await,
+        Some(MigrateDirection::Status) => Migrator::status(&connection).await,
+    }
+}
+
+async fn connect() -> DatabaseConnection {
     let db_url = env::var("DATABASE_URL").expect("DATABASE_URL is not set in .env file");
     let mut opt = ConnectOptions::new(db_url.to_owned());
     // max_connections is properly for double size of the cpu core
@@ -19,9 +166,7 @@ pub async fn init() -> MysqlStorage {
         .max_lifetime(Duration::from_secs(8))
         .sqlx_logging(true)
         .sqlx_logging_level(log::LevelFilter::Debug);
-    MysqlStorage::new(
-        Database::connect(opt)
-            .await
-            .expect("Database connection failed"),
-    )
+    Database::connect(opt)
+        .await
+        .expect("Database connection failed")
 }
@@ -0,0 +1,131 @@
+//!
+//! The objects of a push are written as its pack is decoded. Blobs and tags go to the
+//! transaction of the push as they come, the trees, commits and tags are kept until the pack is
+//! done: the nodes, the index and the ref updates of the push are built from them.
+//!
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use entity::node_data;
+use sea_orm::{DatabaseTransaction, Set, TransactionTrait};
+
+use super::bulk::save_node_data;
+use super::storage::MysqlStorage;
+use crate::git::errors::GitError;
+use crate::git::hash::Hash;
+use crate::git::object::base::commit::Commit;
+use crate::git::object::base::tag::Tag;
+use crate::git::object::base::tree::Tree;
+use crate::git::object::types::ObjectType;
+use crate::git::pack::decode::ObjDecodedMap;
+use crate::git::pack::resolve::{ObjectSink, ResolvedObject};
+use crate::git::protocol::RefCommand;
+use crate::gust::driver::structure::nodes::blob_data_model;
+use crate::gust::driver::PackReceiver;
+
+/// Receives a push to `path` in `txn`, which rolls back when the receiver is dropped unfinished.
+pub(super) struct MysqlPackReceiver {
+    storage: MysqlStorage,
+    txn: DatabaseTransaction,
+    path: PathBuf,
+    /// the trees, commits and tags of the pack, it holds no blob
+    decoded: ObjDecodedMap,
+    /// the blobs of the pack, already written
+    blobs: HashSet<Hash>,
+}
+
+impl MysqlPackReceiver {
+    pub(super) fn new(storage: MysqlStorage, txn: DatabaseTransaction, path: &Path) -> Self {
+        MysqlPackReceiver {
+            storage,
+            txn,
+            path: path.to_path_buf(),
+            decoded: ObjDecodedMap::default(),
+            blobs: HashSet::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl ObjectSink for MysqlPackReceiver {
+    async fn accept(&mut self, objects: Vec<ResolvedObject>) -> Result<(), GitError> {
+        let mut node_datas = Vec::new();
+        // the bases of a thin pack are stored already
+        for resolved in objects.into_iter().filter(|r| r.offset.is_some()) {
+            let object = resolved.object;
+            match object.t {
+                ObjectType::Blob => {
+                    if self.blobs.insert(object.id) {
+                        node_datas.push(blob_data_model(object.id, &object.data));
+                    }
+                }
+                ObjectType::Tree => self.decoded.trees.push(Tree::try_new(object)?),
+                ObjectType::Commit => self.decoded.commits.push(Commit::try_new(object)?),
+                ObjectType::Tag => {
+                    // annotated tags have no node, they are stored to peel and serve the refs
+                    // to them
+                    node_datas.push(node_data::ActiveModel {
+                        git_id: Set(object.id.to_plain_str()),
+                        node_type: Set("tag".to_owned()),
+                        data: Set(object.data.clone()),
+                        created_at: Set(chrono::Utc::now().naive_utc()),
+                    });
+                    self.decoded.tags.push(Tag::try_new(object)?);
+                }
+                _ => return Err(GitError::InvalidObjectType(object.t.to_string())),
+            }
+        }
+        save_node_data(&self.txn, node_datas).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl PackReceiver for MysqlPackReceiver {
+    async fn finish(
+        self: Box<Self>,
+        commands: &[RefCommand],
+    ) -> Result<Vec<Result<(), GitError>>, GitError> {
+        let MysqlPackReceiver {
+            storage,
+            txn,
+            path,
+            decoded,
+            blobs,
+        } = *self;
+        storage.save_pack(&txn, &decoded, blobs, &path).await?;
+        let mut results = Vec::with_capacity(commands.len());
+        for command in commands {
+            // a savepoint, a failed command leaves nothing behind
+            let command_txn = txn.begin().await?;
+            let result = match storage
+                .check_ref_connectivity(&command_txn, command, &path)
+                .await
+            {
+                Ok(()) => storage.handle_refs(&command_txn, command, &path).await,
+                Err(err) => Err(err),
+            };
+            match result {
+                Ok(()) => command_txn.commit().await?,
+                Err(_) => command_txn.rollback().await?,
+            }
+            results.push(result);
+        }
+        txn.commit().await?;
+        // a push to a subdirectory moves the trunk too
+        match storage.get_root_ref(&path).await {
+            Ok(Some(root_ref))
+                if commands.iter().zip(&results).any(|(command, result)| {
+                    result.is_ok() && command.ref_name == root_ref.ref_name
+                }) =>
+            {
+                storage.spawn_trunk_splits(root_ref.repo_path);
+            }
+            Ok(_) => {}
+            // the push is done, the splits catch up on the next read
+            Err(err) => tracing::error!("reading the ref of {:?} failed: {}", path, err),
+        }
+        Ok(results)
+    }
+}
@@ -0,0 +1,211 @@
+//! Signatures of pushed commits and tags: every object is verified against the keys registered
+//! by its committer or tagger when it's saved, and refs listed in `SIGNED_REFS` only move to
+//! commits whose signature was verified.
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::str::FromStr;
+
+use entity::{object_signature, signing_key};
+use sea_orm::sea_query::OnConflict;
+use sea_orm::{ColumnTrait, ConnectionTrait, EntityTrait, QueryFilter, Set};
+
+use crate::git::errors::GitError;
+use crate::git::hash::Hash;
+use crate::git::object::base::commit::Commit;
+use crate::git::object::base::tag::Tag;
+use crate::git::protocol::{Command, RefCommand};
+use crate::git::signature::{verify_commit, verify_tag, PublicKey, SignatureStatus, Verification};
+use crate::gust::driver::commit_graph;
+
+use super::bulk::batch_save_model;
+use super::graph::GraphView;
+use super::storage::MysqlStorage;
+
+/// ids per `IN (...)` lookup
+const LOOKUP_CHUNK: usize = 1000;
+
+impl MysqlStorage {
+    /// Register `public_key` for the signatures of `email`, registering it again replaces it.
+    pub(crate) async fn save_signing_key(
+        &self,
+        email: &str,
+        public_key: &str,
+    ) -> Result<PublicKey, GitError> {
+        let key: PublicKey = public_key.parse()?;
+        let model = signing_key::ActiveModel {
+            email: Set(email.to_owned()),
+            key_type: Set(key.key_type.to_string()),
+            key_id: Set(key.key_id.clone()),
+            public_key: Set(key.text.clone()),
+            created_at: Set(chrono::Utc::now().naive_utc()),
+            ..Default::default()
+        };
+        signing_key::Entity::insert(model)
+            .on_conflict(
+                OnConflict::columns([signing_key::Column::Email, signing_key::Column::KeyId])
+                    .update_column(signing_key::Column::PublicKey)
+                    .to_owned(),
+            )
+            .exec(&self.connection)
+            .await?;
+        Ok(key)
+    }
+
+    /// The registered keys of each of `emails`.
+    pub(crate) async fn signing_keys_of(
+        &self,
+        emails: &[String],
+    ) -> Result<HashMap<String, Vec<PublicKey>>, GitError> {
+        let mut keys: HashMap<String, Vec<PublicKey>> = HashMap::new();
+        for chunk in emails.chunks(LOOKUP_CHUNK) {
+            let models = signing_key::Entity::find()
+                .filter(signing_key::Column::Email.is_in(chunk.to_vec()))
+                .all(&self.connection)
+                .await?;
+            for model in models {
+                // keys are checked when registered, a key failing now is one we stopped
+                // supporting
+                match model.public_key.parse::<PublicKey>() {
+                    Ok(key) => keys.entry(model.email).or_default().push(key),
+                    Err(err) => tracing::warn!("skipping key {}: {}", model.key_id, err),
+                }
+            }
+        }
+        Ok(keys)
+    }
+
+    /// Verify the signatures of `commits` and `tags` and record the results, an object pushed
+    /// again is verified again, e.g. once its signer registered the key.
+    pub(crate) async fn save_signatures<C: ConnectionTrait>(
+        &self,
+        conn: &C,
+        commits: &[Commit],
+        tags: &[Tag],
+    ) -> Result<(), GitError> {
+        let emails: Vec<String> = commits
+            .iter()
+            .map(|c| c.committer.email.clone())
+            .chain(tags.iter().map(|t| t.tagger.email.clone()))
+            .collect::<HashSet<String>>()
+            .into_iter()
+            .collect();
+        let keys = self.signing_keys_of(&emails).await?;
+        let keys_of = |email: &String| keys.get(email).map_or(&[][..], |keys| keys.as_slice());
+        let verifications: Vec<(Hash, Verification)> = commits
+            .iter()
+            .map(|c| (c.meta.id, verify_commit(c, keys_of(&c.committer.email))))
+            .chain(
+                tags.iter()
+                    .map(|t| (t.meta.id, verify_tag(t, keys_of(&t.tagger.email)))),
+            )
+            .collect();
+
+        let now = chrono::Utc::now().naive_utc();
+        let models: Vec<object_signature::ActiveModel> = verifications
+            .into_iter()
+            .map(|(id, verification)| object_signature::ActiveModel {
+                git_id: Set(id.to_plain_str()),
+                status: Set(verification.status.to_string()),
+                key_id: Set(verification.key_id),
+                verified_at: Set(now),
+            })
+            .collect();
+        let on_conflict = OnConflict::column(object_signature::Column::GitId)
+            .update_columns([
+                object_signature::Column::Status,
+                object_signature::Column::KeyId,
+                object_signature::Column::VerifiedAt,
+            ])
+            .to_owned();
+        batch_save_model(conn, models, Some(on_conflict)).await?;
+        Ok(())
+    }
+
+    /// The recorded verifications of `ids`, objects never verified are left out.
+    pub(crate) async fn get_signatures<C: ConnectionTrait>(
+        &self,
+        conn: &C,
+        ids: &[Hash],
+    ) -> Result<HashMap<Hash, Verification>, GitError> {
+        let mut verifications = HashMap::new();
+        for chunk in ids.chunks(LOOKUP_CHUNK) {
+            let models = object_signature::Entity::find()
+                .filter(
+                    object_signature::Column::GitId.is_in(chunk.iter().map(|id| id.to_plain_str())),
+                )
+                .all(conn)
+                .await?;
+            for model in models {
+                let verification = Verification {
+                    status: model.status.parse()?,
+                    key_id: model.key_id,
+                };
+                verifications.insert(Hash::from_str(&model.git_id)?, verification);
+            }
+        }
+        Ok(verifications)
+    }
+
+    /// Reject the update of a ref listed in `SIGNED_REFS` unless every commit it adds to the
+    /// repo has a verified signature.
+    pub(crate) async fn check_signature_policy<C: ConnectionTrait>(
+        &self,
+        conn: &C,
+        command: &RefCommand,
+        repo_path: &Path,
+    ) -> Result<(), GitError> {
+        if let Command::Delete = command.command_type {
+            return Ok(());
+        }
+        if !self
+            .signed_refs
+            .iter()
+            .any(|pattern| ref_matches(pattern, &command.ref_name))
+        {
+            return Ok(());
+        }
+        let known = self.known_tips(command, repo_path).await?;
+        let tip = Hash::from_str(&command.new_id)?;
+        let commits = commit_graph::rev_list(&GraphView(conn), &[tip], &known, None).await?;
+        let verifications = self.get_signatures(conn, &commits).await?;
+        for id in &commits {
+            let status = verifications
+                .get(id)
+                .map_or(SignatureStatus::Unsigned, |v| v.status);
+            if status != SignatureStatus::Verified {
+                return Err(GitError::RefUpdateRejected(format!(
+                    "commit {} is not signed by a registered key ({})",
+                    id.to_plain_str(),
+                    status
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether `ref_name` matches `pattern`, a full ref name or a prefix ended by `*`.
+fn ref_matches(pattern: &str, ref_name: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => ref_name.starts_with(prefix),
+        None => ref_name == pattern,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ref_matches;
+
+    #[test]
+    fn test_ref_matches() {
+        assert!(ref_matches("refs/heads/main", "refs/heads/main"));
+        assert!(!ref_matches("refs/heads/main", "refs/heads/main2"));
+        assert!(ref_matches(
+            "refs/heads/release/*",
+            "refs/heads/release/1.0"
+        ));
+        assert!(!ref_matches("refs/heads/release/*", "refs/heads/dev"));
+        assert!(ref_matches("*", "refs/tags/v1"));
+    }
+}
@@ -0,0 +1,440 @@
+//! Subtree split: rewrite the monorepo history of a subdirectory into a standalone history
+//! whose root tree is the subdirectory, so it can be cloned and fetched like a normal repo.
+//!
+//! Every monorepo commit visited is recorded in the `commit_mapping` table together with the
+//! split commit it maps to, later splits only walk the commits landed since. Splits run in the
+//! background once the trunk moved, advertising a subdirectory only reads its split ref, except
+//! the first time when the split runs before it's advertised.
+//!
+//! One split of a subdirectory runs at a time in a server. Splits of several servers may race,
+//! the split ref is only moved from the value the split started from.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use entity::{commit, commit_mapping, refs};
+use sea_orm::sea_query::{Expr, OnConflict};
+use sea_orm::ActiveValue::NotSet;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseBackend, DbErr, EntityTrait,
+    QueryFilter, QuerySelect, Set, Statement, TransactionTrait,
+};
+
+use crate::git::errors::GitError;
+use crate::git::hash::Hash;
+use crate::git::object::base::commit::Commit;
+use crate::git::object::metadata::MetaData;
+use crate::git::object::types::ObjectType;
+use crate::gust::driver::changed_paths::ChangedPaths;
+
+use super::storage::MysqlStorage;
+
+/// the tries of a split whose ref was moved by the split of another server
+const SPLIT_ATTEMPTS: usize = 3;
+
+/// The lock of every subdirectory split in the server, a split waiting for the lock finds the
+/// work of the previous one done.
+#[derive(Debug, Default)]
+pub(crate) struct SplitLocks(Mutex<HashMap<PathBuf, Arc<tokio::sync::Mutex<()>>>>);
+
+impl SplitLocks {
+    fn get(&self, repo_path: &Path) -> Arc<tokio::sync::Mutex<()>> {
+        let mut locks = self.0.lock().unwrap();
+        locks.entry(repo_path.to_path_buf()).or_default().clone()
+    }
+}
+
+impl MysqlStorage {
+    /// Update the splits of the subdirectories of the monorepo at `root_path` that were split
+    /// before in a background task, run once its trunk moved.
+    pub(crate) fn spawn_trunk_splits(&self, root_path: String) {
+        let storage = self.clone();
+        tokio::spawn(async move {
+            let paths = match storage.split_paths(&root_path).await {
+                Ok(paths) => paths,
+                Err(err) => {
+                    tracing::error!("listing the splits of {} failed: {}", root_path, err);
+                    return;
+                }
+            };
+            for path in paths {
+                storage.refresh_split(Path::new(&path)).await;
+            }
+        });
+    }
+
+    async fn refresh_split(&self, repo_path: &Path) {
+        let root_ref = match self.get_root_ref(repo_path).await {
+            Ok(Some(root_ref)) => root_ref,
+            Ok(None) => return,
+            Err(err) => {
+                tracing::error!("reading the ref of {:?} failed: {}", repo_path, err);
+                return;
+            }
+        };
+        if let Err(err) = self.update_split(&root_ref, repo_path).await {
+            tracing::error!("split {:?} failed: {}", repo_path, err);
+        }
+    }
+
+    /// The subdirectories of the monorepo at `root_path` that were split before.
+    async fn split_paths(&self, root_path: &str) -> Result<Vec<String>, DbErr> {
+        commit_mapping::Entity::find()
+            .select_only()
+            .column(commit_mapping::Column::RepoPath)
+            .distinct()
+            .filter(commit_mapping::Column::RepoPath.starts_with(&format!("{}/", root_path)))
+            .into_tuple()
+            .all(&self.connection)
+            .await
+    }
+
+    /// [`MysqlStorage::split_subtree`] in a transaction of its own, holding the split lock of
+    /// `repo_path`. A split whose ref was moved meanwhile is run again from the current
+    /// monorepo ref.
+    pub(crate) async fn update_split(
+        &self,
+        root_ref: &refs::Model,
+        repo_path: &Path,
+    ) -> Result<Option<Hash>, GitError> {
+        let lock = self.splits.get(repo_path);
+        let _running = lock.lock().await;
+        let mut root_ref = root_ref.clone();
+        let mut attempt = 1;
+        loop {
+            let txn = self.connection.begin().await?;
+            match self.split_subtree(&txn, &root_ref, repo_path).await {
+                Err(GitError::RefUpdateRejected(_)) if attempt < SPLIT_ATTEMPTS => {
+                    txn.rollback().await?;
+                    attempt += 1;
+                    match self.get_root_ref(repo_path).await? {
+                        Some(current) => root_ref = current,
+                        None => return Ok(None),
+                    }
+                }
+                result => {
+                    let split_head = result?;
+                    txn.commit().await?;
+                    return Ok(split_head);
+                }
+            }
+        }
+    }
+
+    /// Split the history of the monorepo ref `root_ref` down to the subdirectory `repo_path`
+    /// and point the same ref of `repo_path` to the split head. Commits that don't change the
+    /// subdirectory are skipped, the others are rewritten with the subtree as root tree and
+    /// their parents mapped to split commits.
+    ///
+    /// The split is written with `conn`, a transaction lands the split commits, their mapping
+    /// and the ref together. Returns `None` if the subdirectory doesn't exist in the head
+    /// commit, and [`GitError::RefUpdateRejected`] if the split ref moved during the split.
+    pub(crate) async fn split_subtree<C: ConnectionTrait>(
+        &self,
+        conn: &C,
+        root_ref: &refs::Model,
+        repo_path: &Path,
+    ) -> Result<Option<Hash>, GitError> {
+        let sub_path = repo_path
+            .strip_prefix(&root_ref.repo_path)
+            .map_err(|_| GitError::InvalidRepoPath(repo_path.display().to_string()))?;
+        let repo_path_str = repo_path.to_str().unwrap();
+        let split_ref: Option<refs::Model> = refs::Entity::find()
+            .filter(refs::Column::RepoPath.eq(repo_path_str))
+            .filter(refs::Column::RefName.eq(&root_ref.ref_name))
+            .one(conn)
+            .await?;
+        let mut mapping = self.get_commit_mapping(conn, repo_path_str).await?;
+        let commits = self
+            .get_new_commits(conn, &root_ref.ref_git_id, &mapping)
+            .await?;
+
+        let mut split_trees: HashMap<Hash, Hash> = HashMap::new();
+        let mut split_commits = Vec::new();
+        let mut mapping_models = Vec::new();
+        let ids: Vec<Hash> = commits.iter().map(|c| c.meta.id).collect();
+        let filters = self.bloom_filters(&ids).await?;
+        for commit in &commits {
+            let unchanged_split = match commit.parent_tree_ids[..] {
+                [parent]
+                    if filters
+                        .get(&commit.meta.id)
+                        .is_some_and(|filter| !filter.might_contain(sub_path)) =>
+                {
+                    mapping.get(&parent).copied()
+                }
+                _ => None,
+            };
+            let split_id = match unchanged_split {
+                // the filter rules out a change of the subdirectory, it's split like the parent
+                Some(split_id) => split_id,
+                None => {
+                    let tree = self
+                        .search_tree_node_by_path(conn, &commit.tree_id.to_plain_str(), sub_path)
+                        .await?
+                        .map(|node| Hash::from_str(&node.git_id))
+                        .transpose()?;
+                    match tree {
+                        None => None,
+                        Some(tree) => {
+                            let mut parents = Vec::new();
+                            for parent in &commit.parent_tree_ids {
+                                if let Some(Some(split_parent)) = mapping.get(parent) {
+                                    if !parents.contains(split_parent) {
+                                        parents.push(*split_parent);
+                                    }
+                                }
+                            }
+                            let unchanged = match parents[..] {
+                                [parent] => {
+                                    self.get_split_tree(conn, parent, &mut split_trees).await?
+                                        == tree
+                                }
+                                _ => false,
+                            };
+                            if unchanged {
+                                Some(parents[0])
+                            } else {
+                                let split_commit =
+                                    commit.rewrite(tree, parents, self.signing_key.as_deref())?;
+                                let split_id = split_commit.meta.id;
+                                split_trees.insert(split_id, tree);
+                                split_commits.push(split_commit);
+                                Some(split_id)
+                            }
+                        }
+                    }
+                }
+            };
+            mapping.insert(commit.meta.id, split_id);
+            mapping_models.push(commit_mapping::ActiveModel {
+                id: NotSet,
+                repo_path: Set(repo_path_str.to_owned()),
+                mono_id: Set(commit.meta.id.to_plain_str()),
+                split_id: Set(split_id.map(|id| id.to_plain_str())),
+                created_at: Set(chrono::Utc::now().naive_utc()),
+            });
+        }
+
+        self.save_commits(conn, &split_commits, repo_path).await?;
+        self.index_commits(conn, &split_commits).await?;
+        self.save_commit_mapping(conn, mapping_models).await?;
+
+        let head = Hash::from_str(&root_ref.ref_git_id)?;
+        let split_head = mapping.get(&head).copied().flatten();
+        if let Some(split_head) = split_head {
+            let old = split_ref.as_ref().map(|r| r.ref_git_id.as_str());
+            if !swap_ref(conn, repo_path_str, &root_ref.ref_name, old, split_head).await? {
+                return Err(GitError::RefUpdateRejected(format!(
+                    "the split of {} was updated by another split",
+                    repo_path_str
+                )));
+            }
+        }
+        Ok(split_head)
+    }
+
+    async fn get_commit_mapping<C: ConnectionTrait>(
+        &self,
+        conn: &C,
+        repo_path: &str,
+    ) -> Result<HashMap<Hash, Option<Hash>>, GitError> {
+        let models = commit_mapping::Entity::find()
+            .filter(commit_mapping::Column::RepoPath.eq(repo_path))
+            .all(conn)
+            .await?;
+        models
+            .into_iter()
+            .map(|m| {
+                Ok((
+                    Hash::from_str(&m.mono_id)?,
+                    m.split_id.map(|id| Hash::from_str(&id)).transpose()?,
+                ))
+            })
+            .collect()
+    }
+
+    /// Mappings are immutable, a mapping already written by a concurrent split is kept.
+    async fn save_commit_mapping<C: ConnectionTrait>(
+        &self,
+        conn: &C,
+        models: Vec<commit_mapping::ActiveModel>,
+    ) -> Result<(), DbErr> {
+        for chunk in models.chunks(1000) {
+            commit_mapping::Entity::insert_many(chunk.iter().cloned())
+                .on_conflict(
+                    OnConflict::columns([
+                        commit_mapping::Column::RepoPath,
+                        commit_mapping::Column::MonoId,
+                    ])
+                    .update_column(commit_mapping::Column::MonoId)
+                    .to_owned(),
+                )
+                .exec(conn)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Load the commits reachable from `head` that are not in `mapping` yet, parents are
+    /// ordered before their children.
+    pub(super) async fn get_new_commits<C: ConnectionTrait, V: Sync>(
+        &self,
+        conn: &C,
+        head: &str,
+        mapping: &HashMap<Hash, V>,
+    ) -> Result<Vec<Commit>, GitError> {
+        let mut commits: HashMap<Hash, Commit> = HashMap::new();
+        let mut frontier: HashSet<Hash> = HashSet::new();
+        let head = Hash::from_str(head)?;
+        if !mapping.contains_key(&head) {
+            frontier.insert(head);
+        }
+        while !frontier.is_empty() {
+            let models = commit::Entity::find()
+                .filter(commit::Column::GitId.is_in(frontier.iter().map(|id| id.to_plain_str())))
+                .all(conn)
+                .await?;
+            let mut parents = HashSet::new();
+            for model in models {
+                let c = Commit::new(Arc::new(MetaData::new(ObjectType::Commit, &model.meta)));
+                if frontier.remove(&c.meta.id) {
+                    parents.extend(c.parent_tree_ids.iter().copied());
+                    commits.insert(c.meta.id, c);
+                }
+            }
+            if let Some(missing) = frontier.iter().next() {
+                return Err(GitError::InvalidCommitObject(missing.to_plain_str()));
+            }
+            frontier = parents
+                .into_iter()
+                .filter(|id| !mapping.contains_key(id) && !commits.contains_key(id))
+                .collect();
+        }
+        Ok(topo_order(commits))
+    }
+
+    async fn get_split_tree<C: ConnectionTrait>(
+        &self,
+        conn: &C,
+        split_id: Hash,
+        split_trees: &mut HashMap<Hash, Hash>,
+    ) -> Result<Hash, GitError> {
+        if let Some(tree) = split_trees.get(&split_id) {
+            return Ok(*tree);
+        }
+        let model = commit::Entity::find()
+            .filter(commit::Column::GitId.eq(split_id.to_plain_str()))
+            .one(conn)
+            .await?
+            .ok_or_else(|| GitError::InvalidCommitObject(split_id.to_plain_str()))?;
+        let tree = Hash::from_str(&model.tree)?;
+        split_trees.insert(split_id, tree);
+        Ok(tree)
+    }
+}
+
+/// Point the ref `ref_name` of `repo_path` to `git_id`, the ref is created if missing.
+pub(super) async fn upsert_ref<C: ConnectionTrait>(
+    conn: &C,
+    repo_path: &str,
+    ref_name: &str,
+    git_id: Hash,
+) -> Result<(), DbErr> {
+    let model: Option<refs::Model> = refs::Entity::find()
+        .filter(refs::Column::RepoPath.eq(repo_path))
+        .filter(refs::Column::RefName.eq(ref_name))
+        .one(conn)
+        .await?;
+    match model {
+        Some(model) if model.ref_git_id == git_id.to_plain_str() => {}
+        Some(model) => {
+            let mut model: refs::ActiveModel = model.into();
+            model.ref_git_id = Set(git_id.to_plain_str());
+            model.updated_at = Set(chrono::Utc::now().naive_utc());
+            model.update(conn).await?;
+        }
+        None => {
+            let model = refs::ActiveModel {
+                id: NotSet,
+                repo_path: Set(repo_path.to_string()),
+                ref_name: Set(ref_name.to_string()),
+                ref_git_id: Set(git_id.to_plain_str()),
+                created_at: Set(chrono::Utc::now().naive_utc()),
+                updated_at: Set(chrono::Utc::now().naive_utc()),
+            };
+            refs::Entity::insert(model).exec(conn).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Point the ref `ref_name` of `repo_path` to `git_id` if it still points to `old`, or create it
+/// if `old` is `None` and it's still missing. Returns false if the ref was moved meanwhile.
+async fn swap_ref<C: ConnectionTrait>(
+    conn: &C,
+    repo_path: &str,
+    ref_name: &str,
+    old: Option<&str>,
+    git_id: Hash,
+) -> Result<bool, DbErr> {
+    let now = chrono::Utc::now().naive_utc();
+    let swapped = match old {
+        Some(old) if old == git_id.to_plain_str() => return Ok(true),
+        Some(old) => {
+            refs::Entity::update_many()
+                .col_expr(refs::Column::RefGitId, Expr::value(git_id.to_plain_str()))
+                .col_expr(refs::Column::UpdatedAt, Expr::value(now))
+                .filter(refs::Column::RepoPath.eq(repo_path))
+                .filter(refs::Column::RefName.eq(ref_name))
+                .filter(refs::Column::RefGitId.eq(old))
+                .exec(conn)
+                .await?
+                .rows_affected
+        }
+        None => conn
+            .execute(Statement::from_sql_and_values(
+                DatabaseBackend::MySql,
+                r#"INSERT INTO refs (repo_path, ref_name, ref_git_id, created_at, updated_at)
+                   SELECT ?, ?, ?, ?, ? FROM DUAL
+                   WHERE NOT EXISTS (SELECT 1 FROM refs WHERE repo_path = ? AND ref_name = ?)"#,
+                [
+                    repo_path.into(),
+                    ref_name.into(),
+                    git_id.to_plain_str().into(),
+                    now.into(),
+                    now.into(),
+                    repo_path.into(),
+                    ref_name.into(),
+                ],
+            ))
+            .await?
+            .rows_affected(),
+    };
+    Ok(swapped == 1)
+}
+
+/// Order the commits so that every parent in the map comes before its children.
+pub(super) fn topo_order(mut commits: HashMap<Hash, Commit>) -> Vec<Commit> {
+    let mut order = Vec::new();
+    let mut visited = HashSet::new();
+    let mut stack: Vec<(Hash, bool)> = commits.keys().map(|id| (*id, false)).collect();
+    while let Some((id, parents_done)) = stack.pop() {
+        if parents_done {
+            order.push(commits.remove(&id).unwrap());
+            continue;
+        }
+        if !visited.insert(id) {
+            continue;
+        }
+        stack.push((id, true));
+        for parent in &commits[&id].parent_tree_ids {
+            if commits.contains_key(parent) && !visited.contains(parent) {
+                stack.push((*parent, false));
+            }
+        }
+    }
+    order
+}
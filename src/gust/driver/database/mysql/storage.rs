@@ -1,41 +1,101 @@
 use std::cmp::min;
 use std::collections::{HashMap, HashSet};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::Arc;
 
 use crate::git::errors::{GitError, GitLFSError};
+use crate::git::fsck::FsckConfig;
 use crate::git::hash::Hash;
 use crate::git::lfs::structs::*;
 use crate::git::object::base::commit::Commit;
-use crate::git::object::base::tree::Tree;
+use crate::git::object::base::tree::TreeItemType;
+use crate::git::object::diff::DeltaOptions;
 use crate::git::object::metadata::MetaData;
 use crate::git::object::types::ObjectType;
 use crate::git::pack::decode::ObjDecodedMap;
-use crate::git::pack::Pack;
+use crate::git::pack::limits::PackLimits;
+use crate::git::pack::resolve::PackThreads;
 use crate::git::protocol::{Command, RefCommand};
+use crate::git::signature::{PublicKey, SigningKey, Verification};
+use crate::gust::driver::commit_graph;
+use crate::gust::driver::commit_search::{like_escape, CommitInfo, CommitQuery};
+use crate::gust::driver::database::mysql::backfill::{self, BACKFILL_PAGE};
+use crate::gust::driver::database::mysql::bulk::{batch_save_model, save_node_data};
+use crate::gust::driver::database::mysql::changelist::{assign_changelists, TRUNK_REF};
+use crate::gust::driver::database::mysql::receive::MysqlPackReceiver;
+use crate::gust::driver::database::mysql::split::SplitLocks;
+use crate::gust::driver::database::mysql::tree_walk::{TreeWalk, TreeWalkOptions};
+use crate::gust::driver::object_cache::ObjectCache;
 use crate::gust::driver::structure::nodes::build_node_tree;
-use crate::gust::driver::{ObjectStorage, ZERO_ID};
-use async_recursion::async_recursion;
+use crate::gust::driver::tree_list::TreeEntryInfo;
+use crate::gust::driver::{ObjectStorage, PackReceiver, ZERO_ID};
 use async_trait::async_trait;
 use chrono::prelude::*;
-use entity::{commit, locks, meta, node, refs};
+use entity::{commit, locks, meta, node, node_data, refs};
 use futures::lock;
 use rayon::vec;
-use sea_orm::ActiveValue::NotSet;
 use sea_orm::{
-    ActiveModelTrait, ColumnTrait, DatabaseBackend, DatabaseConnection, DbErr, EntityTrait,
-    QueryFilter, Set, Statement,
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseBackend, DatabaseConnection,
+    DatabaseTransaction, DbErr, EntityTrait, QueryFilter, QueryOrder, QuerySelect, Set, Statement,
+    TransactionTrait, Value,
 };
+use tokio::io::AsyncWrite;
+
+/// the directories of a path joined by one query, MySQL joins at most 61 tables
+const PATH_JOIN_DEPTH: usize = 60;
+
+/// the name the backfill of the node paths is recorded by
+const NODE_PATH_BACKFILL: &str = "node_path";
+
+/// the name the backfill of the commit metadata columns is recorded by
+const COMMIT_METADATA_BACKFILL: &str = "commit_metadata";
 
 #[derive(Debug, Default, Clone)]
 pub struct MysqlStorage {
     pub connection: DatabaseConnection,
+    /// objects read recently, shared by the clones handed to every request
+    pub cache: Arc<ObjectCache>,
+    /// refs only updated to commits with a verified signature
+    pub signed_refs: Arc<Vec<String>>,
+    /// the key signing the commits created by the server, e.g. subdirectory splits
+    pub signing_key: Option<Arc<SigningKey>>,
+    /// the token of the admin registering signing keys, none can be registered without it
+    pub keys_admin_token: Option<Arc<String>>,
+    /// the checks of the objects received by a push
+    pub fsck: Arc<FsckConfig>,
+    /// the bounds of the packs received by a push
+    pub pack_limits: Arc<PackLimits>,
+    /// the threads decoding the packs received by a push
+    pub pack_threads: Arc<PackThreads>,
+    /// the delta search of the packs sent to clients
+    pub delta_options: Arc<DeltaOptions>,
+    /// the ref whose commits are numbered and split, `refs/heads/master` when none is set
+    pub trunk_ref: Option<Arc<String>>,
+    /// the subdirectory splits running in the server, one per subdirectory at a time
+    pub(crate) splits: Arc<SplitLocks>,
 }
 
 impl MysqlStorage {
-    pub fn new(connection: DatabaseConnection) -> MysqlStorage {
-        MysqlStorage { connection }
+    pub fn new(connection: DatabaseConnection, cache_bytes: usize) -> MysqlStorage {
+        MysqlStorage {
+            connection,
+            cache: Arc::new(ObjectCache::new(cache_bytes)),
+            signed_refs: Arc::default(),
+            signing_key: None,
+            keys_admin_token: None,
+            fsck: Arc::default(),
+            pack_limits: Arc::default(),
+            pack_threads: Arc::default(),
+            delta_options: Arc::default(),
+            trunk_ref: None,
+            splits: Arc::default(),
+        }
+    }
+
+    /// The ref whose commits are numbered and split.
+    pub(crate) fn trunk_ref(&self) -> &str {
+        self.trunk_ref.as_deref().map_or(TRUNK_REF, String::as_str)
     }
 }
 
@@ -43,24 +103,38 @@ impl MysqlStorage {
 impl ObjectStorage for MysqlStorage {
     async fn get_head_object_id(&self, repo_path: &Path) -> String {
         let path_str = repo_path.to_str().unwrap();
-        let refs_list = self.search_refs(path_str).await.unwrap();
-
-        if refs_list.is_empty() {
-            ZERO_ID.to_string()
-        } else {
-            for refs in &refs_list {
-                if repo_path.to_str().unwrap() == refs.repo_path {
-                    return refs.ref_git_id.clone();
-                }
+        match self.get_root_ref(repo_path).await {
+            Ok(None) => ZERO_ID.to_string(),
+            Ok(Some(root_ref)) if root_ref.repo_path == path_str => root_ref.ref_git_id,
+            Err(err) => {
+                tracing::error!("reading the ref of {:?} failed: {}", repo_path, err);
+                ZERO_ID.to_string()
             }
-            for refs in &refs_list {
-                // repo_path is subdirectory of some commit
-                if repo_path.starts_with(refs.repo_path.clone()) {
-                    return self.generate_child_commit_and_refs(refs, repo_path).await;
+            // repo_path is a subdirectory, its split ref is kept up to date by trunk pushes
+            Ok(Some(root_ref)) => {
+                let split_ref = refs::Entity::find()
+                    .filter(refs::Column::RepoPath.eq(path_str))
+                    .filter(refs::Column::RefName.eq(&root_ref.ref_name))
+                    .one(&self.connection)
+                    .await;
+                match split_ref {
+                    Ok(Some(split_ref)) => split_ref.ref_git_id,
+                    // never split before, the first clone waits for the split
+                    Ok(None) => match self.update_split(&root_ref, repo_path).await {
+                        Ok(split_head) => {
+                            split_head.map_or(ZERO_ID.to_string(), |h| h.to_plain_str())
+                        }
+                        Err(err) => {
+                            tracing::error!("split {:?} failed: {}", repo_path, err);
+                            ZERO_ID.to_string()
+                        }
+                    },
+                    Err(err) => {
+                        tracing::error!("reading the split of {:?} failed: {}", repo_path, err);
+                        ZERO_ID.to_string()
+                    }
                 }
             }
-            //situation: repo_path: root/repotest2/src, commit: root/repotest
-            ZERO_ID.to_string()
         }
     }
 
@@ -78,119 +152,238 @@ impl ObjectStorage for MysqlStorage {
         map
     }
 
-    async fn handle_refs(&self, command: &RefCommand, path: &Path) {
-        match command.command_type {
-            Command::Create => self.save_refs(command, path).await,
-            Command::Delete => self.delete_refs(command, path).await,
-            Command::Update => self.update_refs(command, path).await,
-        }
+    async fn receive_pack(&self, path: &Path) -> Result<Box<dyn PackReceiver>, GitError> {
+        // the objects, their index rows and the ref land together or not at all, a rejected
+        // push leaves nothing behind
+        let txn = self.connection.begin().await?;
+        Ok(Box::new(MysqlPackReceiver::new(self.clone(), txn, path)))
+    }
+
+    async fn add_signing_key(&self, email: &str, public_key: &str) -> Result<PublicKey, GitError> {
+        self.save_signing_key(email, public_key).await
+    }
+
+    async fn get_signing_keys(&self, email: &str) -> Result<Vec<PublicKey>, GitError> {
+        let mut keys = self.signing_keys_of(&[email.to_owned()]).await?;
+        Ok(keys.remove(email).unwrap_or_default())
+    }
+
+    async fn get_signature(&self, object_id: &str) -> Result<Verification, GitError> {
+        let id = Hash::from_str(object_id)?;
+        let mut verifications = self.get_signatures(&self.connection, &[id]).await?;
+        verifications
+            .remove(&id)
+            .ok_or_else(|| GitError::NotFountHashValue(object_id.to_owned()))
+    }
+
+    fn server_signing_key(&self) -> Option<&PublicKey> {
+        self.signing_key.as_ref().map(|key| &key.public_key)
     }
 
-    async fn save_packfile(
+    fn keys_admin_token(&self) -> Option<&str> {
+        self.keys_admin_token.as_deref().map(String::as_str)
+    }
+
+    fn fsck_config(&self) -> &FsckConfig {
+        &self.fsck
+    }
+
+    fn pack_limits(&self) -> &PackLimits {
+        &self.pack_limits
+    }
+
+    fn pack_threads(&self) -> &PackThreads {
+        &self.pack_threads
+    }
+
+    fn object_cache(&self) -> &ObjectCache {
+        &self.cache
+    }
+
+    async fn write_full_pack(
         &self,
-        decoded_pack: Pack,
         repo_path: &Path,
-    ) -> Result<(), anyhow::Error> {
-        let mut result = ObjDecodedMap::default();
-        result.update_from_cache(&decoded_pack.result);
-        let nodes = build_node_tree(&result, repo_path).await.unwrap();
-        self.save_nodes(nodes).await.unwrap();
-        self.save_commits(&result.commits, repo_path).await.unwrap();
-        Ok(())
+        writer: &mut (dyn AsyncWrite + Unpin + Send),
+    ) -> Result<(), GitError> {
+        let tips = self
+            .get_ref_object_id(repo_path)
+            .await
+            .into_keys()
+            .filter(|id| id != ZERO_ID)
+            .map(|id| Hash::from_str(&id))
+            .collect::<Result<Vec<Hash>, GitError>>()?;
+        self.write_pack(repo_path, &tips, &[], false, writer).await
     }
 
-    async fn get_full_pack_data(&self, repo_path: &Path) -> Result<Vec<u8>, GitError> {
-        let mut hash_meta: HashMap<String, MetaData> = HashMap::new();
+    async fn write_incremental_pack(
+        &self,
+        repo_path: &Path,
+        want: &HashSet<String>,
+        have: &HashSet<String>,
+        thin: bool,
+        writer: &mut (dyn AsyncWrite + Unpin + Send),
+    ) -> Result<(), GitError> {
+        let want = want
+            .iter()
+            .map(|id| Hash::from_str(id))
+            .collect::<Result<Vec<Hash>, GitError>>()?;
+        let have = have
+            .iter()
+            .map(|id| Hash::from_str(id))
+            .collect::<Result<Vec<Hash>, GitError>>()?;
+        self.write_pack(repo_path, &want, &have, thin, writer).await
+    }
 
-        let commit_metas = self.get_all_commits_by_path(repo_path).await.unwrap();
-        let mut commits = Vec::new();
-        let mut tree_ids = Vec::new();
+    async fn get_commit_by_changelist(
+        &self,
+        repo_path: &Path,
+        cl: i64,
+    ) -> Result<(i64, String), GitError> {
+        self.get_commit_by_cl(repo_path, cl).await
+    }
 
-        for c_meta in commit_metas {
-            let c = Commit::new(Arc::new(c_meta));
-            tree_ids.push(c.tree_id.to_plain_str());
-            commits.push(c);
-        }
-        let trees = self.get_nodes_by_ids(tree_ids).await;
-        for commit in commits {
-            hash_meta.insert(
-                commit.meta.id.to_plain_str(),
-                Arc::try_unwrap(commit.meta).unwrap(),
-            );
-            if let Some(root) = trees.get(&commit.tree_id) {
-                self.get_child_trees(&root, &mut hash_meta).await
-            } else {
-                return Err(GitError::InvalidTreeObject(commit.tree_id.to_plain_str()));
-            };
-        }
-        let result: Vec<u8> = Pack::default().encode(Some(hash_meta.into_values().collect()));
-        Ok(result)
+    async fn get_changelist_by_commit(
+        &self,
+        repo_path: &Path,
+        commit_id: &str,
+    ) -> Result<i64, GitError> {
+        self.get_cl_by_commit(repo_path, commit_id).await
     }
 
-    async fn get_incremental_pack_data(
+    async fn search_commits(
         &self,
         repo_path: &Path,
-        want: &HashSet<String>,
-        _have: &HashSet<String>,
-    ) -> Result<Vec<u8>, GitError> {
-        let mut hash_meta: HashMap<String, MetaData> = HashMap::new();
-        let all_commits = self.get_all_commits_by_path(repo_path).await.unwrap();
-
-        for c_meta in all_commits {
-            if want.contains(&c_meta.id.to_plain_str()) {
-                let c = Commit::new(Arc::new(c_meta));
-                if let Some(root) = self.get_node_by_id(&c.tree_id.to_plain_str()).await {
-                    self.get_child_trees(&root, &mut hash_meta).await
-                } else {
-                    return Err(GitError::InvalidTreeObject(c.tree_id.to_plain_str()));
-                };
+        query: &CommitQuery,
+    ) -> Result<Vec<CommitInfo>, GitError> {
+        // commits of a subdirectory are the split commits
+        if let Some(root_ref) = self.get_root_ref(repo_path).await? {
+            if root_ref.repo_path != repo_path.to_str().unwrap() {
+                self.update_split(&root_ref, repo_path).await?;
             }
         }
+        let mut select =
+            commit::Entity::find().filter(commit::Column::RepoPath.eq(repo_path.to_str().unwrap()));
+        if let Some(path) = &query.path {
+            let head = self.get_head_object_id(repo_path).await;
+            if head == ZERO_ID {
+                return Ok(Vec::new());
+            }
+            // without other filters the walk can stop at the first `limit` matches
+            let limit = (!query.has_metadata_filters()).then(|| query.limit() as usize);
+            let ids =
+                commit_graph::path_history(self, &[Hash::from_str(&head)?], path, limit).await?;
+            if ids.is_empty() {
+                return Ok(Vec::new());
+            }
+            select =
+                select.filter(commit::Column::GitId.is_in(ids.iter().map(|id| id.to_plain_str())));
+        }
+        // prefix matches are answered by the indexes of the columns
+        if let Some(author) = &query.author {
+            select =
+                select.filter(commit::Column::Author.like(&format!("{}%", like_escape(author))));
+        }
+        if let Some(committer) = &query.committer {
+            select = select
+                .filter(commit::Column::Committer.like(&format!("{}%", like_escape(committer))));
+        }
+        if let Some(since) = query.since {
+            select = select.filter(commit::Column::CommitterTime.gte(since));
+        }
+        if let Some(until) = query.until {
+            select = select.filter(commit::Column::CommitterTime.lte(until));
+        }
+        if let Some(message) = &query.message {
+            // no index answers a substring, the messages of the commits left are scanned
+            select =
+                select.filter(commit::Column::Content.like(&format!("%{}%", like_escape(message))));
+        }
+        let commits = select
+            .order_by_desc(commit::Column::CommitterTime)
+            .limit(query.limit())
+            .all(&self.connection)
+            .await?;
+        Ok(commits.into_iter().map(CommitInfo::from).collect())
+    }
 
-        let result: Vec<u8> = Pack::default().encode(Some(hash_meta.into_values().collect()));
-        Ok(result)
+    async fn is_ancestor(&self, ancestor: &str, descendant: &str) -> Result<bool, GitError> {
+        let ancestor = Hash::from_str(ancestor)?;
+        let descendant = Hash::from_str(descendant)?;
+        commit_graph::is_ancestor(self, ancestor, descendant).await
     }
 
-    async fn get_commit_by_hash(&self, hash: &str) -> Result<MetaData, GitError> {
+    async fn merge_base(&self, a: &str, b: &str) -> Result<Option<String>, GitError> {
+        let base = commit_graph::merge_base(self, Hash::from_str(a)?, Hash::from_str(b)?).await?;
+        Ok(base.map(|id| id.to_plain_str()))
+    }
+
+    async fn rev_list(
+        &self,
+        include: &[String],
+        exclude: &[String],
+        limit: Option<usize>,
+    ) -> Result<Vec<String>, GitError> {
+        let include = include
+            .iter()
+            .map(|id| Hash::from_str(id))
+            .collect::<Result<Vec<Hash>, GitError>>()?;
+        let exclude = exclude
+            .iter()
+            .map(|id| Hash::from_str(id))
+            .collect::<Result<Vec<Hash>, GitError>>()?;
+        let ids = commit_graph::rev_list(self, &include, &exclude, limit).await?;
+        Ok(ids.into_iter().map(|id| id.to_plain_str()).collect())
+    }
+
+    async fn get_commit_by_hash(&self, hash: &str) -> Result<Option<MetaData>, GitError> {
+        if let Some(object) = self.cache.get(&Hash::from_str(hash)?) {
+            // trees and blobs share the cache
+            if object.t == ObjectType::Commit {
+                return Ok(Some(object.as_ref().clone()));
+            }
+        }
         let commit: Option<commit::Model> = commit::Entity::find()
             .filter(commit::Column::GitId.eq(hash))
             .one(&self.connection)
+            .await?;
+        Ok(commit.map(|commit| {
+            let object = MetaData::new(ObjectType::Commit, &commit.meta);
+            self.cache.insert(Arc::new(object.clone()));
+            object
+        }))
+    }
+
+    async fn list_tree(
+        &self,
+        repo_path: &Path,
+        commit_id: &str,
+        path: &Path,
+    ) -> Result<Vec<TreeEntryInfo>, GitError> {
+        self.list_directory(repo_path, Hash::from_str(commit_id)?, path)
             .await
-            .unwrap();
-        if let Some(commit) = commit {
-            Ok(MetaData::new(ObjectType::Commit, &commit.meta))
-        } else {
-            return Err(GitError::InvalidCommitObject(hash.to_string()));
-        }
     }
 
-    async fn get_hash_object(&self, hash: &str) -> Result<MetaData, GitError> {
+    async fn get_hash_object(&self, hash: &str) -> Result<Option<MetaData>, GitError> {
         tracing::info!("hash:{}", hash);
-        let model = node::Entity::find()
-            .filter(node::Column::GitId.eq(hash))
+        if let Some(object) = self.cache.get(&Hash::from_str(hash)?) {
+            return Ok(Some(object.as_ref().clone()));
+        }
+        let model = node_data::Entity::find_by_id(hash)
             .one(&self.connection)
-            .await
-            .unwrap();
+            .await?;
 
         if let Some(model) = model {
-            if model.node_type == "tree" {
-                // let mut tree_items: Vec<TreeItem> = Vec::new();
-                // let childs = node::Entity::find()
-                //     .filter(node::Column::Pid.eq(hash))
-                //     .all(&self.connection)
-                //     .await
-                //     .unwrap();
-                // for c in childs {
-                //     tree_items.push(TreeItem::convert_from_model(c));
-                // }
-                // let t = Tree::convert_from_model(&model, tree_items);
-                // let meta = t.encode_metadata().unwrap();
-                Ok(MetaData::new(ObjectType::Tree, &model.data))
-            } else {
-                Ok(MetaData::new(ObjectType::Blob, &model.data))
-            }
+            let object_type = match model.node_type.as_str() {
+                "tree" => ObjectType::Tree,
+                "tag" => ObjectType::Tag,
+                _ => ObjectType::Blob,
+            };
+            let object = MetaData::new(object_type, &model.data);
+            self.cache.insert(Arc::new(object.clone()));
+            Ok(Some(object))
         } else {
-            return Err(GitError::NotFountHashValue(hash.to_string()));
+            // commits are the only other objects stored
+            self.get_commit_by_hash(hash).await
         }
     }
 
@@ -480,247 +673,419 @@ impl ObjectStorage for MysqlStorage {
 }
 
 impl MysqlStorage {
-    async fn get_all_commits_by_path(&self, path: &Path) -> Result<Vec<MetaData>, anyhow::Error> {
-        let commits: Vec<commit::Model> = commit::Entity::find()
-            .filter(commit::Column::RepoPath.eq(path.to_str().unwrap()))
-            .all(&self.connection)
-            .await
-            .unwrap();
-        let mut result = vec![];
-        for commit in commits {
-            result.push(MetaData::new(ObjectType::Commit, &commit.meta))
-        }
-        Ok(result)
+    /// The ref of the monorepo containing `repo_path`, which is the outermost repo path.
+    pub(crate) async fn get_root_ref(
+        &self,
+        repo_path: &Path,
+    ) -> Result<Option<refs::Model>, DbErr> {
+        let refs_list = self.search_refs(repo_path.to_str().unwrap()).await?;
+        // situation: repo_path: root/repotest2/src, commit: root/repotest doesn't match
+        Ok(refs_list
+            .into_iter()
+            .filter(|refs| repo_path.starts_with(&refs.repo_path))
+            .min_by_key(|refs| refs.repo_path.len()))
     }
 
     async fn search_refs(&self, path_str: &str) -> Result<Vec<refs::Model>, DbErr> {
         refs::Entity::find()
-        .from_raw_sql(Statement::from_sql_and_values(
-            DatabaseBackend::MySql,
-            r#"SELECT * FROM gust.refs where ? LIKE CONCAT(repo_path, '%') and ref_name = 'refs/heads/master' "#,
-            [path_str.into()],
-        ))
-        .all(&self.connection)
-        .await
+            .from_raw_sql(Statement::from_sql_and_values(
+                DatabaseBackend::MySql,
+                r#"SELECT * FROM gust.refs where ? LIKE CONCAT(repo_path, '%') and ref_name = ? "#,
+                [path_str.into(), self.trunk_ref().into()],
+            ))
+            .all(&self.connection)
+            .await
+    }
+
+    /// Save the trees, commits and tags of a pushed pack with their nodes, commit graph,
+    /// changed paths and signature rows, the `blobs` of the pack are written already.
+    pub(super) async fn save_pack(
+        &self,
+        txn: &DatabaseTransaction,
+        decoded: &ObjDecodedMap,
+        blobs: HashSet<Hash>,
+        repo_path: &Path,
+    ) -> Result<(), GitError> {
+        // the trees may point to blobs stored by earlier pushes
+        let stored_blobs = decoded
+            .trees
+            .iter()
+            .flat_map(|t| t.tree_items.iter())
+            .filter(|item| !matches!(item.item_type, TreeItemType::Tree | TreeItemType::Commit))
+            .map(|item| item.id)
+            .filter(|id| !blobs.contains(id));
+        let ids: HashSet<Hash> = decoded
+            .trees
+            .iter()
+            .map(|t| t.meta.id)
+            .chain(stored_blobs)
+            .collect();
+        let mut stored_data = self
+            .get_stored_data_ids(txn, ids.into_iter().collect())
+            .await?;
+        stored_data.extend(blobs);
+        let models = build_node_tree(decoded, repo_path, stored_data).await?;
+        let mut nodes = models.nodes;
+        nodes.extend(self.expand_stored_trees(txn, models.stored_trees).await?);
+        save_node_data(txn, models.node_datas).await?;
+        batch_save_model(txn, nodes, None).await?;
+        self.save_commits(txn, &decoded.commits, repo_path).await?;
+        self.index_commits(txn, &decoded.commits).await?;
+        self.save_signatures(txn, &decoded.commits, &decoded.tags)
+            .await
     }
 
-    async fn save_refs(&self, command: &RefCommand, path: &Path) {
+    /// Apply `command` to the refs of `path` once the signature policy allows it.
+    pub(super) async fn handle_refs(
+        &self,
+        txn: &DatabaseTransaction,
+        command: &RefCommand,
+        path: &Path,
+    ) -> Result<(), GitError> {
+        self.check_signature_policy(txn, command, path).await?;
+        if let Some(root_ref) = self.get_root_ref(path).await? {
+            // a push to a subdirectory of the monorepo is written back to the root
+            if root_ref.repo_path != path.to_str().unwrap() && root_ref.ref_name == command.ref_name
+            {
+                return self.push_to_subtree(txn, &root_ref, command, path).await;
+            }
+        }
+        match command.command_type {
+            Command::Create => self.save_refs(txn, command, path).await?,
+            Command::Delete => self.delete_refs(txn, command, path).await?,
+            Command::Update => self.update_refs(txn, command, path).await?,
+        }
+        if command.ref_name == self.trunk_ref() && !matches!(command.command_type, Command::Delete)
+        {
+            let head = Hash::from_str(&command.new_id)?;
+            assign_changelists(txn, path.to_str().unwrap(), head).await?;
+        }
+        Ok(())
+    }
+
+    async fn save_refs(
+        &self,
+        txn: &DatabaseTransaction,
+        command: &RefCommand,
+        path: &Path,
+    ) -> Result<(), DbErr> {
         let save_models: Vec<refs::ActiveModel> =
             vec![command.convert_to_model(path.to_str().unwrap())];
-        batch_save_model(&self.connection, save_models)
-            .await
-            .unwrap();
+        batch_save_model(txn, save_models, None).await
     }
 
-    async fn update_refs(&self, command: &RefCommand, path: &Path) {
-        let ref_data: Option<refs::Model> = refs::Entity::find()
+    async fn update_refs(
+        &self,
+        txn: &DatabaseTransaction,
+        command: &RefCommand,
+        path: &Path,
+    ) -> Result<(), DbErr> {
+        let ref_data: refs::Model = refs::Entity::find()
             .filter(refs::Column::RefGitId.eq(&command.old_id))
             .filter(refs::Column::RepoPath.eq(path.to_str().unwrap()))
-            .one(&self.connection)
-            .await
-            .unwrap();
-        let mut ref_data: refs::ActiveModel = ref_data.unwrap().into();
+            .one(txn)
+            .await?
+            .ok_or_else(|| {
+                DbErr::RecordNotFound(format!("{} at {}", command.ref_name, command.old_id))
+            })?;
+        let mut ref_data: refs::ActiveModel = ref_data.into();
         ref_data.ref_git_id = Set(command.new_id.to_owned());
         ref_data.updated_at = Set(chrono::Utc::now().naive_utc());
-        ref_data.update(&self.connection).await.unwrap();
+        ref_data.update(txn).await?;
+        Ok(())
     }
 
-    async fn delete_refs(&self, command: &RefCommand, path: &Path) {
+    async fn delete_refs(
+        &self,
+        txn: &DatabaseTransaction,
+        command: &RefCommand,
+        path: &Path,
+    ) -> Result<(), DbErr> {
         let delete_ref = refs::ActiveModel {
             ref_git_id: Set(command.old_id.to_owned()),
             repo_path: Set(path.to_str().unwrap().to_owned()),
             ..Default::default()
         };
-        refs::Entity::delete(delete_ref)
-            .exec(&self.connection)
-            .await
-            .unwrap();
-    }
-
-    async fn search_commits(&self, path_str: &str) -> Result<Vec<commit::Model>, DbErr> {
-        commit::Entity::find()
-            .from_raw_sql(Statement::from_sql_and_values(
-                DatabaseBackend::MySql,
-                r#"SELECT * FROM gust.commit where ? LIKE CONCAT(repo_path, '%')"#,
-                [path_str.into()],
-            ))
-            .all(&self.connection)
-            .await
+        refs::Entity::delete(delete_ref).exec(txn).await?;
+        Ok(())
     }
 
-    async fn save_nodes(&self, nodes: Vec<node::ActiveModel>) -> Result<bool, anyhow::Error> {
-        let conn = &self.connection;
-        let mut sum = 0;
-        let mut batch_nodes = Vec::new();
-        for node in nodes {
-            // let model = node.try_into_model().unwrap();
-            let size = node.data.as_ref().len();
-            let limit = 10 * 1024 * 1024;
-            if sum + size < limit && batch_nodes.len() < 50 {
-                sum += size;
-                batch_nodes.push(node);
-            } else {
-                node::Entity::insert_many(batch_nodes)
-                    .exec(conn)
-                    .await
-                    .unwrap();
-                sum = 0;
-                batch_nodes = Vec::new();
-                batch_nodes.push(node);
+    /// Return the hashes in `ids` whose content is already stored.
+    pub(super) async fn get_stored_data_ids<C: ConnectionTrait>(
+        &self,
+        conn: &C,
+        ids: Vec<Hash>,
+    ) -> Result<HashSet<Hash>, GitError> {
+        let mut stored = HashSet::new();
+        for chunk in ids.chunks(1000) {
+            let git_ids: Vec<String> = node_data::Entity::find()
+                .select_only()
+                .column(node_data::Column::GitId)
+                .filter(node_data::Column::GitId.is_in(chunk.iter().map(|id| id.to_plain_str())))
+                .into_tuple()
+                .all(conn)
+                .await?;
+            for id in git_ids {
+                stored.insert(Hash::from_str(&id)?);
             }
         }
-        if batch_nodes.len() != 0 {
-            node::Entity::insert_many(batch_nodes)
-                .exec(conn)
-                .await
-                .unwrap();
-        }
-        Ok(true)
+        Ok(stored)
     }
 
-    async fn save_commits(
+    /// Insert the commit rows of `commits` in `repo_path`.
+    pub(crate) async fn save_commits<C: ConnectionTrait>(
         &self,
-        commits: &Vec<Commit>,
+        conn: &C,
+        commits: &[Commit],
         repo_path: &Path,
-    ) -> Result<bool, anyhow::Error> {
-        let conn = &self.connection;
-        let mut save_models: Vec<commit::ActiveModel> = Vec::new();
-        for commit in commits {
-            save_models.push(commit.convert_to_model(repo_path));
-        }
-        batch_save_model(conn, save_models).await.unwrap();
-        Ok(true)
-    }
-
-    // async fn save_node_data(
-    //     &self,
-    //     save_models: Vec<node_data::ActiveModel>,
-    // ) -> Result<bool, anyhow::Error> {
-    //     batch_save_model(&self.connection, save_models)
-    //         .await
-    //         .unwrap();
-    //     Ok(true)
-    // }
-
-    /// Because the requested path is a subdirectory of the original project directory,
-    /// a new fake commit is needed to point the subdirectory, so we need to
-    /// 1. find root commit by root_ref
-    /// 2. convert commit to git Commit object,  and calculate it's hash
-    /// 3. save the new fake commit with hash and repo_path
-    async fn generate_child_commit_and_refs(&self, refs: &refs::Model, repo_path: &Path) -> String {
-        let root_commit = commit::Entity::find()
-            .filter(commit::Column::GitId.eq(&refs.ref_git_id))
-            .one(&self.connection)
-            .await
-            .unwrap()
-            .unwrap();
+    ) -> Result<(), DbErr> {
+        let save_models: Vec<commit::ActiveModel> = commits
+            .iter()
+            .map(|commit| commit.convert_to_model(repo_path))
+            .collect();
+        batch_save_model(conn, save_models, None).await
+    }
 
-        if let Some(root_tree) = self.search_root_node_by_path(repo_path).await {
-            let child_commit = Commit::build_from_model_and_root(&root_commit, root_tree);
-            self.save_commits(&vec![child_commit.clone()], repo_path)
-                .await
-                .unwrap();
-            let commit_id = child_commit.meta.id.to_plain_str();
-            let child_refs = refs::ActiveModel {
-                id: NotSet,
-                repo_path: Set(repo_path.to_str().unwrap().to_string()),
-                ref_name: Set(refs.ref_name.clone()),
-                ref_git_id: Set(commit_id.clone()),
-                created_at: Set(chrono::Utc::now().naive_utc()),
-                updated_at: Set(chrono::Utc::now().naive_utc()),
+    /// Fill the metadata columns of the commits stored before the columns existed, a page of
+    /// commits at a time in the order they were stored, so they can be searched. Once done
+    /// it's recorded and later boots skip it.
+    pub(crate) async fn backfill_commit_metadata(&self) -> Result<(), GitError> {
+        if backfill::is_done(&self.connection, COMMIT_METADATA_BACKFILL).await? {
+            return Ok(());
+        }
+        let mut last_id = 0;
+        loop {
+            // every commit saved since has an author
+            let page: Vec<(i32, Vec<u8>)> = commit::Entity::find()
+                .select_only()
+                .column(commit::Column::Id)
+                .column(commit::Column::Meta)
+                .filter(commit::Column::Id.gt(last_id))
+                .filter(commit::Column::Author.is_null())
+                .order_by_asc(commit::Column::Id)
+                .limit(BACKFILL_PAGE as u64)
+                .into_tuple()
+                .all(&self.connection)
+                .await?;
+            let Some((last, _)) = page.last() else {
+                break;
             };
-            batch_save_model(&self.connection, vec![child_refs])
-                .await
-                .unwrap();
-            commit_id
-        } else {
-            ZERO_ID.to_string()
+            last_id = *last;
+            tracing::info!("backfilling the metadata of {} commits", page.len());
+            let txn = self.connection.begin().await?;
+            for (id, meta) in page {
+                let meta = MetaData::new(ObjectType::Commit, &meta);
+                let model = Commit::try_new(Arc::new(meta))?.convert_to_model(Path::new(""));
+                commit::ActiveModel {
+                    id: Set(id),
+                    pid: model.pid,
+                    author: model.author,
+                    committer: model.committer,
+                    content: model.content,
+                    author_time: model.author_time,
+                    committer_time: model.committer_time,
+                    ..Default::default()
+                }
+                .update(&txn)
+                .await?;
+            }
+            txn.commit().await?;
         }
+        backfill::mark_done(&self.connection, COMMIT_METADATA_BACKFILL).await?;
+        Ok(())
     }
 
-    async fn search_root_node_by_path(&self, repo_path: &Path) -> Option<node::Model> {
-        tracing::debug!("file_name: {:?}", repo_path.file_name());
-        let res = node::Entity::find()
-            .filter(node::Column::Name.eq(repo_path.file_name().unwrap().to_str().unwrap()))
-            .one(&self.connection)
-            .await
-            .unwrap();
-        if let Some(res) = res {
-            Some(res)
-        } else {
-            node::Entity::find()
-                // .filter(node::Column::Path.eq(repo_path.to_str().unwrap()))
-                .filter(node::Column::Name.eq(""))
-                .one(&self.connection)
-                .await
-                .unwrap()
-        }
+    /// Add saved commits to the commit graph and compute their changed paths, which reads
+    /// their trees so these must be visible to `conn` already.
+    pub(crate) async fn index_commits<C: ConnectionTrait>(
+        &self,
+        conn: &C,
+        commits: &[Commit],
+    ) -> Result<(), GitError> {
+        self.save_commit_graph(conn, commits).await?;
+        self.save_changed_paths(conn, commits).await
     }
 
-    async fn get_node_by_id(&self, id: &str) -> Option<node::Model> {
-        node::Entity::find()
-            .filter(node::Column::GitId.eq(id))
-            .one(&self.connection)
-            .await
-            .unwrap()
+    /// Find the tree node at `path` in the tree `root_tree` of a commit, `path` is relative to
+    /// the repo root. Every directory of the path is joined to its parent by tree and full path,
+    /// so the whole path is looked up in one query unless it's deeper than `PATH_JOIN_DEPTH`.
+    pub(crate) async fn search_tree_node_by_path<C: ConnectionTrait>(
+        &self,
+        conn: &C,
+        root_tree: &str,
+        path: &Path,
+    ) -> Result<Option<node::Model>, DbErr> {
+        let mut current = PathBuf::new();
+        let paths: Vec<String> = path
+            .components()
+            .map(|component| {
+                current.push(component);
+                current.to_str().unwrap().to_owned()
+            })
+            .collect();
+        let mut pid = root_tree.to_owned();
+        let mut result = None;
+        for chunk in paths.chunks(PATH_JOIN_DEPTH) {
+            let mut sql = format!("SELECT n{}.* FROM node n0", chunk.len() - 1);
+            for i in 1..chunk.len() {
+                sql.push_str(&format!(
+                    " JOIN node n{i} ON n{i}.pid = n{}.git_id AND n{i}.path = ? AND n{i}.node_type = 'tree'",
+                    i - 1
+                ));
+            }
+            sql.push_str(" WHERE n0.pid = ? AND n0.path = ? AND n0.node_type = 'tree' LIMIT 1");
+            let mut values: Vec<Value> = chunk[1..].iter().map(|path| path.into()).collect();
+            values.push(pid.into());
+            values.push(chunk[0].as_str().into());
+            let Some(tree_node) = node::Entity::find()
+                .from_raw_sql(Statement::from_sql_and_values(
+                    DatabaseBackend::MySql,
+                    &sql,
+                    values,
+                ))
+                .one(conn)
+                .await?
+            else {
+                return Ok(None);
+            };
+            pid = tree_node.git_id.clone();
+            result = Some(tree_node);
+        }
+        Ok(result)
     }
 
-    async fn get_nodes_by_ids(&self, ids: Vec<String>) -> HashMap<Hash, node::Model> {
-        node::Entity::find()
-            .filter(node::Column::GitId.is_in(ids))
-            .all(&self.connection)
-            .await
-            .unwrap()
+    /// Create the children nodes of trees stored by earlier pushes, for each tree that is new at
+    /// the path it appears at, e.g. a directory copied or moved to another place. Trees already
+    /// stored at the same path have all their children nodes.
+    pub(crate) async fn expand_stored_trees<C: ConnectionTrait>(
+        &self,
+        conn: &C,
+        stored_trees: Vec<(Hash, PathBuf)>,
+    ) -> Result<Vec<node::ActiveModel>, GitError> {
+        let mut roots: Vec<(Hash, PathBuf)> = stored_trees
             .into_iter()
-            .map(|f| (Hash::from_str(&f.git_id).unwrap(), f))
-            .collect()
-    }
-
-    // retrieve all sub trees recursively
-    #[async_recursion]
-    async fn get_child_trees(&self, root: &node::Model, hash_meta: &mut HashMap<String, MetaData>) {
-        let t = Tree::new(Arc::new(MetaData::new(ObjectType::Tree, &root.data)));
-        let mut child_ids = vec![];
-        for item in t.tree_items {
-            if !hash_meta.contains_key(&item.id.to_plain_str()) {
-                child_ids.push(item.id.to_plain_str());
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        let expanded = self.get_stored_tree_paths(conn, &roots).await?;
+        roots.retain(|tree| !expanded.contains(tree));
+        self.expand_trees(conn, roots).await
+    }
+
+    /// Create the children nodes of `roots`, the subtrees already stored at their path aren't
+    /// walked.
+    async fn expand_trees<C: ConnectionTrait>(
+        &self,
+        conn: &C,
+        roots: Vec<(Hash, PathBuf)>,
+    ) -> Result<Vec<node::ActiveModel>, GitError> {
+        let mut nodes = Vec::new();
+        let mut walk = TreeWalk::new(self, conn, roots, TreeWalkOptions::default());
+        while let Some(entries) = walk.next_level().await? {
+            let mut subtrees = Vec::new();
+            for entry in entries {
+                nodes.push(entry.item.convert_to_model(&entry.parent, &entry.path));
+                if entry.item.item_type == TreeItemType::Tree {
+                    subtrees.push((entry.item.id, entry.path));
+                }
             }
+            let expanded = self.get_stored_tree_paths(conn, &subtrees).await?;
+            walk.retain_pending(|id, path| !expanded.contains(&(*id, path.to_path_buf())));
         }
-        let childs = node::Entity::find()
-            .filter(node::Column::GitId.is_in(child_ids))
-            .all(&self.connection)
-            .await
-            .unwrap();
-        for c in childs {
-            if c.node_type == "tree" {
-                self.get_child_trees(&c, hash_meta).await;
-            } else {
-                let b_meta = MetaData::new(ObjectType::Blob, &c.data);
-                hash_meta.insert(b_meta.id.to_plain_str(), b_meta);
+        Ok(nodes)
+    }
+
+    /// Create the nodes of the commits stored before nodes had a parent tree and a path, such
+    /// nodes can't be found by path. The root tree of every commit without children nodes is
+    /// walked again, then the nodes without a path are deleted.
+    pub(crate) async fn backfill_node_paths(&self) -> Result<(), GitError> {
+        if backfill::is_done(&self.connection, NODE_PATH_BACKFILL).await? {
+            return Ok(());
+        }
+        let mut last_id = 0;
+        loop {
+            let page: Vec<(i32, String)> = commit::Entity::find()
+                .select_only()
+                .column(commit::Column::Id)
+                .column(commit::Column::Tree)
+                .filter(commit::Column::Id.gt(last_id))
+                .order_by_asc(commit::Column::Id)
+                .limit(BACKFILL_PAGE as u64)
+                .into_tuple()
+                .all(&self.connection)
+                .await?;
+            let Some((last, _)) = page.last() else {
+                break;
+            };
+            last_id = *last;
+            let trees: HashSet<String> = page.into_iter().map(|(_, tree)| tree).collect();
+            let expanded: HashSet<String> = node::Entity::find()
+                .select_only()
+                .column(node::Column::Pid)
+                .distinct()
+                .filter(node::Column::Pid.is_in(trees.iter().cloned()))
+                .into_tuple::<String>()
+                .all(&self.connection)
+                .await?
+                .into_iter()
+                .collect();
+            let mut roots = Vec::new();
+            for tree in trees.difference(&expanded) {
+                roots.push((Hash::from_str(tree)?, PathBuf::new()));
             }
+            // the nodes of a root land together, a root with some children is taken as done
+            let txn = self.connection.begin().await?;
+            let nodes = self.expand_trees(&txn, roots).await?;
+            tracing::info!("backfilling {} nodes with their path", nodes.len());
+            batch_save_model(&txn, nodes, None).await?;
+            txn.commit().await?;
         }
-        let t_meta = t.meta;
-        tracing::info!("{}, {}", t_meta.id, t.tree_name);
-        hash_meta.insert(t_meta.id.to_plain_str(), Arc::try_unwrap(t_meta).unwrap());
+        // only root trees have no name, the other nodes without a parent tree have no path
+        node::Entity::delete_many()
+            .filter(node::Column::Pid.eq(""))
+            .filter(node::Column::Name.ne(""))
+            .exec(&self.connection)
+            .await?;
+        backfill::mark_done(&self.connection, NODE_PATH_BACKFILL).await?;
+        Ok(())
     }
-}
 
-// mysql sea_orm bathc insert
-async fn batch_save_model<E, A>(
-    conn: &DatabaseConnection,
-    save_models: Vec<A>,
-) -> Result<(), anyhow::Error>
-where
-    E: EntityTrait,
-    A: ActiveModelTrait<Entity = E> + From<<E as EntityTrait>::Model> + Send,
-{
-    let mut futures = Vec::new();
-
-    // notice that sqlx not support packets larger than 16MB now
-    for chunk in save_models.chunks(100) {
-        let save_result = E::insert_many(chunk.iter().cloned()).exec(conn).await;
-        futures.push(save_result);
-    }
-    // futures::future::join_all(futures).await;
-    Ok(())
+    /// Return the `(tree, path)` pairs in `trees` that already have a node stored.
+    async fn get_stored_tree_paths<C: ConnectionTrait>(
+        &self,
+        conn: &C,
+        trees: &[(Hash, PathBuf)],
+    ) -> Result<HashSet<(Hash, PathBuf)>, GitError> {
+        let mut stored = HashSet::new();
+        for chunk in trees.chunks(500) {
+            let pairs: Vec<(String, String)> = node::Entity::find()
+                .select_only()
+                .column(node::Column::GitId)
+                .column(node::Column::Path)
+                .filter(node::Column::GitId.is_in(chunk.iter().map(|(id, _)| id.to_plain_str())))
+                .filter(
+                    node::Column::Path.is_in(
+                        chunk
+                            .iter()
+                            .map(|(_, path)| path.to_str().unwrap().to_owned()),
+                    ),
+                )
+                .filter(node::Column::NodeType.eq("tree"))
+                .into_tuple()
+                .all(conn)
+                .await?;
+            for (id, path) in pairs {
+                stored.insert((Hash::from_str(&id)?, PathBuf::from(path)));
+            }
+        }
+        Ok(stored)
+    }
+
+    pub(crate) async fn get_node_by_id<C: ConnectionTrait>(
+        &self,
+        conn: &C,
+        id: &str,
+    ) -> Result<Option<node_data::Model>, GitError> {
+        Ok(node_data::Entity::find_by_id(id).one(conn).await?)
+    }
 }
@@ -0,0 +1,150 @@
+//! Delta bases of thin packs. A tree or blob sent to a client is paired with the object at the
+//! same path in a commit the client has, the version most likely to differ by a small delta.
+
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+use entity::node_data;
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+
+use crate::git::errors::GitError;
+use crate::git::hash::Hash;
+use crate::git::object::base::tree::{TreeItem, TreeItemType};
+use crate::git::object::metadata::MetaData;
+use crate::git::object::types::ObjectType;
+use crate::gust::driver::commit_graph::CommitGraph;
+
+use super::storage::MysqlStorage;
+use super::tree_walk::TreeCache;
+
+/// ids per `IN (...)` lookup
+const LOOKUP_CHUNK: usize = 1000;
+
+impl MysqlStorage {
+    /// For the trees and blobs of `sending`, the object at the same path in the latest commit
+    /// of `haves` known to the server, if any.
+    pub(crate) async fn thin_pack_bases(
+        &self,
+        wants: &[Hash],
+        haves: &[Hash],
+        sending: &HashSet<Hash>,
+    ) -> Result<HashMap<Hash, MetaData>, GitError> {
+        let known = self.lookup(haves).await?;
+        let Some(have) = known
+            .iter()
+            .max_by_key(|(id, entry)| (entry.generation, **id))
+            .map(|(id, _)| *id)
+        else {
+            return Ok(HashMap::new());
+        };
+        let ids: Vec<Hash> = wants.iter().copied().chain([have]).collect();
+        let roots = self.get_commit_trees(&self.connection, &ids).await?;
+        let Some(&have_root) = roots.get(&have) else {
+            return Ok(HashMap::new());
+        };
+
+        // pairs of (object sent, base), trees are compared one level at a time and only the
+        // subtrees sent are descended into
+        let mut pairs: HashMap<Hash, Hash> = HashMap::new();
+        let mut level: Vec<(Hash, Hash)> = wants
+            .iter()
+            .filter_map(|want| roots.get(want))
+            .filter(|root| sending.contains(root))
+            .map(|root| (*root, have_root))
+            .collect();
+        let mut trees = TreeCache::new();
+        while !level.is_empty() {
+            let ids: Vec<Hash> = level.iter().flat_map(|(new, old)| [*new, *old]).collect();
+            self.load_trees(&self.connection, &ids, &mut trees).await?;
+            let mut next = Vec::new();
+            for (new, old) in level {
+                if pairs.contains_key(&new) {
+                    continue;
+                }
+                pairs.insert(new, old);
+                let old_items: HashMap<&String, &TreeItem> = trees[&old]
+                    .tree_items
+                    .iter()
+                    .map(|item| (&item.filename, item))
+                    .collect();
+                for item in &trees[&new].tree_items {
+                    let Some(old_item) = old_items.get(&item.filename) else {
+                        continue;
+                    };
+                    if !sending.contains(&item.id) {
+                        continue;
+                    }
+                    match (object_type(item.item_type), object_type(old_item.item_type)) {
+                        (Some(ObjectType::Tree), Some(ObjectType::Tree)) => {
+                            next.push((item.id, old_item.id))
+                        }
+                        (Some(ObjectType::Blob), Some(ObjectType::Blob)) => {
+                            pairs.entry(item.id).or_insert(old_item.id);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            level = next;
+        }
+
+        let mut bases = HashMap::new();
+        let mut blob_bases = Vec::new();
+        for (new, old) in &pairs {
+            match trees.get(old) {
+                Some(tree) => {
+                    bases.insert(*new, tree.meta.as_ref().clone());
+                }
+                None => blob_bases.push(*old),
+            }
+        }
+        let blobs = self.load_blobs(&blob_bases).await?;
+        for (new, old) in pairs {
+            if let Some(blob) = blobs.get(&old) {
+                bases.insert(new, blob.clone());
+            }
+        }
+        tracing::info!("{} delta bases for a thin pack", bases.len());
+        Ok(bases)
+    }
+
+    /// The blobs with the given ids, from the object cache or in batched queries. Blobs not
+    /// found are left out.
+    async fn load_blobs(&self, ids: &[Hash]) -> Result<HashMap<Hash, MetaData>, GitError> {
+        let mut blobs = HashMap::new();
+        let mut missing = Vec::new();
+        for id in ids {
+            match self.cache.get(id) {
+                Some(object) => {
+                    blobs.insert(*id, object.as_ref().clone());
+                }
+                None => missing.push(id.to_plain_str()),
+            }
+        }
+        for chunk in missing.chunks(LOOKUP_CHUNK) {
+            let models = node_data::Entity::find()
+                .filter(node_data::Column::GitId.is_in(chunk.to_vec()))
+                .all(&self.connection)
+                .await?;
+            for model in models {
+                blobs.insert(
+                    Hash::from_str(&model.git_id)?,
+                    MetaData::new(ObjectType::Blob, &model.data),
+                );
+            }
+        }
+        Ok(blobs)
+    }
+}
+
+/// The type of the object a tree entry points to, `None` for submodule commits which live in
+/// other repos.
+fn object_type(item_type: TreeItemType) -> Option<ObjectType> {
+    match item_type {
+        TreeItemType::Tree => Some(ObjectType::Tree),
+        TreeItemType::Blob | TreeItemType::BlobExecutable | TreeItemType::Link => {
+            Some(ObjectType::Blob)
+        }
+        TreeItemType::Commit => None,
+    }
+}
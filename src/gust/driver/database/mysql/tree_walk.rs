@@ -0,0 +1,260 @@
+//! Level-order tree walks: every level of a tree hierarchy is loaded with one batched
+//! `node_data` query, whatever the number of directories in it. The walk only loads trees,
+//! blobs are returned as entries and their data is left to the caller to load when needed.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use entity::{commit, node_data};
+use sea_orm::{ColumnTrait, ConnectionTrait, EntityTrait, QueryFilter};
+
+use crate::git::errors::GitError;
+use crate::git::hash::Hash;
+use crate::git::object::base::tree::{Tree, TreeItem, TreeItemType};
+use crate::git::object::metadata::MetaData;
+use crate::git::object::types::ObjectType;
+
+use crate::gust::driver::tree_list::TreeEntryInfo;
+
+use super::storage::MysqlStorage;
+
+/// ids per `IN (...)` lookup
+const LOOKUP_CHUNK: usize = 1000;
+
+/// Trees loaded so far, by id.
+pub(super) type TreeCache = HashMap<Hash, Tree>;
+
+/// Bounds of a tree walk, both relative to the walk roots.
+#[derive(Debug, Clone, Default)]
+pub(super) struct TreeWalkOptions {
+    /// entries deeper than this are left out, the children of a root are at depth 1
+    pub max_depth: Option<usize>,
+    /// only entries at or below this path are returned, only directories leading to it are
+    /// walked
+    pub prefix: Option<PathBuf>,
+}
+
+impl TreeWalkOptions {
+    /// whether the entry at `path` is returned, and whether the walk goes below it
+    fn allows(&self, path: &Path, depth: usize) -> (bool, bool) {
+        if self.max_depth.is_some_and(|max| depth > max) {
+            return (false, false);
+        }
+        let below_max = self.max_depth.is_none_or(|max| depth < max);
+        match &self.prefix {
+            None => (true, below_max),
+            Some(prefix) if path.starts_with(prefix) => (true, below_max),
+            Some(prefix) => (false, below_max && prefix.starts_with(path)),
+        }
+    }
+}
+
+/// An entry of a tree reached by a walk.
+#[derive(Debug, Clone)]
+pub(super) struct TreeEntry {
+    /// the tree holding the entry
+    pub parent: Hash,
+    pub path: PathBuf,
+    pub item: TreeItem,
+}
+
+pub(super) struct TreeWalk<'a, C> {
+    storage: &'a MysqlStorage,
+    /// the connection the trees are read with, a transaction sees the trees it saved
+    conn: &'a C,
+    options: TreeWalkOptions,
+    /// trees whose entries are returned by the next level, with their path and depth
+    pending: Vec<(Hash, PathBuf, usize)>,
+}
+
+impl<'a, C: ConnectionTrait> TreeWalk<'a, C> {
+    /// A walk of the trees `roots`, each given with the path it is at.
+    pub fn new(
+        storage: &'a MysqlStorage,
+        conn: &'a C,
+        roots: Vec<(Hash, PathBuf)>,
+        options: TreeWalkOptions,
+    ) -> TreeWalk<'a, C> {
+        TreeWalk {
+            storage,
+            conn,
+            options,
+            pending: roots.into_iter().map(|(id, path)| (id, path, 0)).collect(),
+        }
+    }
+
+    /// Load the trees of the next level and return their entries, `None` once the walk is
+    /// done. The subtrees among the entries are walked by the next call unless dropped with
+    /// [`TreeWalk::retain_pending`] first. A tree found at several paths is loaded once.
+    pub async fn next_level(&mut self) -> Result<Option<Vec<TreeEntry>>, GitError> {
+        if self.pending.is_empty() {
+            return Ok(None);
+        }
+        let ids: Vec<Hash> = self.pending.iter().map(|(id, _, _)| *id).collect();
+        let mut trees = TreeCache::new();
+        self.storage.load_trees(self.conn, &ids, &mut trees).await?;
+
+        let mut entries = Vec::new();
+        let mut next = Vec::new();
+        for (id, path, depth) in self.pending.drain(..) {
+            for item in &trees[&id].tree_items {
+                let item_path = path.join(&item.filename);
+                let (returned, descend) = self.options.allows(&item_path, depth + 1);
+                if descend && item.item_type == TreeItemType::Tree {
+                    next.push((item.id, item_path.clone(), depth + 1));
+                }
+                if returned {
+                    entries.push(TreeEntry {
+                        parent: id,
+                        path: item_path,
+                        item: item.clone(),
+                    });
+                }
+            }
+        }
+        self.pending = next;
+        Ok(Some(entries))
+    }
+
+    /// Keep only the subtrees for which `f` returns true in the next level, given as
+    /// `(tree id, path)`.
+    pub fn retain_pending<F: FnMut(&Hash, &Path) -> bool>(&mut self, mut f: F) {
+        self.pending.retain(|(id, path, _)| f(id, path));
+    }
+}
+
+impl MysqlStorage {
+    /// The entries of the directory `path` in the tree of `commit`, with the targets of the
+    /// symlinks. A commit of another repo than `repo_path` isn't found.
+    pub(super) async fn list_directory(
+        &self,
+        repo_path: &Path,
+        commit: Hash,
+        path: &Path,
+    ) -> Result<Vec<TreeEntryInfo>, GitError> {
+        let model = commit::Entity::find()
+            .filter(commit::Column::RepoPath.eq(repo_path.to_str().unwrap()))
+            .filter(commit::Column::GitId.eq(commit.to_plain_str()))
+            .one(&self.connection)
+            .await?
+            .ok_or_else(|| GitError::NotFountHashValue(commit.to_plain_str()))?;
+        let root = Hash::from_str(&model.tree)?;
+        let depth = path.components().count();
+        let options = TreeWalkOptions {
+            max_depth: Some(depth + 1),
+            prefix: (depth > 0).then(|| path.to_path_buf()),
+        };
+        let mut walk = TreeWalk::new(
+            self,
+            &self.connection,
+            vec![(root, PathBuf::new())],
+            options,
+        );
+        let mut found = depth == 0;
+        let mut entries = Vec::new();
+        while let Some(level) = walk.next_level().await? {
+            for entry in level {
+                if entry.path == path {
+                    found = entry.item.item_type == TreeItemType::Tree;
+                } else {
+                    entries.push(entry);
+                }
+            }
+        }
+        if !found {
+            return Err(GitError::PathNotFound(path.display().to_string()));
+        }
+
+        let links: Vec<String> = entries
+            .iter()
+            .filter(|entry| entry.item.item_type == TreeItemType::Link)
+            .map(|entry| entry.item.id.to_plain_str())
+            .collect();
+        let mut targets = HashMap::new();
+        for chunk in links.chunks(LOOKUP_CHUNK) {
+            let models = node_data::Entity::find()
+                .filter(node_data::Column::GitId.is_in(chunk.to_vec()))
+                .all(&self.connection)
+                .await?;
+            for model in models {
+                let target = String::from_utf8_lossy(&model.data).into_owned();
+                targets.insert(model.git_id, target);
+            }
+        }
+        Ok(entries
+            .iter()
+            .map(|entry| {
+                let target = targets.get(&entry.item.id.to_plain_str()).cloned();
+                TreeEntryInfo::new(&entry.item, entry.path.display().to_string(), target)
+            })
+            .collect())
+    }
+
+    /// Load the trees with the given ids missing from `trees`, from the object cache or in
+    /// batched queries.
+    pub(super) async fn load_trees<C: ConnectionTrait>(
+        &self,
+        conn: &C,
+        ids: &[Hash],
+        trees: &mut TreeCache,
+    ) -> Result<(), GitError> {
+        let mut missing: Vec<String> = Vec::new();
+        for id in ids {
+            if trees.contains_key(id) {
+                continue;
+            }
+            match self.cache.get(id) {
+                Some(object) => {
+                    trees.insert(*id, Tree::new(object));
+                }
+                None => missing.push(id.to_plain_str()),
+            }
+        }
+        missing.sort();
+        missing.dedup();
+        for chunk in missing.chunks(LOOKUP_CHUNK) {
+            let models = node_data::Entity::find()
+                .filter(node_data::Column::GitId.is_in(chunk.to_vec()))
+                .all(conn)
+                .await?;
+            for model in models {
+                let object = Arc::new(MetaData::new(ObjectType::Tree, &model.data));
+                self.cache.insert(object.clone());
+                trees.insert(Hash::from_str(&model.git_id)?, Tree::new(object));
+            }
+        }
+        match ids.iter().find(|id| !trees.contains_key(id)) {
+            Some(id) => Err(GitError::InvalidTreeObject(id.to_plain_str())),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::TreeWalkOptions;
+
+    #[test]
+    fn test_tree_walk_options() {
+        let all = TreeWalkOptions::default();
+        assert_eq!((true, true), all.allows(Path::new("a/b/c"), 3));
+
+        let options = TreeWalkOptions {
+            max_depth: Some(2),
+            prefix: Some(PathBuf::from("src/gust")),
+        };
+        // directories leading to the prefix are walked but not returned
+        assert_eq!((false, true), options.allows(Path::new("src"), 1));
+        assert_eq!((false, false), options.allows(Path::new("docs"), 1));
+        // the depth limit stops the walk at the prefix
+        assert_eq!((true, false), options.allows(Path::new("src/gust"), 2));
+        assert_eq!(
+            (false, false),
+            options.allows(Path::new("src/gust/main.rs"), 3)
+        );
+    }
+}
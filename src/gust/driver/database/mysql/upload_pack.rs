@@ -0,0 +1,64 @@
+//! Packs sent to clients. The objects of a pack are listed by their object index position
+//! first, then loaded and written in batches, so a clone holds one batch in memory and not
+//! the whole pack. An object is sent as an offset delta against one of the objects written
+//! just before it, or as a ref delta against an object the client has in a thin pack.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use tokio::io::AsyncWrite;
+
+use crate::git::errors::GitError;
+use crate::git::hash::Hash;
+use crate::git::pack::encode::{ref_delta, DeltaWindow};
+use crate::git::pack::writer::PackWriter;
+
+use super::storage::MysqlStorage;
+
+/// objects loaded and written at a time
+const WRITE_CHUNK: usize = 1000;
+
+impl MysqlStorage {
+    /// Write the pack of the objects reachable from `wants` but not from `haves` to `writer`,
+    /// a thin pack may hold deltas against objects of `haves`.
+    pub(crate) async fn write_pack(
+        &self,
+        repo_path: &Path,
+        wants: &[Hash],
+        haves: &[Hash],
+        thin: bool,
+        writer: &mut (dyn AsyncWrite + Unpin + Send),
+    ) -> Result<(), GitError> {
+        let positions = self.bitmap_pack_positions(repo_path, wants, haves).await?;
+        let bases = if thin {
+            let sending = self.object_ids(&positions).await?;
+            self.thin_pack_bases(wants, haves, &sending).await?
+        } else {
+            HashMap::new()
+        };
+        let mut pack = PackWriter::new(writer, positions.len()).await?;
+        let mut window = DeltaWindow::new(self.delta_options.as_ref().clone());
+        for chunk in positions.chunks(WRITE_CHUNK) {
+            for object in self.load_objects(chunk).await? {
+                if let Some(base) = bases.get(&object.id) {
+                    pack.write_object(&ref_delta(base, object, &self.delta_options))
+                        .await?;
+                    continue;
+                }
+                let offset = pack.offset();
+                match window.delta(&object, offset) {
+                    Some(delta) => pack.write_object(&delta).await?,
+                    None => pack.write_object(&object).await?,
+                }
+                window.push(object, offset);
+            }
+        }
+        let signature = pack.finish().await?;
+        tracing::info!(
+            "sent pack {} of {} objects",
+            signature.to_plain_str(),
+            positions.len()
+        );
+        Ok(())
+    }
+}
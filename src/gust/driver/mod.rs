@@ -9,19 +9,34 @@ use std::{
 
 use async_trait::async_trait;
 use hyper::Request;
+use tokio::io::AsyncWrite;
 
+use self::commit_search::{CommitInfo, CommitQuery};
+use self::object_cache::ObjectCache;
+use self::tree_list::TreeEntryInfo;
 use crate::git::lfs::structs::*;
 use crate::git::{
     errors::{GitError, GitLFSError},
+    fsck::FsckConfig,
     object::metadata::MetaData,
-    pack::Pack,
+    pack::{
+        limits::PackLimits,
+        resolve::{ObjectSink, PackThreads},
+    },
     protocol::RefCommand,
+    signature::{PublicKey, Verification},
 };
 
+pub mod bitmap;
+pub mod changed_paths;
+pub mod commit_graph;
+pub mod commit_search;
 pub mod database;
 pub mod fs;
 pub mod lfs_content_store;
+pub mod object_cache;
 pub mod structure;
+pub mod tree_list;
 pub mod utils;
 
 pub const ZERO_ID: &'static str = match std::str::from_utf8(&[b'0'; 40]) {
@@ -35,27 +50,104 @@ pub trait ObjectStorage: Clone + Send + Sync + std::fmt::Debug {
 
     async fn get_ref_object_id(&self, path: &Path) -> HashMap<String, String>;
 
-    async fn handle_refs(&self, command: &RefCommand, path: &Path);
+    /// start receiving a push to `path`, its objects are saved as the pack is decoded and only
+    /// kept once `PackReceiver::finish` applies the push
+    async fn receive_pack(&self, path: &Path) -> Result<Box<dyn PackReceiver>, GitError>;
 
-    async fn save_packfile(
+    /// register `public_key` for verifying the signatures of objects committed or tagged by
+    /// `email`
+    async fn add_signing_key(&self, email: &str, public_key: &str) -> Result<PublicKey, GitError>;
+
+    async fn get_signing_keys(&self, email: &str) -> Result<Vec<PublicKey>, GitError>;
+
+    /// the recorded signature verification of a commit or tag
+    async fn get_signature(&self, object_id: &str) -> Result<Verification, GitError>;
+
+    /// the public key of the commits signed by the server, `None` if they aren't signed
+    fn server_signing_key(&self) -> Option<&PublicKey>;
+
+    /// the token authorizing the registration of signing keys, `None` if no one may register
+    /// keys
+    fn keys_admin_token(&self) -> Option<&str>;
+
+    /// the checks of the objects received by a push
+    fn fsck_config(&self) -> &FsckConfig;
+
+    /// the bounds of the packs received by a push
+    fn pack_limits(&self) -> &PackLimits;
+
+    /// the threads decoding the packs received by a push
+    fn pack_threads(&self) -> &PackThreads;
+
+    /// the objects read recently, the decode of a pack adds the objects it resolves
+    fn object_cache(&self) -> &ObjectCache;
+
+    /// write the pack of all the objects of the repo to `writer` as it's built
+    async fn write_full_pack(
         &self,
-        decoded_pack: Pack,
         repo_path: &Path,
-    ) -> Result<(), anyhow::Error>;
+        writer: &mut (dyn AsyncWrite + Unpin + Send),
+    ) -> Result<(), GitError>;
 
-    async fn get_full_pack_data(&self, repo_path: &Path) -> Result<Vec<u8>, GitError>;
-
-    async fn get_incremental_pack_data(
+    /// write the pack of the objects reachable from `want` but not from `have` to `writer` as
+    /// it's built, a thin pack may hold deltas against objects of `have`
+    async fn write_incremental_pack(
         &self,
         repo_path: &Path,
         want: &HashSet<String>,
         have: &HashSet<String>,
-    ) -> Result<Vec<u8>, GitError>;
+        thin: bool,
+        writer: &mut (dyn AsyncWrite + Unpin + Send),
+    ) -> Result<(), GitError>;
+
+    /// resolve changelist `cl` to the commit `path` was at as of that changelist, returns the
+    /// changelist the commit landed in and its id
+    async fn get_commit_by_changelist(
+        &self,
+        path: &Path,
+        cl: i64,
+    ) -> Result<(i64, String), GitError>;
 
-    async fn get_commit_by_hash(&self, hash: &str) -> Result<MetaData, GitError>;
+    /// the changelist a trunk commit of `path` landed in
+    async fn get_changelist_by_commit(&self, path: &Path, commit_id: &str)
+        -> Result<i64, GitError>;
 
-    // get hash object from db if missing cache in unpack process, this object must be tree or blob
-    async fn get_hash_object(&self, hash: &str) -> Result<MetaData, GitError>;
+    /// search the commits of `path` with the filters of `query`, latest commits first
+    async fn search_commits(
+        &self,
+        path: &Path,
+        query: &CommitQuery,
+    ) -> Result<Vec<CommitInfo>, GitError>;
+
+    /// whether the commit `ancestor` is reachable from `descendant`
+    async fn is_ancestor(&self, ancestor: &str, descendant: &str) -> Result<bool, GitError>;
+
+    /// the best common ancestor of two commits, `None` for unrelated histories
+    async fn merge_base(&self, a: &str, b: &str) -> Result<Option<String>, GitError>;
+
+    /// commits reachable from `include` but not from `exclude`, children before parents
+    async fn rev_list(
+        &self,
+        include: &[String],
+        exclude: &[String],
+        limit: Option<usize>,
+    ) -> Result<Vec<String>, GitError>;
+
+    /// the entries of the directory `path` in the tree of `commit_id`, a commit of the repo at
+    /// `repo_path`, `path` is relative to the repo root
+    async fn list_tree(
+        &self,
+        repo_path: &Path,
+        commit_id: &str,
+        path: &Path,
+    ) -> Result<Vec<TreeEntryInfo>, GitError>;
+
+    /// the commit `hash`, None if it isn't stored
+    async fn get_commit_by_hash(&self, hash: &str) -> Result<Option<MetaData>, GitError>;
+
+    // get hash object from db if missing cache in unpack process, e.g. the base of a delta in a thin pack
+    // None if the object isn't stored
+    async fn get_hash_object(&self, hash: &str) -> Result<Option<MetaData>, GitError>;
 
     async fn lfs_get_meta(&self, v: &RequestVars) -> Result<MetaObject, GitLFSError>;
 
@@ -83,3 +175,17 @@ pub trait ObjectStorage: Clone + Send + Sync + std::fmt::Debug {
         force: bool,
     ) -> Result<Lock, GitLFSError>;
 }
+
+/// The objects of a push on their way to the storage, dropping the receiver before `finish`
+/// discards them.
+#[async_trait]
+pub trait PackReceiver: ObjectSink {
+    /// save what is left of the push and apply `commands` to the refs in one transaction. The
+    /// result of each command is returned in order, a command fails with `MissingObjects`
+    /// unless every object reachable from its new id is stored, checked before its ref moves.
+    /// A failed command doesn't move its ref, the others are applied.
+    async fn finish(
+        self: Box<Self>,
+        commands: &[RefCommand],
+    ) -> Result<Vec<Result<(), GitError>>, GitError>;
+}
@@ -0,0 +1,195 @@
+//!
+//! A byte-size bounded LRU cache of objects, shared by all requests in front of the object
+//! storage. Objects are content addressed and never change, so an entry is only ever evicted,
+//! never invalidated.
+//!
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::git::hash::Hash;
+use crate::git::object::metadata::MetaData;
+
+/// budget of the cache unless configured
+pub const DEFAULT_CACHE_BYTES: usize = 256 * 1024 * 1024;
+
+/// an object larger than this share of the budget is not cached, a few large blobs would
+/// otherwise evict all the trees
+const MAX_OBJECT_SHARE: usize = 64;
+
+/// bookkeeping bytes counted per entry on top of the object data
+const ENTRY_OVERHEAD: usize = 64;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub entries: usize,
+    pub bytes: usize,
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} hits, {} misses, {} evictions, {} objects in {} bytes",
+            self.hits, self.misses, self.evictions, self.entries, self.bytes
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct ObjectCache {
+    budget: usize,
+    max_object_size: usize,
+    lru: Mutex<Lru>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+/// Entries with the tick of their last use, the oldest tick is evicted first.
+#[derive(Debug, Default)]
+struct Lru {
+    entries: HashMap<Hash, (Arc<MetaData>, u64)>,
+    ticks: BTreeMap<u64, Hash>,
+    tick: u64,
+    bytes: usize,
+}
+
+impl Lru {
+    fn touch(&mut self, id: &Hash) -> Option<Arc<MetaData>> {
+        self.tick += 1;
+        let (object, tick) = self.entries.get_mut(id)?;
+        self.ticks.remove(tick);
+        *tick = self.tick;
+        self.ticks.insert(self.tick, *id);
+        Some(object.clone())
+    }
+}
+
+impl Default for ObjectCache {
+    fn default() -> Self {
+        ObjectCache::new(DEFAULT_CACHE_BYTES)
+    }
+}
+
+impl ObjectCache {
+    /// A cache holding up to `budget` bytes of objects, 0 disables it.
+    pub fn new(budget: usize) -> ObjectCache {
+        ObjectCache {
+            budget,
+            max_object_size: budget / MAX_OBJECT_SHARE,
+            lru: Mutex::new(Lru::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    pub fn get(&self, id: &Hash) -> Option<Arc<MetaData>> {
+        let object = self.lru.lock().unwrap().touch(id);
+        let counter = if object.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        object
+    }
+
+    /// Add `object`, evicting the least recently used objects beyond the budget.
+    pub fn insert(&self, object: Arc<MetaData>) {
+        let size = entry_size(&object);
+        if size > self.max_object_size {
+            return;
+        }
+        let mut lru = self.lru.lock().unwrap();
+        if lru.touch(&object.id).is_some() {
+            return;
+        }
+        let mut evicted = 0;
+        while lru.bytes + size > self.budget {
+            let Some((_, id)) = lru.ticks.pop_first() else {
+                break;
+            };
+            let (old, _) = lru.entries.remove(&id).unwrap();
+            lru.bytes -= entry_size(&old);
+            evicted += 1;
+        }
+        let tick = lru.tick;
+        lru.ticks.insert(tick, object.id);
+        lru.entries.insert(object.id, (object, tick));
+        lru.bytes += size;
+        self.evictions.fetch_add(evicted, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let lru = self.lru.lock().unwrap();
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            entries: lru.entries.len(),
+            bytes: lru.bytes,
+        }
+    }
+}
+
+fn entry_size(object: &MetaData) -> usize {
+    object.data.len() + ENTRY_OVERHEAD
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{ObjectCache, ENTRY_OVERHEAD};
+    use crate::git::object::metadata::MetaData;
+    use crate::git::object::types::ObjectType;
+
+    fn blob(content: &str) -> Arc<MetaData> {
+        Arc::new(MetaData::new(
+            ObjectType::Blob,
+            &content.as_bytes().to_vec(),
+        ))
+    }
+
+    #[test]
+    fn test_object_cache_lru() {
+        let (a, b, c) = (blob("aaaa"), blob("bbbb"), blob("cccc"));
+        // room for two objects
+        let cache = ObjectCache {
+            max_object_size: usize::MAX,
+            ..ObjectCache::new(2 * (4 + ENTRY_OVERHEAD))
+        };
+        cache.insert(a.clone());
+        cache.insert(b.clone());
+        assert!(cache.get(&a.id).is_some());
+        // b is the least recently used
+        cache.insert(c.clone());
+        assert!(cache.get(&b.id).is_none());
+        assert!(cache.get(&a.id).is_some());
+        assert!(cache.get(&c.id).is_some());
+
+        let stats = cache.stats();
+        assert_eq!((3, 1, 1), (stats.hits, stats.misses, stats.evictions));
+        assert_eq!(2, stats.entries);
+        assert_eq!(2 * (4 + ENTRY_OVERHEAD), stats.bytes);
+    }
+
+    #[test]
+    fn test_object_cache_skips_large_objects() {
+        let cache = ObjectCache::new(64 * 1024);
+        let large = blob(&"x".repeat(2 * 1024));
+        cache.insert(large.clone());
+        assert!(cache.get(&large.id).is_none());
+
+        let disabled = ObjectCache::new(0);
+        disabled.insert(blob("a"));
+        assert_eq!(0, disabled.stats().entries);
+    }
+}
@@ -1,32 +1,34 @@
 use sea_orm::{ActiveValue::NotSet, Set};
 
 use crate::git::{
+    errors::GitError,
     hash::Hash,
-    object::{
-        base::{
-            blob::Blob,
-            commit::Commit,
-            tree::{Tree, TreeItem},
-        },
-        metadata::MetaData,
-        types::ObjectType,
+    object::base::{
+        blob::Blob,
+        commit::Commit,
+        sign::AuthorSign,
+        tree::{Tree, TreeItem, TreeItemType},
     },
+    signature::{sign_commit, SigningKey},
 };
+use chrono::{DateTime, NaiveDateTime};
 use std::{
+    borrow::Cow,
     path::{Path, PathBuf},
-    str::FromStr,
     sync::Arc,
 };
 
-use self::nodes::{FileNode, Node, TreeNode};
+use self::nodes::{FileNode, Node, NodeType, SymlinkNode, TreeNode};
 use super::utils::id_generator::{self, generate_id};
 use entity::{commit, node};
 
 pub mod nodes;
 
-/// only blob and tree should implement this trait
+/// only blob and tree should implement this trait, gitlinks have no object in the repo
 pub trait GitNodeObject {
-    fn convert_to_node(&self, item: Option<&TreeItem>) -> Box<dyn Node>;
+    /// `pid` is the hash of the parent tree and `path` the full path of the node in the repo,
+    /// both are empty for the root tree.
+    fn convert_to_node(&self, item: Option<&TreeItem>, pid: &str, path: PathBuf) -> Box<dyn Node>;
 
     fn convert_from_model(model: &node::Model) -> Self
     where
@@ -41,12 +43,12 @@ pub trait GitNodeObject {
 }
 
 impl GitNodeObject for Blob {
-    fn convert_to_node(&self, item: Option<&TreeItem>) -> Box<dyn Node> {
-        Box::new(FileNode {
+    fn convert_to_node(&self, item: Option<&TreeItem>, pid: &str, path: PathBuf) -> Box<dyn Node> {
+        let file = FileNode {
             nid: self.generate_id(),
-            pid: "".to_owned(),
+            pid: pid.to_owned(),
             git_id: self.meta.id,
-            path: PathBuf::new(),
+            path,
             mode: if let Some(item) = item {
                 item.mode.clone()
             } else {
@@ -58,7 +60,11 @@ impl GitNodeObject for Blob {
                 "".to_owned()
             },
             data: self.meta.data.clone(),
-        })
+        };
+        match item {
+            Some(item) if item.item_type == TreeItemType::Link => Box::new(SymlinkNode::from(file)),
+            _ => Box::new(file),
+        }
     }
     // pub fn convert_to_model(&self, node_id: i64) -> node::ActiveModel {
     //     node::ActiveModel {
@@ -77,31 +83,72 @@ impl GitNodeObject for Blob {
 }
 
 impl Commit {
-    pub fn build_from_model_and_root(model: &commit::Model, root: node::Model) -> Commit {
-        let mut c = Commit::new(Arc::new(MetaData::new(ObjectType::Commit, &model.meta)));
-        c.tree_id = Hash::from_str(&root.git_id).unwrap();
-        c.parent_tree_ids.clear();
-        c.meta = Arc::new(c.encode_metadata().unwrap());
-        c
+    /// rewrite the commit to point to `tree_id` with `parents`, author, committer and
+    /// message are kept. The signature of the commit doesn't hold for the rewritten one, it's
+    /// replaced by a signature of `signing_key` if there is one.
+    pub fn rewrite(
+        &self,
+        tree_id: Hash,
+        parents: Vec<Hash>,
+        signing_key: Option<&SigningKey>,
+    ) -> Result<Commit, GitError> {
+        let mut c = self.clone();
+        c.tree_id = tree_id;
+        c.parent_tree_ids = parents;
+        match signing_key {
+            Some(key) => sign_commit(&mut c, key)?,
+            None => {
+                c.headers
+                    .retain(|(name, _)| name != "gpgsig" && name != "gpgsig-sha256");
+                c.meta = Arc::new(c.encode_metadata().unwrap());
+            }
+        }
+        Ok(c)
+    }
+
+    /// The message decoded as UTF-8, invalid sequences are replaced.
+    pub fn message_body(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.message)
     }
 
     pub fn convert_to_model(&self, repo_path: &Path) -> commit::ActiveModel {
+        let parents: Vec<String> = self
+            .parent_tree_ids
+            .iter()
+            .map(|id| id.to_plain_str())
+            .collect();
         commit::ActiveModel {
             id: NotSet,
             git_id: Set(self.meta.id.to_plain_str()),
             tree: Set(self.tree_id.to_plain_str()),
-            pid: NotSet,
+            pid: Set((!parents.is_empty()).then(|| parents.join(" "))),
             meta: Set(self.meta.data.clone()),
             repo_path: Set(repo_path.to_str().unwrap().to_owned()),
-            author: NotSet,
-            committer: NotSet,
-            content: NotSet,
+            author: Set(Some(sign_identity(&self.author))),
+            committer: Set(Some(sign_identity(&self.committer))),
+            content: Set(Some(self.message_body().into_owned())),
+            cl: NotSet,
+            author_time: Set(sign_time(&self.author)),
+            committer_time: Set(sign_time(&self.committer)),
             created_at: Set(chrono::Utc::now().naive_utc()),
             updated_at: Set(chrono::Utc::now().naive_utc()),
         }
     }
 }
 
+/// `name <email>` of a signature, cut to the size of the column.
+fn sign_identity(sign: &AuthorSign) -> String {
+    format!("{} <{}>", sign.name, sign.email)
+        .chars()
+        .take(255)
+        .collect()
+}
+
+/// the UTC time of a signature
+fn sign_time(sign: &AuthorSign) -> Option<NaiveDateTime> {
+    DateTime::from_timestamp(sign.timestamp as i64, 0).map(|t| t.naive_utc())
+}
+
 impl GitNodeObject for Tree {
     // pub fn convert_from_model(model: &node::Model, tree_items: Vec<TreeItem>) -> Tree {
     //     Tree {
@@ -111,17 +158,17 @@ impl GitNodeObject for Tree {
     //     }
     // }
 
-    fn convert_to_node(&self, item: Option<&TreeItem>) -> Box<dyn Node> {
+    fn convert_to_node(&self, item: Option<&TreeItem>, pid: &str, path: PathBuf) -> Box<dyn Node> {
         Box::new(TreeNode {
             nid: generate_id(),
-            pid: "".to_owned(),
+            pid: pid.to_owned(),
             git_id: self.meta.id,
             name: if let Some(item) = item {
                 item.filename.clone()
             } else {
                 "".to_owned()
             },
-            path: PathBuf::new(),
+            path,
             mode: if let Some(item) = item {
                 item.mode.clone()
             } else {
@@ -133,6 +180,26 @@ impl GitNodeObject for Tree {
     }
 }
 
+impl TreeItem {
+    /// convert an item of a tree stored by an earlier push to the node at `path` under the
+    /// tree `pid`
+    pub fn convert_to_model(&self, pid: &Hash, path: &Path) -> node::ActiveModel {
+        node::ActiveModel {
+            id: NotSet,
+            node_id: Set(generate_id()),
+            pid: Set(pid.to_plain_str()),
+            git_id: Set(self.id.to_plain_str()),
+            node_type: Set(NodeType::from(self.item_type).to_string()),
+            name: Set(self.filename.clone()),
+            path: Set(path.to_str().unwrap().to_owned()),
+            mode: Set(self.mode.clone()),
+            content_sha: NotSet,
+            created_at: Set(chrono::Utc::now().naive_utc()),
+            updated_at: Set(chrono::Utc::now().naive_utc()),
+        }
+    }
+}
+
 // impl TreeItem {
 //     pub fn convert_from_model(model: node::Model) -> TreeItem {
 //         let item_type = if model.node_type == "tree" {
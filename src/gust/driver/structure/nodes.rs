@@ -1,14 +1,16 @@
 use std::{
     any::Any,
     collections::{HashMap, HashSet},
+    fmt::Display,
     path::{Path, PathBuf},
 };
 
-use entity::node;
+use entity::{node, node_data};
 use sea_orm::{ActiveValue::NotSet, Set};
 
 use crate::{
     git::{
+        errors::GitError,
         hash::Hash,
         object::base::{
             blob::Blob,
@@ -25,9 +27,55 @@ pub struct Repo {
     // pub repo_root: Box<dyn Node>,
     pub tree_map: HashMap<Hash, Tree>,
     pub blob_map: HashMap<Hash, Blob>,
-    pub tree_build_cache: HashSet<Hash>,
-    // todo: limit the size of the cache
-    // pub cache: LruCache<String, FileNode>,
+    // trees already walked in this push, the same tree at another path is walked again
+    pub tree_build_cache: HashSet<(Hash, PathBuf)>,
+    // content hashes already stored in database before this push
+    pub stored_data: HashSet<Hash>,
+    // content hashes already stored in database or queued in this push, content is saved once per hash
+    pub data_cache: HashSet<Hash>,
+    // stored trees reached from the pack, their children are not in the pack
+    pub stored_trees: Vec<(Hash, PathBuf)>,
+}
+
+/// The db models built from a decoded pack.
+pub struct NodeTreeModels {
+    pub nodes: Vec<node::ActiveModel>,
+    pub node_datas: Vec<node_data::ActiveModel>,
+    /// trees already stored by earlier pushes and the path they appear at in this push, the
+    /// storage creates their children nodes if the tree is new at that path
+    pub stored_trees: Vec<(Hash, PathBuf)>,
+}
+
+/// The `node_type` of the node table, `node_data` only holds blobs and trees.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeType {
+    Tree,
+    Blob,
+    Symlink,
+    Gitlink,
+}
+
+impl Display for NodeType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let node_type = match self {
+            NodeType::Tree => "tree",
+            NodeType::Blob => "blob",
+            NodeType::Symlink => "symlink",
+            NodeType::Gitlink => "gitlink",
+        };
+        write!(f, "{}", node_type)
+    }
+}
+
+impl From<TreeItemType> for NodeType {
+    fn from(item_type: TreeItemType) -> Self {
+        match item_type {
+            TreeItemType::Tree => NodeType::Tree,
+            TreeItemType::Blob | TreeItemType::BlobExecutable => NodeType::Blob,
+            TreeItemType::Link => NodeType::Symlink,
+            TreeItemType::Commit => NodeType::Gitlink,
+        }
+    }
 }
 
 pub struct TreeNode {
@@ -52,6 +100,30 @@ pub struct FileNode {
     pub data: Vec<u8>,
 }
 
+/// A symbolic link, its blob holds the link target.
+#[derive(Debug, Clone)]
+pub struct SymlinkNode {
+    pub nid: i64,
+    pub pid: String,
+    pub git_id: Hash,
+    pub name: String,
+    pub path: PathBuf,
+    pub mode: Vec<u8>,
+    pub data: Vec<u8>,
+}
+
+/// A submodule, `git_id` is the commit of the other repo it points to. The commit is not
+/// stored here, so a gitlink has no data.
+#[derive(Debug, Clone)]
+pub struct GitlinkNode {
+    pub nid: i64,
+    pub pid: String,
+    pub git_id: Hash,
+    pub name: String,
+    pub path: PathBuf,
+    pub mode: Vec<u8>,
+}
+
 /// define the node common behaviour
 pub trait Node {
     fn get_id(&self) -> i64;
@@ -62,6 +134,9 @@ pub trait Node {
 
     fn get_name(&self) -> &str;
 
+    /// full path of the node from the repo root, empty for the root tree
+    fn get_path(&self) -> &Path;
+
     fn get_mode(&self) -> Vec<u8>;
 
     fn get_children(&self) -> &Vec<Box<dyn Node>>;
@@ -89,6 +164,9 @@ pub trait Node {
 
     fn convert_to_model(&self) -> node::ActiveModel;
 
+    /// the content to store for the node, `None` for nodes whose object lives elsewhere
+    fn convert_to_data_model(&self) -> Option<node_data::ActiveModel>;
+
     // fn convert_from_model(node: node::Model, children: Vec<Box<dyn Node>>) -> Box<dyn Node>
     // where
     //     Self: Sized;
@@ -109,6 +187,10 @@ impl Node for TreeNode {
         &self.name
     }
 
+    fn get_path(&self) -> &Path {
+        &self.path
+    }
+
     fn get_mode(&self) -> Vec<u8> {
         self.mode.clone()
     }
@@ -137,17 +219,27 @@ impl Node for TreeNode {
         node::ActiveModel {
             id: NotSet,
             node_id: Set(self.nid),
+            pid: Set(self.pid.clone()),
             git_id: Set(self.git_id.to_plain_str()),
-            node_type: Set("tree".to_owned()),
+            node_type: Set(NodeType::Tree.to_string()),
             name: Set(self.name.to_string()),
+            path: Set(self.path.to_str().unwrap().to_owned()),
             mode: Set(self.mode.clone()),
             content_sha: NotSet,
-            data: Set(self.data.clone()),
             created_at: Set(chrono::Utc::now().naive_utc()),
             updated_at: Set(chrono::Utc::now().naive_utc()),
         }
     }
 
+    fn convert_to_data_model(&self) -> Option<node_data::ActiveModel> {
+        Some(node_data::ActiveModel {
+            git_id: Set(self.git_id.to_plain_str()),
+            node_type: Set("tree".to_owned()),
+            data: Set(self.data.clone()),
+            created_at: Set(chrono::Utc::now().naive_utc()),
+        })
+    }
+
     fn find_child(&mut self, name: &str) -> Option<&mut Box<dyn Node>> {
         self.children.iter_mut().find(|c| c.get_name() == name)
     }
@@ -194,6 +286,10 @@ impl Node for FileNode {
         &self.name
     }
 
+    fn get_path(&self) -> &Path {
+        &self.path
+    }
+
     fn get_mode(&self) -> Vec<u8> {
         self.mode.clone()
     }
@@ -218,17 +314,22 @@ impl Node for FileNode {
         node::ActiveModel {
             id: NotSet,
             node_id: Set(self.nid),
+            pid: Set(self.pid.clone()),
             git_id: Set(self.git_id.to_plain_str()),
-            node_type: Set("blob".to_owned()),
+            node_type: Set(NodeType::Blob.to_string()),
             name: Set(self.name.to_string()),
+            path: Set(self.path.to_str().unwrap().to_owned()),
             mode: Set(self.mode.clone()),
             content_sha: NotSet,
-            data: Set(self.data.clone()),
             created_at: Set(chrono::Utc::now().naive_utc()),
             updated_at: Set(chrono::Utc::now().naive_utc()),
         }
     }
 
+    fn convert_to_data_model(&self) -> Option<node_data::ActiveModel> {
+        Some(blob_data_model(self.git_id, &self.data))
+    }
+
     fn find_child(&mut self, _: &str) -> Option<&mut Box<dyn Node>> {
         panic!("not supported")
     }
@@ -257,6 +358,190 @@ impl Node for FileNode {
     // }
 }
 
+impl Node for SymlinkNode {
+    fn get_id(&self) -> i64 {
+        self.nid
+    }
+
+    fn get_pid(&self) -> &str {
+        &self.pid
+    }
+
+    fn get_git_id(&self) -> Hash {
+        self.git_id
+    }
+
+    fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    fn get_mode(&self) -> Vec<u8> {
+        self.mode.clone()
+    }
+
+    fn get_children(&self) -> &Vec<Box<dyn Node>> {
+        panic!("not supported")
+    }
+
+    fn new(name: String, pid: String) -> SymlinkNode {
+        SymlinkNode {
+            nid: generate_id(),
+            pid,
+            path: PathBuf::new(),
+            name,
+            git_id: Hash::default(),
+            mode: TreeItemType::Link.to_bytes().to_vec(),
+            data: Vec::new(),
+        }
+    }
+
+    /// the link target
+    fn read_data(&self) -> String {
+        String::from_utf8_lossy(&self.data).into_owned()
+    }
+
+    fn convert_to_model(&self) -> node::ActiveModel {
+        link_model(self, NodeType::Symlink)
+    }
+
+    /// the target is stored as the blob it is in git, the same blob may be a regular file
+    /// elsewhere
+    fn convert_to_data_model(&self) -> Option<node_data::ActiveModel> {
+        Some(blob_data_model(self.git_id, &self.data))
+    }
+
+    fn find_child(&mut self, _: &str) -> Option<&mut Box<dyn Node>> {
+        panic!("not supported")
+    }
+
+    fn add_child(&mut self, _: Box<dyn Node>) {
+        panic!("not supported")
+    }
+
+    fn is_a_directory(&self) -> bool {
+        false
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl Node for GitlinkNode {
+    fn get_id(&self) -> i64 {
+        self.nid
+    }
+
+    fn get_pid(&self) -> &str {
+        &self.pid
+    }
+
+    fn get_git_id(&self) -> Hash {
+        self.git_id
+    }
+
+    fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    fn get_mode(&self) -> Vec<u8> {
+        self.mode.clone()
+    }
+
+    fn get_children(&self) -> &Vec<Box<dyn Node>> {
+        panic!("not supported")
+    }
+
+    fn new(name: String, pid: String) -> GitlinkNode {
+        GitlinkNode {
+            nid: generate_id(),
+            pid,
+            path: PathBuf::new(),
+            name,
+            git_id: Hash::default(),
+            mode: TreeItemType::Commit.to_bytes().to_vec(),
+        }
+    }
+
+    /// the commit of the submodule
+    fn read_data(&self) -> String {
+        self.git_id.to_plain_str()
+    }
+
+    fn convert_to_model(&self) -> node::ActiveModel {
+        link_model(self, NodeType::Gitlink)
+    }
+
+    fn convert_to_data_model(&self) -> Option<node_data::ActiveModel> {
+        None
+    }
+
+    fn find_child(&mut self, _: &str) -> Option<&mut Box<dyn Node>> {
+        panic!("not supported")
+    }
+
+    fn add_child(&mut self, _: Box<dyn Node>) {
+        panic!("not supported")
+    }
+
+    fn is_a_directory(&self) -> bool {
+        false
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl From<FileNode> for SymlinkNode {
+    fn from(file: FileNode) -> Self {
+        SymlinkNode {
+            nid: file.nid,
+            pid: file.pid,
+            git_id: file.git_id,
+            name: file.name,
+            path: file.path,
+            mode: file.mode,
+            data: file.data,
+        }
+    }
+}
+
+/// The `node_data` row of the content of a blob.
+pub fn blob_data_model(git_id: Hash, data: &[u8]) -> node_data::ActiveModel {
+    node_data::ActiveModel {
+        git_id: Set(git_id.to_plain_str()),
+        node_type: Set("blob".to_owned()),
+        data: Set(data.to_vec()),
+        created_at: Set(chrono::Utc::now().naive_utc()),
+    }
+}
+
+/// the row of a symlink or gitlink node, they differ only by their type
+fn link_model(node: &dyn Node, node_type: NodeType) -> node::ActiveModel {
+    node::ActiveModel {
+        id: NotSet,
+        node_id: Set(node.get_id()),
+        pid: Set(node.get_pid().to_owned()),
+        git_id: Set(node.get_git_id().to_plain_str()),
+        node_type: Set(node_type.to_string()),
+        name: Set(node.get_name().to_owned()),
+        path: Set(node.get_path().to_str().unwrap().to_owned()),
+        mode: Set(node.get_mode()),
+        content_sha: NotSet,
+        created_at: Set(chrono::Utc::now().naive_utc()),
+        updated_at: Set(chrono::Utc::now().naive_utc()),
+    }
+}
+
 impl TreeNode {
     // since root tree doesn't have name, we can only use node id to build it.
     pub fn get_root_from_nid(nid: i64) -> Box<dyn Node> {
@@ -278,10 +563,15 @@ impl TreeNode {
 /// 2. Git Blob => DB Model
 /// current: protocol => storage => structure
 /// expected: protocol => structure => storage
+///
+/// `stored_data` holds the hashes whose content is already in the database, only the content of
+/// the remaining objects is returned, once per hash. Stored trees are not walked, they are
+/// returned in `stored_trees` instead.
 pub async fn build_node_tree(
     result: &ObjDecodedMap,
     _: &Path,
-) -> Result<Vec<node::ActiveModel>, anyhow::Error> {
+    stored_data: HashSet<Hash>,
+) -> Result<NodeTreeModels, GitError> {
     let tree_map: HashMap<Hash, Tree> = result
         .trees
         .clone()
@@ -300,60 +590,127 @@ pub async fn build_node_tree(
         tree_map,
         blob_map,
         tree_build_cache: HashSet::new(),
+        stored_data: stored_data.clone(),
+        data_cache: stored_data,
+        stored_trees: Vec::new(),
     };
 
     let mut nodes = Vec::new();
+    let mut node_datas = Vec::new();
 
     for commit in &result.commits {
         let commit_tree_id = commit.tree_id;
-        let tree = &repo.tree_map.get(&commit_tree_id).unwrap().clone();
-        let mut root_node = tree.convert_to_node(None);
-        repo.build_node_tree(tree, &mut root_node);
-        nodes.extend(repo.convert_node_to_model(root_node.as_ref(), 0));
-        print!("--------------------------------");
+        if !repo
+            .tree_build_cache
+            .insert((commit_tree_id, PathBuf::new()))
+        {
+            continue;
+        }
+        match repo.tree_map.get(&commit_tree_id) {
+            Some(tree) if !repo.stored_data.contains(&commit_tree_id) => {
+                let tree = &tree.clone();
+                let mut root_node = tree.convert_to_node(None, "", PathBuf::new());
+                repo.build_node_tree(tree, &mut root_node)?;
+                repo.convert_node_to_model(root_node.as_ref(), 0, &mut nodes, &mut node_datas);
+            }
+            _ => repo.stored_trees.push((commit_tree_id, PathBuf::new())),
+        }
     }
-    Ok(nodes)
+    Ok(NodeTreeModels {
+        nodes,
+        node_datas,
+        stored_trees: repo.stored_trees,
+    })
 }
 
 impl Repo {
-    /// convert Git TreeItem => Struct Node and build node tree
-    pub fn build_node_tree(&mut self, tree: &Tree, node: &mut Box<dyn Node>) {
+    /// convert Git TreeItem => Struct Node and build node tree, a blob neither in the pack nor
+    /// stored is missing
+    pub fn build_node_tree(
+        &mut self,
+        tree: &Tree,
+        node: &mut Box<dyn Node>,
+    ) -> Result<(), GitError> {
+        let pid = tree.meta.id.to_plain_str();
         for item in &tree.tree_items {
-            if let Some(_) = self.tree_build_cache.get(&item.id) {
-                continue;
-            }
+            let path = node.get_path().join(&item.filename);
             if item.item_type == TreeItemType::Tree {
-                // repo_path.push(item.filename.clone());
-                let tree = self.tree_map.get(&item.id).unwrap();
-                node.add_child(tree.convert_to_node(Some(item)));
-                let child_node = match node.find_child(&item.filename) {
-                    Some(child) => child,
-                    None => panic!("Something wrong!:{}", &item.filename),
-                };
-                let item = self.tree_map.get(&item.id);
-                if let Some(item) = item {
-                    self.build_node_tree(&item.clone(), child_node);
+                let walk = self.tree_build_cache.insert((item.id, path.clone()));
+                match self.tree_map.get(&item.id) {
+                    Some(child) if !self.stored_data.contains(&item.id) => {
+                        let child = child.clone();
+                        node.add_child(child.convert_to_node(Some(item), &pid, path));
+                        // a tree already walked at this path only needs the node itself
+                        if walk {
+                            let child_node = node
+                                .find_child(&item.filename)
+                                .ok_or_else(|| GitError::InvalidTreeItem(item.filename.clone()))?;
+                            self.build_node_tree(&child, child_node)?;
+                        }
+                    }
+                    _ => {
+                        let mut tree_node = TreeNode::new(item.filename.clone(), pid.clone());
+                        tree_node.git_id = item.id;
+                        tree_node.mode = item.mode.clone();
+                        tree_node.path = path.clone();
+                        node.add_child(Box::new(tree_node));
+                        if walk {
+                            self.stored_trees.push((item.id, path));
+                        }
+                    }
+                }
+            } else if item.item_type == TreeItemType::Commit {
+                // the commit of a submodule is in another repo, only its id is kept
+                let mut gitlink = GitlinkNode::new(item.filename.clone(), pid.clone());
+                gitlink.git_id = item.id;
+                gitlink.mode = item.mode.clone();
+                gitlink.path = path;
+                node.add_child(Box::new(gitlink));
+            } else if let Some(blob) = self.blob_map.get(&item.id) {
+                node.add_child(blob.convert_to_node(Some(item), &pid, path));
+            } else if self.data_cache.contains(&item.id) {
+                // the blob content is already stored, only the node itself is needed
+                let mut file_node = FileNode::new(item.filename.clone(), pid.clone());
+                file_node.git_id = item.id;
+                file_node.mode = item.mode.clone();
+                file_node.path = path;
+                if item.item_type == TreeItemType::Link {
+                    node.add_child(Box::new(SymlinkNode::from(file_node)));
+                } else {
+                    node.add_child(Box::new(file_node));
                 }
-                // repo_path.pop();
             } else {
-                let blob = self.blob_map.get(&item.id).unwrap();
-                node.add_child(blob.convert_to_node(Some(item)));
+                return Err(GitError::MissingObjects(format!(
+                    "blob {} of tree {}",
+                    item.id, tree.meta.id
+                )));
             }
-            self.tree_build_cache.insert(item.id);
         }
-    }
-
-    /// conver Node to db entity and for later persistent
-    pub fn convert_node_to_model(&self, node: &dyn Node, depth: u32) -> Vec<node::ActiveModel> {
+        Ok(())
+    }
+
+    /// conver Node to db entity and for later persistent, the content is skipped if it's hash
+    /// already in the `data_cache`
+    pub fn convert_node_to_model(
+        &mut self,
+        node: &dyn Node,
+        depth: u32,
+        nodes: &mut Vec<node::ActiveModel>,
+        node_datas: &mut Vec<node_data::ActiveModel>,
+    ) {
         print_node(node, depth);
-        let mut nodes: Vec<node::ActiveModel> = Vec::new();
         nodes.push(node.convert_to_model());
+        if !self.data_cache.contains(&node.get_git_id()) {
+            if let Some(data) = node.convert_to_data_model() {
+                self.data_cache.insert(node.get_git_id());
+                node_datas.push(data);
+            }
+        }
         if node.is_a_directory() {
             for child in node.get_children() {
-                nodes.extend(self.convert_node_to_model(child.as_ref(), depth + 1));
+                self.convert_node_to_model(child.as_ref(), depth + 1, nodes, node_datas);
             }
         }
-        nodes
     }
 }
 
@@ -394,9 +751,18 @@ mod test {
         structure::nodes::{Node, TreeNode},
         utils::id_generator,
     };
-    use std::path::PathBuf;
+    use std::collections::HashSet;
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
 
-    use super::FileNode;
+    use crate::git::errors::GitError;
+    use crate::git::hash::Hash;
+    use crate::git::object::base::{blob::Blob, commit::Commit, tree::Tree};
+    use crate::git::object::metadata::MetaData;
+    use crate::git::object::types::ObjectType;
+    use crate::git::pack::decode::ObjDecodedMap;
+
+    use super::{build_node_tree, FileNode};
 
     #[test]
     pub fn main() {
@@ -419,6 +785,99 @@ mod test {
         // traverse_node(root.as_ref(), 0, &mut save_models);
     }
 
+    #[tokio::test]
+    async fn test_build_node_tree_links() {
+        id_generator::set_up_options().ok();
+        let submodule = Hash::from_bytes(b"8ab686eafeb1f44702738c8b0f24f2567c36da6d").unwrap();
+        let target = Blob::new(Arc::new(MetaData::new(
+            ObjectType::Blob,
+            &b"a.txt".to_vec(),
+        )));
+        let mut data = Vec::new();
+        for (mode, name, id) in [
+            ("120000", "link", target.meta.id),
+            ("160000", "lib", submodule),
+        ] {
+            data.extend_from_slice(format!("{} {}\0", mode, name).as_bytes());
+            data.extend_from_slice(&id.0);
+        }
+        let tree = Tree::new(Arc::new(MetaData::new(ObjectType::Tree, &data)));
+        let commit = format!(
+            "tree {}\nauthor A <a> 1 +0000\ncommitter A <a> 1 +0000\n\nmsg\n",
+            tree.meta.id
+        );
+        let commit = Commit::new(Arc::new(MetaData::new(
+            ObjectType::Commit,
+            &commit.into_bytes(),
+        )));
+        let result = ObjDecodedMap {
+            blobs: vec![target.clone()],
+            trees: vec![tree],
+            commits: vec![commit],
+            ..Default::default()
+        };
+
+        let models = build_node_tree(&result, Path::new("/"), HashSet::new())
+            .await
+            .unwrap();
+        let node_types: Vec<String> = models
+            .nodes
+            .iter()
+            .map(|node| node.node_type.clone().unwrap())
+            .collect();
+        assert_eq!(vec!["tree", "symlink", "gitlink"], node_types);
+        // the submodule commit isn't stored, the link target is a blob
+        let data: Vec<(String, String)> = models
+            .node_datas
+            .iter()
+            .map(|data| {
+                (
+                    data.git_id.clone().unwrap(),
+                    data.node_type.clone().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(2, data.len());
+        assert!(data.contains(&(target.meta.id.to_plain_str(), "blob".to_owned())));
+    }
+
+    #[tokio::test]
+    async fn test_build_node_tree_missing_blob() {
+        id_generator::set_up_options().ok();
+        let blob = Blob::new(Arc::new(MetaData::new(
+            ObjectType::Blob,
+            &b"stored".to_vec(),
+        )));
+        let mut data = b"100644 a.txt\0".to_vec();
+        data.extend_from_slice(&blob.meta.id.0);
+        let tree = Tree::new(Arc::new(MetaData::new(ObjectType::Tree, &data)));
+        let commit = format!(
+            "tree {}\nauthor A <a> 1 +0000\ncommitter A <a> 1 +0000\n\nmsg\n",
+            tree.meta.id
+        );
+        let commit = Commit::new(Arc::new(MetaData::new(
+            ObjectType::Commit,
+            &commit.into_bytes(),
+        )));
+        let result = ObjDecodedMap {
+            trees: vec![tree],
+            commits: vec![commit],
+            ..Default::default()
+        };
+
+        let result_err = build_node_tree(&result, Path::new("/"), HashSet::new()).await;
+        assert!(matches!(result_err, Err(GitError::MissingObjects(_))));
+        // a blob stored by an earlier push is no missing one
+        let models = build_node_tree(&result, Path::new("/"), HashSet::from([blob.meta.id]))
+            .await
+            .unwrap();
+        assert_eq!(2, models.nodes.len());
+        assert!(models
+            .node_datas
+            .iter()
+            .all(|data| data.node_type.clone().unwrap() != "blob"));
+    }
+
     fn build_tree(node: &mut Box<dyn Node>, path: &PathBuf, depth: usize) {
         let parts: Vec<&str> = path.to_str().unwrap().split("/").collect();
 
@@ -0,0 +1,40 @@
+//!
+//! Directory listings of the tree of a commit.
+//!
+
+use serde::Serialize;
+
+use crate::git::object::base::tree::TreeItem;
+
+use super::structure::nodes::NodeType;
+
+/// An entry of a directory, symlinks come with their target and gitlinks with the commit of
+/// the submodule.
+#[derive(Debug, Clone, Serialize)]
+pub struct TreeEntryInfo {
+    pub name: String,
+    /// path from the repo root
+    pub path: String,
+    pub mode: String,
+    /// `tree`, `blob`, `symlink` or `gitlink`
+    pub node_type: String,
+    pub id: String,
+    pub target: Option<String>,
+}
+
+impl TreeEntryInfo {
+    pub fn new(item: &TreeItem, path: String, target: Option<String>) -> TreeEntryInfo {
+        let node_type = NodeType::from(item.item_type);
+        TreeEntryInfo {
+            name: item.filename.clone(),
+            path,
+            mode: String::from_utf8_lossy(&item.mode).into_owned(),
+            node_type: node_type.to_string(),
+            id: item.id.to_plain_str(),
+            target: match node_type {
+                NodeType::Gitlink => Some(item.id.to_plain_str()),
+                _ => target,
+            },
+        }
+    }
+}
@@ -43,6 +43,7 @@ use std::path::PathBuf;
 use anyhow::Result;
 use clap::{command, Args, Parser, Subcommand};
 use gateway::api::lib;
+use gust::driver::database::mysql;
 use gust::driver::utils::id_generator;
 
 #[derive(Parser)]
@@ -87,6 +88,9 @@ pub async fn main() -> Result<()> {
         ServeCommand::Ssh(config) => {
             gateway::ssh_server::server(config).await.unwrap();
         }
+        ServeCommand::Db(DbCommand::Migrate(config)) => {
+            mysql::migrate(config).await.unwrap();
+        }
     }
     Ok(())
 }
@@ -97,6 +101,39 @@ pub enum ServeCommand {
     Http(ServeConfig),
     /// start ssh server
     Ssh(ServeConfig),
+    /// manage the database schema
+    #[command(subcommand)]
+    Db(DbCommand),
+}
+
+#[derive(Subcommand)]
+pub enum DbCommand {
+    /// apply or roll back versioned schema migrations, apply all pending ones by default
+    Migrate(MigrateConfig),
+}
+
+#[derive(Args, Clone)]
+pub struct MigrateConfig {
+    #[command(subcommand)]
+    direction: Option<MigrateDirection>,
+}
+
+#[derive(Subcommand, Clone)]
+pub enum MigrateDirection {
+    /// apply pending migrations
+    Up {
+        /// number of migrations to apply, all pending ones if not set
+        #[arg(short = 'n', long)]
+        steps: Option<u32>,
+    },
+    /// roll back applied migrations
+    Down {
+        /// number of migrations to roll back
+        #[arg(short = 'n', long, default_value_t = 1)]
+        steps: u32,
+    },
+    /// print the applied and pending migrations
+    Status,
 }
 
 #[derive(Args, Clone)]